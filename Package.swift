@@ -0,0 +1,15 @@
+// swift-tools-version:5.9
+// The C interface from src/ffi.rs as a Swift package. Run
+// ./build_xcframework.sh first, then `import Dumac`.
+import PackageDescription
+
+let package = Package(
+    name: "Dumac",
+    platforms: [.macOS(.v12)],
+    products: [
+        .library(name: "Dumac", targets: ["Dumac"]),
+    ],
+    targets: [
+        .binaryTarget(name: "Dumac", path: "target/Dumac.xcframework"),
+    ]
+)
@@ -0,0 +1,15 @@
+# Builds target/Dumac.xcframework, a universal static libdumac.a with
+# include/dumac.h and its module map, for Package.swift
+set -e
+cargo build --release --features ffi --target aarch64-apple-darwin
+cargo build --release --features ffi --target x86_64-apple-darwin
+mkdir -p target/universal
+lipo -create \
+    target/aarch64-apple-darwin/release/libdumac.a \
+    target/x86_64-apple-darwin/release/libdumac.a \
+    -output target/universal/libdumac.a
+rm -rf target/Dumac.xcframework
+xcodebuild -create-xcframework \
+    -library target/universal/libdumac.a \
+    -headers include \
+    -output target/Dumac.xcframework
@@ -0,0 +1,36 @@
+// Generate a random tree to benchmark against:
+//
+//     cargo run --release --features testing --example generate_tree -- temp/random 5
+use dumac::testing::TreeBuilder;
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} directory [depth] [seed]", args[0]);
+        std::process::exit(2);
+    }
+    let depth = args
+        .get(2)
+        .map_or(5, |depth| depth.parse().expect("invalid depth"));
+    let seed = args
+        .get(3)
+        .map_or(1, |seed| seed.parse().expect("invalid seed"));
+
+    let tree = TreeBuilder::new(seed)
+        .depth(depth)
+        .fanout(4)
+        .files(50)
+        .hardlinks(0.05)
+        .sparse(0.01)
+        .non_utf8_names(0.01)
+        .build(&args[1])
+        .unwrap_or_else(|e| {
+            eprintln!("{}: {}", args[1], e);
+            std::process::exit(1);
+        });
+    println!(
+        "{} directories, {} files ({} hardlinks, {} sparse, {} non-UTF-8 names), {} blocks",
+        tree.dirs, tree.files, tree.hardlinks, tree.sparse, tree.non_utf8, tree.blocks
+    );
+}
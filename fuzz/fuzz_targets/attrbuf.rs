@@ -0,0 +1,14 @@
+// cargo +nightly fuzz run attrbuf
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte is the entry count getattrlistbulk claimed
+    if let Some((&count, buf)) = data.split_first() {
+        // Entries stop after the first malformed one
+        for entry in dumac::attrbuf::entries(buf, count as usize).flatten() {
+            std::hint::black_box(entry);
+        }
+    }
+});
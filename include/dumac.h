@@ -0,0 +1,52 @@
+// Link against libdumac built with --features ffi. dumac_scan returns 0 or an
+// errno, calls back with each completed directory, and cancels when the
+// callback returns false or another thread calls dumac_cancel on the
+// options' token.
+
+#ifndef DUMAC_H
+#define DUMAC_H
+
+// Generated by cbindgen from src/ffi.rs, don't edit by hand
+
+#include <stdbool.h>
+#include <stdint.h>
+
+typedef struct DumacCancel DumacCancel;
+
+typedef struct DumacOptions {
+  uint32_t threads;
+  bool clones;
+  bool compression;
+  bool cloud;
+  bool xattrs;
+  bool exclude_backups;
+  bool exclude_system;
+  const struct DumacCancel *cancel;
+} DumacOptions;
+
+typedef bool (*DumacDirCallback)(const char *path, int64_t blocks, void *context);
+
+typedef struct DumacResult {
+  int64_t blocks;
+  int64_t apparent_bytes;
+  uint64_t files;
+  uint64_t dirs;
+  uint64_t symlinks;
+  uint64_t hardlinks;
+  uint64_t errors;
+  bool cancelled;
+} DumacResult;
+
+struct DumacCancel *dumac_cancel_new(void);
+
+void dumac_cancel(const struct DumacCancel *cancel);
+
+void dumac_cancel_free(struct DumacCancel *cancel);
+
+int dumac_scan(const char *path,
+               const struct DumacOptions *options,
+               DumacDirCallback callback,
+               void *context,
+               struct DumacResult *result);
+
+#endif  /* DUMAC_H */
@@ -0,0 +1,91 @@
+use super::format_size;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 24 * 60 * 60;
+
+// Upper bounds of each bucket but the last, in days since modification
+const BUCKETS: [(i64, &str); 3] = [
+    (30, "under 30 days"),
+    (90, "30 to 90 days"),
+    (365, "90 days to a year"),
+];
+const OLDEST: &str = "over a year";
+
+// Blocks counted by how long ago files were modified, for --by-age
+pub struct Ages {
+    // When the scan started, files modified since count as new
+    now: i64,
+    files: [AtomicU64; BUCKETS.len() + 1],
+    blocks: [AtomicI64; BUCKETS.len() + 1],
+}
+
+// One directory's files, added to the scan's once it's been processed
+#[derive(Default)]
+pub struct Counts {
+    files: [u64; BUCKETS.len() + 1],
+    blocks: [i64; BUCKETS.len() + 1],
+}
+
+impl Counts {
+    pub fn add(&mut self, ages: &Ages, mtime: i64, blocks: i64) {
+        let days = (ages.now - mtime) / DAY;
+        let bucket = BUCKETS
+            .iter()
+            .position(|(limit, _)| days < *limit)
+            .unwrap_or(BUCKETS.len());
+        self.files[bucket] += 1;
+        self.blocks[bucket] += blocks;
+    }
+}
+
+impl Ages {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        Ages {
+            now,
+            files: Default::default(),
+            blocks: Default::default(),
+        }
+    }
+
+    pub fn record(&self, counts: &Counts) {
+        for bucket in 0..=BUCKETS.len() {
+            self.files[bucket].fetch_add(counts.files[bucket], Ordering::Relaxed);
+            self.blocks[bucket].fetch_add(counts.blocks[bucket], Ordering::Relaxed);
+        }
+    }
+
+    // Newest first, with each bucket's share of the files' blocks
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let blocks: Vec<i64> = self
+            .blocks
+            .iter()
+            .map(|blocks| blocks.load(Ordering::Relaxed))
+            .collect();
+        let total: i64 = blocks.iter().sum();
+        let labels = BUCKETS.iter().map(|(_, label)| *label).chain([OLDEST]);
+        writeln!(out, "by age:")?;
+        for (bucket, label) in labels.enumerate() {
+            let files = self.files[bucket].load(Ordering::Relaxed);
+            let noun = if files == 1 { "file" } else { "files" };
+            let share = match total {
+                0 => 0.0,
+                total => blocks[bucket] as f64 * 100.0 / total as f64,
+            };
+            writeln!(
+                out,
+                "  {}\t{:.0}%\t{} {}\t{}",
+                format_size(blocks[bucket]),
+                share,
+                files,
+                noun,
+                label
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+// A directory over --fail-if-over, reported on stderr and, with --notify, in
+// Notification Center. Alerts are posted through osascript: the
+// UserNotifications framework only serves app bundles, which a command line
+// tool run from cron or launchd isn't.
+use dumac::format_size;
+use std::process::{Command, Stdio};
+
+const OSASCRIPT: &str = "/usr/bin/osascript";
+
+// `root` holds `blocks`, more than `limit`
+pub fn over_limit(root: &str, blocks: i64, limit: i64, notify: bool) {
+    eprintln!(
+        "dumac: {}: {} is over the {} limit",
+        root,
+        format_size(blocks),
+        format_size(limit)
+    );
+    if notify {
+        let message = format!(
+            "{} grew past {}, it holds {}",
+            home_relative(root),
+            format_size(limit),
+            format_size(blocks)
+        );
+        post(&message);
+    }
+}
+
+// A Notification Center alert titled dumac
+pub fn post(message: &str) {
+    let script = format!(
+        "display notification {} with title \"dumac\"",
+        quoted(message)
+    );
+    let posted = Command::new(OSASCRIPT)
+        .args(["-e", &script])
+        .stdout(Stdio::null())
+        .status();
+    if !posted.is_ok_and(|status| status.success()) {
+        eprintln!("dumac: cannot post a notification with {}", OSASCRIPT);
+    }
+}
+
+// An AppleScript string literal
+fn quoted(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+// ~/Library/Caches for /Users/me/Library/Caches, shorter in an alert
+fn home_relative(path: &str) -> String {
+    let home = std::env::var("HOME").unwrap_or_default();
+    match path.strip_prefix(home.as_str()) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            format!("~{}", rest)
+        }
+        _ => path.to_string(),
+    }
+}
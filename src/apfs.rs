@@ -0,0 +1,107 @@
+// `dumac apfs-diff`: comparing a directory across APFS snapshots of its
+// volume, such as the local snapshots Time Machine keeps (`tmutil
+// listlocalsnapshots /` names them). Each snapshot is mounted read-only with
+// mount_apfs, which needs root, scanned and unmounted again.
+use super::diff::print_diff;
+use super::failure::Failure;
+use dumac::pool::ThreadPool;
+use dumac::snapshot::Snapshot;
+use dumac::volume;
+use dumac::{DumacError, ScanOptions};
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// By full path, since this usually runs under sudo
+const MOUNT_APFS: &str = "/sbin/mount_apfs";
+
+// A snapshot mounted on a directory of its own, unmounted when dropped
+struct Mount {
+    dir: PathBuf,
+}
+
+impl Mount {
+    fn new(volume: &str, name: &str) -> Result<Mount, Failure> {
+        let dir = std::env::temp_dir().join(format!("dumac-{}-{}", std::process::id(), name));
+        fs::create_dir(&dir).map_err(|e| DumacError::Io {
+            op: "mkdir".into(),
+            path: dir.clone(),
+            errno: e.raw_os_error().unwrap_or(libc::EIO),
+        })?;
+        let mounted = Command::new(MOUNT_APFS)
+            .args(["-o", "ro,nobrowse", "-s", name, volume])
+            .arg(&dir)
+            .status()
+            .is_ok_and(|status| status.success());
+        if !mounted {
+            let _ = fs::remove_dir(&dir);
+            let hint = match unsafe { libc::geteuid() } {
+                0 => "",
+                _ => ", which needs root",
+            };
+            return Err(Failure::Snapshot(format!(
+                "{}: cannot mount this snapshot of {}{}",
+                name, volume, hint
+            )));
+        }
+        Ok(Mount { dir })
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        if let Ok(c_dir) = CString::new(self.dir.as_os_str().as_bytes()) {
+            unsafe { libc::unmount(c_dir.as_ptr(), 0) };
+        }
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+// Scan `dir` as it was in the snapshot `old` and as it is in `new`, or on
+// the live volume without `new`, and print each directory that changed the
+// way `dumac diff` does
+pub fn run_diff(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    old: &str,
+    new: Option<&str>,
+    dir: &str,
+) -> Result<(), Failure> {
+    let resolved = fs::canonicalize(dir).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: dir.into(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    let c_dir = CString::new(resolved.as_os_str().as_bytes())
+        .map_err(|_| DumacError::InvalidPath(dir.to_string()))?;
+    let volume = volume::volume_for_path(&c_dir)
+        .filter(|volume| volume.fstype == "apfs")
+        .ok_or_else(|| Failure::Snapshot(format!("{}: not on an APFS volume", dir)))?;
+    // Firmlinked directories like /Users are on the data volume, mounted at
+    // /System/Volumes/Data, under the same name
+    let relative = resolved
+        .strip_prefix(&volume.mount_point)
+        .or_else(|_| resolved.strip_prefix("/"))
+        .unwrap_or(&resolved)
+        .to_path_buf();
+
+    let scan_snapshot = |name: &str| {
+        let mount = Mount::new(&volume.mount_point, name)?;
+        let path = mount.dir.join(&relative);
+        let path = path
+            .to_str()
+            .ok_or_else(|| DumacError::InvalidPath(path.to_string_lossy().into_owned()))?;
+        let mut scanned = Snapshot::scan(options, pool, path)?;
+        scanned.root = Path::new(dir).to_path_buf();
+        Ok::<_, Failure>(scanned)
+    };
+    let before = scan_snapshot(old)?;
+    let after = match new {
+        Some(new) => scan_snapshot(new)?,
+        None => Snapshot::scan(options, pool, dir)?,
+    };
+    print_diff(&before, &after);
+    Ok(())
+}
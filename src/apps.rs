@@ -0,0 +1,205 @@
+// `dumac apps`: what each application keeps in a Library folder, in its
+// sandbox container, its group containers and its Application Support
+// folder, totalled per app. Folders are named by bundle id, or by app name
+// in Application Support, and matched with the apps installed.
+use super::plist;
+use dumac::internal::Mutex;
+use dumac::pool::ThreadPool;
+use dumac::stream::DirResult;
+use dumac::{format_size, DumacError, ScanOptions, Scanner};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Where apps keep their data, inside the Library folder
+const PLACES: [&str; 3] = ["Containers", "Group Containers", "Application Support"];
+
+// Containers named by UUID say whose they are in here
+const CONTAINER_METADATA: &str = ".com.apple.containermanagerd.metadata.plist";
+
+// One application's folders, largest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppUsage {
+    // "Name (bundle id)", or the folder's identifier for apps not installed
+    pub app: String,
+    pub blocks: i64,
+    pub folders: Vec<(PathBuf, i64)>,
+}
+
+// An installed application
+struct App {
+    id: String,
+    name: String,
+}
+
+// Every app's folders under `library`, largest app first. Places that are
+// missing are skipped and ones that can't be read are reported.
+pub fn attribute(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    library: &str,
+) -> Result<Vec<AppUsage>, DumacError> {
+    if let Err(e) = fs::metadata(library) {
+        return Err(DumacError::Io {
+            op: "stat".into(),
+            path: library.into(),
+            errno: e.raw_os_error().unwrap_or(libc::EIO),
+        });
+    }
+    let found = Arc::new(Mutex::new(Vec::new()));
+    // One scanner, so files linked from more than one place count once
+    let mut scanner = Scanner::new(options.clone());
+    let places: Vec<PathBuf> = PLACES
+        .iter()
+        .map(|place| Path::new(library).join(place))
+        .filter(|place| place.is_dir())
+        .collect();
+    let results = found.clone();
+    let tops = places.clone();
+    scanner.on_result(move |dir: DirResult| {
+        if dir
+            .path
+            .parent()
+            .is_some_and(|parent| tops.iter().any(|top| top == parent))
+        {
+            results.lock().push((dir.path, dir.blocks));
+        }
+    });
+    for place in &places {
+        let Some(place) = place.to_str() else {
+            continue;
+        };
+        if let Err(e) = pool.install(|| scanner.calculate_size(place.to_string())) {
+            eprintln!("dumac: {}", e);
+        }
+    }
+    scanner.report();
+
+    let apps = installed();
+    let mut usage: Vec<AppUsage> = Vec::new();
+    for (folder, blocks) in std::mem::take(&mut *found.lock()) {
+        let app = owner(&folder, &apps);
+        let i = match usage.iter().position(|known| known.app == app) {
+            Some(i) => i,
+            None => {
+                usage.push(AppUsage {
+                    app,
+                    blocks: 0,
+                    folders: Vec::new(),
+                });
+                usage.len() - 1
+            }
+        };
+        usage[i].blocks += blocks;
+        usage[i].folders.push((folder, blocks));
+    }
+    for app in &mut usage {
+        app.folders
+            .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    }
+    usage.sort_by(|a, b| b.blocks.cmp(&a.blocks).then_with(|| a.app.cmp(&b.app)));
+    Ok(usage)
+}
+
+// Whose `folder` is, as "Name (bundle id)" when the app is installed
+fn owner(folder: &Path, apps: &[App]) -> String {
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let place = folder.parent().and_then(Path::file_name);
+    let id = match place.and_then(|place| place.to_str()) {
+        Some("Application Support") => name,
+        place => {
+            let id = plist::string(&folder.join(CONTAINER_METADATA), "MCMMetadataIdentifier")
+                .unwrap_or(name);
+            match place {
+                Some("Group Containers") => group_app_id(&id).to_string(),
+                _ => id,
+            }
+        }
+    };
+    let matching = |app: &&App| {
+        let app_id = app.id.to_lowercase();
+        let id = id.to_lowercase();
+        // Helpers and extensions are named after their app, as in
+        // com.apple.Safari.SafeBrowsing
+        id == app_id
+            || id
+                .strip_prefix(&app_id)
+                .is_some_and(|rest| rest.starts_with('.'))
+            || id == app.name.to_lowercase()
+    };
+    // The longest id is the closest match
+    match apps.iter().filter(matching).max_by_key(|app| app.id.len()) {
+        Some(app) => format!("{} ({})", app.name, app.id),
+        None => id,
+    }
+}
+
+// The app a group container belongs to, from its identifier: TEAMID.com.x.y
+// or group.com.x.y become com.x.y
+fn group_app_id(id: &str) -> &str {
+    let id = match id.split_once('.') {
+        Some((team, rest))
+            if team.len() == 10
+                && team
+                    .bytes()
+                    .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => id,
+    };
+    id.strip_prefix("group.").unwrap_or(id)
+}
+
+// The apps in the usual folders and the folders inside them, such as
+// /Applications/Utilities
+fn installed() -> Vec<App> {
+    let mut folders = vec![
+        (PathBuf::from("/Applications"), true),
+        (PathBuf::from("/System/Applications"), true),
+    ];
+    if let Some(home) = std::env::var_os("HOME") {
+        folders.push((Path::new(&home).join("Applications"), true));
+    }
+    let mut apps = Vec::new();
+    while let Some((folder, top)) = folders.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "app") {
+                apps.extend(read_app(&path));
+            } else if top && path.is_dir() {
+                folders.push((path, false));
+            }
+        }
+    }
+    apps
+}
+
+fn read_app(bundle: &Path) -> Option<App> {
+    let info = bundle.join("Contents/Info.plist");
+    let id = plist::string(&info, "CFBundleIdentifier")?;
+    let name = plist::string(&info, "CFBundleDisplayName")
+        .or_else(|| plist::string(&info, "CFBundleName"))
+        .or_else(|| Some(bundle.file_stem()?.to_string_lossy().into_owned()))?;
+    Some(App { id, name })
+}
+
+// Each app's total and folders, then what they hold together
+pub fn run(options: &ScanOptions, pool: &ThreadPool, library: &str) -> Result<(), DumacError> {
+    let usage = attribute(options, pool, library)?;
+    for app in &usage {
+        println!("{}\t{}", format_size(app.blocks), app.app);
+        for (folder, blocks) in &app.folders {
+            println!("\t{}\t{}", format_size(*blocks), folder.display());
+        }
+    }
+    let total: i64 = usage.iter().map(|app| app.blocks).sum();
+    println!("{}\tin {} apps", format_size(total), usage.len());
+    Ok(())
+}
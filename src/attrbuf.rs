@@ -0,0 +1,249 @@
+// Parsing getattrlistbulk's buffer. Lengths and offsets in it come from the
+// filesystem, and buggy FUSE and SMB servers have been seen returning
+// nonsense, so every read is checked against the entry it belongs to. A bad
+// entry ends the parse rather than being skipped, since the next entry's
+// position depends on it. fuzz/ runs this over arbitrary bytes.
+use super::{ATTR_CMN_ERROR, VNON};
+use std::fmt;
+
+// The attributes one entry returned, in the scanner's terms. Those left out
+// of the returned set keep the defaults below.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<'a> {
+    // Without its NUL
+    pub name: Option<&'a [u8]>,
+    // Nonzero when the entry couldn't be read, with nothing after it
+    pub error: u32,
+    pub dev: u32,
+    pub obj_type: u32,
+    // Requested with --birthtime
+    pub crtime: Option<(i64, i64)>,
+    pub mtime: Option<(i64, i64)>,
+    // Requested with --by-owner
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub flags: u32,
+    pub inode: u64,
+    // Directories only
+    pub entries: u32,
+    pub mount_point: bool,
+    // Non-directories only
+    pub nlink: u32,
+    pub data_length: Option<i64>,
+    pub data_alloc_size: Option<i64>,
+    pub rsrc_alloc_size: i64,
+    // Requested with --clones
+    pub private_size: Option<i64>,
+    pub clone_id: Option<u64>,
+    pub ext_flags: u64,
+}
+
+impl Default for Entry<'_> {
+    fn default() -> Self {
+        Entry {
+            name: None,
+            error: 0,
+            dev: 0,
+            obj_type: VNON,
+            crtime: None,
+            mtime: None,
+            uid: None,
+            gid: None,
+            flags: 0,
+            inode: 0,
+            entries: 0,
+            mount_point: false,
+            nlink: 1,
+            data_length: None,
+            data_alloc_size: None,
+            rsrc_alloc_size: 0,
+            private_size: None,
+            clone_id: None,
+            ext_flags: 0,
+        }
+    }
+}
+
+// An entry that runs past the buffer or points outside itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Malformed {
+    // Where the bad entry starts in the buffer
+    pub offset: usize,
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "malformed attribute buffer entry at offset {}",
+            self.offset
+        )
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+// The first `count` entries in `buf`, as getattrlistbulk returned them
+pub fn entries(buf: &[u8], count: usize) -> Entries<'_> {
+    Entries {
+        buf,
+        offset: 0,
+        remaining: count,
+    }
+}
+
+pub struct Entries<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, Malformed>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let offset = self.offset;
+        let malformed = Malformed { offset };
+        let entry = self.buf.get(offset..).and_then(|rest| {
+            let length = u32::from_ne_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            // At least the length itself, so the next entry is further on
+            rest.get(..length).filter(|_| length >= 4)
+        });
+        let Some(entry) = entry else {
+            self.remaining = 0;
+            return Some(Err(malformed));
+        };
+        self.offset += entry.len();
+        self.remaining -= 1;
+        match parse(entry) {
+            Some(parsed) => Some(Ok(parsed)),
+            None => {
+                self.remaining = 0;
+                Some(Err(malformed))
+            }
+        }
+    }
+}
+
+// Reads fixed-size fields in order through one entry
+struct Fields<'a> {
+    entry: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let field = self.entry.get(self.at..self.at + N)?;
+        self.at += N;
+        field.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_ne_bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.take().map(i32::from_ne_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_ne_bytes)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take().map(i64::from_ne_bytes)
+    }
+
+    // An attrreference_t, whose offset is from the reference itself
+    fn name(&mut self) -> Option<Option<&'a [u8]>> {
+        let reference = self.at;
+        let data_offset = self.i32()?;
+        let length = self.u32()? as usize;
+        if length == 0 {
+            return Some(None);
+        }
+        let start = reference.checked_add_signed(data_offset as isize)?;
+        self.entry
+            .get(start..start.checked_add(length - 1)?)
+            .map(Some)
+    }
+}
+
+// Attributes follow the returned set in the order the scanner requests
+// them in get_dir_info
+fn parse(entry: &[u8]) -> Option<Entry<'_>> {
+    let mut fields = Fields { entry, at: 4 };
+    let commonattr = fields.u32()?;
+    let _volattr = fields.u32()?;
+    let dirattr = fields.u32()?;
+    let fileattr = fields.u32()?;
+    let forkattr = fields.u32()?;
+
+    let mut parsed = Entry::default();
+    if commonattr & libc::ATTR_CMN_NAME != 0 {
+        parsed.name = fields.name()?;
+    }
+    if commonattr & ATTR_CMN_ERROR != 0 {
+        parsed.error = fields.u32()?;
+        if parsed.error != 0 {
+            return Some(parsed);
+        }
+    }
+    // dev_t is 32 bits on macOS
+    if commonattr & libc::ATTR_CMN_DEVID != 0 {
+        parsed.dev = fields.i32()? as u32;
+    }
+    if commonattr & libc::ATTR_CMN_OBJTYPE != 0 {
+        parsed.obj_type = fields.u32()?;
+    }
+    // Timespecs, two 64-bit fields each
+    if commonattr & libc::ATTR_CMN_CRTIME != 0 {
+        parsed.crtime = Some((fields.i64()?, fields.i64()?));
+    }
+    if commonattr & libc::ATTR_CMN_MODTIME != 0 {
+        parsed.mtime = Some((fields.i64()?, fields.i64()?));
+    }
+    if commonattr & libc::ATTR_CMN_OWNERID != 0 {
+        parsed.uid = Some(fields.u32()?);
+    }
+    if commonattr & libc::ATTR_CMN_GRPID != 0 {
+        parsed.gid = Some(fields.u32()?);
+    }
+    if commonattr & libc::ATTR_CMN_FLAGS != 0 {
+        parsed.flags = fields.u32()?;
+    }
+    if commonattr & libc::ATTR_CMN_FILEID != 0 {
+        parsed.inode = fields.u64()?;
+    }
+    if dirattr & libc::ATTR_DIR_ENTRYCOUNT != 0 {
+        parsed.entries = fields.u32()?;
+    }
+    if dirattr & libc::ATTR_DIR_MOUNTSTATUS != 0 {
+        parsed.mount_point = fields.u32()? & libc::DIR_MNTSTATUS_MNTPOINT != 0;
+    }
+    if fileattr & libc::ATTR_FILE_LINKCOUNT != 0 {
+        parsed.nlink = fields.u32()?;
+    }
+    if fileattr & libc::ATTR_FILE_DATALENGTH != 0 {
+        parsed.data_length = Some(fields.i64()?);
+    }
+    if fileattr & libc::ATTR_FILE_DATAALLOCSIZE != 0 {
+        parsed.data_alloc_size = Some(fields.i64()?);
+    }
+    if fileattr & libc::ATTR_FILE_RSRCALLOCSIZE != 0 {
+        parsed.rsrc_alloc_size = fields.i64()?;
+    }
+    if forkattr & libc::ATTR_CMNEXT_PRIVATESIZE != 0 {
+        parsed.private_size = Some(fields.i64()?);
+    }
+    if forkattr & libc::ATTR_CMNEXT_CLONEID != 0 {
+        parsed.clone_id = Some(fields.u64()?);
+    }
+    if forkattr & libc::ATTR_CMNEXT_EXT_FLAGS != 0 {
+        parsed.ext_flags = fields.u64()?;
+    }
+    Some(parsed)
+}
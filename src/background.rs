@@ -0,0 +1,28 @@
+use std::os::raw::c_int;
+
+// macOS I/O policy constants not in libc crate (<sys/resource.h>)
+const IOPOL_TYPE_DISK: c_int = 0;
+pub const IOPOL_SCOPE_PROCESS: c_int = 0;
+pub const IOPOL_SCOPE_THREAD: c_int = 1;
+const IOPOL_THROTTLE: c_int = 3;
+
+extern "C" {
+    pub fn setiopolicy_np(iotype: c_int, scope: c_int, policy: c_int) -> c_int;
+}
+
+// Throttle the process's disk I/O so it yields to foreground work
+pub fn throttle_io() -> Result<(), String> {
+    let ret = unsafe { setiopolicy_np(IOPOL_TYPE_DISK, IOPOL_SCOPE_PROCESS, IOPOL_THROTTLE) };
+    if ret != 0 {
+        return Err("failed to set I/O throttling policy".to_string());
+    }
+    Ok(())
+}
+
+// Run the calling thread at background QoS, so the scheduler prefers
+// everything else and it lands on efficiency cores
+pub fn lower_thread_qos() {
+    unsafe {
+        libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0);
+    }
+}
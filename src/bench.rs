@@ -0,0 +1,78 @@
+use dumac::{profile, ScanOptions, Scanner};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+// Time repeated scans of a directory. Warm runs are preceded by an untimed
+// scan to fill the cache, cold runs purge the filesystem cache before each scan.
+pub fn run(
+    options: &ScanOptions,
+    cache: bool,
+    root_dir: &str,
+    iterations: usize,
+    cold: bool,
+) -> Result<(), String> {
+    // Entry counts come from the profile counters
+    profile::enable();
+
+    if !cold {
+        scan(options, cache, root_dir)?;
+    }
+
+    let mut times = Vec::with_capacity(iterations);
+    let mut entries = 0;
+    for _ in 0..iterations {
+        if cold {
+            purge()?;
+        }
+        let before = profile::ENTRIES.get();
+        let start = Instant::now();
+        scan(options, cache, root_dir)?;
+        times.push(start.elapsed());
+        entries = profile::ENTRIES.get() - before;
+    }
+
+    let total: Duration = times.iter().sum();
+    let mean = total / iterations as u32;
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+
+    println!(
+        "iterations: {} ({} cache)",
+        iterations,
+        if cold { "cold" } else { "warm" }
+    );
+    println!("entries: {}", entries);
+    println!(
+        "time: mean {:.3}s, min {:.3}s, max {:.3}s",
+        mean.as_secs_f64(),
+        min.as_secs_f64(),
+        max.as_secs_f64()
+    );
+    println!(
+        "entries/sec: {:.0}",
+        entries as f64 / mean.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+fn scan(options: &ScanOptions, cache: bool, root_dir: &str) -> Result<i64, String> {
+    // Every run starts from scratch, or hardlinks would be skipped after the first
+    let mut scanner = Scanner::new(options.clone());
+    if cache {
+        scanner.load_cache(root_dir).map_err(|e| e.to_string())?;
+    }
+    scanner
+        .calculate_size(root_dir.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// Drop the filesystem cache, which needs root on recent macOS
+fn purge() -> Result<(), String> {
+    let status = Command::new("purge")
+        .status()
+        .map_err(|e| format!("cannot run purge: {}", e))?;
+    if !status.success() {
+        return Err("purge failed, cold runs need root (try sudo)".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use super::dataless::SF_DATALESS;
+use super::sync::Mutex;
+use super::tally::Tally;
+use super::{profile, CloneInfo, DirInfo, FileInfo, SubdirInfo};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+// Bumped whenever the file layout changes, older caches are then ignored
+const MAGIC: &[u8; 8] = b"dumacc9\n";
+
+// Identity and modification time of a directory. A directory's mtime changes
+// whenever an entry is added, removed or renamed, so an unchanged stamp means
+// its listing can be reused.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DirStamp {
+    pub dev: u64,
+    pub inode: u64,
+    pub mtime: (i64, i64),
+}
+
+// What a directory's listing contributed: the blocks of its plain files, the
+// files that need more than their blocks (hardlinks and clones are still
+// deduplicated on reuse, compressed and cloud-only files reported, extended
+// attributes counted), its subdirectories and what it held, for --stats
+#[derive(Clone)]
+struct Entry {
+    mtime: (i64, i64),
+    blocks: i64,
+    special: Vec<FileInfo>,
+    subdirs: Vec<SubdirInfo>,
+    tally: Tally,
+}
+
+// Per-directory listings from the previous scan of a root, and the ones seen
+// in this scan, which replace them when saved. Saving only what was seen
+// keeps deleted directories from piling up.
+pub struct Cache {
+    // None for a cache that's only kept in memory
+    path: Option<PathBuf>,
+    previous: HashMap<(u64, u64), Entry>,
+    current: Mutex<HashMap<(u64, u64), Entry>>,
+}
+
+impl Cache {
+    // Load the cache for a root directory, starting empty if there is none.
+    // Listings depend on the accounting options, so each `variant` of them
+    // gets a cache of its own.
+    pub fn open(root_dir: &str, variant: u64) -> Result<Cache, String> {
+        let dir = cache_dir().ok_or("cannot find a cache directory, set HOME")?;
+        let root = fs::canonicalize(root_dir)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| root_dir.to_string());
+        let mut key = root.into_bytes();
+        key.extend_from_slice(&variant.to_le_bytes());
+        let path = dir.join(format!("{:016x}", fnv1a(&key)));
+
+        let previous = match File::open(&path) {
+            Ok(file) => read_entries(&mut BufReader::new(file)).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+        Ok(Cache {
+            path: Some(path),
+            previous,
+            current: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // A cache that starts empty and is never saved, for --watch
+    pub fn in_memory() -> Cache {
+        Cache {
+            path: None,
+            previous: HashMap::new(),
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Start over for the next scan, with this scan's listings as the previous
+    // ones
+    pub fn into_next(self) -> Cache {
+        Cache {
+            path: self.path,
+            previous: self.current.into_inner(),
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Drop a directory's previous listing, when it's known to have changed in
+    // ways its mtime doesn't show, such as a file in it growing
+    pub fn forget(&mut self, dev: u64, inode: u64) {
+        self.previous.remove(&(dev, inode));
+    }
+
+    // The directory's listing from the previous scan, if it hasn't changed since
+    pub fn lookup(&self, stamp: DirStamp) -> Option<DirInfo> {
+        let entry = self.previous.get(&(stamp.dev, stamp.inode))?;
+        if entry.mtime != stamp.mtime {
+            return None;
+        }
+        profile::CACHE_HITS.add(1);
+
+        let mut files = vec![FileInfo {
+            blocks: entry.blocks,
+            dev: 0,
+            inode: 0,
+            nlink: 1,
+            clone: None,
+            flags: 0,
+            data_length: None,
+            xattr_bytes: 0,
+            owner: None,
+            dated: None,
+            entry: None,
+        }];
+        files.extend(entry.special.iter().cloned());
+        // Subdirectory mtimes may have changed since, leave them to be looked up
+        let subdirs = entry
+            .subdirs
+            .iter()
+            .map(|subdir| SubdirInfo {
+                mtime: None,
+                ..subdir.clone()
+            })
+            .collect();
+        self.current
+            .lock()
+            .insert((stamp.dev, stamp.inode), entry.clone());
+        Some(DirInfo {
+            files,
+            subdirs,
+            tally: entry.tally,
+        })
+    }
+
+    // Remember a directory's fresh listing for the next scan
+    pub fn store(&self, stamp: DirStamp, dir_info: &DirInfo) {
+        let entry = entry_for(stamp.mtime, dir_info);
+        self.current.lock().insert((stamp.dev, stamp.inode), entry);
+    }
+
+    // Write this scan's listings out, replacing the previous cache for the root
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let error = |e: io::Error| format!("cannot write cache {}: {}", path.display(), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        // Write to a temporary file first so an interrupted save can't leave a
+        // truncated cache behind
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp).map_err(error)?);
+        write_entries(&mut writer, &self.current.lock()).map_err(error)?;
+        writer.flush().map_err(error)?;
+        fs::rename(&tmp, path).map_err(error)
+    }
+}
+
+fn entry_for(mtime: (i64, i64), dir_info: &DirInfo) -> Entry {
+    let mut blocks = 0;
+    let mut special = Vec::new();
+    for file in &dir_info.files {
+        let reported = file.flags & (libc::UF_COMPRESSED | SF_DATALESS) != 0;
+        if file.nlink > 1 || file.clone.is_some() || reported || file.xattr_bytes > 0 {
+            special.push(file.clone());
+        } else {
+            blocks += file.blocks;
+        }
+    }
+    let subdirs = dir_info.subdirs.clone();
+    Entry {
+        mtime,
+        blocks,
+        special,
+        subdirs,
+        tally: dir_info.tally,
+    }
+}
+
+// $XDG_CACHE_HOME/dumac, or ~/.cache/dumac
+fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("dumac"))
+}
+
+// Stable across builds, unlike std's hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn write_entries(out: &mut impl Write, entries: &HashMap<(u64, u64), Entry>) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (&(dev, inode), entry) in entries {
+        for value in [dev, inode, entry.mtime.0 as u64, entry.mtime.1 as u64] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&entry.blocks.to_le_bytes())?;
+        let tally = entry.tally;
+        for value in [tally.files, tally.symlinks, tally.apparent_bytes as u64] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&(entry.special.len() as u32).to_le_bytes())?;
+        for file in &entry.special {
+            write_file(out, file)?;
+        }
+        out.write_all(&(entry.subdirs.len() as u32).to_le_bytes())?;
+        for subdir in &entry.subdirs {
+            write_subdir(out, subdir)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_entries(input: &mut impl Read) -> io::Result<HashMap<(u64, u64), Entry>> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown cache format",
+        ));
+    }
+
+    let count = read_u64(input)?;
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let dev = read_u64(input)?;
+        let inode = read_u64(input)?;
+        let mtime = (read_u64(input)? as i64, read_u64(input)? as i64);
+        let blocks = read_u64(input)? as i64;
+        let tally = Tally {
+            files: read_u64(input)?,
+            symlinks: read_u64(input)?,
+            apparent_bytes: read_u64(input)? as i64,
+        };
+
+        let special_count = read_u32(input)?;
+        let mut special = Vec::new();
+        for _ in 0..special_count {
+            special.push(read_file(input)?);
+        }
+
+        let subdir_count = read_u32(input)?;
+        let mut subdirs = Vec::new();
+        for _ in 0..subdir_count {
+            subdirs.push(read_subdir(input)?);
+        }
+
+        entries.insert(
+            (dev, inode),
+            Entry {
+                mtime,
+                blocks,
+                special,
+                subdirs,
+                tally,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn write_file(out: &mut impl Write, file: &FileInfo) -> io::Result<()> {
+    out.write_all(&file.blocks.to_le_bytes())?;
+    out.write_all(&file.dev.to_le_bytes())?;
+    out.write_all(&file.inode.to_le_bytes())?;
+    out.write_all(&file.nlink.to_le_bytes())?;
+    // A zero shared size marks no clone
+    let clone = file.clone.unwrap_or(CloneInfo {
+        id: 0,
+        shared_blocks: 0,
+    });
+    out.write_all(&clone.id.to_le_bytes())?;
+    out.write_all(&clone.shared_blocks.to_le_bytes())?;
+    out.write_all(&file.flags.to_le_bytes())?;
+    // And a negative length no length
+    out.write_all(&file.data_length.unwrap_or(-1).to_le_bytes())?;
+    out.write_all(&file.xattr_bytes.to_le_bytes())
+}
+
+fn read_file(input: &mut impl Read) -> io::Result<FileInfo> {
+    let blocks = read_u64(input)? as i64;
+    let dev = read_u32(input)?;
+    let inode = read_u64(input)?;
+    let nlink = read_u32(input)?;
+    let id = read_u64(input)?;
+    let shared_blocks = read_u64(input)? as i64;
+    let flags = read_u32(input)?;
+    let data_length = read_u64(input)? as i64;
+    let xattr_bytes = read_u64(input)? as i64;
+    Ok(FileInfo {
+        blocks,
+        dev,
+        inode,
+        nlink,
+        clone: (shared_blocks != 0).then_some(CloneInfo { id, shared_blocks }),
+        flags,
+        data_length: (data_length >= 0).then_some(data_length),
+        xattr_bytes,
+        owner: None,
+        dated: None,
+        entry: None,
+    })
+}
+
+fn write_subdir(out: &mut impl Write, subdir: &SubdirInfo) -> io::Result<()> {
+    out.write_all(&(subdir.name.len() as u32).to_le_bytes())?;
+    out.write_all(&subdir.name)?;
+    out.write_all(&subdir.entries.to_le_bytes())?;
+    out.write_all(&[subdir.mount_point as u8 | (subdir.dataless as u8) << 1])?;
+    out.write_all(&subdir.dev.to_le_bytes())?;
+    out.write_all(&subdir.inode.to_le_bytes())
+}
+
+fn read_subdir(input: &mut impl Read) -> io::Result<SubdirInfo> {
+    let mut name = vec![0u8; read_u32(input)? as usize];
+    input.read_exact(&mut name)?;
+    let entries = read_u32(input)?;
+    let mut kind = [0u8; 1];
+    input.read_exact(&mut kind)?;
+    let dev = read_u32(input)?;
+    let inode = read_u64(input)?;
+    Ok(SubdirInfo {
+        name: name.into(),
+        entries,
+        mount_point: kind[0] & 1 != 0,
+        dataless: kind[0] & 2 != 0,
+        dev,
+        inode,
+        mtime: None,
+    })
+}
+
+// Little-endian integers and length-prefixed paths, as cache, snapshot and
+// history files hold them
+pub fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(super) fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn write_path(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+pub fn read_path(input: &mut impl Read) -> io::Result<PathBuf> {
+    let length = read_u32(input)? as u64;
+    let mut bytes = Vec::new();
+    input.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
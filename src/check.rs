@@ -0,0 +1,136 @@
+use super::reference;
+use dumac::pool::{self, ThreadPool};
+use dumac::{format_size, DumacError, Listing, Listings, ScanOptions, Scanner};
+use std::collections::BTreeSet;
+
+// Differences printed before the rest are only counted
+const MAX_LISTED: usize = 20;
+
+// Scan a directory with all workers and then with one, and compare. Listings
+// that differ mean the tree changed between the passes, totals that differ
+// over identical listings mean dumac miscounted. Returns whether both agreed.
+pub fn run(options: &ScanOptions, pool: &ThreadPool, root_dir: &str) -> Result<bool, DumacError> {
+    let single = pool::build(1, || {})
+        .map_err(|e| DumacError::Threads(format!("cannot start a single-threaded pass: {}", e)))?;
+    let (parallel_total, parallel) = pass(options, pool, root_dir)?;
+    let (single_total, single) = pass(options, &single, root_dir)?;
+
+    print_totals(
+        root_dir,
+        &[
+            ("parallel", parallel_total),
+            ("single-threaded", single_total),
+        ],
+    );
+
+    let changed = differing(&parallel, &single);
+    if changed.is_empty() {
+        if parallel_total == single_total {
+            println!("consistent: {} directories", parallel.len());
+            return Ok(true);
+        }
+        println!("totals differ over identical listings, this is a bug in dumac");
+        return Ok(false);
+    }
+
+    println!(
+        "{} directories changed between the passes, the tree is changing on disk:",
+        changed.len()
+    );
+    print_differences(&changed, &parallel, &single);
+    Ok(false)
+}
+
+// Scan a directory with dumac and with the reference walker in reference.rs,
+// and print the directories they disagree on. Only plain accounting is
+// compared, so `plain` should leave out clone, compression, cloud and
+// exclude options.
+pub fn verify_du(
+    plain: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+) -> Result<bool, DumacError> {
+    let (dumac_total, dumac) = pass(plain, pool, root_dir)?;
+    let (reference_total, reference) = reference::walk(root_dir)?;
+
+    print_totals(
+        root_dir,
+        &[("dumac", dumac_total), ("reference", reference_total)],
+    );
+
+    let differ = differing(&dumac, &reference);
+    if differ.is_empty() {
+        if dumac_total == reference_total {
+            println!("agree: {} directories", dumac.len());
+            return Ok(true);
+        }
+        println!("totals differ over identical listings, hardlinks were counted differently");
+        return Ok(false);
+    }
+
+    println!(
+        "{} directories disagree (dumac vs reference):",
+        differ.len()
+    );
+    print_differences(&differ, &dumac, &reference);
+    Ok(false)
+}
+
+// Exact block counts too, a small difference can round away
+fn print_totals(root_dir: &str, totals: &[(&str, i64)]) {
+    for &(name, total) in totals {
+        println!(
+            "{:<16} {}\t{} ({} blocks)",
+            format!("{}:", name),
+            format_size(total),
+            root_dir,
+            total
+        );
+    }
+}
+
+// Paths whose listings differ, in order
+fn differing<'a>(a: &'a Listings, b: &'a Listings) -> Vec<&'a [u8]> {
+    let paths: BTreeSet<&[u8]> = a.keys().chain(b.keys()).map(|p| &**p).collect();
+    paths
+        .into_iter()
+        .filter(|&path| a.get(path) != b.get(path))
+        .collect()
+}
+
+fn print_differences(paths: &[&[u8]], a: &Listings, b: &Listings) {
+    for &path in paths.iter().take(MAX_LISTED) {
+        println!(
+            "  {}: {} vs {}",
+            String::from_utf8_lossy(path),
+            describe(a.get(path)),
+            describe(b.get(path))
+        );
+    }
+    if paths.len() > MAX_LISTED {
+        println!("  ... and {} more", paths.len() - MAX_LISTED);
+    }
+}
+
+fn pass(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+) -> Result<(i64, Listings), DumacError> {
+    let mut scanner = Scanner::new(options.clone());
+    scanner.record_listings();
+    let total = pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+    Ok((total, scanner.take_listings()))
+}
+
+fn describe(listing: Option<&Listing>) -> String {
+    match listing {
+        Some(listing) => format!(
+            "{} files, {} subdirectories, {}",
+            listing.files,
+            listing.subdirs,
+            format_size(listing.blocks)
+        ),
+        None => "not seen".to_string(),
+    }
+}
@@ -0,0 +1,350 @@
+// `dumac clean`: of the directories devjunk finds, the ones that can go
+// without losing anything still in use, with what each would free. Nothing
+// is removed until that's confirmed, on the terminal or with --yes.
+use super::devjunk::Junk;
+use super::plist;
+use dumac::internal::strerror;
+use dumac::pool::ThreadPool;
+use dumac::snapshot::Snapshot;
+use dumac::{format_size, DumacError, ScanOptions};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const TTY: &str = "/dev/tty";
+
+// DerivedData untouched for this long belongs to a project not being worked on
+const STALE_DAYS: u64 = 30;
+
+// A directory that can be removed, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub path: PathBuf,
+    pub blocks: i64,
+    pub reason: String,
+}
+
+// What can be removed under `root_dir`, largest first
+pub fn suggest(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+) -> Result<Vec<Suggestion>, DumacError> {
+    let snapshot = Snapshot::scan(options, pool, root_dir)?;
+    let dirs: BTreeMap<PathBuf, i64> = snapshot
+        .dirs
+        .into_iter()
+        .map(|(relative, blocks)| (snapshot.root.join(relative), blocks))
+        .collect();
+
+    let mut matched = HashSet::new();
+    let mut suggestions = Vec::new();
+    // Sorted, so a directory comes before anything inside it
+    for (path, &blocks) in &dirs {
+        if path.ancestors().skip(1).any(|dir| matched.contains(dir)) {
+            continue;
+        }
+        let Some(junk) = Junk::detect(path) else {
+            continue;
+        };
+        matched.insert(path.clone());
+        let inside = || children(&dirs, path);
+        let whole = |reason: Option<String>| {
+            reason.map(|reason| Suggestion {
+                path: path.clone(),
+                blocks,
+                reason,
+            })
+        };
+        match junk {
+            Junk::DerivedData => suggestions.extend(stale_derived_data(inside())),
+            Junk::DeviceSupport => suggestions.extend(old_device_support(inside())),
+            Junk::SimulatorRuntimes => suggestions.extend(old_runtimes(inside())),
+            Junk::NodeModules => suggestions.extend(whole(orphaned(path, &["package.json"]))),
+            Junk::RustTarget => suggestions.extend(whole(orphaned(path, &["Cargo.toml"]))),
+            Junk::PythonVenv => suggestions.extend(whole(broken_venv(path))),
+            Junk::Gradle | Junk::Docker => {}
+        }
+    }
+    suggestions.sort_by(|a, b| b.blocks.cmp(&a.blocks).then_with(|| a.path.cmp(&b.path)));
+    Ok(suggestions)
+}
+
+// The directories directly inside `parent`, with their totals
+fn children<'a>(dirs: &'a BTreeMap<PathBuf, i64>, parent: &'a Path) -> Vec<(&'a Path, i64)> {
+    dirs.range(parent.to_path_buf()..)
+        .skip(1)
+        .take_while(|(path, _)| path.starts_with(parent))
+        .filter(|(path, _)| path.parent() == Some(parent))
+        .map(|(path, &blocks)| (path.as_path(), blocks))
+        .collect()
+}
+
+// Each project's build products, once the project is gone or has been left
+// alone for a while. Xcode keeps the project's path in info.plist and
+// touches it when the project is opened.
+fn stale_derived_data(projects: Vec<(&Path, i64)>) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for (path, blocks) in projects {
+        let info = path.join("info.plist");
+        let workspace = plist::string(&info, "WorkspacePath");
+        let reason = match workspace {
+            Some(workspace) if !Path::new(&workspace).exists() => {
+                format!("build products of {}, which is gone", workspace)
+            }
+            _ => match days_untouched(&info).or_else(|| days_untouched(path)) {
+                Some(days) if days >= STALE_DAYS => {
+                    format!("build products not used for {} days", days)
+                }
+                _ => continue,
+            },
+        };
+        suggestions.push(Suggestion {
+            path: path.to_path_buf(),
+            blocks,
+            reason,
+        });
+    }
+    suggestions
+}
+
+// Every OS version's symbols but the one used last. Xcode copies them again
+// when a device with that version is connected.
+fn old_device_support(versions: Vec<(&Path, i64)>) -> Vec<Suggestion> {
+    let newest = versions
+        .iter()
+        .max_by_key(|(path, _)| days_untouched(path).map(std::cmp::Reverse))
+        .map(|(path, _)| *path);
+    versions
+        .into_iter()
+        .filter(|(path, _)| Some(*path) != newest)
+        .map(|(path, blocks)| Suggestion {
+            path: path.to_path_buf(),
+            blocks,
+            reason: "device support files for an older OS version".to_string(),
+        })
+        .collect()
+}
+
+// Every simulator runtime but the newest for each platform, such as
+// "iOS 16.4.simruntime" when "iOS 17.2.simruntime" is there too
+fn old_runtimes(runtimes: Vec<(&Path, i64)>) -> Vec<Suggestion> {
+    let parsed: Vec<(&Path, i64, &str, Vec<u32>)> = runtimes
+        .into_iter()
+        .filter_map(|(path, blocks)| {
+            let name = path.file_name()?.to_str()?.strip_suffix(".simruntime")?;
+            let (platform, version) = name.rsplit_once(' ')?;
+            let version = version
+                .split('.')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            Some((path, blocks, platform, version))
+        })
+        .collect();
+    parsed
+        .iter()
+        .filter_map(|(path, blocks, platform, version)| {
+            let newer = parsed
+                .iter()
+                .filter(|(_, _, other, _)| other == platform)
+                .map(|(_, _, _, other)| other)
+                .max()
+                .filter(|newest| *newest != version)?;
+            Some(Suggestion {
+                path: path.to_path_buf(),
+                blocks: *blocks,
+                reason: format!(
+                    "{} runtime older than {}",
+                    platform,
+                    newer
+                        .iter()
+                        .map(u32::to_string)
+                        .collect::<Vec<_>>()
+                        .join(".")
+                ),
+            })
+        })
+        .collect()
+}
+
+// Build output or packages left behind by a project that's been removed.
+// Only called for directories detect found its tool's records in, so one
+// that merely shares the name is never offered for removal.
+fn orphaned(path: &Path, manifests: &[&str]) -> Option<String> {
+    let parent = path.parent()?;
+    if manifests
+        .iter()
+        .any(|manifest| parent.join(manifest).exists())
+    {
+        return None;
+    }
+    Some(format!("no {} next to it", manifests.join(" or ")))
+}
+
+// A virtualenv whose interpreter was uninstalled, which can't run anything.
+// pyvenv.cfg names the directory it was created from as `home`.
+fn broken_venv(path: &Path) -> Option<String> {
+    let config = fs::read_to_string(path.join("pyvenv.cfg")).ok()?;
+    let home = config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "home").then(|| value.trim().to_string())
+    })?;
+    match Path::new(&home).exists() {
+        true => None,
+        false => Some(format!("its Python in {} is gone", home)),
+    }
+}
+
+fn days_untouched(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let since = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    Some(since.as_secs() / (24 * 60 * 60))
+}
+
+// List what can be removed under `root_dir`, then remove it if `yes` is set
+// or it's confirmed. Returns whether everything chosen was removed.
+pub fn run(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    yes: bool,
+    dry_run: bool,
+) -> Result<bool, DumacError> {
+    let suggestions = suggest(options, pool, root_dir)?;
+    for suggestion in &suggestions {
+        println!(
+            "{}\t{}\t{}",
+            format_size(suggestion.blocks),
+            suggestion.path.display(),
+            suggestion.reason
+        );
+    }
+    let total: i64 = suggestions.iter().map(|suggestion| suggestion.blocks).sum();
+    println!(
+        "{}\treclaimable in {} directories",
+        format_size(total),
+        suggestions.len()
+    );
+    if suggestions.is_empty() || dry_run {
+        return Ok(true);
+    }
+    if !yes && !confirm(&format!("Remove these, freeing {}?", format_size(total))) {
+        return Ok(true);
+    }
+
+    let mut freed = 0;
+    let mut removed_all = true;
+    for suggestion in &suggestions {
+        match fs::remove_dir_all(&suggestion.path) {
+            Ok(()) => freed += suggestion.blocks,
+            Err(e) => {
+                eprintln!(
+                    "dumac: cannot remove {}: {}",
+                    suggestion.path.display(),
+                    strerror(e.raw_os_error().unwrap_or(libc::EIO))
+                );
+                removed_all = false;
+            }
+        }
+    }
+    println!("{}\tfreed", format_size(freed));
+    Ok(removed_all)
+}
+
+// Asked on the terminal, so the listing can be piped elsewhere. No answer,
+// or no terminal, is a no.
+fn confirm(question: &str) -> bool {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open(TTY) else {
+        eprintln!("dumac: no terminal to confirm on, nothing removed; use --yes");
+        return false;
+    };
+    if write!(tty, "{} [y/N] ", question).is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    if BufReader::new(tty).read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes" | "YES" | "Yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_clean_suggestions() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("kept/node_modules/x")).unwrap();
+        fs::write(root.join("kept/node_modules/.package-lock.json"), "{}").unwrap();
+        fs::write(root.join("kept/package.json"), "{}").unwrap();
+        fs::create_dir_all(root.join("gone/node_modules/y/node_modules")).unwrap();
+        fs::write(root.join("gone/node_modules/.package-lock.json"), "{}").unwrap();
+        fs::write(root.join("gone/node_modules/y/index.js"), vec![b'x'; 8192]).unwrap();
+        let pool = dumac::pool::build(2, || {}).unwrap();
+
+        // Only the outer node_modules of a removed project is suggested
+        let suggestions = suggest(&ScanOptions::new(), &pool, root.to_str().unwrap()).unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].path, root.join("gone/node_modules"));
+        assert!(suggestions[0].blocks > 0);
+    }
+
+    #[test]
+    fn test_only_tools_output_is_orphaned() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for dir in [
+            "built/target/debug/.fingerprint",
+            "kept/target/debug/.fingerprint",
+            "java/target/classes",
+            "npm/node_modules/left-pad",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("kept/Cargo.toml"), "").unwrap();
+        // A target Cargo didn't make, and packages no manager recorded
+        fs::write(root.join("java/target/CACHEDIR.TAG"), "").unwrap();
+        fs::write(root.join("java/target/classes/Main.class"), vec![0; 8192]).unwrap();
+        fs::write(
+            root.join("npm/node_modules/left-pad/index.js"),
+            vec![0; 8192],
+        )
+        .unwrap();
+        let pool = dumac::pool::build(2, || {}).unwrap();
+
+        let suggestions = suggest(&ScanOptions::new(), &pool, root.to_str().unwrap()).unwrap();
+        let paths: Vec<&Path> = suggestions.iter().map(|s| s.path.as_path()).collect();
+        assert_eq!(paths, [root.join("built/target")]);
+        assert_eq!(suggestions[0].reason, "no Cargo.toml next to it");
+    }
+
+    #[test]
+    fn test_broken_venv() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for (venv, home) in [
+            ("gone/.venv", "/nonexistent/python3.9/bin"),
+            ("kept/.venv", "/"),
+        ] {
+            fs::create_dir_all(root.join(venv)).unwrap();
+            fs::write(
+                root.join(venv).join("pyvenv.cfg"),
+                format!("home = {}\nversion = 3.9\n", home),
+            )
+            .unwrap();
+        }
+        let pool = dumac::pool::build(2, || {}).unwrap();
+
+        let suggestions = suggest(&ScanOptions::new(), &pool, root.to_str().unwrap()).unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].path, root.join("gone/.venv"));
+        assert!(suggestions[0].reason.contains("/nonexistent/python3.9/bin"));
+    }
+}
@@ -0,0 +1,870 @@
+use crate::history;
+use crate::remote::Target;
+use crate::schedule::{self, Interval};
+use dumac::{user_id, Regex};
+use std::time::Duration;
+
+// What to do with the directory
+#[derive(Debug, Default)]
+pub enum Command {
+    // Print the directory's disk usage
+    #[default]
+    Scan,
+    // Scan twice and compare, see check.rs
+    Check,
+    // Compare with a reference walker, see check.rs
+    VerifyDu,
+    // Keep the totals up to date as files change, see watch.rs
+    Watch,
+    // Browse the scanned tree, see tui.rs
+    Interactive,
+    // Time repeated scans, see bench.rs
+    Bench {
+        iterations: usize,
+        cold: bool,
+    },
+    // Keep every directory's total up to date and answer queries for them,
+    // see daemon.rs
+    Daemon,
+    // Ask a running daemon
+    Query,
+    // Save every directory's total, see snapshot.rs
+    Snapshot,
+    // Compare a snapshot with another or with a new scan
+    Diff,
+    // Compare a directory across APFS snapshots, see apfs.rs
+    ApfsDiff,
+    // Find files with the same contents, see dupes.rs
+    Dupes,
+    // Find build and package caches, see devjunk.rs
+    DevJunk,
+    // Remove what devjunk finds that's safe to, see clean.rs
+    Clean {
+        // Without asking first
+        yes: bool,
+        // List only
+        dry_run: bool,
+    },
+    // Total each app's data in a Library folder, see apps.rs
+    Apps,
+    // Show the Trash and purgeable space on each volume, see trash.rs
+    Trash,
+    // Answer HTTP requests for scan results, see serve.rs
+    Serve,
+    // Run scans from launchd, see schedule.rs
+    Schedule(ScheduleAction),
+    // Record a scan and print how the directory grew, see history.rs
+    History {
+        depth: usize,
+        // Print only, without scanning
+        show: bool,
+    },
+}
+
+impl Command {
+    // The subcommand's name, for those given as one
+    fn subcommand(&self) -> Option<&'static str> {
+        match self {
+            Command::Bench { .. } => Some("bench"),
+            Command::Daemon => Some("daemon"),
+            Command::Query => Some("query"),
+            Command::Snapshot => Some("snapshot"),
+            Command::Diff => Some("diff"),
+            Command::ApfsDiff => Some("apfs-diff"),
+            Command::Dupes => Some("dupes"),
+            Command::DevJunk => Some("devjunk"),
+            Command::Clean { .. } => Some("clean"),
+            Command::Apps => Some("apps"),
+            Command::Trash => Some("trash"),
+            Command::Serve => Some("serve"),
+            Command::Schedule(_) => Some("schedule"),
+            Command::History { .. } => Some("history"),
+            _ => None,
+        }
+    }
+}
+
+// What `dumac schedule` does with the directory
+#[derive(Debug)]
+pub enum ScheduleAction {
+    Install {
+        interval: Interval,
+        prometheus: Option<String>,
+        // Passed on to each scan
+        flags: Vec<String>,
+    },
+    Remove,
+    // List every scheduled scan, for no directory
+    Status,
+}
+
+// Default number of timed runs for `dumac bench`
+const BENCH_ITERATIONS: usize = 10;
+
+// Command line arguments
+#[derive(Debug, Default)]
+pub struct Args {
+    pub command: Command,
+    // At least one, only one for bench, daemon, snapshot, dupes, devjunk,
+    // clean, apps, serve and history, and for diff a snapshot and optionally
+    // another or a directory. For apfs-diff, one or two APFS snapshot names
+    // and then the directory.
+    pub roots: Vec<String>,
+    // The daemon's socket, when not the default
+    pub socket: Option<String>,
+    // Where snapshot writes to
+    pub output: Option<String>,
+    // The address serve listens on
+    pub listen: Option<String>,
+    pub threads: Option<usize>,
+    pub stats: bool,
+    pub volume_threads: Vec<(String, usize)>,
+    pub background: bool,
+    pub profile: bool,
+    pub inode_memory: Option<usize>,
+    pub cache: bool,
+    pub clones: bool,
+    pub compression: bool,
+    pub cloud: bool,
+    pub by_volume: bool,
+    pub by_extension: bool,
+    pub by_owner: bool,
+    pub by_age: bool,
+    pub report_broken_symlinks: bool,
+    pub report_hardlinks: bool,
+    pub report_clones: bool,
+    pub report_empty: bool,
+    // Fewer files than this counts as empty
+    pub empty_below: Option<usize>,
+    // Files to list after the totals, none when 0
+    pub largest_files: usize,
+    // Print how many files and directories each root holds beside its size
+    pub count: bool,
+    // Bytes a root may hold before the run fails
+    pub fail_if_over: Option<usize>,
+    // Also post going over it to Notification Center
+    pub notify: bool,
+    // Where to write the totals for Prometheus
+    pub prometheus: Option<String>,
+    // Where to send them as StatsD gauges
+    pub statsd: Option<String>,
+    // Where to POST a summary of the scan
+    pub webhook: Option<String>,
+    pub xattrs: bool,
+    pub exclude_backups: bool,
+    pub exclude_system: bool,
+    pub exclude: Vec<String>,
+    // Matched against paths relative to the directory
+    pub exclude_regex: Vec<Regex>,
+    pub respect_gitignore: bool,
+    // Only files matching one of these count
+    pub include_only: Vec<String>,
+    // Or those with one of these extensions, and not with any of those
+    pub ext: Vec<String>,
+    pub no_ext: Vec<String>,
+    // And only those within these sizes, in bytes
+    pub min_file_size: Option<usize>,
+    pub max_file_size: Option<usize>,
+    // And only those dated within, or before, this long ago
+    pub newer_than: Option<Duration>,
+    pub older_than: Option<Duration>,
+    // And only those owned by this user, as given and the uid it names
+    pub user: Option<(String, u32)>,
+    // Date them by when they were created
+    pub birthtime: bool,
+    pub respect_duignore: bool,
+    // Skip dotfiles and dot-directories
+    pub skip_hidden: bool,
+    // Leave out what's mounted inside the directories, like du -x
+    pub one_file_system: bool,
+    // Scan every mounted volume instead of directories given
+    pub all_volumes: bool,
+    // Directories on other hosts, scanned over ssh alongside the roots
+    pub remotes: Vec<Target>,
+    // Print totals as JSON lines
+    pub ndjson: bool,
+    // Levels listed inside a package by -i and serve, all when not given
+    pub package_depth: Option<usize>,
+    // 1 for -v, 2 or more for -vv
+    pub verbose: u8,
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "usage: {0} [--threads N] [--volume-threads FSTYPE=N] [--inode-memory SIZE] [--cache] [--clones] [--compression] [--cloud] [--xattrs] [--by-volume] [--by-extension] [--by-owner] [--by-age] [--report-broken-symlinks] [--report-hardlinks] [--report-clones] [--report-empty [--empty-below N]] [--largest-files N] [--count] [--fail-if-over SIZE [--notify]] [--prometheus FILE] [--statsd HOST:PORT] [--webhook URL] [--ndjson] [--exclude NAME] [--exclude-regex PATTERN] [--include-only PATTERN] [--ext EXT,...] [--no-ext EXT,...] [--min-file-size SIZE] [--max-file-size SIZE] [--newer-than AGE] [--older-than AGE] [--birthtime] [--user NAME|UID] [--respect-gitignore] [--respect-duignore] [--skip-hidden] [--exclude-backups] [--exclude-system] [-x] [--all-volumes] [--remote [USER@]HOST:PATH] [--background] [--stats] [--profile] [-v | -vv] [--check] [--verify-du] [--watch] [-i [--package-depth N]] directory...\n       {0} bench [--iterations N] [--cold] [options] directory\n       {0} daemon [--socket PATH] [options] directory\n       {0} query [--socket PATH] directory...\n       {0} snapshot -o FILE [options] directory\n       {0} diff [options] snapshot [snapshot | directory]\n       {0} apfs-diff [options] old-snapshot [new-snapshot] directory\n       {0} dupes [options] directory\n       {0} devjunk [options] directory\n       {0} clean [--yes | --dry-run] [options] directory\n       {0} apps [options] library\n       {0} trash [options] directory...\n       {0} serve --listen ADDRESS:PORT [--package-depth N] [options] directory\n       {0} history [--depth N] [--show] [options] directory\n       {0} schedule install [--hourly | --daily | --weekly] [--prometheus FILE] [options] directory\n       {0} schedule remove directory\n       {0} schedule status",
+        program
+    )
+}
+
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut positional_only = false;
+    if args.get(1).is_some_and(|arg| arg == "schedule") {
+        return parse_schedule(args);
+    }
+    let mut iter = args.iter().skip(1).peekable();
+
+    let command = match iter.peek().map(|arg| arg.as_str()) {
+        Some("bench") => Some(Command::Bench {
+            iterations: BENCH_ITERATIONS,
+            cold: false,
+        }),
+        Some("daemon") => Some(Command::Daemon),
+        Some("query") => Some(Command::Query),
+        Some("snapshot") => Some(Command::Snapshot),
+        Some("diff") => Some(Command::Diff),
+        Some("apfs-diff") => Some(Command::ApfsDiff),
+        Some("dupes") => Some(Command::Dupes),
+        Some("devjunk") => Some(Command::DevJunk),
+        Some("apps") => Some(Command::Apps),
+        Some("trash") => Some(Command::Trash),
+        Some("serve") => Some(Command::Serve),
+        Some("clean") => Some(Command::Clean {
+            yes: false,
+            dry_run: false,
+        }),
+        Some("history") => Some(Command::History {
+            depth: history::DEFAULT_DEPTH,
+            show: false,
+        }),
+        _ => None,
+    };
+    if let Some(command) = command {
+        iter.next();
+        parsed.command = command;
+    }
+
+    while let Some(arg) = iter.next() {
+        if positional_only || !arg.starts_with('-') || arg == "-" {
+            parsed.roots.push(arg.clone());
+            continue;
+        }
+
+        // Support both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+
+        match flag {
+            // How to scan is up to the daemon
+            _ if matches!(parsed.command, Command::Query) && !matches!(flag, "--socket" | "--") => {
+                return Err(format!("option '{}' is not valid with query", flag));
+            }
+            "--threads" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.threads = Some(parse_threads(&value)?);
+            }
+            "--volume-threads" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                let (fstype, threads) = value.split_once('=').ok_or_else(|| {
+                    format!("invalid volume threads '{}', expected FSTYPE=N", value)
+                })?;
+                parsed
+                    .volume_threads
+                    .push((fstype.to_string(), parse_threads(threads)?));
+            }
+            "--inode-memory" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.inode_memory = Some(parse_size(&value)?);
+            }
+            "--iterations" | "--cold" if !matches!(parsed.command, Command::Bench { .. }) => {
+                return Err(format!("option '{}' is only valid with bench", flag));
+            }
+            "--iterations" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                if let Command::Bench { iterations, .. } = &mut parsed.command {
+                    *iterations = parse_count(&value)?;
+                }
+            }
+            "--cold" => {
+                if let Command::Bench { cold, .. } = &mut parsed.command {
+                    *cold = true;
+                }
+            }
+            "--yes" | "-y" | "--dry-run" if !matches!(parsed.command, Command::Clean { .. }) => {
+                return Err(format!("option '{}' is only valid with clean", flag));
+            }
+            "--yes" | "-y" => {
+                if let Command::Clean { yes, .. } = &mut parsed.command {
+                    *yes = true;
+                }
+            }
+            "--dry-run" => {
+                if let Command::Clean { dry_run, .. } = &mut parsed.command {
+                    *dry_run = true;
+                }
+            }
+            "--depth" | "--show" if !matches!(parsed.command, Command::History { .. }) => {
+                return Err(format!("option '{}' is only valid with history", flag));
+            }
+            "--depth" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                if let Command::History { depth, .. } = &mut parsed.command {
+                    *depth = value
+                        .parse()
+                        .map_err(|_| format!("invalid depth '{}'", value))?;
+                }
+            }
+            "--show" => {
+                if let Command::History { show, .. } = &mut parsed.command {
+                    *show = true;
+                }
+            }
+            "--check" | "--verify-du" | "--watch" | "-i" | "--interactive"
+                if parsed.command.subcommand().is_some() =>
+            {
+                return Err(format!(
+                    "option '{}' is not valid with {}",
+                    flag,
+                    parsed.command.subcommand().unwrap_or_default()
+                ));
+            }
+            "--socket" if !matches!(parsed.command, Command::Daemon | Command::Query) => {
+                return Err(format!(
+                    "option '{}' is only valid with daemon and query",
+                    flag
+                ));
+            }
+            "--socket" => parsed.socket = Some(flag_value(flag, inline_value, &mut iter)?),
+            "-o" | "--output" if !matches!(parsed.command, Command::Snapshot) => {
+                return Err(format!("option '{}' is only valid with snapshot", flag));
+            }
+            "-o" | "--output" => parsed.output = Some(flag_value(flag, inline_value, &mut iter)?),
+            "--listen" if !matches!(parsed.command, Command::Serve) => {
+                return Err(format!("option '{}' is only valid with serve", flag));
+            }
+            "--listen" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                // Only the user should see what the replies name
+                let loopback = value
+                    .parse::<std::net::SocketAddr>()
+                    .is_ok_and(|address| address.ip().is_loopback());
+                if !loopback {
+                    return Err(format!(
+                        "invalid listen address '{}', expected a loopback ADDRESS:PORT such as 127.0.0.1:8080",
+                        value
+                    ));
+                }
+                parsed.listen = Some(value);
+            }
+            "--check" => parsed.command = Command::Check,
+            "--verify-du" => parsed.command = Command::VerifyDu,
+            "--watch" => parsed.command = Command::Watch,
+            "-i" | "--interactive" => parsed.command = Command::Interactive,
+            "--cache" => parsed.cache = true,
+            "--clones" => parsed.clones = true,
+            "--compression" => parsed.compression = true,
+            "--cloud" => parsed.cloud = true,
+            "--xattrs" => parsed.xattrs = true,
+            "--by-volume" => parsed.by_volume = true,
+            "--by-extension" => parsed.by_extension = true,
+            "--by-owner" => parsed.by_owner = true,
+            "--by-age" => parsed.by_age = true,
+            "--report-broken-symlinks" => parsed.report_broken_symlinks = true,
+            "--report-hardlinks" => parsed.report_hardlinks = true,
+            "--report-clones" => parsed.report_clones = true,
+            "--report-empty" => parsed.report_empty = true,
+            "--empty-below" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.empty_below = Some(parse_count(&value)?);
+            }
+            "--count" => parsed.count = true,
+            "--package-depth" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                // 0 lists a package as one entry
+                parsed.package_depth = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid depth '{}'", value))?,
+                );
+            }
+            "--largest-files" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.largest_files = parse_count(&value)?;
+            }
+            "--fail-if-over" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.fail_if_over = Some(parse_size(&value)?);
+            }
+            "--notify" => parsed.notify = true,
+            "--prometheus" => parsed.prometheus = Some(flag_value(flag, inline_value, &mut iter)?),
+            "--statsd" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                let port = value
+                    .rsplit_once(':')
+                    .map(|(host, port)| (host, port.parse::<u16>()));
+                if !matches!(port, Some((host, Ok(_))) if !host.is_empty()) {
+                    return Err(format!(
+                        "invalid StatsD address '{}', expected HOST:PORT such as localhost:8125",
+                        value
+                    ));
+                }
+                parsed.statsd = Some(value);
+            }
+            "--exclude" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.exclude.push(value);
+            }
+            "--exclude-regex" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                let regex =
+                    Regex::new(&value).map_err(|e| format!("invalid regex '{}': {}", value, e))?;
+                parsed.exclude_regex.push(regex);
+            }
+            "--include-only" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.include_only.push(value);
+            }
+            "--ext" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.ext.extend(parse_extensions(flag, &value)?);
+            }
+            "--no-ext" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.no_ext.extend(parse_extensions(flag, &value)?);
+            }
+            "--min-file-size" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.min_file_size = Some(parse_size(&value)?);
+            }
+            "--max-file-size" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.max_file_size = Some(parse_size(&value)?);
+            }
+            "--newer-than" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.newer_than = Some(parse_age(&value)?);
+            }
+            "--older-than" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                parsed.older_than = Some(parse_age(&value)?);
+            }
+            "--birthtime" => parsed.birthtime = true,
+            "--user" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                let uid = parse_user(&value)?;
+                parsed.user = Some((value, uid));
+            }
+            "--respect-gitignore" => parsed.respect_gitignore = true,
+            "--respect-duignore" => parsed.respect_duignore = true,
+            "--skip-hidden" => parsed.skip_hidden = true,
+            "--exclude-backups" => parsed.exclude_backups = true,
+            "--exclude-system" => parsed.exclude_system = true,
+            "-x" | "--one-file-system" => parsed.one_file_system = true,
+            "--all-volumes" => parsed.all_volumes = true,
+            "--remote" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                let target = Target::parse(&value).ok_or_else(|| {
+                    format!("invalid remote '{}', expected [USER@]HOST:PATH", value)
+                })?;
+                parsed.remotes.push(target);
+            }
+            "--ndjson" => parsed.ndjson = true,
+            "--webhook" => {
+                let value = flag_value(flag, inline_value, &mut iter)?;
+                if !value.starts_with("https://") && !value.starts_with("http://") {
+                    return Err(format!(
+                        "invalid webhook URL '{}', expected http:// or https://",
+                        value
+                    ));
+                }
+                parsed.webhook = Some(value);
+            }
+            "--background" | "--nice" => parsed.background = true,
+            "--stats" => parsed.stats = true,
+            "--profile" => parsed.profile = true,
+            "-v" | "--verbose" => parsed.verbose += 1,
+            "-vv" => parsed.verbose += 2,
+            "--" => positional_only = true,
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    if parsed.roots.is_empty() && matches!(parsed.command, Command::Diff) {
+        return Err("missing snapshot argument".to_string());
+    }
+    if parsed.all_volumes && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--all-volumes' is only valid when printing totals".to_string());
+    }
+    if parsed.all_volumes && !(parsed.roots.is_empty() && parsed.remotes.is_empty()) {
+        return Err("too many arguments, --all-volumes takes no directory".to_string());
+    }
+    if parsed.roots.is_empty() && !parsed.all_volumes && parsed.remotes.is_empty() {
+        return Err("missing directory argument".to_string());
+    }
+    if matches!(parsed.command, Command::Bench { .. }) && parsed.roots.len() > 1 {
+        return Err("too many arguments, bench takes one directory".to_string());
+    }
+    match parsed.command {
+        Command::Daemon
+        | Command::Snapshot
+        | Command::Dupes
+        | Command::DevJunk
+        | Command::Clean { .. }
+        | Command::Apps
+        | Command::Serve
+        | Command::History { .. }
+            if parsed.roots.len() > 1 =>
+        {
+            return Err(format!(
+                "too many arguments, {} takes one directory",
+                parsed.command.subcommand().unwrap_or_default()
+            ));
+        }
+        Command::Snapshot if parsed.output.is_none() => {
+            return Err("snapshot requires -o FILE".to_string());
+        }
+        Command::Serve if parsed.listen.is_none() => {
+            return Err("serve requires --listen ADDRESS:PORT".to_string());
+        }
+        Command::Diff if parsed.roots.len() > 2 => {
+            return Err("too many arguments, diff takes a snapshot and one more".to_string());
+        }
+        Command::ApfsDiff if parsed.roots.len() < 2 => {
+            return Err("missing directory argument".to_string());
+        }
+        Command::ApfsDiff if parsed.roots.len() > 3 => {
+            return Err(
+                "too many arguments, apfs-diff takes two snapshots and a directory".to_string(),
+            );
+        }
+        _ => {}
+    }
+    // Only a plain scan prints anything after the totals, and it and --watch
+    // check them
+    if parsed.largest_files > 0 && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--largest-files' is only valid when printing totals".to_string());
+    }
+    if parsed.count && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--count' is only valid when printing totals".to_string());
+    }
+    if parsed.fail_if_over.is_some() && !matches!(parsed.command, Command::Scan | Command::Watch) {
+        return Err("option '--fail-if-over' is only valid when printing totals".to_string());
+    }
+    if parsed.prometheus.is_some() && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--prometheus' is only valid when printing totals".to_string());
+    }
+    if parsed.statsd.is_some() && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--statsd' is only valid when printing totals".to_string());
+    }
+    if parsed.webhook.is_some() && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--webhook' is only valid when printing totals".to_string());
+    }
+    if !parsed.remotes.is_empty() && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--remote' is only valid when printing totals".to_string());
+    }
+    if parsed.ndjson && !matches!(parsed.command, Command::Scan) {
+        return Err("option '--ndjson' is only valid when printing totals".to_string());
+    }
+    if parsed.package_depth.is_some()
+        && !matches!(parsed.command, Command::Interactive | Command::Serve)
+    {
+        return Err("option '--package-depth' is only valid with -i and serve".to_string());
+    }
+    if parsed.empty_below.is_some() && !parsed.report_empty {
+        return Err("option '--empty-below' requires --report-empty".to_string());
+    }
+    if let (Some(min), Some(max)) = (parsed.min_file_size, parsed.max_file_size) {
+        if min > max {
+            return Err("option '--min-file-size' is over '--max-file-size'".to_string());
+        }
+    }
+    if parsed.birthtime
+        && !(parsed.by_age || parsed.newer_than.is_some() || parsed.older_than.is_some())
+    {
+        return Err(
+            "option '--birthtime' requires --by-age, --newer-than or --older-than".to_string(),
+        );
+    }
+    if parsed.notify && parsed.fail_if_over.is_none() {
+        return Err("option '--notify' requires --fail-if-over".to_string());
+    }
+    if matches!(parsed.command, Command::Watch) && parsed.roots.len() > 1 {
+        return Err("too many arguments, --watch takes one directory".to_string());
+    }
+    if matches!(parsed.command, Command::Interactive) && parsed.roots.len() > 1 {
+        return Err("too many arguments, -i takes one directory".to_string());
+    }
+    Ok(parsed)
+}
+
+// `schedule install [--hourly | --daily | --weekly] [--prometheus FILE]
+// [options] directory`, `schedule remove directory` or `schedule status`.
+// The options are checked here as the scheduled command line, so a mistake
+// shows now rather than in the agent's log.
+fn parse_schedule(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let rest = args.get(3..).unwrap_or_default();
+    let action = match args.get(2).map(String::as_str) {
+        Some("install") => {
+            let mut interval = Interval::default();
+            let mut prometheus = None;
+            let mut flags = Vec::new();
+            let mut iter = rest.iter();
+            while let Some(arg) = iter.next() {
+                let (flag, inline_value) = match arg.split_once('=') {
+                    Some((flag, value)) => (flag, Some(value.to_string())),
+                    None => (arg.as_str(), None),
+                };
+                if let Some(every) = Interval::from_flag(flag) {
+                    interval = every;
+                } else if flag == "--prometheus" {
+                    prometheus = Some(flag_value(flag, inline_value, &mut iter)?);
+                } else {
+                    flags.push(arg.clone());
+                }
+            }
+            let directory = match flags.pop() {
+                Some(directory) if !directory.starts_with('-') => directory,
+                _ => return Err("missing directory argument".to_string()),
+            };
+            let mut scheduled = vec![args[0].clone()];
+            scheduled.extend(schedule::job_arguments(
+                &directory,
+                prometheus.as_deref(),
+                &flags,
+            ));
+            let checked = parse_args(&scheduled)?;
+            if checked.roots.len() > 1 {
+                return Err("too many arguments, schedule takes one directory".to_string());
+            }
+            if !matches!(
+                checked.command,
+                Command::Scan | Command::History { show: false, .. }
+            ) {
+                return Err("only scans that record or print totals can be scheduled".to_string());
+            }
+            parsed.roots.push(directory);
+            ScheduleAction::Install {
+                interval,
+                prometheus,
+                flags,
+            }
+        }
+        Some("remove") => {
+            match rest {
+                [directory] => parsed.roots.push(directory.clone()),
+                [] => return Err("missing directory argument".to_string()),
+                _ => return Err("too many arguments, schedule takes one directory".to_string()),
+            }
+            ScheduleAction::Remove
+        }
+        Some("status") if rest.is_empty() => ScheduleAction::Status,
+        Some("status") => return Err("too many arguments, schedule status takes none".to_string()),
+        _ => return Err("schedule takes install, remove or status".to_string()),
+    };
+    parsed.command = Command::Schedule(action);
+    Ok(parsed)
+}
+
+// The options a remote scan gets: what decides what's counted and
+// reported, but not this machine's threads or memory, nor what's done with
+// the totals once they're here
+pub fn remote_flags(args: &Args) -> Vec<String> {
+    let mut flags = Vec::new();
+    let switches = [
+        (args.clones, "--clones"),
+        (args.compression, "--compression"),
+        (args.cloud, "--cloud"),
+        (args.xattrs, "--xattrs"),
+        (args.by_volume, "--by-volume"),
+        (args.by_extension, "--by-extension"),
+        (args.by_owner, "--by-owner"),
+        (args.by_age, "--by-age"),
+        (args.report_broken_symlinks, "--report-broken-symlinks"),
+        (args.report_hardlinks, "--report-hardlinks"),
+        (args.report_clones, "--report-clones"),
+        (args.report_empty, "--report-empty"),
+        (args.birthtime, "--birthtime"),
+        (args.respect_gitignore, "--respect-gitignore"),
+        (args.respect_duignore, "--respect-duignore"),
+        (args.skip_hidden, "--skip-hidden"),
+        (args.exclude_backups, "--exclude-backups"),
+        (args.exclude_system, "--exclude-system"),
+        (args.one_file_system, "-x"),
+        (args.background, "--background"),
+        (args.cache, "--cache"),
+        (args.count, "--count"),
+    ];
+    for (on, flag) in switches {
+        if on {
+            flags.push(flag.to_string());
+        }
+    }
+    for name in &args.exclude {
+        flags.extend(["--exclude".to_string(), name.clone()]);
+    }
+    for pattern in &args.include_only {
+        flags.extend(["--include-only".to_string(), pattern.clone()]);
+    }
+    if !args.ext.is_empty() {
+        flags.extend(["--ext".to_string(), args.ext.join(",")]);
+    }
+    if !args.no_ext.is_empty() {
+        flags.extend(["--no-ext".to_string(), args.no_ext.join(",")]);
+    }
+    for regex in &args.exclude_regex {
+        flags.extend(["--exclude-regex".to_string(), regex.as_str().to_string()]);
+    }
+    if let Some(bytes) = args.min_file_size {
+        flags.extend(["--min-file-size".to_string(), bytes.to_string()]);
+    }
+    if let Some(bytes) = args.max_file_size {
+        flags.extend(["--max-file-size".to_string(), bytes.to_string()]);
+    }
+    // In seconds, which parse_age takes as they are
+    if let Some(age) = args.newer_than {
+        flags.extend(["--newer-than".to_string(), format!("{}s", age.as_secs())]);
+    }
+    if let Some(age) = args.older_than {
+        flags.extend(["--older-than".to_string(), format!("{}s", age.as_secs())]);
+    }
+    // As given, so a name is looked up on the remote host
+    if let Some((user, _)) = &args.user {
+        flags.extend(["--user".to_string(), user.clone()]);
+    }
+    if let Some(below) = args.empty_below {
+        flags.extend(["--empty-below".to_string(), below.to_string()]);
+    }
+    if args.largest_files > 0 {
+        flags.extend([
+            "--largest-files".to_string(),
+            args.largest_files.to_string(),
+        ]);
+    }
+    flags
+}
+
+fn flag_value<'a>(
+    flag: &str,
+    inline_value: Option<String>,
+    iter: &mut impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    inline_value
+        .or_else(|| iter.next().cloned())
+        .ok_or_else(|| format!("option '{}' requires a value", flag))
+}
+
+fn parse_threads(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(threads) if threads > 0 => Ok(threads),
+        _ => Err(format!("invalid thread count '{}'", value)),
+    }
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("invalid count '{}'", value)),
+    }
+}
+
+// Parse a comma-separated list of extensions, each with or without its dot
+fn parse_extensions(flag: &str, value: &str) -> Result<Vec<String>, String> {
+    let extensions: Vec<String> = value
+        .split(',')
+        .map(|extension| extension.trim().trim_start_matches('.'))
+        .filter(|extension| !extension.is_empty())
+        .map(str::to_string)
+        .collect();
+    if extensions.is_empty() {
+        return Err(format!(
+            "option '{}' requires extensions, e.g. mp4,mov",
+            flag
+        ));
+    }
+    Ok(extensions)
+}
+
+// Parse a user name, or a uid as it is
+fn parse_user(value: &str) -> Result<u32, String> {
+    value
+        .parse::<u32>()
+        .ok()
+        .or_else(|| user_id(value))
+        .ok_or_else(|| format!("no such user '{}'", value))
+}
+
+// Parse an age: a count with an s, h, d, w, m or y suffix for seconds, hours,
+// days, weeks, months of 30 days and years of 365, or days without one
+fn parse_age(value: &str) -> Result<Duration, String> {
+    const DAY: u64 = 24 * 60 * 60;
+    let invalid = || format!("invalid age '{}', expected e.g. 90d or 1y", value);
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        Some((i, 'd')) => (&value[..i], DAY),
+        Some((i, 'w')) => (&value[..i], 7 * DAY),
+        Some((i, 'm')) => (&value[..i], 30 * DAY),
+        Some((i, 'y')) => (&value[..i], 365 * DAY),
+        _ => (value, DAY),
+    };
+    let count = digits.parse::<u64>().map_err(|_| invalid())?;
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+// Parse a byte count with an optional K, M, G or T suffix (powers of 1024)
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size '{}'", value);
+    let upper = value.to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, shift) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 10),
+        Some((i, 'M')) => (&digits[..i], 20),
+        Some((i, 'G')) => (&digits[..i], 30),
+        Some((i, 'T')) => (&digits[..i], 40),
+        _ => (digits, 0),
+    };
+    let count = digits.parse::<usize>().map_err(|_| invalid())?;
+    count.checked_mul(1 << shift).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("100mb"), Ok(100 << 20));
+        assert_eq!(parse_size("50G"), Ok(50 << 30));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+        for invalid in ["", "K", "1.5G", "-1", "10X", "99999999999T"] {
+            assert!(parse_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_age() {
+        const DAY: u64 = 24 * 60 * 60;
+        assert_eq!(parse_age("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_age("36h"), Ok(Duration::from_secs(36 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * DAY)));
+        assert_eq!(parse_age("1m"), Ok(Duration::from_secs(30 * DAY)));
+        assert_eq!(parse_age("1y"), Ok(Duration::from_secs(365 * DAY)));
+        // Days without a unit
+        assert_eq!(parse_age("90"), Ok(Duration::from_secs(90 * DAY)));
+        for invalid in ["", "d", "1.5d", "-1d", "3x", "99999999999999999y"] {
+            assert!(parse_age(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_extensions() {
+        assert_eq!(
+            parse_extensions("--ext", "mp4,.MOV, heic,,"),
+            Ok(vec![
+                "mp4".to_string(),
+                "MOV".to_string(),
+                "heic".to_string()
+            ])
+        );
+        assert!(parse_extensions("--ext", ",.").is_err());
+        assert!(parse_extensions("--no-ext", "").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use super::format_size;
+use super::sync::Mutex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+// Files in each APFS clone family and the paths to them, for
+// --report-clones. A family's files share the blocks of the file they were
+// cloned from, kept on disk once, and each holds the blocks it has changed
+// since privately.
+#[derive(Default)]
+pub struct Families {
+    families: Mutex<HashMap<(u32, u64), HashMap<u64, Member>>>,
+}
+
+// A file in a family, by inode, so hardlinks to it count once
+struct Member {
+    blocks: i64,
+    shared_blocks: i64,
+    paths: Vec<Vec<u8>>,
+}
+
+// A family's files and their space, in 512-byte blocks
+struct Family {
+    paths: Vec<Vec<u8>>,
+    files: usize,
+    // What the files would take if each were a copy
+    allocated: i64,
+    // What they do take: the shared blocks once and every file's own
+    on_disk: i64,
+}
+
+impl Families {
+    // Note a file that shares `shared_blocks` of its `blocks` with clone
+    // family `id` on device `dev`
+    pub fn record(
+        &self,
+        dev: u32,
+        id: u64,
+        inode: u64,
+        blocks: i64,
+        shared_blocks: i64,
+        path: Vec<u8>,
+    ) {
+        self.families
+            .lock()
+            .entry((dev, id))
+            .or_default()
+            .entry(inode)
+            .or_insert_with(|| Member {
+                blocks,
+                shared_blocks,
+                paths: Vec::new(),
+            })
+            .paths
+            .push(path);
+    }
+
+    // Families with more than one file, most shared first. Files whose
+    // clones are all outside the scan are only counted.
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let families = std::mem::take(&mut *self.families.lock());
+        let mut alone = 0;
+        let mut shown = Vec::new();
+        for members in families.into_values() {
+            if members.len() < 2 {
+                alone += 1;
+                continue;
+            }
+            let shared = members.values().map(|m| m.shared_blocks).max();
+            let private: i64 = members.values().map(|m| m.blocks - m.shared_blocks).sum();
+            let mut paths: Vec<Vec<u8>> = members
+                .values()
+                .flat_map(|member| member.paths.iter().cloned())
+                .collect();
+            paths.sort_unstable();
+            shown.push(Family {
+                paths,
+                files: members.len(),
+                allocated: members.values().map(|m| m.blocks).sum(),
+                on_disk: shared.unwrap_or(0) + private,
+            });
+        }
+        shown.sort_unstable_by(|a, b| {
+            (Reverse(a.allocated - a.on_disk), &a.paths)
+                .cmp(&(Reverse(b.allocated - b.on_disk), &b.paths))
+        });
+
+        let files: usize = shown.iter().map(|family| family.files).sum();
+        let allocated: i64 = shown.iter().map(|family| family.allocated).sum();
+        let on_disk: i64 = shown.iter().map(|family| family.on_disk).sum();
+        writeln!(
+            out,
+            "clone families: {} of {} files, {} as copies, {} on disk, {} shared",
+            shown.len(),
+            files,
+            format_size(allocated),
+            format_size(on_disk),
+            format_size(allocated - on_disk)
+        )?;
+        for family in &shown {
+            writeln!(
+                out,
+                "  {}\ton disk, {} as {} copies",
+                format_size(family.on_disk),
+                format_size(family.allocated),
+                family.files
+            )?;
+            for path in &family.paths {
+                writeln!(out, "    {}", String::from_utf8_lossy(path))?;
+            }
+        }
+        if alone > 0 {
+            writeln!(
+                out,
+                "  files sharing blocks only with clones outside the scan: {}",
+                alone
+            )?;
+        }
+        Ok(())
+    }
+}
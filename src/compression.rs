@@ -0,0 +1,74 @@
+use super::tally::Totals;
+use super::{blocks_from_bytes, format_size};
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+// Totals for the --compression report
+#[derive(Default)]
+pub struct Stats {
+    files: AtomicU64,
+    logical_bytes: AtomicI64,
+    stored_blocks: AtomicI64,
+}
+
+impl Stats {
+    // Count a transparently compressed file by its uncompressed size and the
+    // blocks it was counted for
+    pub fn record(&self, logical_bytes: i64, stored_blocks: i64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.logical_bytes
+            .fetch_add(logical_bytes, Ordering::Relaxed);
+        self.stored_blocks
+            .fetch_add(stored_blocks, Ordering::Relaxed);
+    }
+
+    // What compression saved, and what compressing the rest of the scan's
+    // files as well would save at the same ratio, to tell whether running
+    // afsctool or ditto --hfsCompression over them is worth it
+    pub fn report(&self, totals: &Totals, out: &mut String) -> fmt::Result {
+        let files = self.files.load(Ordering::Relaxed);
+        let logical_bytes = self.logical_bytes.load(Ordering::Relaxed);
+        let logical = blocks_from_bytes(logical_bytes);
+        let stored = self.stored_blocks.load(Ordering::Relaxed);
+        let saved = match logical > stored {
+            true => format!(
+                "{} saved ({}%)",
+                format_size(logical - stored),
+                (logical - stored) * 100 / logical
+            ),
+            false => "nothing saved".to_string(),
+        };
+        writeln!(
+            out,
+            "compressed: {} files, {} logical, {} on disk, {}",
+            files,
+            format_size(logical),
+            format_size(stored),
+            saved
+        )?;
+
+        // The rest of the files the scan counted, leaving out excluded and
+        // filtered ones like the totals do. Their bytes are counted once per
+        // inode in both, but the totals count a hardlinked file once per
+        // link, so with hardlinks the rest is fewer files than this.
+        let other_files = totals.files.load(Ordering::Relaxed).saturating_sub(files);
+        let other_bytes = (totals.apparent_bytes.load(Ordering::Relaxed) - logical_bytes).max(0);
+        if other_files == 0 {
+            return Ok(());
+        }
+        let mut line = format!(
+            "  not compressed: {} files, {} logical",
+            other_files,
+            format_size(blocks_from_bytes(other_bytes))
+        );
+        if logical > stored {
+            let ratio = (logical - stored) as f64 / logical as f64;
+            let estimate = (blocks_from_bytes(other_bytes) as f64 * ratio) as i64;
+            line.push_str(&format!(
+                ", compressing them at the same ratio would save about {}",
+                format_size(estimate)
+            ));
+        }
+        writeln!(out, "{}", line)
+    }
+}
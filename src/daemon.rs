@@ -0,0 +1,176 @@
+// `dumac daemon` and `dumac query`. Requests on the socket are absolute,
+// resolved paths, one per line, and each reply is a line holding the
+// directory's blocks, or `!` and why there are none.
+use dumac::internal::{strerror, Condvar, Mutex};
+use dumac::pool::ThreadPool;
+use dumac::watch::{self, Totals};
+use dumac::{DumacError, ScanOptions};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+// $TMPDIR/dumac.sock, which on macOS is in a directory only the user can
+// read, or /tmp/dumac-UID.sock
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("TMPDIR") {
+        Some(dir) => PathBuf::from(dir).join("dumac.sock"),
+        None => PathBuf::from(format!("/tmp/dumac-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+// Every directory's total from the latest scan, keyed by resolved path
+struct Index {
+    root: PathBuf,
+    blocks: HashMap<PathBuf, i64>,
+}
+
+// The index, empty until the first scan completes
+#[derive(Default)]
+struct Shared {
+    index: Mutex<Option<Index>>,
+    ready: Condvar,
+}
+
+// Scan a directory, keep every directory's total in memory and answer
+// queries for them on `socket`, rescanning as FSEvents reports changes the
+// same way --watch does. Queries made during the first scan wait for it.
+// Runs until interrupted or the directory can't be scanned.
+pub fn serve(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    socket: &Path,
+) -> Result<(), DumacError> {
+    let listener = bind(socket).map_err(|e| DumacError::Io {
+        op: "bind".into(),
+        path: socket.to_path_buf(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    let shared = Arc::new(Shared::default());
+    let accepting = shared.clone();
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let shared = accepting.clone();
+            thread::spawn(move || answer(&shared, client));
+        }
+    });
+
+    let result = watch::watch(options, pool, root_dir, Totals::All, |root, _, dirs| {
+        let blocks = dirs.into_iter().map(|dir| (dir.path, dir.blocks)).collect();
+        *shared.index.lock() = Some(Index {
+            root: root.to_path_buf(),
+            blocks,
+        });
+        shared.ready.notify_all();
+    });
+    let _ = fs::remove_file(socket);
+    result
+}
+
+// A socket left behind by a daemon that's gone is replaced, one that still
+// answers isn't
+fn bind(socket: &Path) -> io::Result<UnixListener> {
+    let listener = match UnixListener::bind(socket) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(socket).is_err() => {
+            fs::remove_file(socket)?;
+            UnixListener::bind(socket)?
+        }
+        listener => listener?,
+    };
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn answer(shared: &Shared, client: UnixStream) {
+    let Ok(mut replies) = client.try_clone() else {
+        return;
+    };
+    for request in BufReader::new(client).split(b'\n') {
+        let Ok(request) = request else {
+            return;
+        };
+        let path = Path::new(OsStr::from_bytes(&request));
+        let reply = {
+            let mut index = shared.index.lock();
+            while index.is_none() {
+                shared.ready.wait(&mut index);
+            }
+            match index.as_ref() {
+                Some(index) => reply(index, path),
+                None => return,
+            }
+        };
+        if writeln!(replies, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+fn reply(index: &Index, path: &Path) -> String {
+    match index.blocks.get(path) {
+        Some(blocks) => blocks.to_string(),
+        None if !path.starts_with(&index.root) => {
+            format!(
+                "!not under {}, the directory being watched",
+                index.root.display()
+            )
+        }
+        None => "!not a directory, or excluded from the scan".to_string(),
+    }
+}
+
+// Ask the daemon listening on `socket` for each path's blocks. A path's
+// result is an error message when the daemon has no total for it.
+pub fn query(socket: &Path, paths: &[String]) -> Result<Vec<Result<i64, String>>, DumacError> {
+    let failed = |e: io::Error| DumacError::Io {
+        op: "connect".into(),
+        path: socket.to_path_buf(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    };
+    let mut daemon = UnixStream::connect(socket).map_err(failed)?;
+    let mut requests = Vec::new();
+    let mut results = vec![None; paths.len()];
+    for (path, result) in paths.iter().zip(&mut results) {
+        // The daemon's index is keyed by resolved path
+        match fs::canonicalize(path) {
+            Ok(resolved) if !resolved.as_os_str().as_bytes().contains(&b'\n') => {
+                requests.extend_from_slice(resolved.as_os_str().as_bytes());
+                requests.push(b'\n');
+            }
+            Ok(_) => *result = Some(Err("Invalid path".to_string())),
+            Err(e) => *result = Some(Err(strerror(e.raw_os_error().unwrap_or(libc::EIO)))),
+        }
+    }
+    daemon.write_all(&requests).map_err(failed)?;
+    daemon.shutdown(std::net::Shutdown::Write).map_err(failed)?;
+
+    // A reply for each path that was sent, in order
+    let mut replies = BufReader::new(daemon).lines();
+    let mut answered = Vec::new();
+    for result in results {
+        let result = match result {
+            Some(result) => result,
+            None => {
+                let reply = replies
+                    .next()
+                    .unwrap_or_else(|| Err(io::Error::from_raw_os_error(libc::ECONNRESET)))
+                    .map_err(failed)?;
+                match reply.strip_prefix('!') {
+                    Some(message) => Err(message.to_string()),
+                    None => reply
+                        .parse()
+                        .map_err(|_| format!("bad reply from the daemon '{}'", reply)),
+                }
+            }
+        };
+        answered.push(result);
+    }
+    Ok(answered)
+}
@@ -0,0 +1,85 @@
+use super::background::{setiopolicy_np, IOPOL_SCOPE_PROCESS, IOPOL_SCOPE_THREAD};
+use super::{blocks_from_bytes, format_size};
+use std::cell::Cell;
+use std::fmt::{self, Write};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+// macOS constants not in libc crate (<sys/resource.h>, <sys/stat.h>)
+const IOPOL_TYPE_VFS_MATERIALIZE_DATALESS_FILES: c_int = 3;
+const IOPOL_MATERIALIZE_DATALESS_FILES_OFF: c_int = 1;
+pub const SF_DATALESS: u32 = 0x40000000;
+
+// Totals for the --cloud report
+#[derive(Default)]
+pub struct Stats {
+    files: AtomicU64,
+    logical_bytes: AtomicI64,
+    dirs: AtomicU64,
+}
+
+// Make sure nothing dumac touches is downloaded from iCloud. Dataless files
+// already report only their local allocation, this also keeps directory
+// reads from fetching evicted listings.
+pub fn never_materialize() -> Result<(), String> {
+    let ret = unsafe {
+        setiopolicy_np(
+            IOPOL_TYPE_VFS_MATERIALIZE_DATALESS_FILES,
+            IOPOL_SCOPE_PROCESS,
+            IOPOL_MATERIALIZE_DATALESS_FILES_OFF,
+        )
+    };
+    if ret != 0 {
+        return Err("failed to disable dataless file materialization".to_string());
+    }
+    Ok(())
+}
+
+// The same for the calling thread only, once per thread. Scans call it on
+// every thread they list directories on, so that a library scan doesn't
+// download anything either, without changing the rest of the application.
+// Threads of a pool passed to ScanOptions::thread_pool keep it afterwards.
+pub(super) fn never_materialize_thread() {
+    thread_local! {
+        static SET: Cell<bool> = const { Cell::new(false) };
+    }
+    if SET.replace(true) {
+        return;
+    }
+    let ret = unsafe {
+        setiopolicy_np(
+            IOPOL_TYPE_VFS_MATERIALIZE_DATALESS_FILES,
+            IOPOL_SCOPE_THREAD,
+            IOPOL_MATERIALIZE_DATALESS_FILES_OFF,
+        )
+    };
+    if ret != 0 {
+        tracing::debug!("failed to disable dataless file materialization for a thread");
+    }
+}
+
+impl Stats {
+    // Count a file whose data lives only in the cloud, by its logical size
+    pub fn record_file(&self, logical_bytes: i64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.logical_bytes
+            .fetch_add(logical_bytes, Ordering::Relaxed);
+    }
+
+    // Count a directory whose listing lives only in the cloud, which is skipped
+    pub fn record_dir(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        writeln!(
+            out,
+            "cloud only: {} files, {} not downloaded, {} directories skipped",
+            self.files.load(Ordering::Relaxed),
+            format_size(blocks_from_bytes(
+                self.logical_bytes.load(Ordering::Relaxed)
+            )),
+            self.dirs.load(Ordering::Relaxed)
+        )
+    }
+}
@@ -0,0 +1,128 @@
+use super::sync::Mutex;
+use std::env;
+use std::fmt::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+// Denied paths printed before the rest are only counted
+const MAX_LISTED: usize = 20;
+
+// Locations in a home directory that macOS privacy protections (TCC) keep
+// closed to apps without Full Disk Access
+const PROTECTED: &[&str] = &[
+    ".Trash",
+    "Desktop",
+    "Documents",
+    "Downloads",
+    "Library/Accounts",
+    "Library/Application Support/AddressBook",
+    "Library/Application Support/CallHistoryDB",
+    "Library/Application Support/CallHistoryTransactions",
+    "Library/Application Support/FaceTime",
+    "Library/Application Support/Knowledge",
+    "Library/Application Support/MobileSync",
+    "Library/Application Support/com.apple.TCC",
+    "Library/Biome",
+    "Library/Calendars",
+    "Library/Containers",
+    "Library/Cookies",
+    "Library/Group Containers",
+    "Library/HomeKit",
+    "Library/IdentityServices",
+    "Library/Mail",
+    "Library/Messages",
+    "Library/Metadata/CoreSpotlight",
+    "Library/PersonalizationPortrait",
+    "Library/Reminders",
+    "Library/Safari",
+    "Library/Sharing",
+    "Library/Suggestions",
+    "Pictures/Photos Library.photoslibrary",
+];
+
+// Paths macOS protections kept the scan out of, summed up after the scan
+// instead of an error each
+#[derive(Default)]
+pub struct Denials {
+    // Privacy protections (TCC), lifted by Full Disk Access
+    privacy: Mutex<Vec<String>>,
+    // System Integrity Protection and sandbox restrictions, which nothing
+    // short of disabling them lifts
+    restricted: Mutex<Vec<String>>,
+}
+
+impl Denials {
+    // Note a path that couldn't be read if protections are why, returns
+    // whether they were
+    pub fn record(&self, path: &[u8], errno: i32) -> bool {
+        let list = if is_denial(path, errno) {
+            &self.privacy
+        } else if errno == libc::EPERM {
+            &self.restricted
+        } else {
+            return false;
+        };
+        list.lock().push(String::from_utf8_lossy(path).into_owned());
+        true
+    }
+
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        report(
+            "skipped due to privacy protections (grant Full Disk Access)",
+            &self.privacy,
+            out,
+        )?;
+        report(
+            "skipped due to system protections (SIP)",
+            &self.restricted,
+            out,
+        )
+    }
+}
+
+fn report(reason: &str, paths: &Mutex<Vec<String>>, out: &mut String) -> fmt::Result {
+    let mut paths = paths.lock();
+    if paths.is_empty() {
+        return Ok(());
+    }
+    paths.sort_unstable();
+    writeln!(
+        out,
+        "{}: {} paths, not included in the totals",
+        reason,
+        paths.len()
+    )?;
+    for path in paths.iter().take(MAX_LISTED) {
+        writeln!(out, "  {}", path)?;
+    }
+    if paths.len() > MAX_LISTED {
+        writeln!(out, "  ... and {} more", paths.len() - MAX_LISTED)?;
+    }
+    Ok(())
+}
+
+// Privacy protections refuse with EPERM where ordinary permissions refuse
+// with EACCES, in the locations they cover
+pub fn is_denial(path: &[u8], errno: i32) -> bool {
+    errno == libc::EPERM && protected(path)
+}
+
+// Whether a path lies in a protected location of the user's home, or of
+// anyone's under /Users
+fn protected(path: &[u8]) -> bool {
+    let home = env::var_os("HOME");
+    let in_home = home
+        .as_ref()
+        .and_then(|home| path.strip_prefix(home.as_bytes())?.strip_prefix(b"/"));
+    let in_users = || {
+        let rest = path.strip_prefix(b"/Users/")?;
+        let slash = rest.iter().position(|&b| b == b'/')?;
+        Some(&rest[slash + 1..])
+    };
+    let Some(rest) = in_home.or_else(in_users) else {
+        return false;
+    };
+    PROTECTED.iter().any(|dir| {
+        rest.strip_prefix(dir.as_bytes())
+            .is_some_and(|tail| tail.is_empty() || tail.starts_with(b"/"))
+    })
+}
@@ -0,0 +1,242 @@
+// `dumac devjunk`: directories that build tools and package managers fill
+// and can fill again, found and sized in one scan. One inside another, such
+// as node_modules in node_modules, counts as part of the outer one.
+use dumac::internal::Mutex;
+use dumac::pool::ThreadPool;
+use dumac::stream::DirResult;
+use dumac::visit::{self, Kind};
+use dumac::{format_size, DumacError, ScanOptions, Scanner};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// What a found directory is, in the order the report lists them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Junk {
+    NodeModules,
+    RustTarget,
+    PythonVenv,
+    DerivedData,
+    DeviceSupport,
+    SimulatorRuntimes,
+    Gradle,
+    Docker,
+}
+
+impl Junk {
+    pub fn label(self) -> &'static str {
+        match self {
+            Junk::NodeModules => "node_modules",
+            Junk::RustTarget => "Rust target directories",
+            Junk::PythonVenv => "Python virtualenvs",
+            Junk::DerivedData => "Xcode DerivedData",
+            Junk::DeviceSupport => "Xcode device support files",
+            Junk::SimulatorRuntimes => "simulator runtimes",
+            Junk::Gradle => "Gradle caches",
+            Junk::Docker => "Docker Desktop data",
+        }
+    }
+
+    // What the directory at `path` is, if it's one of these. Names are
+    // checked first so only likely ones are looked into, and a common name
+    // needs what only the tool leaves behind, since clean offers to remove
+    // what's found.
+    pub(super) fn detect(path: &Path) -> Option<Junk> {
+        let name = path.file_name()?.to_str()?;
+        let parent = path.parent()?;
+        let has = |marker: &str| path.join(marker).exists();
+        match name {
+            // Each package manager's record of what it installed
+            "node_modules"
+                if has(".package-lock.json")
+                    || has(".modules.yaml")
+                    || has(".yarn-integrity")
+                    || has(".yarn-state.yml") =>
+            {
+                Some(Junk::NodeModules)
+            }
+            // What Cargo knows of the compiler, and its record of a build.
+            // Other tools write CACHEDIR.TAG, and a project may keep
+            // something else in a directory called target.
+            "target" if has(".rustc_info.json") || has("debug/.fingerprint") => {
+                Some(Junk::RustTarget)
+            }
+            ".venv" | "venv" | "env" | ".env" | "virtualenv" if has("pyvenv.cfg") => {
+                Some(Junk::PythonVenv)
+            }
+            "DerivedData" if parent.ends_with("Xcode") => Some(Junk::DerivedData),
+            // iOS DeviceSupport, watchOS DeviceSupport and so on, one
+            // directory for each OS version a device was connected with
+            _ if name.ends_with(" DeviceSupport") && parent.ends_with("Xcode") => {
+                Some(Junk::DeviceSupport)
+            }
+            // /Library/Developer/CoreSimulator/Profiles/Runtimes
+            "Runtimes" if parent.ends_with("CoreSimulator/Profiles") => {
+                Some(Junk::SimulatorRuntimes)
+            }
+            ".gradle" => Some(Junk::Gradle),
+            // ~/Library/Containers/com.docker.docker, holding the VM's disk
+            "com.docker.docker" if parent.ends_with("Containers") => Some(Junk::Docker),
+            _ => None,
+        }
+    }
+}
+
+// A directory found, with its total
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+    pub path: PathBuf,
+    pub junk: Junk,
+    pub blocks: i64,
+}
+
+// Every such directory under `root_dir`, grouped by kind and largest first
+// within each
+pub fn find(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+) -> Result<Vec<Found>, DumacError> {
+    let matched: Arc<Mutex<HashMap<PathBuf, Junk>>> = Arc::default();
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let mut scanner = Scanner::new(options.clone());
+
+    let seen = matched.clone();
+    scanner.visit(move |entry: &visit::Entry| {
+        if entry.kind == Kind::Dir {
+            if let Some(junk) = Junk::detect(entry.path) {
+                // Directories are visited before anything inside them
+                let mut seen = seen.lock();
+                if !entry.path.ancestors().any(|dir| seen.contains_key(dir)) {
+                    seen.insert(entry.path.to_path_buf(), junk);
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    let sized = found.clone();
+    let wanted = matched.clone();
+    scanner.on_result(move |dir: DirResult| {
+        let junk = wanted.lock().get(&dir.path).copied();
+        if let Some(junk) = junk {
+            sized.lock().push(Found {
+                path: dir.path,
+                junk,
+                blocks: dir.blocks,
+            });
+        }
+    });
+    pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+    scanner.report();
+
+    let mut found = std::mem::take(&mut *found.lock());
+    found.sort_by(|a, b| {
+        a.junk
+            .cmp(&b.junk)
+            .then(b.blocks.cmp(&a.blocks))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(found)
+}
+
+// Print each kind's total and directories, then what all of them hold
+pub fn run(options: &ScanOptions, pool: &ThreadPool, root_dir: &str) -> Result<(), DumacError> {
+    let found = find(options, pool, root_dir)?;
+    for group in found.chunk_by(|a, b| a.junk == b.junk) {
+        let blocks: i64 = group.iter().map(|found| found.blocks).sum();
+        println!(
+            "{}\t{} ({})",
+            format_size(blocks),
+            group[0].junk.label(),
+            group.len()
+        );
+        for found in group {
+            println!("\t{}\t{}", format_size(found.blocks), found.path.display());
+        }
+    }
+    let total: i64 = found.iter().map(|found| found.blocks).sum();
+    println!(
+        "{}\treclaimable in {} directories",
+        format_size(total),
+        found.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn make(root: &Path, dirs: &[&str], files: &[&str]) {
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in files {
+            fs::write(root.join(file), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_names_need_evidence() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        make(
+            root,
+            &[
+                "npm/node_modules",
+                "pnpm/node_modules",
+                "bare/node_modules",
+                "cargo/target/debug/.fingerprint",
+                "rustc/target",
+                // Tagged, and next to a Cargo.toml, but not a build
+                "java/target",
+                "py/.venv",
+                "py/env",
+            ],
+            &[
+                "npm/node_modules/.package-lock.json",
+                "pnpm/node_modules/.modules.yaml",
+                "rustc/target/.rustc_info.json",
+                "java/target/CACHEDIR.TAG",
+                "java/Cargo.toml",
+                "py/.venv/pyvenv.cfg",
+            ],
+        );
+        let detect = |dir: &str| Junk::detect(&root.join(dir));
+        assert_eq!(detect("npm/node_modules"), Some(Junk::NodeModules));
+        assert_eq!(detect("pnpm/node_modules"), Some(Junk::NodeModules));
+        assert_eq!(detect("bare/node_modules"), None);
+        assert_eq!(detect("cargo/target"), Some(Junk::RustTarget));
+        assert_eq!(detect("rustc/target"), Some(Junk::RustTarget));
+        assert_eq!(detect("java/target"), None);
+        assert_eq!(detect("py/.venv"), Some(Junk::PythonVenv));
+        assert_eq!(detect("py/env"), None);
+    }
+
+    #[test]
+    fn test_places_decide() {
+        let detect = |path: &str| Junk::detect(Path::new(path));
+        assert_eq!(
+            detect("/Users/a/Library/Developer/Xcode/DerivedData"),
+            Some(Junk::DerivedData)
+        );
+        assert_eq!(detect("/Users/a/src/DerivedData"), None);
+        assert_eq!(
+            detect("/Users/a/Library/Developer/Xcode/iOS DeviceSupport"),
+            Some(Junk::DeviceSupport)
+        );
+        assert_eq!(
+            detect("/Library/Developer/CoreSimulator/Profiles/Runtimes"),
+            Some(Junk::SimulatorRuntimes)
+        );
+        assert_eq!(detect("/Users/a/src/Runtimes"), None);
+        assert_eq!(
+            detect("/Users/a/Library/Containers/com.docker.docker"),
+            Some(Junk::Docker)
+        );
+        assert_eq!(detect("/Users/a/.gradle"), Some(Junk::Gradle));
+    }
+}
@@ -0,0 +1,49 @@
+// `dumac diff`: the directories whose totals differ between a snapshot and
+// another, or a new scan
+use dumac::pool::ThreadPool;
+use dumac::snapshot::{diff, Snapshot};
+use dumac::{format_size, DumacError, ScanOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Compare the snapshot at `before` with the one at `after`, or with a new
+// scan of `after` or, without it, of the directory the snapshot was taken
+// of. Prints each directory that changed and by how much, the biggest
+// changes first.
+pub fn run(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    before: &str,
+    after: Option<&str>,
+) -> Result<(), DumacError> {
+    let before = Snapshot::load(Path::new(before))?;
+    let after = match after {
+        Some(after) if Snapshot::is_snapshot(Path::new(after)) => Snapshot::load(Path::new(after))?,
+        Some(after) => Snapshot::scan(options, pool, after)?,
+        None => Snapshot::scan(options, pool, &before.root.to_string_lossy())?,
+    };
+    print_diff(&before, &after);
+    Ok(())
+}
+
+// Each directory that changed from `before` to `after` and by how much, under
+// `after`'s root, the biggest changes first
+pub fn print_diff(before: &Snapshot, after: &Snapshot) {
+    // Often piped to head, so stop quietly once nothing reads the output
+    let mut out = io::stdout().lock();
+    for change in diff(before, after) {
+        let sign = if change.after > change.before {
+            '+'
+        } else {
+            '-'
+        };
+        let path = match change.path.as_os_str().is_empty() {
+            true => after.root.clone(),
+            false => after.root.join(&change.path),
+        };
+        let size = format_size((change.after - change.before).abs());
+        if writeln!(out, "{}{}\t{}", sign, size, path.display()).is_err() {
+            break;
+        }
+    }
+}
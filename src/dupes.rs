@@ -0,0 +1,201 @@
+// `dumac dupes`: files with identical contents. The scan gives every file's
+// size, only files sharing a size are read, first their opening bytes and
+// then, for those still alike, the whole file, on the scan's pool.
+use dumac::internal::{strerror, Mutex};
+use dumac::pool::{self, ThreadPool};
+use dumac::visit::{self, Kind};
+use dumac::{dataless, format_size, DumacError, ScanOptions, Scanner};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{self, Read};
+use std::ops::ControlFlow;
+use std::os::macos::fs::MetadataExt as _;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Read first from each candidate, most files that differ do so here
+const HEAD_BYTES: u64 = 4096;
+
+// Contents that are the same, as far as two 64-bit hashes with keys picked
+// for this run can tell
+type Digest = (u64, u64);
+
+// Files found to be copies of each other
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub apparent_bytes: i64,
+    // Each copy's allocation
+    pub blocks: i64,
+    // One path per inode, hardlinks aren't copies
+    pub paths: Vec<PathBuf>,
+}
+
+impl Set {
+    // Freed by keeping one copy, or by making the others APFS clones of it
+    pub fn reclaimable_blocks(&self) -> i64 {
+        self.blocks * (self.paths.len() as i64 - 1)
+    }
+}
+
+// A file that could have copies
+struct Candidate {
+    path: PathBuf,
+    apparent_bytes: i64,
+    blocks: i64,
+}
+
+// Sets of copies under `root_dir`, the most space to reclaim first. Files
+// that can't be read are reported on stderr and left out.
+pub fn find(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+) -> Result<Vec<Set>, DumacError> {
+    let files = Arc::new(Mutex::new(Vec::new()));
+    let mut scanner = Scanner::new(options.clone());
+    let found = files.clone();
+    scanner.visit(move |entry: &visit::Entry| {
+        // Empty files are all alike and take no space
+        if entry.kind == Kind::File && entry.apparent_bytes > 0 {
+            found.lock().push(Candidate {
+                path: entry.path.to_path_buf(),
+                apparent_bytes: entry.apparent_bytes,
+                blocks: entry.blocks,
+            });
+        }
+        ControlFlow::Continue(())
+    });
+    pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+    scanner.report();
+
+    let mut by_size: HashMap<i64, Vec<Candidate>> = HashMap::new();
+    for file in std::mem::take(&mut *files.lock()) {
+        by_size.entry(file.apparent_bytes).or_default().push(file);
+    }
+    let groups = by_size
+        .into_values()
+        .map(distinct_inodes)
+        .filter(|group| group.len() > 1)
+        .collect();
+
+    let keys = (RandomState::new(), RandomState::new());
+    let groups = regroup(pool, groups, &keys, Some(HEAD_BYTES));
+    // Files no longer than the head were read whole already
+    let (small, large): (Vec<_>, Vec<_>) = groups
+        .into_iter()
+        .partition(|group| group[0].apparent_bytes as u64 <= HEAD_BYTES);
+    let groups = small.into_iter().chain(regroup(pool, large, &keys, None));
+
+    let mut sets: Vec<Set> = groups
+        .map(|group| {
+            let mut paths: Vec<PathBuf> = group.iter().map(|file| file.path.clone()).collect();
+            paths.sort();
+            Set {
+                apparent_bytes: group[0].apparent_bytes,
+                blocks: group[0].blocks,
+                paths,
+            }
+        })
+        .collect();
+    sets.sort_by_key(|set| std::cmp::Reverse(set.reclaimable_blocks()));
+    Ok(sets)
+}
+
+// One candidate per inode. Hardlinks share their blocks already. Files
+// evicted to iCloud are left out, reading them would download them.
+fn distinct_inodes(group: Vec<Candidate>) -> Vec<Candidate> {
+    let mut inodes = HashMap::new();
+    for file in group {
+        if let Ok(metadata) = fs::symlink_metadata(&file.path) {
+            if metadata.st_flags() & dataless::SF_DATALESS != 0 {
+                continue;
+            }
+            inodes
+                .entry((metadata.dev(), metadata.ino()))
+                .or_insert(file);
+        }
+    }
+    inodes.into_values().collect()
+}
+
+// Split each group by the digest of its files' first `limit` bytes, or of
+// all of them, keeping groups of two or more
+fn regroup(
+    pool: &ThreadPool,
+    groups: Vec<Vec<Candidate>>,
+    keys: &(RandomState, RandomState),
+    limit: Option<u64>,
+) -> Vec<Vec<Candidate>> {
+    let digests = Mutex::new(HashMap::new());
+    pool.install(|| {
+        pool::scope(|scope| {
+            for (index, group) in groups.into_iter().enumerate() {
+                for file in group {
+                    let digests = &digests;
+                    scope.spawn(move |_| match digest(&file, keys, limit) {
+                        Ok(digest) => {
+                            let mut digests = digests.lock();
+                            let same: &mut Vec<Candidate> =
+                                digests.entry((index, digest)).or_default();
+                            same.push(file);
+                        }
+                        Err(e) => eprintln!(
+                            "dumac: {}: {}",
+                            file.path.display(),
+                            strerror(e.raw_os_error().unwrap_or(libc::EIO))
+                        ),
+                    });
+                }
+            }
+        })
+    });
+    digests
+        .into_inner()
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+fn digest(
+    file: &Candidate,
+    keys: &(RandomState, RandomState),
+    limit: Option<u64>,
+) -> io::Result<Digest> {
+    let mut hashers = (keys.0.build_hasher(), keys.1.build_hasher());
+    let mut contents = File::open(&file.path)?.take(limit.unwrap_or(u64::MAX));
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = contents.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hashers.0.write(&buf[..read]);
+        hashers.1.write(&buf[..read]);
+    }
+    Ok((hashers.0.finish(), hashers.1.finish()))
+}
+
+// Print each set of copies, the most to reclaim first, then the total
+pub fn run(options: &ScanOptions, pool: &ThreadPool, root_dir: &str) -> Result<(), DumacError> {
+    let sets = find(options, pool, root_dir)?;
+    for set in &sets {
+        println!(
+            "{}\t{} copies of {}",
+            format_size(set.reclaimable_blocks()),
+            set.paths.len(),
+            format_size((set.apparent_bytes + 511) / 512)
+        );
+        for path in &set.paths {
+            println!("\t{}", path.display());
+        }
+    }
+    let total: i64 = sets.iter().map(Set::reclaimable_blocks).sum();
+    println!(
+        "{}\treclaimable in {} sets of copies",
+        format_size(total),
+        sets.len()
+    );
+    Ok(())
+}
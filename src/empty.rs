@@ -0,0 +1,104 @@
+use super::sync::Mutex;
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+
+// Directories with no files anywhere under them, or fewer than a threshold,
+// for --report-empty. Only the outermost of nested ones is listed, with a
+// count of those inside it.
+pub struct Empty {
+    // Fewer files than this makes a directory empty, 1 for none at all
+    below: u64,
+    found: Mutex<Vec<(Vec<u8>, u64)>>,
+    // Not known to be empty, and neither are the directories holding them
+    unreadable: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Empty {
+    pub fn new(below: u64) -> Empty {
+        Empty {
+            below,
+            found: Mutex::new(Vec::new()),
+            unreadable: Mutex::new(Vec::new()),
+        }
+    }
+
+    // A directory whose subtree has been counted, holding `files` files and
+    // symlinks
+    pub fn offer(&self, files: u64, path: impl FnOnce() -> Vec<u8>) {
+        if files < self.below {
+            self.found.lock().push((path(), files));
+        }
+    }
+
+    pub fn unreadable(&self, path: Vec<u8>) {
+        self.unreadable.lock().push(path);
+    }
+
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let mut found = std::mem::take(&mut *self.found.lock());
+        let mut excluded = HashSet::new();
+        for path in self.unreadable.lock().iter() {
+            excluded.insert(path.clone());
+            for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                excluded.insert(path[..i.max(1)].to_vec());
+            }
+        }
+        found.retain(|(path, _)| {
+            let trimmed = match path.len() > 1 {
+                true => path.strip_suffix(b"/").unwrap_or(path),
+                false => path,
+            };
+            !excluded.contains(trimmed)
+        });
+        // By component, so a directory comes right before everything inside
+        // it, even "a/b-c" sorting before "a/b/c" as bytes
+        found.sort_unstable_by(|(a, _), (b, _)| {
+            a.split(|&c| c == b'/').cmp(b.split(|&c| c == b'/'))
+        });
+
+        let mut outermost: Vec<(Vec<u8>, u64, usize)> = Vec::new();
+        for (path, files) in found {
+            if let Some((outer, _, inside)) = outermost.last_mut() {
+                if is_inside(&path, outer) {
+                    *inside += 1;
+                    continue;
+                }
+            }
+            outermost.push((path, files, 0));
+        }
+        let inside: usize = outermost.iter().map(|(_, _, inside)| inside).sum();
+        match self.below {
+            1 => writeln!(
+                out,
+                "empty directories: {}, and {} inside them",
+                outermost.len(),
+                inside
+            )?,
+            below => writeln!(
+                out,
+                "directories with fewer than {} files: {}, and {} inside them",
+                below,
+                outermost.len(),
+                inside
+            )?,
+        }
+        for (path, files, inside) in &outermost {
+            let path = String::from_utf8_lossy(path);
+            let files = match (self.below, files) {
+                (1, _) => String::new(),
+                (_, 1) => "1 file\t".to_string(),
+                (_, files) => format!("{} files\t", files),
+            };
+            match inside {
+                0 => writeln!(out, "  {}{}", files, path)?,
+                inside => writeln!(out, "  {}{}\t{} inside", files, path, inside)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_inside(path: &[u8], dir: &[u8]) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| dir.ends_with(b"/") || rest.starts_with(b"/"))
+}
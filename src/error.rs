@@ -0,0 +1,51 @@
+use super::strerror;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+
+// Why a scan, or one of its roots, failed as a whole. Errors on directories
+// and entries below a root don't fail it, they go to the ErrorSink. More
+// kinds may come, so matches need a catch-all.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum DumacError {
+    // A system call failed on a path. `op` names it, e.g. "stat" or "list",
+    // and is only owned once deserialized.
+    Io {
+        op: Cow<'static, str>,
+        path: PathBuf,
+        errno: i32,
+    },
+    // Refused by privacy protections, which Full Disk Access would lift
+    Protected {
+        path: PathBuf,
+        errno: i32,
+    },
+    // A path with a NUL byte in it, which no system call accepts
+    InvalidPath(String),
+    // The cache couldn't be located or written
+    Cache(String),
+    // The worker threads couldn't be started
+    Threads(String),
+}
+
+impl fmt::Display for DumacError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumacError::Io { path, errno, .. } => {
+                write!(f, "{}: {}", path.display(), strerror(*errno))
+            }
+            DumacError::Protected { path, errno } => write!(
+                f,
+                "{}: {} (privacy protections, grant Full Disk Access)",
+                path.display(),
+                strerror(*errno)
+            ),
+            DumacError::InvalidPath(path) => write!(f, "{}: Invalid path", path),
+            DumacError::Cache(message) | DumacError::Threads(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for DumacError {}
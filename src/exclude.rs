@@ -0,0 +1,177 @@
+use super::ignore::Ignores;
+use super::names;
+use super::patterns::Regex;
+use super::volume::Volume;
+use super::DirNode;
+use std::ffi::CString;
+
+// Directories holding Time Machine backups or mounted local snapshots, whose
+// contents are copies of data counted elsewhere
+const BACKUP_DIRS: &[&str] = &[
+    "Backups.backupdb",
+    ".timemachine",
+    "com.apple.TimeMachine.localsnapshots",
+    ".MobileBackups",
+];
+
+// Housekeeping directories macOS keeps at the root of volumes
+const SYSTEM_DIRS: &[&str] = &[
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".Trashes",
+    ".DocumentRevisions-V100",
+];
+
+// Swap and the sleep image. /private is a firmlink into the data volume, so
+// either path may be the one reached first, and since macOS 11 swap has a
+// volume of its own.
+const SYSTEM_PATHS: &[&str] = &[
+    "/private/var/vm",
+    "/System/Volumes/Data/private/var/vm",
+    "/System/Volumes/VM",
+];
+
+// What a scan leaves out
+#[derive(Debug, Default, Clone)]
+pub struct Rules {
+    // --exclude-backups
+    pub backups: bool,
+    // --exclude-system
+    pub system: bool,
+    // --exclude
+    pub names: Vec<Name>,
+    // --exclude-regex, for directories and files alike
+    pub regexes: Vec<Regex>,
+    // --respect-gitignore
+    pub gitignore: bool,
+    // --respect-duignore
+    pub duignore: bool,
+    // --skip-hidden, for names starting with a dot
+    pub hidden: bool,
+    // -x, and --all-volumes, which scans each volume on its own
+    pub one_volume: bool,
+}
+
+// A directory name given with --exclude, folded both ways up front
+#[derive(Debug, Clone)]
+pub struct Name {
+    exact: String,
+    folded: String,
+}
+
+impl Name {
+    pub fn new(name: &str) -> Self {
+        Name {
+            exact: names::fold(name, true),
+            folded: names::fold(name, false),
+        }
+    }
+}
+
+impl Rules {
+    // Whether a subdirectory is left out of the scan. `volume` is the one the
+    // subdirectory lives on, a mount point's own. `crossing` is set for mount
+    // points and firmlinks, which lead onto another volume. `ignores` are the
+    // ignore files' rules in its parent.
+    pub(super) fn skip_dir(
+        &self,
+        node: &DirNode,
+        mount_point: bool,
+        crossing: bool,
+        volume: &Volume,
+        ignores: &Ignores,
+    ) -> bool {
+        let name = &*node.name;
+        let backup = || {
+            BACKUP_DIRS.iter().any(|dir| dir.as_bytes() == name) || (mount_point && volume.snapshot)
+        };
+        let system = || {
+            SYSTEM_DIRS.iter().any(|dir| dir.as_bytes() == name)
+                // The full path is only built when the name matches
+                || SYSTEM_PATHS.iter().any(|dir| {
+                    dir.rsplit('/').next().map(str::as_bytes) == Some(name)
+                        && node.c_path().as_bytes() == dir.as_bytes()
+                })
+        };
+        (self.one_volume && crossing)
+            || (self.backups && backup())
+            || (self.system && system())
+            || (self.hidden && hidden(name))
+            || (!self.names.is_empty() && self.named(name, volume.case_sensitive))
+            || self.skip_path(|| node.relative_path(), true, ignores)
+    }
+
+    // Whether any files may be left out, which takes their names
+    pub fn skips_files(&self) -> bool {
+        !self.regexes.is_empty() || self.ignore_files() || self.hidden
+    }
+
+    // Whether any ignore files are read
+    pub fn ignore_files(&self) -> bool {
+        self.gitignore || self.duignore
+    }
+
+    // Whether a file listed in `dir` is left out of the total
+    pub(super) fn skip_file(&self, dir: &DirNode, name: &[u8], ignores: &Ignores) -> bool {
+        if self.hidden && hidden(name) {
+            return true;
+        }
+        let path = || {
+            let mut path = dir.relative_path();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(name);
+            path
+        };
+        self.skip_path(path, false, ignores)
+    }
+
+    // Whether a path below the root is left out by --exclude-regex or an
+    // ignore file, only built when there's something to match it with
+    fn skip_path(
+        &self,
+        relative_path: impl FnOnce() -> Vec<u8>,
+        is_dir: bool,
+        ignores: &Ignores,
+    ) -> bool {
+        if self.regexes.is_empty() && ignores.is_empty() {
+            return false;
+        }
+        let path = relative_path();
+        ignores.skip(&path, is_dir) || self.matches(path)
+    }
+
+    // Whether a path below the root matches a pattern given with
+    // --exclude-regex
+    fn matches(&self, relative_path: Vec<u8>) -> bool {
+        // Names never hold a NUL
+        let Ok(path) = CString::new(relative_path) else {
+            return false;
+        };
+        self.regexes.iter().any(|regex| regex.is_match(&path))
+    }
+
+    // Whether a name is one given with --exclude, by the volume's rules
+    fn named(&self, name: &[u8], case_sensitive: bool) -> bool {
+        // Names given on the command line are UTF-8, so others can't match
+        let Ok(name) = std::str::from_utf8(name) else {
+            return false;
+        };
+        let name = names::fold(name, case_sensitive);
+        self.names.iter().any(|given| {
+            let given = if case_sensitive {
+                &given.exact
+            } else {
+                &given.folded
+            };
+            *given == name
+        })
+    }
+}
+
+// Whether Finder hides a name by default, as it does dotfiles. Those it
+// hides with the hidden flag, like ~/Library, are still counted.
+fn hidden(name: &[u8]) -> bool {
+    name.first() == Some(&b'.')
+}
@@ -0,0 +1,165 @@
+use super::format_size;
+use super::sync::Mutex;
+use std::cmp::Reverse;
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+
+// Lines in the report, smaller extensions are summed into one more
+const SHOWN: usize = 25;
+
+// Bytes read to tell what a file without an extension holds
+const SNIFF_BYTES: usize = 512;
+
+// Files under one extension, or one kind of content for files without
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    files: u64,
+    blocks: i64,
+}
+
+// Keyed by lowercased extension with its dot, or by what the contents look
+// like for files without one
+type Table = Vec<(Box<[u8]>, Usage)>;
+
+// Blocks counted for each file extension, for --by-extension
+#[derive(Default)]
+pub struct Extensions {
+    usage: Mutex<Table>,
+}
+
+// One directory's files, added to the scan's once it's been processed.
+// Directories hold few extensions, so a list searched in order does.
+#[derive(Default)]
+pub struct Counts {
+    usage: Table,
+}
+
+impl Counts {
+    // A file named `name` adding `blocks` to the total. Without an
+    // extension, its first bytes are read from `path` when `sniff` is set.
+    pub fn add(&mut self, name: &[u8], blocks: i64, sniff: bool, path: impl FnOnce() -> Vec<u8>) {
+        let key = match extension(name) {
+            Some(extension) => extension,
+            None if sniff => content_kind(&path()).as_bytes(),
+            None => b"no extension",
+        };
+        add(&mut self.usage, key, 1, blocks);
+    }
+}
+
+impl Extensions {
+    pub fn record(&self, counts: Counts) {
+        let mut usage = self.usage.lock();
+        for (key, counted) in counts.usage {
+            add(&mut usage, &key, counted.files, counted.blocks);
+        }
+    }
+
+    // Largest first, the long tail summed into one line
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let mut usage = std::mem::take(&mut *self.usage.lock());
+        usage.sort_unstable_by_key(|(key, usage)| (Reverse(usage.blocks), key.clone()));
+        writeln!(out, "by extension:")?;
+        for (key, usage) in usage.iter().take(SHOWN) {
+            print_line(&String::from_utf8_lossy(key), usage, out)?;
+        }
+        if usage.len() > SHOWN {
+            let rest = usage[SHOWN..]
+                .iter()
+                .fold(Usage::default(), |sum, (_, usage)| Usage {
+                    files: sum.files + usage.files,
+                    blocks: sum.blocks + usage.blocks,
+                });
+            print_line(&format!("{} others", usage.len() - SHOWN), &rest, out)?;
+        }
+        Ok(())
+    }
+}
+
+fn print_line(label: &str, usage: &Usage, out: &mut String) -> fmt::Result {
+    let noun = if usage.files == 1 { "file" } else { "files" };
+    writeln!(
+        out,
+        "  {}\t{} {}\t{}",
+        format_size(usage.blocks),
+        usage.files,
+        noun,
+        label
+    )
+}
+
+fn add(table: &mut Table, key: &[u8], files: u64, blocks: i64) {
+    let usage = match table
+        .iter_mut()
+        .position(|(known, _)| known.eq_ignore_ascii_case(key))
+    {
+        Some(i) => &mut table[i].1,
+        None => {
+            let key = match key.first() {
+                Some(b'.') => key.to_ascii_lowercase().into(),
+                _ => key.into(),
+            };
+            table.push((key, Usage::default()));
+            &mut table.last_mut().unwrap().1
+        }
+    };
+    usage.files += files;
+    usage.blocks += blocks;
+}
+
+// ".mp4" for "clip.MP4". Dotfiles like ".zshrc" have none, and neither do
+// names whose last dot is followed by something too long or with spaces in
+// it to be an extension.
+pub(super) fn extension(name: &[u8]) -> Option<&[u8]> {
+    let dot = name
+        .iter()
+        .rposition(|&b| b == b'.')
+        .filter(|&dot| dot > 0)?;
+    let extension = &name[dot..];
+    let plausible = (2..=12).contains(&extension.len())
+        && extension[1..]
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    plausible.then_some(extension)
+}
+
+// What a file without an extension holds, going by its first bytes
+fn content_kind(path: &[u8]) -> &'static str {
+    let mut head = [0u8; SNIFF_BYTES];
+    let read = File::open(OsStr::from_bytes(path)).and_then(|mut file| {
+        let mut read = 0;
+        while read < head.len() {
+            match file.read(&mut head[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    });
+    let Ok(read) = read else {
+        return "no extension, unreadable";
+    };
+    let head = &head[..read];
+    match head {
+        [] => "no extension, empty",
+        [0xcf, 0xfa, 0xed, 0xfe, ..]
+        | [0xce, 0xfa, 0xed, 0xfe, ..]
+        | [0xca, 0xfe, 0xba, 0xbe, ..] => "no extension, Mach-O binary",
+        [0x7f, b'E', b'L', b'F', ..] => "no extension, ELF binary",
+        [b'#', b'!', ..] => "no extension, script",
+        [b'P', b'K', 3, 4, ..] => "no extension, zip archive",
+        [0x1f, 0x8b, ..] => "no extension, gzip",
+        // Loose git objects are zlib streams
+        [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => "no extension, zlib data",
+        _ if head.starts_with(b"SQLite format 3\0") => "no extension, SQLite database",
+        _ if head.starts_with(b"bplist") => "no extension, binary plist",
+        _ if head.starts_with(b"%PDF") => "no extension, PDF",
+        _ if head.starts_with(b"\x89PNG") => "no extension, PNG image",
+        [0xff, 0xd8, 0xff, ..] => "no extension, JPEG image",
+        _ if !head.contains(&0) => "no extension, text",
+        _ => "no extension, data",
+    }
+}
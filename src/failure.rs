@@ -0,0 +1,38 @@
+// Why a subcommand failed, when it's the work it does around its scans
+// rather than a scan: the library knows nothing of these
+use dumac::DumacError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Failure {
+    // A scan, or a path the subcommand needed, failed as it would in one
+    Dumac(DumacError),
+    // An APFS snapshot couldn't be mounted
+    Snapshot(String),
+    // The history database couldn't be located, read or written
+    History(String),
+    // A launchd agent couldn't be written, loaded or removed
+    Schedule(String),
+    // A scan on another host couldn't be run or its results read
+    Remote(String),
+}
+
+impl From<DumacError> for Failure {
+    fn from(e: DumacError) -> Self {
+        Failure::Dumac(e)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Dumac(e) => e.fmt(f),
+            Failure::Snapshot(message)
+            | Failure::History(message)
+            | Failure::Schedule(message)
+            | Failure::Remote(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
@@ -0,0 +1,144 @@
+use super::tally::Tally;
+use super::{
+    entry_error, errno, is_dot_or_dotdot, profile, visit, xattrs, DirInfo, FileInfo, ListError,
+    Scanner, SubdirInfo,
+};
+use std::ffi::CStr;
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+
+// List a directory with readdir and one fstatat per entry. Slower than
+// getattrlistbulk, but relies only on what every filesystem implements.
+// Entries that can't be read are reported and counted as errors. `dirfd` is
+// the directory at `c_path`.
+pub fn get_dir_info(
+    scanner: &Scanner,
+    c_path: &CStr,
+    dirfd: OwnedFd,
+) -> Result<DirInfo, ListError> {
+    // Subdirectories on another device than this one are mount points
+    let mut parent: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(dirfd.as_raw_fd(), &mut parent) } != 0 {
+        return Err(ListError::new(c_path, errno()));
+    }
+
+    // Owns dirfd from here on, closedir closes both
+    let dirfd = dirfd.into_raw_fd();
+    let dir = unsafe { libc::fdopendir(dirfd) };
+    if dir.is_null() {
+        let error = ListError::new(c_path, errno());
+        unsafe { libc::close(dirfd) };
+        return Err(error);
+    }
+
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    let mut tally = Tally::default();
+    let result = loop {
+        // readdir returns null both at the end and on error, errno tells them apart
+        unsafe { *libc::__error() = 0 };
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            match errno() {
+                0 => break Ok(()),
+                e => break Err(ListError::new(c_path, e)),
+            }
+        }
+
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if is_dot_or_dotdot(name.to_bytes()) {
+            continue;
+        }
+        profile::ENTRIES.add(1);
+
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatat(dirfd, name.as_ptr(), &mut st, libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+            entry_error(scanner, c_path, name.to_bytes(), errno());
+            continue;
+        }
+
+        let xattr_bytes = match st.st_mode & libc::S_IFMT {
+            libc::S_IFREG | libc::S_IFLNK if scanner.options.xattrs => {
+                xattrs::size(dirfd, name.to_bytes())
+            }
+            _ => 0,
+        };
+
+        let owner = scanner.reads_owners().then_some((st.st_uid, st.st_gid));
+        let dated = scanner
+            .dates_files()
+            .then_some(match scanner.options.birthtime {
+                true => st.st_birthtime,
+                false => st.st_mtime,
+            });
+
+        let kind = match st.st_mode & libc::S_IFMT {
+            libc::S_IFLNK => visit::Kind::Symlink,
+            libc::S_IFREG => visit::Kind::File,
+            _ => visit::Kind::Other,
+        };
+        let entry = scanner.keep_names().then(|| (name.to_bytes().into(), kind));
+
+        if st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            tally.add(
+                st.st_mode & libc::S_IFMT == libc::S_IFLNK,
+                st.st_nlink as u32,
+                st.st_size,
+            );
+        }
+
+        match st.st_mode & libc::S_IFMT {
+            libc::S_IFDIR => subdirs.push(SubdirInfo {
+                name: name.to_bytes().into(),
+                // Unknown without reading it, so it is scheduled as small
+                entries: 0,
+                mount_point: st.st_dev != parent.st_dev,
+                dataless: false,
+                dev: st.st_dev as u32,
+                inode: st.st_ino,
+                mtime: Some((st.st_mtime, st.st_mtime_nsec)),
+            }),
+            // Symlink - count the link itself as 1 (du default behavior)
+            libc::S_IFLNK => {
+                if scanner.options.broken_symlinks {
+                    scanner
+                        .broken_symlinks
+                        .check(dirfd, c_path, name.to_bytes());
+                }
+                files.push(FileInfo {
+                    blocks: 1,
+                    dev: st.st_dev as u32,
+                    inode: st.st_ino,
+                    nlink: st.st_nlink as u32,
+                    clone: None,
+                    flags: 0,
+                    data_length: Some(st.st_size),
+                    xattr_bytes,
+                    owner,
+                    dated,
+                    entry,
+                });
+            }
+            _ => files.push(FileInfo {
+                blocks: st.st_blocks,
+                dev: st.st_dev as u32,
+                inode: st.st_ino,
+                nlink: st.st_nlink as u32,
+                clone: None,
+                flags: 0,
+                data_length: Some(st.st_size),
+                xattr_bytes,
+                owner,
+                dated,
+                entry,
+            }),
+        }
+    };
+    unsafe { libc::closedir(dir) };
+    profile::DIRS.add(1);
+
+    result.map(|()| DirInfo {
+        files,
+        subdirs,
+        tally,
+    })
+}
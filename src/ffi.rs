@@ -0,0 +1,151 @@
+// C interface for embedding the scanner in Swift and Objective-C apps.
+// include/dumac.h is generated from this file with cbindgen.
+use super::{scan_streaming, DumacError, ScanOptions};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// What to count. Zeroed options give a plain du-style scan on the global
+// pool.
+#[repr(C)]
+pub struct DumacOptions {
+    // 0 for the default, one per CPU
+    pub threads: u32,
+    pub clones: bool,
+    pub compression: bool,
+    pub cloud: bool,
+    pub xattrs: bool,
+    pub exclude_backups: bool,
+    pub exclude_system: bool,
+    // NULL, or a token from dumac_cancel_new to stop the scan from another
+    // thread
+    pub cancel: *const DumacCancel,
+}
+
+// Cancels a scan in progress, e.g. from a UI's stop button while the scan
+// runs on a background queue
+pub struct DumacCancel(Arc<AtomicBool>);
+
+#[no_mangle]
+pub extern "C" fn dumac_cancel_new() -> *mut DumacCancel {
+    Box::into_raw(Box::new(DumacCancel(Arc::new(AtomicBool::new(false)))))
+}
+
+// Safe to call from any thread while dumac_scan runs. The scan returns soon
+// after with `cancelled` set and a partial total.
+//
+// Safety: `cancel` must come from dumac_cancel_new and not yet be freed.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn dumac_cancel(cancel: *const DumacCancel) {
+    if let Some(cancel) = cancel.as_ref() {
+        cancel.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// Safety: `cancel` must come from dumac_cancel_new, and no scan may still be
+// using it.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn dumac_cancel_free(cancel: *mut DumacCancel) {
+    if !cancel.is_null() {
+        drop(Box::from_raw(cancel));
+    }
+}
+
+// What a scan found, see ScanResult
+#[repr(C)]
+pub struct DumacResult {
+    pub blocks: i64,
+    pub apparent_bytes: i64,
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub hardlinks: u64,
+    pub errors: u64,
+    pub cancelled: bool,
+}
+
+// Called on the scanning thread with each directory's path and total as its
+// subtree completes. Return false to cancel the scan, which also cancels
+// the options' token if there is one.
+pub type DumacDirCallback =
+    Option<unsafe extern "C" fn(path: *const c_char, blocks: i64, context: *mut c_void) -> bool>;
+
+// Scan `path`, calling `callback` (which may be NULL) for each completed
+// directory and filling in `result`. `options` may be NULL for the defaults.
+// Returns 0, or an errno for a root that couldn't be scanned: EINVAL for a
+// bad path and EIO when the scan couldn't start.
+//
+// Safety: `path` must be a NUL-terminated string, `options` and `result`
+// must be NULL or valid, as must the options' cancel token, and `context` is
+// passed to `callback` untouched.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn dumac_scan(
+    path: *const c_char,
+    options: *const DumacOptions,
+    callback: DumacDirCallback,
+    context: *mut c_void,
+    result: *mut DumacResult,
+) -> c_int {
+    if path.is_null() {
+        return libc::EINVAL;
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return libc::EINVAL;
+    };
+
+    let options = options.as_ref();
+    let cancel = match options.and_then(|options| options.cancel.as_ref()) {
+        Some(token) => token.0.clone(),
+        None => Arc::new(AtomicBool::new(false)),
+    };
+    let mut scan_options = ScanOptions::new().cancel_on(cancel.clone());
+    if let Some(options) = options {
+        if options.threads > 0 {
+            scan_options = scan_options.threads(options.threads as usize);
+        }
+        scan_options = scan_options
+            .clones(options.clones)
+            .compression(options.compression)
+            .cloud(options.cloud)
+            .xattrs(options.xattrs)
+            .exclude_backups(options.exclude_backups)
+            .exclude_system(options.exclude_system);
+    }
+
+    let mut stream = scan_streaming(path, &scan_options);
+    if let Some(callback) = callback {
+        for dir in stream.by_ref() {
+            // File names can't hold NUL, so neither can the path
+            let dir_path = CString::new(dir.path.as_os_str().as_bytes()).unwrap();
+            if !callback(dir_path.as_ptr(), dir.blocks, context) {
+                cancel.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+
+    match stream.finish() {
+        Ok(scanned) => {
+            if let Some(result) = result.as_mut() {
+                *result = DumacResult {
+                    blocks: scanned.blocks,
+                    apparent_bytes: scanned.apparent_bytes,
+                    files: scanned.files,
+                    dirs: scanned.dirs,
+                    symlinks: scanned.symlinks,
+                    hardlinks: scanned.hardlinks,
+                    errors: scanned.errors,
+                    cancelled: scanned.cancelled,
+                };
+            }
+            0
+        }
+        Err(DumacError::Io { errno, .. } | DumacError::Protected { errno, .. }) => errno,
+        Err(DumacError::InvalidPath(_)) => libc::EINVAL,
+        Err(DumacError::Cache(_) | DumacError::Threads(_)) => libc::EIO,
+    }
+}
@@ -0,0 +1,193 @@
+// Which files count towards totals and reports. Unlike exclusions, filters
+// never keep a directory from being scanned, they only pick out the files in
+// it that are added up.
+use super::extensions::extension;
+use super::patterns::glob_match;
+use super::{DirNode, FileInfo};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    // --include-only, of which a file must match one
+    include: Vec<Glob>,
+    // --min-file-size and --max-file-size, in bytes
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+    // --newer-than and --older-than, how recently a file must be dated or
+    // how long ago, from when the scan started
+    newer_than: Option<Duration>,
+    older_than: Option<Duration>,
+    // When the scan started, as seconds since the epoch
+    now: i64,
+    // --user, the uid whose files count
+    user: Option<u32>,
+    // --ext and --no-ext, lowercased with their dots, of which a file's
+    // extension must be one of the first and none of the second
+    extensions: Vec<Box<[u8]>>,
+    skip_extensions: Vec<Box<[u8]>>,
+}
+
+// A pattern given with --include-only. Like a .gitignore line, one without
+// a `/` is matched against names and one with it against the path from the
+// scanned directory.
+#[derive(Debug, Clone)]
+struct Glob {
+    glob: Vec<u8>,
+    anchored: bool,
+}
+
+impl Filter {
+    // --include-only, once per pattern
+    pub fn include(&mut self, pattern: &str) {
+        let glob = pattern.trim_start_matches('/');
+        self.include.push(Glob {
+            glob: glob.as_bytes().to_vec(),
+            anchored: pattern.contains('/'),
+        });
+    }
+
+    // --min-file-size and --max-file-size, each inclusive
+    pub fn size(&mut self, min: Option<u64>, max: Option<u64>) {
+        let bytes = |size: u64| i64::try_from(size).unwrap_or(i64::MAX);
+        self.min_size = min.map(bytes).or(self.min_size);
+        self.max_size = max.map(bytes).or(self.max_size);
+    }
+
+    // --newer-than
+    pub fn newer_than(&mut self, age: Duration) {
+        self.newer_than = Some(age);
+    }
+
+    // --older-than
+    pub fn older_than(&mut self, age: Duration) {
+        self.older_than = Some(age);
+    }
+
+    // Date files from now, as a scan starts. Options outlive their scans,
+    // as with --watch and the daemon, so ages can't be fixed any earlier.
+    pub(super) fn start(&mut self) {
+        self.now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+    }
+
+    // --ext, once per extension
+    pub fn extension(&mut self, extension: &str) {
+        self.extensions.push(dotted(extension));
+    }
+
+    // --no-ext, once per extension
+    pub fn skip_extension(&mut self, extension: &str) {
+        self.skip_extensions.push(dotted(extension));
+    }
+
+    // --user
+    pub fn user(&mut self, uid: u32) {
+        self.user = Some(uid);
+    }
+
+    // Whether any files may be left out
+    pub fn is_active(&self) -> bool {
+        self.needs_names()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.by_date()
+            || self.by_owner()
+    }
+
+    // Whether deciding takes each file's date
+    pub fn by_date(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+
+    // Whether deciding takes each file's owner
+    pub fn by_owner(&self) -> bool {
+        self.user.is_some()
+    }
+
+    // Whether deciding takes each file's name
+    pub fn needs_names(&self) -> bool {
+        !self.include.is_empty() || !self.extensions.is_empty() || !self.skip_extensions.is_empty()
+    }
+
+    // Whether a file listed in `dir` counts
+    pub(super) fn counts(&self, file: &FileInfo, dir: &DirNode) -> bool {
+        // Sized as ls shows them, which is what a size band is thought of in,
+        // rather than by what they take on disk
+        let size = file.data_length.unwrap_or(file.blocks * 512);
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        if self.by_date() {
+            // A file whose listing has no date can't be shown to be in range
+            let Some(dated) = file.dated else {
+                return false;
+            };
+            if self
+                .newer_than
+                .is_some_and(|age| dated < self.before_now(age))
+                || self
+                    .older_than
+                    .is_some_and(|age| dated >= self.before_now(age))
+            {
+                return false;
+            }
+        }
+        // Nor one whose listing has no owner to be the user
+        if self.user.is_some() && file.owner.map(|(owner, _)| owner) != self.user {
+            return false;
+        }
+        let Some((name, _)) = &file.entry else {
+            return true;
+        };
+        if !self.extensions.is_empty() || !self.skip_extensions.is_empty() {
+            let extension = extension(name);
+            let listed = |extensions: &[Box<[u8]>]| {
+                extension.is_some_and(|extension| {
+                    extensions
+                        .iter()
+                        .any(|listed| listed.eq_ignore_ascii_case(extension))
+                })
+            };
+            if (!self.extensions.is_empty() && !listed(&self.extensions))
+                || listed(&self.skip_extensions)
+            {
+                return false;
+            }
+        }
+        self.include.is_empty() || self.included(dir, name)
+    }
+
+    // Seconds since the epoch `age` before the scan started
+    fn before_now(&self, age: Duration) -> i64 {
+        self.now
+            .saturating_sub(age.as_secs().try_into().unwrap_or(i64::MAX))
+    }
+
+    fn included(&self, dir: &DirNode, name: &[u8]) -> bool {
+        // The path is only built for patterns that need it
+        let mut path = None;
+        self.include.iter().any(|include| match include.anchored {
+            false => glob_match(&include.glob, name),
+            true => {
+                let path = path.get_or_insert_with(|| {
+                    let mut path = dir.relative_path();
+                    if !path.is_empty() {
+                        path.push(b'/');
+                    }
+                    path.extend_from_slice(name);
+                    path
+                });
+                glob_match(&include.glob, path)
+            }
+        })
+    }
+}
+
+// ".mp4" for "mp4", ".MP4" or ".mp4", as --by-extension keys them
+fn dotted(extension: &str) -> Box<[u8]> {
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    format!(".{}", extension).into_bytes().into()
+}
@@ -0,0 +1,47 @@
+use super::sync::Mutex;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::sync::LazyLock;
+
+// Firmlinked paths on the system volume and where they lead on the data volume
+const FIRMLINKS_FILE: &str = "/usr/share/firmlinks";
+const DATA_VOLUME: &str = "/System/Volumes/Data";
+
+// Directories reachable both through a firmlink (/Users) and through the data
+// volume's mount point (/System/Volumes/Data/Users), as (device, inode)
+static TARGETS: LazyLock<HashSet<(u32, u64)>> = LazyLock::new(load_targets);
+
+// Targets already counted through one of their paths during a scan
+#[derive(Default)]
+pub struct Claims(Mutex<HashSet<(u32, u64)>>);
+
+// Each line is the firmlink's path, a tab, and the target's path relative to
+// the data volume
+fn load_targets() -> HashSet<(u32, u64)> {
+    let Ok(contents) = std::fs::read_to_string(FIRMLINKS_FILE) else {
+        return HashSet::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter_map(|(_, target)| {
+            let path = CString::new(format!("{}/{}", DATA_VOLUME, target)).ok()?;
+            let mut st: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::stat(path.as_ptr(), &mut st) } != 0 {
+                return None;
+            }
+            Some((st.st_dev as u32, st.st_ino))
+        })
+        .collect()
+}
+
+impl Claims {
+    // Whether a directory should be counted here. Firmlink targets are counted
+    // through whichever path reaches them first and skipped on the other.
+    pub fn claim(&self, dev: u32, inode: u64) -> bool {
+        if !TARGETS.contains(&(dev, inode)) {
+            return true;
+        }
+        self.0.lock().insert((dev, inode))
+    }
+}
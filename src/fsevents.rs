@@ -0,0 +1,206 @@
+use std::ffi::{c_char, c_void, CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// CoreFoundation and FSEvents types and constants not in libc crate
+// (<CoreServices/FSEvents.h>)
+type CFAllocatorRef = *const c_void;
+type CFArrayRef = *const c_void;
+type CFStringRef = *const c_void;
+type CFIndex = isize;
+type FSEventStreamRef = *mut c_void;
+type DispatchQueue = *mut c_void;
+type FSEventStreamCallback = extern "C" fn(
+    stream: FSEventStreamRef,
+    info: *mut c_void,
+    count: usize,
+    paths: *mut c_void,
+    flags: *const u32,
+    ids: *const u64,
+);
+
+#[repr(C)]
+struct CFArrayCallBacks {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct FSEventStreamContext {
+    version: CFIndex,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+const K_CF_STRING_ENCODING_UTF8: u32 = 0x08000100;
+const K_FS_EVENT_STREAM_EVENT_ID_SINCE_NOW: u64 = u64::MAX;
+const K_FS_EVENT_STREAM_CREATE_FLAG_NO_DEFER: u32 = 0x02;
+const K_FS_EVENT_STREAM_CREATE_FLAG_WATCH_ROOT: u32 = 0x04;
+const K_FS_EVENT_STREAM_EVENT_FLAG_MUST_SCAN_SUB_DIRS: u32 = 0x01;
+const K_FS_EVENT_STREAM_EVENT_FLAG_USER_DROPPED: u32 = 0x02;
+const K_FS_EVENT_STREAM_EVENT_FLAG_KERNEL_DROPPED: u32 = 0x04;
+const K_FS_EVENT_STREAM_EVENT_FLAG_ROOT_CHANGED: u32 = 0x20;
+
+#[cfg_attr(target_os = "macos", link(name = "CoreServices", kind = "framework"))]
+extern "C" {
+    static kCFTypeArrayCallBacks: CFArrayCallBacks;
+    fn CFStringCreateWithCString(
+        allocator: CFAllocatorRef,
+        string: *const c_char,
+        encoding: u32,
+    ) -> CFStringRef;
+    fn CFArrayCreate(
+        allocator: CFAllocatorRef,
+        values: *const *const c_void,
+        count: CFIndex,
+        callbacks: *const CFArrayCallBacks,
+    ) -> CFArrayRef;
+    fn CFRelease(object: *const c_void);
+    fn FSEventStreamCreate(
+        allocator: CFAllocatorRef,
+        callback: FSEventStreamCallback,
+        context: *const FSEventStreamContext,
+        paths: CFArrayRef,
+        since: u64,
+        latency: f64,
+        flags: u32,
+    ) -> FSEventStreamRef;
+    fn FSEventStreamSetDispatchQueue(stream: FSEventStreamRef, queue: DispatchQueue);
+    fn FSEventStreamStart(stream: FSEventStreamRef) -> u8;
+    fn FSEventStreamStop(stream: FSEventStreamRef);
+    fn FSEventStreamInvalidate(stream: FSEventStreamRef);
+    fn FSEventStreamRelease(stream: FSEventStreamRef);
+    fn dispatch_queue_create(label: *const c_char, attr: *const c_void) -> DispatchQueue;
+    fn dispatch_release(object: DispatchQueue);
+}
+
+// A directory FSEvents says has changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    // Events below it were coalesced or dropped, so its whole subtree has to
+    // be listed again
+    pub recursive: bool,
+}
+
+// Changes under a directory, delivered in batches from a dispatch queue
+// until dropped
+pub struct EventStream {
+    stream: FSEventStreamRef,
+    queue: DispatchQueue,
+    sender: *mut mpsc::Sender<Vec<Change>>,
+}
+
+impl EventStream {
+    // Batches are at least `latency` apart, so bursts of changes arrive
+    // together
+    pub fn start(
+        path: &Path,
+        latency: Duration,
+    ) -> Result<(EventStream, mpsc::Receiver<Vec<Change>>), String> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| format!("{}: Invalid path", path.display()))?;
+        let (sender, changes) = mpsc::channel();
+        let sender = Box::into_raw(Box::new(sender));
+        let context = FSEventStreamContext {
+            version: 0,
+            info: sender as *mut c_void,
+            retain: ptr::null(),
+            release: ptr::null(),
+            copy_description: ptr::null(),
+        };
+
+        unsafe {
+            let cf_path =
+                CFStringCreateWithCString(ptr::null(), c_path.as_ptr(), K_CF_STRING_ENCODING_UTF8);
+            let paths = CFArrayCreate(ptr::null(), &cf_path, 1, &kCFTypeArrayCallBacks);
+            let stream = FSEventStreamCreate(
+                ptr::null(),
+                callback,
+                &context,
+                paths,
+                K_FS_EVENT_STREAM_EVENT_ID_SINCE_NOW,
+                latency.as_secs_f64(),
+                K_FS_EVENT_STREAM_CREATE_FLAG_NO_DEFER | K_FS_EVENT_STREAM_CREATE_FLAG_WATCH_ROOT,
+            );
+            CFRelease(paths);
+            CFRelease(cf_path);
+            if stream.is_null() {
+                drop(Box::from_raw(sender));
+                return Err(format!("{}: cannot watch for changes", path.display()));
+            }
+
+            let queue = dispatch_queue_create(c"dumac.fsevents".as_ptr(), ptr::null());
+            FSEventStreamSetDispatchQueue(stream, queue);
+            if FSEventStreamStart(stream) == 0 {
+                FSEventStreamInvalidate(stream);
+                FSEventStreamRelease(stream);
+                dispatch_release(queue);
+                drop(Box::from_raw(sender));
+                return Err(format!("{}: cannot watch for changes", path.display()));
+            }
+            let events = EventStream {
+                stream,
+                queue,
+                sender,
+            };
+            Ok((events, changes))
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        // Once invalidated the callback won't run again, so the sender can go
+        unsafe {
+            FSEventStreamStop(self.stream);
+            FSEventStreamInvalidate(self.stream);
+            FSEventStreamRelease(self.stream);
+            dispatch_release(self.queue);
+            drop(Box::from_raw(self.sender));
+        }
+    }
+}
+
+extern "C" fn callback(
+    _stream: FSEventStreamRef,
+    info: *mut c_void,
+    count: usize,
+    paths: *mut c_void,
+    flags: *const u32,
+    _ids: *const u64,
+) {
+    let recursive_flags = K_FS_EVENT_STREAM_EVENT_FLAG_MUST_SCAN_SUB_DIRS
+        | K_FS_EVENT_STREAM_EVENT_FLAG_USER_DROPPED
+        | K_FS_EVENT_STREAM_EVENT_FLAG_KERNEL_DROPPED
+        | K_FS_EVENT_STREAM_EVENT_FLAG_ROOT_CHANGED;
+    // Without kFSEventStreamCreateFlagUseCFTypes the paths are C strings
+    let (paths, flags) = unsafe {
+        (
+            std::slice::from_raw_parts(paths as *const *const c_char, count),
+            std::slice::from_raw_parts(flags, count),
+        )
+    };
+    let changes = paths
+        .iter()
+        .zip(flags)
+        .map(|(&path, &flags)| {
+            let path = unsafe { CStr::from_ptr(path) }.to_bytes();
+            // Directories are reported with a trailing slash
+            let path = path
+                .strip_suffix(b"/")
+                .filter(|path| !path.is_empty())
+                .unwrap_or(path);
+            Change {
+                path: PathBuf::from(OsStr::from_bytes(path)),
+                recursive: flags & recursive_flags != 0,
+            }
+        })
+        .collect();
+    let sender = unsafe { &*(info as *const mpsc::Sender<Vec<Change>>) };
+    let _ = sender.send(changes);
+}
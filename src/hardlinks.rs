@@ -0,0 +1,81 @@
+use super::format_size;
+use super::sync::Mutex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+// Files with more than one link and the paths the scan reached them by, for
+// --report-hardlinks
+#[derive(Default)]
+pub struct Groups {
+    inodes: Mutex<HashMap<(u32, u64), Group>>,
+}
+
+struct Group {
+    blocks: i64,
+    nlink: u32,
+    paths: Vec<Vec<u8>>,
+}
+
+impl Group {
+    // What copying each path found would take on top of the one file
+    fn saved(&self) -> i64 {
+        self.blocks * (self.paths.len() as i64 - 1)
+    }
+}
+
+impl Groups {
+    // Note a path to a hardlinked inode
+    pub fn record(&self, dev: u32, inode: u64, nlink: u32, blocks: i64, path: Vec<u8>) {
+        self.inodes
+            .lock()
+            .entry((dev, inode))
+            .or_insert_with(|| Group {
+                blocks,
+                nlink,
+                paths: Vec::new(),
+            })
+            .paths
+            .push(path);
+    }
+
+    // Most space saved first. An inode with links outside the scan says how
+    // many of its links were found.
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let inodes = std::mem::take(&mut *self.inodes.lock());
+        let mut groups: Vec<Group> = inodes.into_values().collect();
+        for group in &mut groups {
+            group.paths.sort_unstable();
+        }
+        groups.sort_unstable_by(|a, b| {
+            (Reverse(a.saved()), &a.paths).cmp(&(Reverse(b.saved()), &b.paths))
+        });
+        let paths: usize = groups.iter().map(|group| group.paths.len()).sum();
+        let saved: i64 = groups.iter().map(Group::saved).sum();
+        writeln!(
+            out,
+            "hardlinks: {} files by {} paths, {} saved by linking",
+            groups.len(),
+            paths,
+            format_size(saved)
+        )?;
+        for group in &groups {
+            let found = group.paths.len();
+            let found = match (found as u32) < group.nlink {
+                true => format!(", {} of {} links found", found, group.nlink),
+                false => String::new(),
+            };
+            writeln!(
+                out,
+                "  {}\tsaved, {} each{}",
+                format_size(group.saved()),
+                format_size(group.blocks),
+                found
+            )?;
+            for path in &group.paths {
+                writeln!(out, "    {}", String::from_utf8_lossy(path))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+// `dumac history`: each scan's directory totals, down to a depth, appended
+// to a database, and how one directory's total has changed across them
+use super::failure::Failure;
+use dumac::internal::{read_path, read_u64, strerror, write_path, Mutex};
+use dumac::pool::ThreadPool;
+use dumac::stream::DirResult;
+use dumac::{format_size, DumacError, ScanOptions, Scanner};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bumped whenever the file layout changes
+const MAGIC: &[u8; 8] = b"dumach1\n";
+
+// Directories below the scanned one that are recorded, when not given
+pub const DEFAULT_DEPTH: usize = 2;
+
+// Width of the longest bar in the printed history
+const BAR_WIDTH: usize = 40;
+
+// One directory's total at one scan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    // Seconds since the epoch
+    pub time: i64,
+    pub blocks: i64,
+}
+
+// $XDG_DATA_HOME/dumac/history, or ~/.local/share/dumac/history
+pub fn database_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+    Some(base.join("dumac").join("history"))
+}
+
+// Scan `root_dir` and append the totals of it and of the directories up to
+// `depth` levels below it to `database`, under their resolved paths.
+// Returns the resolved root.
+pub fn record(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    depth: usize,
+    database: &Path,
+) -> Result<PathBuf, Failure> {
+    let root = fs::canonicalize(root_dir).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: root_dir.into(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let mut scanner = Scanner::new(options.clone());
+    let results = found.clone();
+    let top = root.clone();
+    scanner.on_result(move |dir: DirResult| {
+        let shallow = dir
+            .path
+            .strip_prefix(&top)
+            .is_ok_and(|relative| relative.components().count() <= depth);
+        if shallow {
+            results.lock().push(dir);
+        }
+    });
+    let scan_root = root
+        .to_str()
+        .ok_or_else(|| DumacError::InvalidPath(root.to_string_lossy().into_owned()))?;
+    pool.install(|| scanner.calculate_size(scan_root.to_string()))?;
+    scanner.report();
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let dirs = std::mem::take(&mut *found.lock());
+    let mut scan = Vec::new();
+    scan.extend_from_slice(&time.to_le_bytes());
+    scan.extend_from_slice(&(dirs.len() as u64).to_le_bytes());
+    for dir in &dirs {
+        write_path(&mut scan, &dir.path).map_err(|e| history_error(database, e))?;
+        scan.extend_from_slice(&dir.blocks.to_le_bytes());
+    }
+    append(database, &scan).map_err(|e| history_error(database, e))?;
+    Ok(root)
+}
+
+// One scan's records in a single write, so scans recorded at the same time
+// don't interleave
+fn append(database: &Path, scan: &[u8]) -> io::Result<()> {
+    if let Some(dir) = database.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(database)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(MAGIC)?;
+    }
+    file.write_all(scan)
+}
+
+// Every recorded total of the directory at `path`, oldest first
+pub fn load(database: &Path, path: &Path) -> Result<Vec<Point>, Failure> {
+    let file = File::open(database).map_err(|e| history_error(database, e))?;
+    let mut input = BufReader::new(file);
+    let mut magic = [0u8; 8];
+    if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(Failure::History(format!(
+            "{}: not a dumac history database",
+            database.display()
+        )));
+    }
+    let mut points = Vec::new();
+    // A scan cut short while being appended ends the history
+    while let Ok(time) = read_u64(&mut input) {
+        if read_scan(&mut input, time as i64, path, &mut points).is_err() {
+            break;
+        }
+    }
+    points.sort_by_key(|point| point.time);
+    Ok(points)
+}
+
+// The directories of one scan made at `time`, keeping `path`'s total
+fn read_scan(
+    input: &mut impl Read,
+    time: i64,
+    path: &Path,
+    points: &mut Vec<Point>,
+) -> io::Result<()> {
+    let count = read_u64(input)?;
+    for _ in 0..count {
+        let dir = read_path(input)?;
+        let blocks = read_u64(input)? as i64;
+        if dir == path {
+            points.push(Point { time, blocks });
+        }
+    }
+    Ok(())
+}
+
+// Record a scan of `root_dir` unless `show` is set, then print how the
+// directory's total has changed over the recorded scans
+pub fn run(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    depth: usize,
+    show: bool,
+) -> Result<(), Failure> {
+    let database = database_path().ok_or_else(|| {
+        Failure::History("cannot find a directory for the history, set HOME".to_string())
+    })?;
+    let path = match show {
+        // The directory may be gone by now
+        true => fs::canonicalize(root_dir).unwrap_or_else(|_| PathBuf::from(root_dir)),
+        false => record(options, pool, root_dir, depth, &database)?,
+    };
+    let points = load(&database, &path)?;
+    if points.is_empty() {
+        return Err(Failure::History(format!(
+            "{}: no scans recorded, run `dumac history` on it first",
+            path.display()
+        )));
+    }
+    print_growth(&points);
+    Ok(())
+}
+
+fn print_growth(points: &[Point]) {
+    let largest = points.iter().map(|point| point.blocks).max().unwrap_or(0);
+    let mut previous = None;
+    for point in points {
+        let change = match previous {
+            Some(previous) => signed_size(point.blocks - previous),
+            None => String::new(),
+        };
+        let bar = match largest {
+            0 => 0,
+            largest => (point.blocks * BAR_WIDTH as i64 / largest) as usize,
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            local_time(point.time),
+            format_size(point.blocks),
+            change,
+            "#".repeat(bar)
+        );
+        previous = Some(point.blocks);
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let days = (last.time - first.time) as f64 / (24.0 * 60.0 * 60.0);
+    if points.len() > 1 && days > 0.0 {
+        let change = last.blocks - first.blocks;
+        println!(
+            "{} over {:.1} days, {} a day",
+            signed_size(change),
+            days,
+            signed_size((change as f64 / days) as i64)
+        );
+    }
+}
+
+fn signed_size(blocks: i64) -> String {
+    let sign = if blocks < 0 { '-' } else { '+' };
+    format!("{}{}", sign, format_size(blocks.abs()))
+}
+
+// As YYYY-MM-DD HH:MM in the local time zone
+fn local_time(time: i64) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = time as libc::time_t;
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return time.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+fn history_error(database: &Path, e: io::Error) -> Failure {
+    let message = match e.raw_os_error() {
+        Some(errno) => strerror(errno),
+        None => e.to_string(),
+    };
+    Failure::History(format!("{}: {}", database.display(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("tree");
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/one"), vec![1u8; 64 * 1024]).unwrap();
+        let database = temp_dir.path().join("history");
+        let pool = dumac::pool::build(2, || {}).unwrap();
+        let options = ScanOptions::new();
+
+        let resolved = record(&options, &pool, root.to_str().unwrap(), 1, &database).unwrap();
+        fs::write(root.join("a/two"), vec![1u8; 64 * 1024]).unwrap();
+        record(&options, &pool, root.to_str().unwrap(), 1, &database).unwrap();
+
+        // Each scan is kept, down to the depth asked for
+        let points = load(&database, &resolved.join("a")).unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(points[1].blocks > points[0].blocks);
+        assert_eq!(load(&database, &resolved).unwrap().len(), 2);
+        assert!(load(&database, &resolved.join("a/b")).unwrap().is_empty());
+    }
+}
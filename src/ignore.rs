@@ -0,0 +1,311 @@
+// Ignore files' rules, .gitignore for --respect-gitignore and .duignore
+// for --respect-duignore. Each directory's rules are those of its parent
+// plus its own files', read as the scan reaches it, so ignored directories
+// are never listed. Like git, a work tree's rules start at its top, with
+// .git/info/exclude, and stop at a nested one. .duignore files use the same
+// syntax and apply anywhere, from the scanned directory down.
+use super::patterns::glob_match;
+use super::{entry_path, DirNode, FileInfo, SubdirInfo};
+use std::ffi::{CStr, OsStr};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+const GITIGNORE: &[u8] = b".gitignore";
+const DUIGNORE: &[u8] = b".duignore";
+const GIT_DIR: &[u8] = b".git";
+// Rules kept out of the repository, which .gitignore files override
+const INFO_EXCLUDE: &[u8] = b".git/info/exclude";
+
+// The rules in effect in a directory
+#[derive(Clone, Default)]
+pub struct Ignores {
+    // Innermost file first, None outside a git work tree
+    git: Option<Arc<Level>>,
+    // Innermost file first, None above the first one
+    du: Option<Arc<Level>>,
+}
+
+// One directory's patterns, and where the directory is
+struct Level {
+    parent: Option<Arc<Level>>,
+    // From the directory down to the scanned one, for those above it
+    prefix: Vec<u8>,
+    // Components of a path below the scanned directory that lead to it, for
+    // those below
+    skip: usize,
+    patterns: Vec<Pattern>,
+}
+
+// A line of an ignore file
+struct Pattern {
+    glob: Vec<u8>,
+    // Starts with `!`, taking back what an earlier line ignored
+    negated: bool,
+    // Ends with `/`, matching only directories
+    dir_only: bool,
+    // Has a `/` before its end, so it's matched from the ignore file's
+    // directory rather than against names at any depth
+    anchored: bool,
+}
+
+impl Ignores {
+    // The rules of the work tree `root` is in, from its top down to the
+    // directory above `root`, whose own rules come with its listing
+    pub fn enclosing(root: &CStr) -> Self {
+        let Ok(root) = fs::canonicalize(OsStr::from_bytes(root.to_bytes())) else {
+            return Ignores::default();
+        };
+        let Some(top) = root
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.join(OsStr::from_bytes(GIT_DIR)).exists())
+        else {
+            return Ignores::default();
+        };
+        let dirs: Vec<&Path> = root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(top))
+            .collect();
+        let mut git = None;
+        for dir in dirs.into_iter().rev() {
+            let mut patterns = Vec::new();
+            if dir == top {
+                patterns = read(&dir.join(OsStr::from_bytes(INFO_EXCLUDE)));
+            }
+            patterns.extend(read(&dir.join(OsStr::from_bytes(GITIGNORE))));
+            let prefix = root.strip_prefix(dir).unwrap_or(&root);
+            git = Some(Arc::new(Level {
+                parent: git,
+                prefix: prefix.as_os_str().as_bytes().to_vec(),
+                skip: 0,
+                patterns,
+            }));
+        }
+        Ignores { git, du: None }
+    }
+
+    // The rules in `node`'s directory, from its parent's and its listing:
+    // the parent's and its own files', with .gitignore files only read when
+    // `git` is set, from the top of a work tree, and .duignore ones when
+    // `du` is
+    pub(super) fn for_dir(
+        &self,
+        node: &DirNode,
+        files: &[FileInfo],
+        subdirs: &[SubdirInfo],
+        git: bool,
+        du: bool,
+    ) -> Self {
+        let listed = |name: &[u8]| {
+            files.iter().any(|file| {
+                file.entry
+                    .as_ref()
+                    .is_some_and(|(entry, _)| **entry == *name)
+            })
+        };
+        let mut ignores = self.clone();
+        // A file in linked work trees and submodules
+        let top = git && (subdirs.iter().any(|subdir| *subdir.name == *GIT_DIR) || listed(GIT_DIR));
+        let gitignore = git && listed(GITIGNORE);
+        if top || (self.git.is_some() && gitignore) {
+            let dir = node.c_path();
+            let mut patterns = Vec::new();
+            if top {
+                patterns = read_listed(&dir, INFO_EXCLUDE);
+            }
+            if gitignore {
+                patterns.extend(read_listed(&dir, GITIGNORE));
+            }
+            let level = Level {
+                parent: if top { None } else { self.git.clone() },
+                prefix: Vec::new(),
+                skip: node.depth(),
+                patterns,
+            };
+            ignores.git = Some(Arc::new(level));
+        }
+        if du && listed(DUIGNORE) {
+            let level = Level {
+                parent: self.du.clone(),
+                prefix: Vec::new(),
+                skip: node.depth(),
+                patterns: read_listed(&node.c_path(), DUIGNORE),
+            };
+            ignores.du = Some(Arc::new(level));
+        }
+        ignores
+    }
+
+    // Whether there are any rules to check
+    pub fn is_empty(&self) -> bool {
+        self.git.is_none() && self.du.is_none()
+    }
+
+    // Whether a path below the scanned directory is ignored by either kind
+    // of file
+    pub fn skip(&self, relative_path: &[u8], is_dir: bool) -> bool {
+        [&self.git, &self.du]
+            .into_iter()
+            .any(|innermost| ignored(innermost.as_deref(), relative_path, is_dir))
+    }
+}
+
+// Whether a chain of files ignores a path. The innermost file with a
+// matching line decides, and within it the last such line.
+fn ignored(innermost: Option<&Level>, relative_path: &[u8], is_dir: bool) -> bool {
+    let mut level = innermost;
+    while let Some(current) = level {
+        if let Some(ignored) = current.decide(relative_path, is_dir) {
+            return ignored;
+        }
+        level = current.parent.as_deref();
+    }
+    false
+}
+
+impl Level {
+    fn decide(&self, relative_path: &[u8], is_dir: bool) -> Option<bool> {
+        let mut path = relative_path;
+        for _ in 0..self.skip {
+            let slash = path.iter().position(|&byte| byte == b'/')?;
+            path = &path[slash + 1..];
+        }
+        let joined;
+        if !self.prefix.is_empty() {
+            joined = [&self.prefix[..], path].join(&b'/');
+            path = &joined;
+        }
+        let name = path.rsplit(|&byte| byte == b'/').next().unwrap_or(path);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, name, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+}
+
+impl Pattern {
+    // None for blank lines and comments
+    fn parse(line: &[u8]) -> Option<Self> {
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        // Trailing spaces don't count unless escaped
+        while line.ends_with(b" ") && !line.ends_with(b"\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+        let negated = line[0] == b'!';
+        if negated {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with(b"/");
+        if dir_only {
+            line = &line[..line.len() - 1];
+        }
+        let anchored = line.contains(&b'/');
+        let glob = line.strip_prefix(b"/").unwrap_or(line);
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            glob: glob.to_vec(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &[u8], name: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match self.anchored {
+            true => glob_match(&self.glob, path),
+            false => glob_match(&self.glob, name),
+        }
+    }
+}
+
+// An ignore file's patterns in order, none if it can't be read
+fn read(path: &Path) -> Vec<Pattern> {
+    fs::read(path)
+        .map(|contents| {
+            contents
+                .split(|&byte| byte == b'\n')
+                .filter_map(Pattern::parse)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The patterns of an ignore file in `dir`
+fn read_listed(dir: &CStr, name: &[u8]) -> Vec<Pattern> {
+    read(Path::new(OsStr::from_bytes(&entry_path(dir, name))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(lines: &[&str]) -> Level {
+        Level {
+            parent: None,
+            prefix: Vec::new(),
+            skip: 0,
+            patterns: lines
+                .iter()
+                .filter_map(|line| Pattern::parse(line.as_bytes()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_anchored_patterns() {
+        // No `/` before the end: any name at any depth
+        let rules = level(&["*.log"]);
+        assert_eq!(rules.decide(b"a.log", false), Some(true));
+        assert_eq!(rules.decide(b"x/y/a.log", false), Some(true));
+        // A `/` anchors it to the file's directory
+        let rules = level(&["/build", "docs/*.html"]);
+        assert_eq!(rules.decide(b"build", true), Some(true));
+        assert_eq!(rules.decide(b"x/build", true), None);
+        assert_eq!(rules.decide(b"docs/a.html", false), Some(true));
+        assert_eq!(rules.decide(b"x/docs/a.html", false), None);
+    }
+
+    #[test]
+    fn test_trailing_slash_matches_directories() {
+        let rules = level(&["cache/"]);
+        assert_eq!(rules.decide(b"cache", true), Some(true));
+        assert_eq!(rules.decide(b"x/cache", true), Some(true));
+        assert_eq!(rules.decide(b"cache", false), None);
+    }
+
+    #[test]
+    fn test_last_matching_line_decides() {
+        let rules = level(&["*.log", "!keep.log"]);
+        assert_eq!(rules.decide(b"a.log", false), Some(true));
+        assert_eq!(rules.decide(b"keep.log", false), Some(false));
+        let rules = level(&["!keep.log", "*.log"]);
+        assert_eq!(rules.decide(b"keep.log", false), Some(true));
+        // The innermost file with a matching line wins over its parents
+        let inner = Level {
+            parent: Some(Arc::new(level(&["*.log"]))),
+            ..level(&["!keep.log"])
+        };
+        assert!(ignored(Some(&inner), b"a.log", false));
+        assert!(!ignored(Some(&inner), b"keep.log", false));
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let rules = level(&["# *.log", "", "\\#notes", "trailing  "]);
+        assert_eq!(rules.patterns.len(), 2);
+        assert_eq!(rules.decide(b"a.log", false), None);
+        assert_eq!(rules.decide(b"trailing", false), Some(true));
+    }
+}
@@ -0,0 +1,415 @@
+use super::profile;
+use super::spill::SpillSet;
+use super::sync::Mutex;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Number of hash buckets (as a power of two)
+const BUCKET_BITS: u32 = 18;
+const BUCKET_COUNT: usize = 1 << BUCKET_BITS;
+
+// Bloom filter size in bits (as a power of two, 1MB) and probes per inode
+const BLOOM_BITS: u32 = 23;
+const BLOOM_HASHES: u64 = 3;
+
+// Memory used by the buckets and the Bloom filter, before any nodes, taken
+// by the first insert
+const FIXED_BYTES: usize = BUCKET_COUNT * 8 + (1 << BLOOM_BITS) / 8;
+
+// Memory the inode sets of a scan share, so that --inode-memory bounds them
+// all together however many roots and options ask for one
+pub struct Budget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Budget {
+            limit,
+            used: AtomicUsize::new(0),
+        })
+    }
+
+    // Take `bytes`, unless that would go over the limit
+    pub(super) fn take(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            })
+            .is_ok()
+    }
+
+    // Take `bytes` whatever the limit, for memory that can't do without
+    pub(super) fn charge(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(super) fn give_back(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+// Bucket list node, immutable once published
+struct Node {
+    inode: u64,
+    dev: u32,
+    next: *mut Node,
+}
+
+// The hash buckets and the Bloom filter in front of them
+struct Tables {
+    buckets: Box<[AtomicPtr<Node>]>,
+    bloom: Box<[AtomicU64]>,
+}
+
+impl Tables {
+    fn new() -> Self {
+        Tables {
+            buckets: (0..BUCKET_COUNT)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            bloom: (0..(1 << BLOOM_BITS) / 64)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    fn bucket_for(&self, key: u64) -> &AtomicPtr<Node> {
+        // Fibonacci hashing spreads the mostly sequential inode numbers
+        let hash = key.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        &self.buckets[(hash >> (64 - BUCKET_BITS)) as usize]
+    }
+
+    // Set the key's filter bits, returns true if all were already set
+    fn bloom_insert(&self, key: u64) -> bool {
+        let h1 = key.wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        let h2 = key.wrapping_mul(0x1656_67B1_9E37_79F9) | 1;
+        let mut maybe_present = true;
+        for i in 0..BLOOM_HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) >> (64 - BLOOM_BITS);
+            let mask = 1 << (bit % 64);
+            // Relaxed is enough, ordering comes from the bucket head (see insert)
+            let prev = self.bloom[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            maybe_present &= prev & mask != 0;
+        }
+        maybe_present
+    }
+}
+
+// Lock-free, insert-only inode set for hardlink deduplication.
+// Each bucket is a singly linked list that only grows at the head via CAS,
+// so workers never block each other and published nodes are never mutated.
+// A Bloom filter in front lets the common "never seen" case skip walking the
+// bucket's chain, which is mostly cache misses.
+// Once the budget is spent the chains are frozen and new inodes go to an
+// on-disk SpillSet instead, serialized behind a mutex.
+pub struct InodeSet {
+    // Allocated by the first insert, most sets see few inodes or none. None
+    // if the budget had no room for them, every inode then spills.
+    tables: OnceLock<Option<Tables>>,
+    budget: Arc<Budget>,
+    // Slots taken from the budget, each a node's worth
+    reserved: AtomicUsize,
+    // Set by the first insert the budget refuses, no slot is taken after it
+    full: AtomicBool,
+    // Inserts that may be holding a slot whose node isn't published yet
+    in_flight: AtomicUsize,
+    // Set once the chains are full and no insert is in flight
+    frozen: AtomicBool,
+    // None once spilling has failed, further inodes are then assumed unseen
+    spill: Mutex<Option<SpillSet>>,
+    // Why spilling failed, until the scan reports it
+    spill_error: Mutex<Option<io::Error>>,
+}
+
+impl InodeSet {
+    // Keep inodes in memory while `budget` allows, then spill
+    pub fn new(budget: Arc<Budget>) -> Self {
+        let spill = SpillSet::new(budget.clone());
+        InodeSet {
+            tables: OnceLock::new(),
+            budget,
+            reserved: AtomicUsize::new(0),
+            full: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            frozen: AtomicBool::new(false),
+            spill: Mutex::new(Some(spill)),
+            spill_error: Mutex::new(None),
+        }
+    }
+
+    fn tables(&self) -> Option<&Tables> {
+        self.tables
+            .get_or_init(|| self.budget.take(FIXED_BYTES).then(Tables::new))
+            .as_ref()
+    }
+
+    // Returns true if the inode was newly inserted, false if already present.
+    // Inode numbers are only unique within a device, so both make the key.
+    pub fn insert(&self, dev: u32, inode: u64) -> bool {
+        let Some(tables) = self.tables() else {
+            profile::INODE_INSERTS.add(1);
+            return self.spill_insert((dev as u128) << 64 | inode as u128);
+        };
+        // Only used for hashing, equality compares both fields
+        let key = inode ^ (dev as u64).rotate_left(32);
+        let bucket = tables.bucket_for(key);
+        let head = bucket.load(Ordering::Acquire);
+        // Nodes at and after this point have already been checked
+        let mut checked: *mut Node = ptr::null_mut();
+
+        // Every inserter sets its filter bits before publishing its node, so
+        // if any bit was unset the inode can't be in the chain loaded above
+        if !tables.bloom_insert(key) {
+            checked = head;
+            profile::BLOOM_SKIPS.add(1);
+        }
+        profile::INODE_INSERTS.add(1);
+
+        if !self.frozen.load(Ordering::Acquire) {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+            // Memory given back by another set later doesn't reopen this one,
+            // inodes spilled by then would be missed
+            if !self.full.load(Ordering::SeqCst) && self.budget.take(size_of::<Node>()) {
+                self.reserved.fetch_add(1, Ordering::Relaxed);
+                let inserted = self.insert_node(bucket, head, checked, dev, inode);
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                return inserted;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.full.store(true, Ordering::SeqCst);
+            self.freeze();
+        }
+
+        // The chains no longer change, so an inode missing from them can
+        // only be in the spill
+        let mut cur = bucket.load(Ordering::Acquire);
+        while cur != checked {
+            let existing = unsafe { &*cur };
+            if existing.inode == inode && existing.dev == dev {
+                return false;
+            }
+            cur = existing.next;
+        }
+        self.spill_insert((dev as u128) << 64 | inode as u128)
+    }
+
+    fn insert_node(
+        &self,
+        bucket: &AtomicPtr<Node>,
+        mut head: *mut Node,
+        mut checked: *mut Node,
+        dev: u32,
+        inode: u64,
+    ) -> bool {
+        let mut node: *mut Node = ptr::null_mut();
+
+        loop {
+            // Only walk the nodes pushed since the last attempt
+            let mut cur = head;
+            while cur != checked {
+                let existing = unsafe { &*cur };
+                if existing.inode == inode && existing.dev == dev {
+                    if !node.is_null() {
+                        drop(unsafe { Box::from_raw(node) });
+                    }
+                    return false;
+                }
+                cur = existing.next;
+            }
+            checked = head;
+
+            if node.is_null() {
+                node = Box::into_raw(Box::new(Node {
+                    inode,
+                    dev,
+                    next: head,
+                }));
+            } else {
+                unsafe { (*node).next = head };
+            }
+
+            match bucket.compare_exchange_weak(head, node, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => {
+                    head = actual;
+                    profile::INODE_CAS_RETRIES.add(1);
+                }
+            }
+        }
+    }
+
+    // Wait for inserts that got a slot before the limit was hit to publish
+    // their nodes. No slot is handed out after this, so the chains are final.
+    fn freeze(&self) {
+        while self.in_flight.load(Ordering::SeqCst) != 0 {
+            std::hint::spin_loop();
+        }
+        self.frozen.store(true, Ordering::Release);
+    }
+
+    fn spill_insert(&self, key: u128) -> bool {
+        let mut spill = self.spill.lock();
+        let Some(set) = spill.as_mut() else {
+            return true;
+        };
+        profile::INODE_SPILLS.add(1);
+        match set.insert(key) {
+            Ok(inserted) => inserted,
+            Err(e) => {
+                *self.spill_error.lock() = Some(e);
+                *spill = None;
+                true
+            }
+        }
+    }
+
+    // Why spilling to disk failed, if it has since this was last called
+    pub fn take_spill_error(&self) -> Option<io::Error> {
+        self.spill_error.lock().take()
+    }
+}
+
+impl Drop for InodeSet {
+    fn drop(&mut self) {
+        let Some(Some(tables)) = self.tables.get() else {
+            return;
+        };
+        let nodes = self.reserved.load(Ordering::Relaxed);
+        self.budget
+            .give_back(FIXED_BYTES + nodes * size_of::<Node>());
+        for bucket in tables.buckets.iter() {
+            let mut cur = bucket.load(Ordering::Acquire);
+            while !cur.is_null() {
+                let node = unsafe { Box::from_raw(cur) };
+                cur = node.next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const THREADS: u64 = 8;
+
+    #[test]
+    fn test_sets_share_a_budget() {
+        let budget = Budget::new(FIXED_BYTES + 10 * size_of::<Node>());
+        let first = InodeSet::new(budget.clone());
+        let second = InodeSet::new(budget.clone());
+        for inode in 0..10 {
+            assert!(first.insert(1, inode));
+        }
+        // Nothing is left for the second set, which spills from the start
+        // without ever allocating its tables
+        assert!(second.insert(1, 0));
+        assert!(!second.insert(1, 0));
+        assert!(matches!(second.tables.get(), Some(None)));
+
+        drop(first);
+        drop(second);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_tables_wait_for_an_insert() {
+        let budget = Budget::new(usize::MAX);
+        let set = InodeSet::new(budget.clone());
+        assert!(set.tables.get().is_none());
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+
+        assert!(set.insert(1, 1));
+        assert_eq!(
+            budget.used.load(Ordering::Relaxed),
+            FIXED_BYTES + size_of::<Node>()
+        );
+        drop(set);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    // Every thread inserts the same inodes, each must be new to exactly one
+    fn insert_from_every_thread(set: &InodeSet, count: u64) {
+        let inserted: u64 = thread::scope(|scope| {
+            let workers: Vec<_> = (0..THREADS)
+                .map(|_| scope.spawn(|| (0..count).filter(|&inode| set.insert(1, inode)).count()))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap() as u64)
+                .sum()
+        });
+        assert_eq!(inserted, count);
+        for inode in 0..count {
+            assert!(!set.insert(1, inode));
+        }
+    }
+
+    #[test]
+    fn test_duplicates_from_many_threads() {
+        let set = InodeSet::new(Budget::new(usize::MAX));
+        insert_from_every_thread(&set, 20_000);
+        assert!(!set.full.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_duplicates_across_the_spill() {
+        // The chains fill partway through, while threads race on the same
+        // inodes
+        let budget = Budget::new(FIXED_BYTES + 1000 * size_of::<Node>());
+        let set = InodeSet::new(budget.clone());
+        insert_from_every_thread(&set, 5000);
+        assert!(set.full.load(Ordering::Relaxed));
+        assert!(set.frozen.load(Ordering::Relaxed));
+        assert_eq!(set.reserved.load(Ordering::Relaxed), 1000);
+        drop(set);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_inserts_beside_lookups() {
+        let set = InodeSet::new(Budget::new(FIXED_BYTES + 3000 * size_of::<Node>()));
+        for inode in (0..4000).step_by(2) {
+            assert!(set.insert(1, inode));
+        }
+        // Half the threads add odd inodes while the others find the even
+        // ones, as the chains fill and the rest spill
+        thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let set = &set;
+                scope.spawn(move || {
+                    let inodes = (thread / 2..2000).step_by((THREADS / 2) as usize);
+                    for inode in inodes {
+                        match thread % 2 {
+                            0 => assert!(!set.insert(1, inode * 2)),
+                            _ => assert!(set.insert(1, inode * 2 + 1)),
+                        }
+                    }
+                });
+            }
+        });
+        for inode in 0..4000 {
+            assert!(!set.insert(1, inode));
+        }
+    }
+
+    #[test]
+    fn test_devices_kept_apart() {
+        // (1, 0) and (0, 1 << 32) hash alike, the others share an inode
+        let pairs = [(1, 0), (0, 1 << 32), (1, 5), (2, 5), (u32::MAX, 5)];
+        for budget in [usize::MAX, 0] {
+            let set = InodeSet::new(Budget::new(budget));
+            for (dev, inode) in pairs {
+                assert!(set.insert(dev, inode));
+            }
+            for (dev, inode) in pairs {
+                assert!(!set.insert(dev, inode));
+            }
+        }
+    }
+}
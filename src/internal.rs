@@ -0,0 +1,14 @@
+// What the dumac binary's commands share with the scanner: its locks, the
+// layout of its files and its path and error helpers. Not part of the API,
+// it changes whenever the commands need it to.
+pub use super::cache::{read_path, read_u64, write_path};
+pub use super::paths::{hold_limit, normalize};
+pub use super::sync::{Condvar, Mutex};
+use std::ffi::CStr;
+
+// The system's description of an error number
+pub fn strerror(errno: i32) -> String {
+    unsafe { CStr::from_ptr(libc::strerror(errno)) }
+        .to_string_lossy()
+        .into_owned()
+}
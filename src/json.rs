@@ -0,0 +1,126 @@
+// Just enough JSON for the replies serve writes and the lines --ndjson
+// writes and --remote reads back: string literals, and flat objects of
+// strings and integers.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+}
+
+// A JSON string literal
+pub fn string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// An object whose values are all strings or integers, None for anything
+// else
+pub fn object(text: &str) -> Option<HashMap<String, Value>> {
+    let mut chars = text.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_space(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return chars.next().is_none().then_some(fields);
+    }
+    loop {
+        skip_space(&mut chars);
+        let name = parse_string(&mut chars)?;
+        skip_space(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_space(&mut chars);
+        let value = match chars.peek()? {
+            '"' => Value::String(parse_string(&mut chars)?),
+            _ => Value::Integer(parse_integer(&mut chars)?),
+        };
+        fields.insert(name, value);
+        skip_space(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => break,
+            _ => return None,
+        }
+    }
+    chars.next().is_none().then_some(fields)
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_space(chars: &mut Chars) {
+    while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_integer(chars: &mut Chars) -> Option<i64> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '-') {
+        digits.push(c);
+        chars.next();
+    }
+    digits.parse().ok()
+}
+
+fn parse_string(chars: &mut Chars) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let unit = hex4(chars)?;
+                    // Outside the Basic Multilingual Plane, as a surrogate pair
+                    let code = match unit {
+                        0xd800..=0xdbff => {
+                            if chars.next()? != '\\' || chars.next()? != 'u' {
+                                return None;
+                            }
+                            let low = hex4(chars).filter(|low| (0xdc00..=0xdfff).contains(low))?;
+                            0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                        }
+                        _ => unit,
+                    };
+                    text.push(char::from_u32(code)?);
+                }
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+fn hex4(chars: &mut Chars) -> Option<u32> {
+    let hex: String = chars.take(4).collect();
+    (hex.len() == 4).then(|| u32::from_str_radix(&hex, 16).ok())?
+}
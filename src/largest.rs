@@ -0,0 +1,75 @@
+use super::sync::{Mutex, MutexGuard};
+use super::{entry_path, pool};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+// Heaps kept, workers share one only past this many threads
+const HEAPS: usize = 64;
+
+// The biggest files seen by a scan, for --largest-files. Each worker keeps
+// its own few in a heap, merged once the scan is done.
+pub struct Largest {
+    heaps: Box<[Mutex<Heap>]>,
+    count: usize,
+}
+
+// Up to `count` files, the smallest on top to be pushed out first
+pub struct Heap {
+    files: BinaryHeap<Reverse<(i64, Box<[u8]>)>>,
+    count: usize,
+}
+
+impl Largest {
+    pub fn new(count: usize) -> Self {
+        let heaps = (0..HEAPS)
+            .map(|_| {
+                Mutex::new(Heap {
+                    files: BinaryHeap::with_capacity(count + 1),
+                    count,
+                })
+            })
+            .collect();
+        Largest { heaps, count }
+    }
+
+    // The calling worker's heap, locked once for a directory's files
+    pub fn heap(&self) -> MutexGuard<'_, Heap> {
+        self.heaps[pool::current_thread_index() % HEAPS].lock()
+    }
+
+    // The biggest files and their blocks, largest first
+    pub fn take(&self) -> Vec<(PathBuf, i64)> {
+        let mut files: Vec<_> = self
+            .heaps
+            .iter()
+            .flat_map(|heap| std::mem::take(&mut heap.lock().files))
+            .map(|Reverse(file)| file)
+            .collect();
+        files.sort_unstable_by(|a, b| b.cmp(a));
+        files.truncate(self.count);
+        files
+            .into_iter()
+            .map(|(blocks, path)| (OsString::from_vec(path.into_vec()).into(), blocks))
+            .collect()
+    }
+}
+
+impl Heap {
+    // A file named `name` in `dir`, kept if it's among the biggest so far.
+    // The path is only built for files that are.
+    pub fn offer(&mut self, blocks: i64, dir: impl FnOnce() -> CString, name: &[u8]) {
+        if self.files.len() == self.count {
+            match self.files.peek() {
+                Some(Reverse((smallest, _))) if blocks > *smallest => {
+                    self.files.pop();
+                }
+                _ => return,
+            }
+        }
+        let path = entry_path(&dir(), name);
+        self.files.push(Reverse((blocks, path.into())));
+    }
+}
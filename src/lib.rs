@@ -0,0 +1,2105 @@
+// dumac's scanner, usable from other Rust programs as well as from the
+// command line. `scan` sizes a directory, `scan_with_visitor` also hands
+// each entry to a callback, `scan_streaming` yields each directory's total as
+// it completes, and a `Scanner` sizes several with hardlinks counted once
+// across them and collects the reports. The subcommands, and everything that
+// prints, deletes or talks to other programs, live in the binary.
+mod ages;
+pub mod attrbuf;
+pub mod background;
+mod cache;
+mod clones;
+mod compression;
+pub mod dataless;
+mod denials;
+mod empty;
+mod error;
+mod exclude;
+mod extensions;
+mod fallback;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+mod firmlink;
+mod fsevents;
+mod hardlinks;
+mod ignore;
+mod inode_set;
+#[doc(hidden)]
+pub mod internal;
+mod largest;
+pub mod mounts;
+mod names;
+mod owners;
+mod paths;
+mod patterns;
+pub mod pool;
+pub mod profile;
+#[cfg(feature = "python")]
+mod python;
+mod roots;
+mod sink;
+pub mod snapshot;
+pub mod source;
+mod spill;
+pub mod stream;
+mod subtotals;
+mod symlinks;
+mod sync;
+mod tally;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod visit;
+pub mod volume;
+pub mod watch;
+mod xattrs;
+
+use cache::{Cache, DirStamp};
+use inode_set::{Budget, InodeSet};
+use internal::strerror;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsString};
+use std::fmt::{self, Write};
+use std::ops::ControlFlow;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sync::Mutex;
+use volume::Volume;
+
+pub use error::DumacError;
+pub use owners::user_id;
+pub use patterns::Regex;
+pub use sink::{Abort, Collect, ErrorSink, Print, ScanError};
+
+// macOS-specific constants not in libc crate
+const ATTR_CMN_ERROR: u32 = 0x20000000;
+const VNON: u32 = 0;
+const VREG: u32 = 1;
+const VDIR: u32 = 2;
+const VBLK: u32 = 3;
+const VCHR: u32 = 4;
+const VLNK: u32 = 5;
+const VSOCK: u32 = 6;
+const VFIFO: u32 = 7;
+const EF_MAY_SHARE_BLOCKS: u64 = 0x00000001;
+
+// Subdirectories with fewer entries than this are batched with their siblings
+const SMALL_DIR_ENTRIES: u32 = 32;
+
+// Target number of entries per batch of small subdirectories
+const BATCH_ENTRIES: u32 = 256;
+
+// File information for size calculation
+#[derive(Debug, Clone)]
+struct FileInfo {
+    blocks: i64,
+    dev: u32,
+    inode: u64,
+    nlink: u32,
+    clone: Option<CloneInfo>,
+    // BSD flags, and the logical size
+    flags: u32,
+    data_length: Option<i64>,
+    // Combined size of its extended attributes, only read for --xattrs
+    xattr_bytes: i64,
+    // User and group, only read for --by-owner and --user
+    owner: Option<(u32, u32)>,
+    // Seconds since the epoch it was last modified, or created with
+    // --birthtime, only read for --by-age and the age filters
+    dated: Option<i64>,
+    // Name and kind, only kept for a visitor
+    entry: Option<(Box<[u8]>, visit::Kind)>,
+}
+
+// APFS clone family and the part of a file's blocks shared with it
+#[derive(Debug, Clone, Copy)]
+struct CloneInfo {
+    id: u64,
+    shared_blocks: i64,
+}
+
+// Subdirectory name and its entry count, used to size scheduling
+#[derive(Debug, Clone)]
+struct SubdirInfo {
+    // Raw bytes, names need not be UTF-8
+    name: Box<[u8]>,
+    entries: u32,
+    mount_point: bool,
+    // Evicted to iCloud, reading it would download it
+    dataless: bool,
+    dev: u32,
+    inode: u64,
+    // Only fetched when the cache is on
+    mtime: Option<(i64, i64)>,
+}
+
+// Directory contents
+#[derive(Debug)]
+struct DirInfo {
+    files: Vec<FileInfo>,
+    subdirs: Vec<SubdirInfo>,
+    tally: tally::Tally,
+}
+
+// A directory that couldn't be listed. The errno tells privacy denials apart
+// from other failures.
+#[derive(Debug)]
+struct ListError {
+    errno: i32,
+    message: String,
+}
+
+impl ListError {
+    fn new(c_path: &CStr, errno: i32) -> Self {
+        ListError {
+            errno,
+            message: format!("{}: {}", c_path.to_string_lossy(), strerror(errno)),
+        }
+    }
+}
+
+// What scanning a directory found
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanResult {
+    // Disk usage in 512-byte blocks, as du counts it
+    pub blocks: i64,
+    // The same in bytes
+    pub bytes: i64,
+    // Sum of logical file sizes, a hardlinked file once per inode as with
+    // du -A
+    pub apparent_bytes: i64,
+    // Entries listed. Files include devices, sockets and FIFOs, directories
+    // include the scanned one.
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    // Files left out because another link to them was already counted
+    pub hardlinks: u64,
+    // Directories and entries that couldn't be read, and so aren't counted
+    pub errors: u64,
+    // Stopped early through the options' cancel flag or by the error sink,
+    // the blocks are only what was counted before
+    pub cancelled: bool,
+    pub elapsed: Duration,
+}
+
+// What a directory's own listing held, before any deduplication. Unlike
+// totals these don't depend on which thread reached a hardlink first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Listing {
+    pub files: usize,
+    pub subdirs: usize,
+    pub blocks: i64,
+}
+
+// Listings by directory path, from Scanner::record_listings
+pub type Listings = HashMap<Box<[u8]>, Listing>;
+
+// Entries under a root. Files include symlinks, devices, sockets and FIFOs,
+// directories include the root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counts {
+    pub files: u64,
+    pub dirs: u64,
+}
+
+// Size a directory, or a file, with its own Scanner. Runs on the options'
+// thread pool if one was given, on a pool of the options' threads if set,
+// and otherwise on the current rayon pool, the global one unless called
+// inside ThreadPool::install. Without the parallel feature it runs on the
+// calling thread.
+pub fn scan(path: &str, options: &ScanOptions) -> Result<ScanResult, DumacError> {
+    run(Scanner::new(options.clone()), path, options)
+}
+
+// Like `scan`, also calling `visitor` for every entry below the directory as
+// it's listed. Breaking on a directory prunes it, its subtree isn't listed or
+// counted, and breaking on a file leaves it out of the total.
+pub fn scan_with_visitor<F>(
+    path: &str,
+    options: &ScanOptions,
+    visitor: F,
+) -> Result<ScanResult, DumacError>
+where
+    F: Fn(&visit::Entry) -> ControlFlow<()> + Send + Sync + 'static,
+{
+    let mut scanner = Scanner::new(options.clone());
+    scanner.visitor = Some(Box::new(visitor));
+    run(scanner, path, options)
+}
+
+// Scan on a thread of its own, yielding each directory's total as soon as
+// its subtree is counted. Workers wait while the consumer falls behind, so
+// memory stays bounded however large the tree.
+pub fn scan_streaming(path: &str, options: &ScanOptions) -> stream::Stream {
+    let (sender, results) = mpsc::sync_channel(stream::BOUND);
+    let mut scanner = Scanner::new(options.clone());
+    // Gone if the consumer stopped listening, the scan carries on
+    scanner.results = Some(Box::new(move |result| {
+        let _ = sender.send(result);
+    }));
+    let path = path.to_string();
+    let options = options.clone();
+    let scan = std::thread::spawn(move || run(scanner, &path, &options));
+    stream::Stream { results, scan }
+}
+
+// `scan_streaming` for async code: the scan runs on tokio's blocking pool
+// and directories are received with `next().await`. Must be called from
+// within a tokio runtime.
+#[cfg(feature = "async")]
+pub fn scan_async(path: &str, options: &ScanOptions) -> stream::AsyncStream {
+    let (sender, results) = tokio::sync::mpsc::channel(stream::BOUND);
+    let mut scanner = Scanner::new(options.clone());
+    scanner.results = Some(Box::new(move |result| {
+        let _ = sender.blocking_send(result);
+    }));
+    let path = path.to_string();
+    let options = options.clone();
+    let scan = tokio::task::spawn_blocking(move || run(scanner, &path, &options));
+    stream::AsyncStream { results, scan }
+}
+
+fn run(scanner: Scanner, path: &str, options: &ScanOptions) -> Result<ScanResult, DumacError> {
+    let start = Instant::now();
+    if let Some(pool) = &options.pool {
+        let blocks = pool.install(|| scanner.calculate_size(path.to_string()))?;
+        return Ok(scanner.result(blocks, start.elapsed()));
+    }
+    let blocks = match options.threads {
+        Some(threads) => pool::build(threads, || {})
+            .map_err(|e| DumacError::Threads(format!("cannot start {} threads: {}", threads, e)))?
+            .install(|| scanner.calculate_size(path.to_string()))?,
+        None => scanner.calculate_size(path.to_string())?,
+    };
+    Ok(scanner.result(blocks, start.elapsed()))
+}
+
+// What a scan counts and reports. Built the same way by the command line
+// and by callers, starting from `ScanOptions::new()`:
+//
+//     ScanOptions::new().clones(true).exclude("node_modules").threads(8)
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    // Memory for tracking hardlinked inodes before they spill to disk
+    inode_memory: Option<usize>,
+    // Count blocks shared between APFS clones once per clone family
+    clones: bool,
+    // Collect the compression and cloud reports
+    compression: bool,
+    cloud: bool,
+    // Break the total down by volume
+    by_volume: bool,
+    // Break the total down by file extension
+    by_extension: bool,
+    // Break the total down by user and group
+    by_owner: bool,
+    // Break the total down by how long ago files were modified
+    by_age: bool,
+    // List symlinks whose targets don't resolve
+    broken_symlinks: bool,
+    // List files with several links and the paths to them
+    report_hardlinks: bool,
+    // List APFS clone families and the space they share
+    report_clones: bool,
+    // List directories with fewer files than this under them, none when 0
+    report_empty: u64,
+    // Keep this many of the biggest files, for --largest-files
+    largest_files: usize,
+    // Count extended attributes, and report how much they take
+    xattrs: bool,
+    // Date files by when they were created rather than modified
+    birthtime: bool,
+    exclude: exclude::Rules,
+    // Which files count, in directories that are still scanned
+    filter: filter::Filter,
+    // Workers for `scan`, which otherwise runs on the current rayon pool
+    threads: Option<usize>,
+    // The caller's own pool, used instead of building one
+    pool: Option<Arc<pool::ThreadPool>>,
+    // Listings from somewhere other than the filesystem
+    source: Option<source::Source>,
+    // Set by the caller to stop the scan, checked before each directory
+    cancel: Option<Arc<AtomicBool>>,
+    errors: sink::Sink,
+}
+
+impl ScanOptions {
+    // Plain du accounting, nothing excluded
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // --inode-memory
+    pub fn inode_memory(mut self, bytes: usize) -> Self {
+        self.inode_memory = Some(bytes);
+        self
+    }
+
+    // --clones
+    pub fn clones(mut self, on: bool) -> Self {
+        self.clones = on;
+        self
+    }
+
+    // --compression
+    pub fn compression(mut self, on: bool) -> Self {
+        self.compression = on;
+        self
+    }
+
+    // --cloud
+    pub fn cloud(mut self, on: bool) -> Self {
+        self.cloud = on;
+        self
+    }
+
+    // --by-volume
+    pub fn by_volume(mut self, on: bool) -> Self {
+        self.by_volume = on;
+        self
+    }
+
+    // --by-extension
+    pub fn by_extension(mut self, on: bool) -> Self {
+        self.by_extension = on;
+        self
+    }
+
+    // --by-owner
+    pub fn by_owner(mut self, on: bool) -> Self {
+        self.by_owner = on;
+        self
+    }
+
+    // --by-age
+    pub fn by_age(mut self, on: bool) -> Self {
+        self.by_age = on;
+        self
+    }
+
+    // --report-broken-symlinks
+    pub fn report_broken_symlinks(mut self, on: bool) -> Self {
+        self.broken_symlinks = on;
+        self
+    }
+
+    // --report-hardlinks
+    pub fn report_hardlinks(mut self, on: bool) -> Self {
+        self.report_hardlinks = on;
+        self
+    }
+
+    // --report-clones
+    pub fn report_clones(mut self, on: bool) -> Self {
+        self.report_clones = on;
+        self
+    }
+
+    // --report-empty, with 1 for directories without any files or
+    // --empty-below's count
+    pub fn report_empty(mut self, below: u64) -> Self {
+        self.report_empty = below;
+        self
+    }
+
+    // --largest-files
+    pub fn largest_files(mut self, count: usize) -> Self {
+        self.largest_files = count;
+        self
+    }
+
+    // --xattrs
+    pub fn xattrs(mut self, on: bool) -> Self {
+        self.xattrs = on;
+        self
+    }
+
+    // --exclude, once per name
+    pub fn exclude(mut self, name: &str) -> Self {
+        self.exclude.names.push(exclude::Name::new(name));
+        self
+    }
+
+    // --exclude-regex, once per pattern, matched against paths relative to
+    // the scanned directory
+    pub fn exclude_regex(mut self, regex: Regex) -> Self {
+        self.exclude.regexes.push(regex);
+        self
+    }
+
+    // --include-only, once per pattern
+    pub fn include_only(mut self, pattern: &str) -> Self {
+        self.filter.include(pattern);
+        self
+    }
+
+    // --ext, once per extension, with or without its dot
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.filter.extension(extension);
+        self
+    }
+
+    // --no-ext, once per extension
+    pub fn skip_extension(mut self, extension: &str) -> Self {
+        self.filter.skip_extension(extension);
+        self
+    }
+
+    // --min-file-size, in bytes
+    pub fn min_file_size(mut self, bytes: u64) -> Self {
+        self.filter.size(Some(bytes), None);
+        self
+    }
+
+    // --max-file-size, in bytes
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.filter.size(None, Some(bytes));
+        self
+    }
+
+    // --newer-than, counting only files dated within `age` of when each scan
+    // starts
+    pub fn newer_than(mut self, age: Duration) -> Self {
+        self.filter.newer_than(age);
+        self
+    }
+
+    // --older-than, counting only files dated over `age` before each scan
+    // starts
+    pub fn older_than(mut self, age: Duration) -> Self {
+        self.filter.older_than(age);
+        self
+    }
+
+    // --user, counting only files owned by `uid`
+    pub fn user(mut self, uid: u32) -> Self {
+        self.filter.user(uid);
+        self
+    }
+
+    // --birthtime, for --by-age and the age filters
+    pub fn birthtime(mut self, on: bool) -> Self {
+        self.birthtime = on;
+        self
+    }
+
+    // --respect-gitignore
+    pub fn respect_gitignore(mut self, on: bool) -> Self {
+        self.exclude.gitignore = on;
+        self
+    }
+
+    // --respect-duignore
+    pub fn respect_duignore(mut self, on: bool) -> Self {
+        self.exclude.duignore = on;
+        self
+    }
+
+    // --skip-hidden
+    pub fn skip_hidden(mut self, on: bool) -> Self {
+        self.exclude.hidden = on;
+        self
+    }
+
+    // --exclude-backups
+    pub fn exclude_backups(mut self, on: bool) -> Self {
+        self.exclude.backups = on;
+        self
+    }
+
+    // --exclude-system
+    pub fn exclude_system(mut self, on: bool) -> Self {
+        self.exclude.system = on;
+        self
+    }
+
+    // Stay on the volume each root is on, leaving out what's mounted inside
+    // it, for -x and --all-volumes
+    pub fn one_volume(mut self, on: bool) -> Self {
+        self.exclude.one_volume = on;
+        self
+    }
+
+    // --threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    // Scan on an existing pool rather than building one, takes precedence
+    // over threads
+    pub fn thread_pool(mut self, pool: Arc<pool::ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // List directories through `source` instead of the filesystem
+    pub fn dir_source(mut self, source: Arc<dyn source::DirSource>) -> Self {
+        self.source = Some(source::Source(source));
+        self
+    }
+
+    // Stop scanning once `flag` is set, from any thread
+    pub fn cancel_on(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    // Where unreadable directories and entries are reported, printed to
+    // stderr by default
+    pub fn error_sink(mut self, sink: Arc<dyn ErrorSink>) -> Self {
+        self.errors = sink::Sink(sink);
+        self
+    }
+}
+
+// A scan's options and everything it accumulates. Roots scanned with the same
+// Scanner share it, so a hardlink is counted once across all of them, while
+// separate Scanners don't affect each other.
+pub struct Scanner {
+    options: ScanOptions,
+    // --inode-memory, shared by the sets below and each root's directories
+    inode_budget: Arc<Budget>,
+    // Inodes of hardlinked files already counted
+    seen_inodes: InodeSet,
+    // Clone families whose shared blocks have been counted, keyed like inodes
+    seen_clones: Option<InodeSet>,
+    // Shared blocks skipped because their clone family was already counted
+    clone_saved_blocks: AtomicI64,
+    compression: compression::Stats,
+    cloud: dataless::Stats,
+    xattrs: xattrs::Stats,
+    firmlinks: firmlink::Claims,
+    nested: roots::Nested,
+    // Filled in unless staying on one volume, reported for --by-volume or
+    // when the scan crossed onto another
+    volumes: subtotals::Subtotals,
+    // Only filled in for --by-extension
+    extensions: extensions::Extensions,
+    // Only filled in for --by-owner
+    owners: owners::Owners,
+    // Only kept for --by-age
+    ages: Option<ages::Ages>,
+    // Only filled in for --report-broken-symlinks
+    broken_symlinks: symlinks::Broken,
+    // Only kept for --report-hardlinks
+    hardlinks: Option<hardlinks::Groups>,
+    // Only kept for --report-clones
+    clone_families: Option<clones::Families>,
+    // Only kept for --report-empty
+    empty: Option<empty::Empty>,
+    // Only kept for --largest-files
+    largest: Option<largest::Largest>,
+    denials: denials::Denials,
+    cache: Option<Cache>,
+    // Counted for ScanResult and --stats
+    totals: tally::Totals,
+    // Each root's, for --count
+    root_counts: Mutex<HashMap<String, Counts>>,
+    // Directories and entries that couldn't be read
+    errors: AtomicU64,
+    // Set when the error sink stops the scan
+    aborted: AtomicBool,
+    // Directories held open for their subdirectories, and how many may be
+    held_dirs: AtomicUsize,
+    hold_limit: usize,
+    // Directories and entries deleted or renamed between being listed and
+    // being read, counted as empty rather than as errors
+    vanished: AtomicU64,
+    // Every directory's listing, only kept for --check
+    listings: Option<Mutex<Listings>>,
+    visitor: Option<visit::Visitor>,
+    // Completed directories, only sent for scan_streaming and scan_async
+    results: Option<stream::Sender>,
+}
+
+impl Scanner {
+    pub fn new(mut options: ScanOptions) -> Self {
+        options.filter.start();
+        let inode_budget = Budget::new(options.inode_memory.unwrap_or_else(default_inode_memory));
+        Scanner {
+            seen_inodes: InodeSet::new(inode_budget.clone()),
+            seen_clones: options.clones.then(|| InodeSet::new(inode_budget.clone())),
+            inode_budget,
+            clone_saved_blocks: AtomicI64::new(0),
+            compression: compression::Stats::default(),
+            cloud: dataless::Stats::default(),
+            xattrs: xattrs::Stats::default(),
+            firmlinks: firmlink::Claims::default(),
+            nested: roots::Nested::default(),
+            volumes: subtotals::Subtotals::default(),
+            extensions: extensions::Extensions::default(),
+            owners: owners::Owners::default(),
+            ages: options.by_age.then(ages::Ages::new),
+            broken_symlinks: symlinks::Broken::default(),
+            hardlinks: options.report_hardlinks.then(hardlinks::Groups::default),
+            clone_families: options.report_clones.then(clones::Families::default),
+            empty: (options.report_empty > 0).then(|| empty::Empty::new(options.report_empty)),
+            largest: (options.largest_files > 0)
+                .then(|| largest::Largest::new(options.largest_files)),
+            denials: denials::Denials::default(),
+            cache: None,
+            totals: tally::Totals::default(),
+            root_counts: Mutex::new(HashMap::new()),
+            errors: AtomicU64::new(0),
+            aborted: AtomicBool::new(false),
+            held_dirs: AtomicUsize::new(0),
+            hold_limit: paths::hold_limit(),
+            vanished: AtomicU64::new(0),
+            listings: None,
+            visitor: None,
+            results: None,
+            options,
+        }
+    }
+
+    // Reuse listings of unchanged directories from the previous scan of `root_dir`
+    pub fn load_cache(&mut self, root_dir: &str) -> Result<(), DumacError> {
+        // Options that change what a listing records
+        let variant = self.reads_clones() as u64
+            | (self.options.compression as u64) << 1
+            | (self.options.cloud as u64) << 2
+            | (self.options.xattrs as u64) << 3;
+        self.cache = Some(Cache::open(root_dir, variant).map_err(DumacError::Cache)?);
+        Ok(())
+    }
+
+    pub fn save_cache(&self) -> Result<(), DumacError> {
+        match &self.cache {
+            Some(cache) => cache.save().map_err(DumacError::Cache),
+            None => Ok(()),
+        }
+    }
+
+    // Hand the reports asked for in the options to the error sink, after
+    // scanning
+    pub fn report(&self) {
+        let mut out = String::new();
+        // Only fails if formatting a value does, which none of these can
+        let _ = self.write_report(&mut out);
+        if !out.is_empty() {
+            self.options.errors.0.report(&out);
+        }
+    }
+
+    fn write_report(&self, out: &mut String) -> fmt::Result {
+        if self.options.clones {
+            let saved = self.clone_saved_blocks.load(Ordering::Relaxed);
+            writeln!(out, "clone savings: {}", format_size(saved))?;
+        }
+        if self.options.compression {
+            self.compression.report(&self.totals, out)?;
+        }
+        if self.options.cloud {
+            self.cloud.report(out)?;
+        }
+        if self.options.xattrs {
+            self.xattrs.report(out)?;
+        }
+        // A scan of / that went into external disks and network shares says
+        // how much came from each even without --by-volume
+        if self.options.by_volume || (!self.options.exclude.one_volume && self.volumes.crossed()) {
+            self.volumes.report(out)?;
+        }
+        if self.options.by_extension {
+            self.extensions.report(out)?;
+        }
+        if self.options.by_owner {
+            self.owners.report(out)?;
+        }
+        if let Some(ages) = &self.ages {
+            ages.report(out)?;
+        }
+        if self.options.broken_symlinks {
+            self.broken_symlinks.report(out)?;
+        }
+        if let Some(hardlinks) = &self.hardlinks {
+            hardlinks.report(out)?;
+        }
+        if let Some(families) = &self.clone_families {
+            families.report(out)?;
+        }
+        if let Some(empty) = &self.empty {
+            empty.report(out)?;
+        }
+        self.denials.report(out)?;
+        let vanished = self.vanished.load(Ordering::Relaxed);
+        if vanished > 0 {
+            writeln!(out, "vanished during the scan: {} paths", vanished)?;
+        }
+        Ok(())
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    // The biggest files counted, largest first, with their blocks. Empty
+    // unless the options asked for them.
+    pub fn largest_files(&self) -> Vec<(PathBuf, i64)> {
+        self.largest
+            .as_ref()
+            .map_or_else(Vec::new, largest::Largest::take)
+    }
+
+    // Whether listings keep each entry's name, for a visitor,
+    // --largest-files, --by-extension, --report-hardlinks, --report-clones,
+    // excluding files by path or filtering them, which also takes each kept
+    // file's kind to tally it
+    fn keep_names(&self) -> bool {
+        self.visitor.is_some()
+            || self.largest.is_some()
+            || self.options.by_extension
+            || self.hardlinks.is_some()
+            || self.clone_families.is_some()
+            || self.options.exclude.skips_files()
+            || self.options.filter.is_active()
+    }
+
+    // Reports, exclusions and filters that go file by file, which cached
+    // listings can't give
+    fn per_file_reports(&self) -> bool {
+        self.largest.is_some()
+            || self.options.exclude.skips_files()
+            || self.options.filter.is_active()
+            || self.options.by_extension
+            || self.options.by_owner
+            || self.ages.is_some()
+            || self.hardlinks.is_some()
+            || self.clone_families.is_some()
+    }
+
+    // Whether listings read who owns each file, for --by-owner and --user
+    fn reads_owners(&self) -> bool {
+        self.options.by_owner || self.options.filter.by_owner()
+    }
+
+    // Whether listings read when each file was modified or created, for
+    // --by-age and the age filters
+    fn dates_files(&self) -> bool {
+        self.ages.is_some() || self.options.filter.by_date()
+    }
+
+    // Whether listings read which clone family each file is in
+    fn reads_clones(&self) -> bool {
+        self.options.clones || self.options.report_clones
+    }
+
+    // Everything counted so far, for a total of `blocks` taken in `elapsed`
+    pub fn result(&self, blocks: i64, elapsed: Duration) -> ScanResult {
+        let totals = &self.totals;
+        ScanResult {
+            blocks,
+            bytes: blocks * 512,
+            apparent_bytes: totals.apparent_bytes.load(Ordering::Relaxed),
+            files: totals.files.load(Ordering::Relaxed),
+            dirs: totals.dirs.load(Ordering::Relaxed),
+            symlinks: totals.symlinks.load(Ordering::Relaxed),
+            hardlinks: totals.hardlinks.load(Ordering::Relaxed),
+            errors: self.errors(),
+            cancelled: self.cancelled(),
+            elapsed,
+        }
+    }
+
+    // Whether the options' cancel flag was set or the error sink stopped
+    // the scan
+    pub fn cancelled(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+            || self
+                .options
+                .cancel
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    // Hand an error to the options' sink and count it
+    fn error(&self, path: &[u8], message: String) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        let error = ScanError {
+            path: OsString::from_vec(path.to_vec()).into(),
+            message,
+        };
+        if self.options.errors.0.error(error).is_break() {
+            self.aborted.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Keep a listed directory open for its subdirectories to be opened
+    // relative to, unless too many are held already
+    fn hold(&self, node: &DirNode, fd: OwnedFd) {
+        if self.held_dirs.fetch_add(1, Ordering::Relaxed) < self.hold_limit {
+            *node.fd.lock() = Some(fd);
+        } else {
+            self.held_dirs.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn release(&self, node: &DirNode) {
+        if node.fd.lock().take().is_some() {
+            self.held_dirs.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    // Note that one of the node's subdirectories was opened or passed over,
+    // closing the node once none are left
+    fn opened(&self, node: &DirNode) {
+        if node.unopened.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.release(node);
+        }
+    }
+
+    // Call `visitor` for every entry as it's listed, as scan_with_visitor
+    // does
+    pub fn visit<F>(&mut self, visitor: F)
+    where
+        F: Fn(&visit::Entry) -> ControlFlow<()> + Send + Sync + 'static,
+    {
+        self.visitor = Some(Box::new(visitor));
+    }
+
+    // Hand each directory's total to `send` once its subtree is counted,
+    // from the scan's workers, as scan_streaming does
+    pub fn on_result<F>(&mut self, send: F)
+    where
+        F: Fn(stream::DirResult) + Send + Sync + 'static,
+    {
+        self.results = Some(Box::new(send));
+    }
+
+    // Keep what each directory listed, for comparing scans
+    pub fn record_listings(&mut self) {
+        self.listings = Some(Mutex::new(Listings::new()));
+    }
+
+    pub fn take_listings(&mut self) -> Listings {
+        self.listings
+            .take()
+            .map(Mutex::into_inner)
+            .unwrap_or_default()
+    }
+
+    // Whether a subdirectory is reached for the first time under its root.
+    // Like du, one reached again (through a bind mount or a directory
+    // hardlink) is only counted the first time, and one that is its own
+    // ancestor is reported.
+    fn first_visit(&self, visited: &InodeSet, parent: &DirNode, subdir: &SubdirInfo) -> bool {
+        // Some filesystems don't number their directories
+        if subdir.inode == 0 || visited.insert(subdir.dev, subdir.inode) {
+            return true;
+        }
+        if leads_back(parent, subdir) {
+            let path = entry_path(&parent.c_path(), &subdir.name);
+            let message = format!(
+                "{}: directory causes a cycle",
+                String::from_utf8_lossy(&path)
+            );
+            self.error(&path, message);
+        }
+        false
+    }
+
+    // Returns the blocks to add for a file, leaving out what was already counted
+    // through another hardlink or clone
+    fn file_blocks(&self, file: &FileInfo) -> i64 {
+        // Files with a single link can't be seen twice, skip the inode set
+        if file.nlink > 1 {
+            if !self.seen_inodes.insert(file.dev, file.inode) {
+                self.totals.record_hardlink();
+                return 0;
+            }
+            self.totals.record_apparent(file.data_length.unwrap_or(0));
+        }
+        let blocks = match (file.clone, &self.seen_clones) {
+            (Some(clone), Some(seen_clones)) if !seen_clones.insert(file.dev, clone.id) => {
+                self.clone_saved_blocks
+                    .fetch_add(clone.shared_blocks, Ordering::Relaxed);
+                file.blocks - clone.shared_blocks
+            }
+            _ => file.blocks,
+        };
+        if let Some(data_length) = file.data_length {
+            if file.flags & libc::UF_COMPRESSED != 0 {
+                self.compression.record(data_length, blocks);
+            }
+            if file.flags & dataless::SF_DATALESS != 0 {
+                self.cloud.record_file(data_length);
+            }
+        }
+        if file.xattr_bytes > 0 {
+            let xattr_blocks = blocks_from_bytes(file.xattr_bytes);
+            self.xattrs.record(xattr_blocks);
+            return blocks + xattr_blocks;
+        }
+        blocks
+    }
+}
+
+// Default inode memory: a quarter of physical memory
+fn default_inode_memory() -> usize {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if pages <= 0 || page_size <= 0 {
+        return usize::MAX;
+    }
+    (pages as usize).saturating_mul(page_size as usize) / 4
+}
+
+// Convert bytes to 512-byte blocks (du default)
+fn blocks_from_bytes(bytes: i64) -> i64 {
+    (bytes + 511) / 512
+}
+
+// Convert blocks to human readable format (du -h style)
+pub fn format_size(blocks: i64) -> String {
+    let bytes = blocks * 512;
+
+    if bytes < 1024 {
+        format!("{}B", bytes)
+    } else if bytes < 1024 * 1024 {
+        let kb = bytes as f64 / 1024.0;
+        if kb.fract() == 0.0 {
+            format!("{}K", kb as i64)
+        } else {
+            format!("{:.1}K", kb)
+        }
+    } else if bytes < 1024 * 1024 * 1024 {
+        let mb = bytes as f64 / (1024.0 * 1024.0);
+        if mb.fract() == 0.0 {
+            format!("{}M", mb as i64)
+        } else {
+            format!("{:.1}M", mb)
+        }
+    } else if bytes < 1024_i64.pow(4) {
+        let gb = bytes as f64 / (1024.0 * 1024.0 * 1024.0);
+        if gb.fract() == 0.0 {
+            format!("{}G", gb as i64)
+        } else {
+            format!("{:.1}G", gb)
+        }
+    } else {
+        let tb = bytes as f64 / (1024.0 * 1024.0 * 1024.0 * 1024.0);
+        if tb.fract() == 0.0 {
+            format!("{}T", tb as i64)
+        } else {
+            format!("{:.1}T", tb)
+        }
+    }
+}
+
+fn is_dot_or_dotdot(filename: &[u8]) -> bool {
+    filename == b"." || filename == b".."
+}
+
+// Error number of the last failed call on this thread
+fn errno() -> i32 {
+    unsafe { *libc::__error() }
+}
+
+// Per-directory accumulator. A directory is complete once its own entries and
+// all of its subdirectories have been counted, at which point its total is
+// folded into its parent.
+// Nodes also form the path arena: each holds only its own name and full paths
+// are assembled from the parent chain when needed, instead of allocating one
+// per subdirectory up front.
+struct DirNode {
+    parent: Option<Arc<DirNode>>,
+    // Path component, or the normalized path for the root
+    name: Box<[u8]>,
+    // As listed in the parent, to tell a directory that leads back to it
+    dev: u32,
+    inode: u64,
+    blocks: AtomicI64,
+    // Files and symlinks under it
+    files: AtomicU64,
+    // Directories under it, itself included
+    dirs: AtomicU64,
+    // Outstanding work: the directory's own listing plus one per subdirectory
+    pending: AtomicUsize,
+    // Open from its listing until its subdirectories are, so that they're
+    // opened by name rather than by walking the whole path again
+    fd: Mutex<Option<OwnedFd>>,
+    // Subdirectories not opened or passed over yet
+    unopened: AtomicUsize,
+}
+
+impl DirNode {
+    fn new(parent: Option<Arc<DirNode>>, name: Box<[u8]>, dev: u32, inode: u64) -> Self {
+        DirNode {
+            parent,
+            name,
+            dev,
+            inode,
+            blocks: AtomicI64::new(0),
+            files: AtomicU64::new(0),
+            dirs: AtomicU64::new(1),
+            pending: AtomicUsize::new(1),
+            fd: Mutex::new(None),
+            unopened: AtomicUsize::new(0),
+        }
+    }
+
+    // Call `at` with the parent's descriptor and this directory's name, if
+    // the parent is still open
+    fn at_parent<T>(&self, at: impl FnOnce(libc::c_int, &CStr) -> T) -> Option<T> {
+        let parent = self.parent.as_ref()?;
+        let fd = parent.fd.lock();
+        let fd = fd.as_ref()?;
+        // Names come from listings, so there's no NUL inside
+        let name = CString::new(&*self.name).ok()?;
+        Some(at(fd.as_raw_fd(), &name))
+    }
+
+    // Open the directory, whose path is `c_path`. Returns -1 with errno set
+    // on failure, like open.
+    fn open(&self, c_path: &CStr) -> libc::c_int {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+        self.at_parent(|fd, name| {
+            paths::retry(|| unsafe { libc::openat(fd, name.as_ptr(), flags) })
+        })
+        .unwrap_or_else(|| paths::open_dir(c_path, flags))
+    }
+
+    fn stat(&self, c_path: &CStr) -> Option<libc::stat> {
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        match self.at_parent(|fd, name| unsafe { libc::fstatat(fd, name.as_ptr(), &mut st, 0) }) {
+            Some(0) => Some(st),
+            Some(_) => None,
+            None => paths::stat_dir(c_path),
+        }
+    }
+
+    // Write the full, NUL-terminated path into `buf`
+    fn write_path(&self, buf: &mut Vec<u8>) {
+        let mut len = 1;
+        let mut node = self;
+        while let Some(parent) = &node.parent {
+            len += node.name.len() + separator_len(parent);
+            node = parent;
+        }
+        len += node.name.len();
+
+        // Fill from the end, child components first
+        buf.clear();
+        buf.resize(len, 0);
+        let mut end = len - 1;
+        let mut node = self;
+        loop {
+            let start = end - node.name.len();
+            buf[start..end].copy_from_slice(&node.name);
+            end = start;
+            let Some(parent) = &node.parent else {
+                break;
+            };
+            if separator_len(parent) == 1 {
+                end -= 1;
+                buf[end] = b'/';
+            }
+            node = parent;
+        }
+    }
+
+    // The path below the root, empty for the root itself
+    fn relative_path(&self) -> Vec<u8> {
+        let mut names = Vec::new();
+        let mut node = self;
+        while let Some(parent) = &node.parent {
+            names.push(&*node.name);
+            node = parent;
+        }
+        names.reverse();
+        names.join(&b'/')
+    }
+
+    // 0 for the root
+    fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut node = self;
+        while let Some(parent) = &node.parent {
+            depth += 1;
+            node = parent;
+        }
+        depth
+    }
+
+    fn c_path(&self) -> CString {
+        let mut buf = Vec::new();
+        self.write_path(&mut buf);
+        CString::from_vec_with_nul(buf).unwrap()
+    }
+}
+
+// Only the root can end in a slash, as in "/" or "dir/"
+fn separator_len(parent: &DirNode) -> usize {
+    usize::from(!parent.name.ends_with(b"/"))
+}
+
+thread_local! {
+    // Reused by each worker to build the path of the directory it opens
+    static PATH_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Drop for DirNode {
+    // Unlink ancestors iteratively so dropping a deep chain can't overflow the stack
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(node) = parent {
+            parent = Arc::into_inner(node).and_then(|mut node| node.parent.take());
+        }
+    }
+}
+
+// Mark one unit of a directory's work as done, propagating completed totals
+// up the tree with a loop rather than recursion
+fn finish_dir(scanner: &Scanner, mut node: Arc<DirNode>) {
+    while node.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+        // Normally closed once its subdirectories were opened, but not when
+        // a cancelled scan dropped them unread
+        scanner.release(&node);
+        // A cancelled scan's totals are partial, they aren't sent
+        if let Some(results) = scanner.results.as_ref().filter(|_| !scanner.cancelled()) {
+            let path = node.c_path().into_bytes();
+            results(stream::DirResult {
+                path: OsString::from_vec(path).into(),
+                blocks: node.blocks.load(Ordering::Relaxed),
+            });
+        }
+        if let Some(empty) = scanner.empty.as_ref().filter(|_| !scanner.cancelled()) {
+            empty.offer(node.files.load(Ordering::Relaxed), || {
+                node.c_path().into_bytes()
+            });
+        }
+        let Some(parent) = node.parent.clone() else {
+            break;
+        };
+        parent
+            .blocks
+            .fetch_add(node.blocks.load(Ordering::Relaxed), Ordering::Relaxed);
+        parent
+            .files
+            .fetch_add(node.files.load(Ordering::Relaxed), Ordering::Relaxed);
+        parent
+            .dirs
+            .fetch_add(node.dirs.load(Ordering::Relaxed), Ordering::Relaxed);
+        node = parent;
+    }
+}
+
+// A directory waiting to be scanned
+struct DirTask {
+    node: Arc<DirNode>,
+    volume: Arc<Volume>,
+    // From the parent's listing, when known
+    stamp: Option<DirStamp>,
+    // Directories reached so far under this task's root
+    visited: Arc<InodeSet>,
+    // The ignore files' rules in the parent
+    ignores: ignore::Ignores,
+}
+
+impl Scanner {
+    // Size of each root, in argument order. A root inside another is sized
+    // from the enclosing root's scan, so shared subtrees are only traversed once.
+    pub fn calculate_sizes(&self, roots: &[String]) -> Vec<Result<i64, DumacError>> {
+        let enclosing = roots::enclosing_roots(roots);
+        let keys: Vec<_> = roots
+            .iter()
+            .zip(&enclosing)
+            .map(|(root, outer)| outer.and_then(|_| self.nested.watch(root)))
+            .collect();
+
+        let mut sizes: Vec<_> = roots.iter().map(|_| None).collect();
+        for (i, root) in roots.iter().enumerate() {
+            if enclosing[i].is_none() {
+                sizes[i] = Some(self.calculate_size(root.clone()));
+            }
+        }
+        // A nested root the enclosing scan didn't reach (excluded, failed, or
+        // behind a mount point) gets a scan of its own
+        for (i, root) in roots.iter().enumerate() {
+            if sizes[i].is_none() {
+                sizes[i] = Some(match keys[i].and_then(|key| self.nested.take(key)) {
+                    Some(node) => {
+                        self.record_counts(root, &node);
+                        Ok(node.blocks.load(Ordering::Relaxed))
+                    }
+                    None => self.calculate_size(root.clone()),
+                });
+            }
+        }
+        sizes.into_iter().flatten().collect()
+    }
+
+    // Calculate total size using rayon work stealing. Each directory is a spawned
+    // task on the pool's work deques, so stack usage is constant regardless of depth.
+    pub fn calculate_size(&self, root_dir: String) -> Result<i64, DumacError> {
+        dataless::never_materialize_thread();
+        // Errors on the root directory itself are fatal
+        let c_root = CString::new(root_dir.as_str())
+            .map_err(|_| DumacError::InvalidPath(root_dir.clone()))?;
+
+        let st = self
+            .root_metadata(&c_root)
+            .map_err(|errno| DumacError::Io {
+                op: "stat".into(),
+                path: root_dir.clone().into(),
+                errno,
+            })?;
+        // Anything but a directory is counted on its own, as du does
+        if !st.is_dir {
+            self.totals.record_file(st.apparent_bytes);
+            self.root_counts
+                .lock()
+                .insert(root_dir, Counts { files: 1, dirs: 0 });
+            return Ok(st.blocks);
+        }
+
+        let volume =
+            volume::volume_for_path(&c_root).unwrap_or_else(|| Arc::new(Volume::unknown()));
+        // Errors below the root name it in its tidied form
+        let root_name = paths::normalize(&root_dir).into_bytes();
+        let root = Arc::new(DirNode::new(None, root_name.into(), st.dev, st.inode));
+        let dir_info = read_dir(self, &root, &c_root, &volume, None).map_err(|e| {
+            let path = root_dir.clone().into();
+            if denials::is_denial(c_root.as_bytes(), e.errno) {
+                DumacError::Protected {
+                    path,
+                    errno: e.errno,
+                }
+            } else {
+                DumacError::Io {
+                    op: "list".into(),
+                    path,
+                    errno: e.errno,
+                }
+            }
+        })?;
+
+        self.nested.reached(st.dev, st.inode, &root);
+        let visited = Arc::new(InodeSet::new(self.inode_budget.clone()));
+        // So that a cycle back to the root is caught
+        visited.insert(st.dev, st.inode);
+        let ignores = match self.options.exclude.gitignore {
+            true => ignore::Ignores::enclosing(&c_root),
+            false => ignore::Ignores::default(),
+        };
+        let task = DirTask {
+            node: root.clone(),
+            volume,
+            stamp: None,
+            visited: visited.clone(),
+            ignores,
+        };
+        pool::scope(|scope| process_dir(self, scope, task, dir_info));
+        self.spill_errors(&root_dir, &visited);
+
+        self.record_counts(&root_dir, &root);
+        Ok(root.blocks.load(Ordering::Relaxed))
+    }
+
+    // Report inode sets that couldn't spill to disk. Inodes they were given
+    // after that were taken as unseen.
+    fn spill_errors(&self, root_dir: &str, visited: &InodeSet) {
+        let sets = [
+            Some(visited),
+            Some(&self.seen_inodes),
+            self.seen_clones.as_ref(),
+        ];
+        for error in sets
+            .into_iter()
+            .flatten()
+            .filter_map(InodeSet::take_spill_error)
+        {
+            let message = format!(
+                "{}: cannot spill inodes to disk: {}, hardlinks may be counted more than once",
+                root_dir, error
+            );
+            self.error(root_dir.as_bytes(), message);
+        }
+    }
+
+    // Files and directories under a root sized by calculate_size or
+    // calculate_sizes, by the name it was given as
+    pub fn counts(&self, root: &str) -> Option<Counts> {
+        self.root_counts.lock().get(root).copied()
+    }
+
+    fn record_counts(&self, root: &str, node: &DirNode) {
+        let counts = Counts {
+            files: node.files.load(Ordering::Relaxed),
+            dirs: node.dirs.load(Ordering::Relaxed),
+        };
+        self.root_counts.lock().insert(root.to_string(), counts);
+    }
+
+    fn root_metadata(&self, c_root: &CStr) -> Result<source::Metadata, i32> {
+        if let Some(source) = &self.options.source {
+            return source::metadata(source, c_root);
+        }
+        // Symlinks given on the command line are followed, like du -H, so
+        // `dumac /tmp` scans /private/tmp
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::stat(c_root.as_ptr(), &mut st) } != 0 {
+            return Err(errno());
+        }
+        Ok(source::Metadata {
+            dev: st.st_dev as u32,
+            inode: st.st_ino,
+            is_dir: st.st_mode & libc::S_IFMT == libc::S_IFDIR,
+            blocks: st.st_blocks,
+            apparent_bytes: st.st_size,
+        })
+    }
+}
+
+fn scan_dir<'scope>(scanner: &'scope Scanner, scope: &pool::Scope<'scope>, task: DirTask) {
+    // Queued directories are dropped unread once cancelled, so the scan
+    // winds down as fast as the directories in progress finish
+    if scanner.cancelled() {
+        if let Some(parent) = &task.node.parent {
+            scanner.opened(parent);
+        }
+        finish_dir(scanner, task.node);
+        return;
+    }
+    dataless::never_materialize_thread();
+    // Respect the volume's concurrency limit while the directory is read
+    let permit = task.volume.acquire();
+    // Per-directory timings are only taken at -vv
+    let start = tracing::enabled!(tracing::Level::TRACE).then(Instant::now);
+    let result = PATH_BUF.with_borrow_mut(|buf| {
+        task.node.write_path(buf);
+        // Names come from getattrlistbulk and the root was checked up front,
+        // so the only NUL is the terminator
+        let c_path = CStr::from_bytes_with_nul(buf).unwrap();
+        let node = &task.node;
+        let result = match read_dir(scanner, node, c_path, &task.volume, task.stamp) {
+            // Gone since its parent was listed. Try once more in case it was
+            // replaced rather than removed, as atomic saves do.
+            Err(e) if e.errno == libc::ENOENT => {
+                tracing::debug!(path = %c_path.to_string_lossy(), "gone since listed, retrying");
+                read_dir(scanner, node, c_path, &task.volume, None)
+            }
+            result => result,
+        };
+        if let (Some(start), Ok(dir_info)) = (start, &result) {
+            tracing::trace!(
+                path = %c_path.to_string_lossy(),
+                files = dir_info.files.len(),
+                subdirs = dir_info.subdirs.len(),
+                elapsed = ?start.elapsed(),
+                "listed"
+            );
+        }
+        result
+    });
+    drop(permit);
+    if let Some(parent) = &task.node.parent {
+        scanner.opened(parent);
+    }
+
+    match result {
+        Ok(dir_info) => process_dir(scanner, scope, task, dir_info),
+        Err(e) if e.errno == libc::ENOENT => {
+            tracing::debug!(path = %task.node.c_path().to_string_lossy(), "vanished");
+            scanner.vanished.fetch_add(1, Ordering::Relaxed);
+            finish_dir(scanner, task.node);
+        }
+        Err(e) => {
+            // Protected directories are summed up after the scan instead
+            let c_path = task.node.c_path();
+            if scanner.denials.record(c_path.as_bytes(), e.errno) {
+                scanner.errors.fetch_add(1, Ordering::Relaxed);
+            } else {
+                scanner.error(c_path.as_bytes(), e.message);
+            }
+            if let Some(empty) = &scanner.empty {
+                empty.unreadable(c_path.into_bytes());
+            }
+            finish_dir(scanner, task.node);
+        }
+    }
+}
+
+fn scan_batch<'scope>(scanner: &'scope Scanner, scope: &pool::Scope<'scope>, batch: Vec<DirTask>) {
+    for task in batch {
+        scan_dir(scanner, scope, task);
+    }
+}
+
+fn process_dir<'scope>(
+    scanner: &'scope Scanner,
+    scope: &pool::Scope<'scope>,
+    task: DirTask,
+    dir_info: DirInfo,
+) {
+    let DirTask {
+        node,
+        volume,
+        visited,
+        ignores,
+        ..
+    } = task;
+
+    if let Some(listings) = &scanner.listings {
+        let listing = Listing {
+            files: dir_info.files.len(),
+            subdirs: dir_info.subdirs.len(),
+            blocks: dir_info.files.iter().map(|file| file.blocks).sum(),
+        };
+        listings
+            .lock()
+            .insert(node.c_path().into_bytes().into(), listing);
+    }
+
+    let mut files = dir_info.files;
+    // Its own ignore files' rules join those it inherited, for its entries
+    // and what's below them
+    let rules = &scanner.options.exclude;
+    let ignores = match rules.ignore_files() {
+        true => ignores.for_dir(
+            &node,
+            &files,
+            &dir_info.subdirs,
+            rules.gitignore,
+            rules.duignore,
+        ),
+        false => ignores,
+    };
+    // Excluded and filtered out files count towards nothing, not even
+    // --count, so only those left are tallied
+    let mut tally = dir_info.tally;
+    if rules.skips_files() || scanner.options.filter.is_active() {
+        tally = tally::Tally::default();
+        files.retain(|file| {
+            let excluded = match &file.entry {
+                Some((name, _)) => rules.skip_file(&node, name, &ignores),
+                None => false,
+            };
+            let counts = !excluded && scanner.options.filter.counts(file, &node);
+            if counts {
+                let symlink = file
+                    .entry
+                    .as_ref()
+                    .is_some_and(|(_, kind)| *kind == visit::Kind::Symlink);
+                tally.add(symlink, file.nlink, file.data_length.unwrap_or(0));
+            }
+            counts
+        });
+    }
+    scanner.totals.record_dir(&tally);
+    node.files
+        .fetch_add(tally.files + tally.symlinks, Ordering::Relaxed);
+
+    // Depth of this directory's entries, only needed for a visitor
+    let depth = scanner.visitor.as_ref().map_or(0, |_| node.depth() + 1);
+    if let Some(visitor) = &scanner.visitor {
+        let dir = node.c_path();
+        files.retain(|file| match &file.entry {
+            Some((name, kind)) => visit::visit(
+                visitor,
+                &entry_path(&dir, name),
+                file.blocks,
+                file.data_length.unwrap_or(0),
+                *kind,
+                depth,
+            ),
+            None => true,
+        });
+    }
+
+    // Process files in this directory, deduplicating hardlinked inodes
+    let mut largest = scanner.largest.as_ref().map(largest::Largest::heap);
+    let mut extensions = scanner
+        .options
+        .by_extension
+        .then(extensions::Counts::default);
+    let mut owners = scanner.options.by_owner.then(owners::Counts::default);
+    let mut ages = scanner.ages.as_ref().map(|_| ages::Counts::default());
+    let total_size: i64 = files
+        .iter()
+        .map(|file| {
+            let blocks = scanner.file_blocks(file);
+            if let (Some(counts), Some((uid, gid))) = (&mut owners, file.owner) {
+                counts.add(uid, gid, blocks);
+            }
+            if let (Some(counts), Some(all), Some(dated)) = (&mut ages, &scanner.ages, file.dated) {
+                counts.add(all, dated, blocks);
+            }
+            if let Some((name, visit::Kind::File)) = &file.entry {
+                if let Some(groups) = scanner.hardlinks.as_ref().filter(|_| file.nlink > 1) {
+                    let path = entry_path(&node.c_path(), name);
+                    groups.record(file.dev, file.inode, file.nlink, file.blocks, path);
+                }
+                if let (Some(families), Some(clone)) = (&scanner.clone_families, file.clone) {
+                    let path = entry_path(&node.c_path(), name);
+                    families.record(
+                        file.dev,
+                        clone.id,
+                        file.inode,
+                        file.blocks,
+                        clone.shared_blocks,
+                        path,
+                    );
+                }
+                if let Some(heap) = &mut largest {
+                    heap.offer(blocks, || node.c_path(), name);
+                }
+                if let Some(counts) = &mut extensions {
+                    // Reading an evicted file would download it, and a
+                    // listing from a DirSource has no file to read
+                    let sniff =
+                        file.flags & dataless::SF_DATALESS == 0 && scanner.options.source.is_none();
+                    counts.add(name, blocks, sniff, || entry_path(&node.c_path(), name));
+                }
+            }
+            blocks
+        })
+        .sum();
+    drop(largest);
+    if let Some(counts) = extensions {
+        scanner.extensions.record(counts);
+    }
+    if let Some(counts) = owners {
+        scanner.owners.record(counts);
+    }
+    if let (Some(counts), Some(all)) = (ages, &scanner.ages) {
+        all.record(&counts);
+    }
+    node.blocks.fetch_add(total_size, Ordering::Relaxed);
+    if scanner.options.by_volume || !scanner.options.exclude.one_volume {
+        scanner.volumes.record(&volume, total_size);
+    }
+
+    let mut subdirs = dir_info.subdirs;
+    subdirs.retain(|subdir| {
+        if subdir.dataless {
+            scanner.cloud.record_dir();
+            return false;
+        }
+        scanner.firmlinks.claim(subdir.dev, subdir.inode)
+            && scanner.first_visit(&visited, &node, subdir)
+    });
+
+    // Queue subdirectories for the pool
+    node.pending.fetch_add(subdirs.len(), Ordering::Relaxed);
+    node.unopened.store(subdirs.len(), Ordering::Relaxed);
+    if subdirs.is_empty() {
+        scanner.release(&node);
+    }
+    // Big subtrees go first so the scan doesn't end with one thread grinding
+    // through a huge directory alone. Thieves take the oldest spawned task and
+    // this thread the newest, so the rest are spawned largest first and the
+    // largest is spawned last, to be picked up here next.
+    subdirs.sort_unstable_by_key(|subdir| Reverse(subdir.entries));
+    let mut largest = None;
+    // Small subdirectories are grouped into batches so that each task does a
+    // meaningful amount of work, larger ones get a task each
+    let mut batch = Vec::new();
+    let mut batch_entries = 0;
+    for subdir in subdirs {
+        let child_node = Arc::new(DirNode::new(
+            Some(node.clone()),
+            subdir.name,
+            subdir.dev,
+            subdir.inode,
+        ));
+        // A firmlink leads from the system volume into the data volume
+        // without being a mount point, only the device changes
+        let firmlink = !subdir.mount_point && volume.dev != 0 && subdir.dev != volume.dev as u32;
+        // Crossing into another filesystem, which may have different limits
+        let volume = if subdir.mount_point || firmlink {
+            child_volume(&child_node).unwrap_or_else(|| volume.clone())
+        } else {
+            volume.clone()
+        };
+        if scanner.options.exclude.skip_dir(
+            &child_node,
+            subdir.mount_point,
+            subdir.mount_point || firmlink,
+            &volume,
+            &ignores,
+        ) {
+            // Nothing to count, only the wait for it ends
+            scanner.opened(&node);
+            finish_dir(scanner, node.clone());
+            continue;
+        }
+        if let Some(visitor) = &scanner.visitor {
+            let path = child_node.c_path();
+            if !visit::visit(visitor, path.as_bytes(), 0, 0, visit::Kind::Dir, depth) {
+                scanner.opened(&node);
+                finish_dir(scanner, node.clone());
+                continue;
+            }
+        }
+        scanner
+            .nested
+            .reached(subdir.dev, subdir.inode, &child_node);
+        // A mount point's listed attributes may be those of the covered
+        // directory, leave it to be looked up
+        let stamp = match subdir.mtime {
+            Some(mtime) if !subdir.mount_point && !firmlink => Some(DirStamp {
+                dev: volume.dev,
+                inode: subdir.inode,
+                mtime,
+            }),
+            _ => None,
+        };
+        let child = DirTask {
+            node: child_node,
+            volume,
+            stamp,
+            visited: visited.clone(),
+            ignores: ignores.clone(),
+        };
+        if subdir.entries >= SMALL_DIR_ENTRIES {
+            if largest.is_none() {
+                largest = Some(child);
+            } else {
+                scope.spawn(move |scope| scan_dir(scanner, scope, child));
+            }
+            continue;
+        }
+
+        // Count the directory itself too, opening it has a cost
+        batch_entries += subdir.entries + 1;
+        batch.push(child);
+        if batch_entries >= BATCH_ENTRIES {
+            let full_batch = std::mem::take(&mut batch);
+            batch_entries = 0;
+            scope.spawn(move |scope| scan_batch(scanner, scope, full_batch));
+        }
+    }
+    if !batch.is_empty() {
+        scope.spawn(move |scope| scan_batch(scanner, scope, batch));
+    }
+    if let Some(child) = largest {
+        scope.spawn(move |scope| scan_dir(scanner, scope, child));
+    }
+
+    finish_dir(scanner, node);
+}
+
+// List a directory, reusing the previous scan's listing if the cache is on
+// and the directory hasn't changed since
+fn read_dir(
+    scanner: &Scanner,
+    node: &DirNode,
+    c_path: &CStr,
+    volume: &Volume,
+    stamp: Option<DirStamp>,
+) -> Result<DirInfo, ListError> {
+    let Some(cache) = &scanner.cache else {
+        return list_dir(scanner, node, c_path, volume);
+    };
+    let stamp = stamp.or_else(|| {
+        let st = node.stat(c_path)?;
+        Some(DirStamp {
+            dev: st.st_dev as u64,
+            inode: st.st_ino,
+            mtime: (st.st_mtime, st.st_mtime_nsec),
+        })
+    });
+    // Cached listings don't say where symlinks lead or name plain files, so
+    // directories are listed afresh when those are wanted
+    let cached = stamp
+        .filter(|_| !scanner.options.broken_symlinks && !scanner.per_file_reports())
+        .and_then(|stamp| cache.lookup(stamp));
+    if let Some(dir_info) = cached {
+        return Ok(dir_info);
+    }
+    let dir_info = list_dir(scanner, node, c_path, volume)?;
+    if let Some(stamp) = stamp {
+        cache.store(stamp, &dir_info);
+    }
+    Ok(dir_info)
+}
+
+// List a directory with getattrlistbulk, switching the whole volume over to
+// readdir the first time getattrlistbulk falls short on it. A directory
+// with subdirectories is held open for them.
+fn list_dir(
+    scanner: &Scanner,
+    node: &DirNode,
+    c_path: &CStr,
+    volume: &Volume,
+) -> Result<DirInfo, ListError> {
+    if let Some(source) = &scanner.options.source {
+        return source::list(scanner, source, c_path);
+    }
+    let open = || {
+        let open_start = profile::start();
+        let fd = node.open(c_path);
+        profile::OPEN.record(open_start);
+        match fd {
+            -1 => Err(ListError::new(c_path, errno())),
+            fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        }
+    };
+    let mut fd = open()?;
+    let dir_info = match volume.uses_fallback() {
+        false => get_dir_info(scanner, c_path, fd.as_raw_fd())?,
+        true => None,
+    };
+    let dir_info = match dir_info {
+        Some(dir_info) => dir_info,
+        None => {
+            if !volume.uses_fallback() {
+                volume.fall_back();
+                // getattrlistbulk has read part of it already
+                fd = open()?;
+            }
+            let dirfd = fd
+                .try_clone()
+                .map_err(|e| ListError::new(c_path, e.raw_os_error().unwrap_or(libc::EIO)))?;
+            fallback::get_dir_info(scanner, c_path, dirfd)?
+        }
+    };
+    if !dir_info.subdirs.is_empty() {
+        scanner.hold(node, fd);
+    }
+    Ok(dir_info)
+}
+
+// The volume of a subdirectory that is a mount point or firmlink
+fn child_volume(node: &DirNode) -> Option<Arc<Volume>> {
+    let fd = node.open(&node.c_path());
+    if fd == -1 {
+        return None;
+    }
+    let dir = unsafe { OwnedFd::from_raw_fd(fd) };
+    volume::volume_for_fd(dir.as_raw_fd())
+}
+
+// Whether a subdirectory is `parent` or one of its ancestors
+fn leads_back(parent: &DirNode, subdir: &SubdirInfo) -> bool {
+    let mut node = Some(parent);
+    while let Some(ancestor) = node {
+        if (ancestor.dev, ancestor.inode) == (subdir.dev, subdir.inode) {
+            return true;
+        }
+        node = ancestor.parent.as_deref();
+    }
+    false
+}
+
+// The path of an entry listed in `dir`
+fn entry_path(dir: &CStr, name: &[u8]) -> Vec<u8> {
+    let mut path = dir.to_bytes().to_vec();
+    if !path.ends_with(b"/") {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    path
+}
+
+// Report an entry that couldn't be read, unless protections are why or it
+// was deleted since the listing
+fn entry_error(scanner: &Scanner, dir: &CStr, name: &[u8], errno: i32) {
+    if errno == libc::ENOENT {
+        scanner.vanished.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let path = entry_path(dir, name);
+    if scanner.denials.record(&path, errno) {
+        scanner.errors.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let message = format!(
+        "cannot access '{}': {}",
+        String::from_utf8_lossy(&path),
+        strerror(errno)
+    );
+    scanner.error(&path, message);
+}
+
+// None when the volume's getattrlistbulk doesn't support what the scan needs.
+// `dirfd` is the directory at `c_path`, left open.
+fn get_dir_info(
+    scanner: &Scanner,
+    c_path: &CStr,
+    dirfd: libc::c_int,
+) -> Result<Option<DirInfo>, ListError> {
+    // Set up attribute list for getattrlistbulk
+    let mut attrlist = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_RETURNED_ATTRS
+            | libc::ATTR_CMN_NAME
+            | ATTR_CMN_ERROR
+            | libc::ATTR_CMN_DEVID
+            | libc::ATTR_CMN_OBJTYPE
+            | libc::ATTR_CMN_FLAGS
+            | libc::ATTR_CMN_FILEID,
+        volattr: 0,
+        dirattr: libc::ATTR_DIR_ENTRYCOUNT | libc::ATTR_DIR_MOUNTSTATUS,
+        fileattr: libc::ATTR_FILE_LINKCOUNT
+            | libc::ATTR_FILE_DATAALLOCSIZE
+            | libc::ATTR_FILE_RSRCALLOCSIZE,
+        forkattr: 0,
+    };
+    // Subdirectory mtimes let the cache skip unchanged directories, file
+    // mtimes or birth times date the data for --by-age and the age filters
+    if scanner.cache.is_some() || scanner.dates_files() {
+        attrlist.commonattr |= libc::ATTR_CMN_MODTIME;
+    }
+    if scanner.dates_files() && scanner.options.birthtime {
+        attrlist.commonattr |= libc::ATTR_CMN_CRTIME;
+    }
+    if scanner.reads_owners() {
+        attrlist.commonattr |= libc::ATTR_CMN_OWNERID | libc::ATTR_CMN_GRPID;
+    }
+    // The data length is the apparent size, and also the uncompressed size
+    // of compressed files and the cloud size of dataless ones
+    attrlist.fileattr |= libc::ATTR_FILE_DATALENGTH;
+    // Extended attributes identify clones and how much of each is shared
+    let mut options = 0;
+    if scanner.reads_clones() {
+        attrlist.forkattr =
+            libc::ATTR_CMNEXT_PRIVATESIZE | libc::ATTR_CMNEXT_CLONEID | libc::ATTR_CMNEXT_EXT_FLAGS;
+        options |= libc::FSOPT_ATTR_CMN_EXTENDED as u64;
+    }
+
+    let mut attrbuf = [0u8; 128 * 1024];
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    let mut tally = tally::Tally::default();
+    // Entries that couldn't be read, with their errnos, and symlinks to
+    // check. They're only reported once the whole listing has parsed, since
+    // readdir lists the directory again if getattrlistbulk falls short.
+    let mut failed: Vec<(Option<Box<[u8]>>, i32)> = Vec::new();
+    let mut symlinks: Vec<Box<[u8]>> = Vec::new();
+
+    loop {
+        let bulk_start = profile::start();
+        let retcount = loop {
+            let count = unsafe {
+                libc::getattrlistbulk(
+                    dirfd,
+                    &mut attrlist as *mut libc::attrlist as *mut libc::c_void,
+                    attrbuf.as_mut_ptr() as *mut libc::c_void,
+                    attrbuf.len(),
+                    options,
+                )
+            };
+            if count != -1 || errno() != libc::EINTR {
+                break count;
+            }
+        };
+        profile::GETATTRLISTBULK.record(bulk_start);
+
+        if retcount <= 0 {
+            if retcount < 0 {
+                let errno = errno();
+                if errno == libc::ENOTSUP || errno == libc::EINVAL {
+                    return Ok(None);
+                }
+                return Err(ListError::new(c_path, errno));
+            }
+            break;
+        }
+
+        // Parse attribute buffer
+        let parse_start = profile::start();
+        let mut unsupported = false;
+        for entry in attrbuf::entries(&attrbuf, retcount as usize) {
+            // Nothing after a bad entry can be trusted, readdir lists the
+            // directory instead
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    tracing::debug!(path = %c_path.to_string_lossy(), "{}, listing with readdir", e);
+                    unsupported = true;
+                    break;
+                }
+            };
+            let filename = entry.name;
+            if filename.is_some_and(is_dot_or_dotdot) {
+                continue;
+            }
+
+            // Check for errors
+            if entry.error != 0 {
+                failed.push((filename.map(Into::into), entry.error as i32));
+                continue;
+            }
+
+            let attrbuf::Entry {
+                dev,
+                obj_type,
+                mtime,
+                flags,
+                inode,
+                entries,
+                mount_point,
+                nlink,
+                data_length,
+                ..
+            } = entry;
+
+            // Both forks together, as du counts them. Compressed files keep
+            // their payload in the resource fork, so this is also right
+            // for them.
+            let alloc_size = entry
+                .data_alloc_size
+                .map(|size| size + entry.rsrc_alloc_size);
+
+            // Files that may share blocks with clones
+            let clone = match (entry.clone_id, entry.private_size, alloc_size) {
+                (Some(id), Some(private_size), Some(alloc_size))
+                    if entry.ext_flags & EF_MAY_SHARE_BLOCKS != 0 =>
+                {
+                    Some(CloneInfo {
+                        id,
+                        shared_blocks: blocks_from_bytes(alloc_size)
+                            - blocks_from_bytes(private_size),
+                    })
+                }
+                _ => None,
+            };
+
+            let owner = entry.uid.zip(entry.gid);
+            let dated = match scanner.options.birthtime {
+                true => entry.crtime,
+                false => mtime,
+            }
+            .filter(|_| scanner.dates_files())
+            .map(|(secs, _)| secs);
+
+            // Regular files need their allocation and inode, some network
+            // and FUSE filesystems leave them out
+            if obj_type == VREG && (alloc_size.is_none() || inode == 0) {
+                unsupported = true;
+                break;
+            }
+
+            let xattr_bytes = match filename {
+                Some(name) if scanner.options.xattrs && (obj_type == VREG || obj_type == VLNK) => {
+                    xattrs::size(dirfd, name)
+                }
+                _ => 0,
+            };
+
+            let kind = match obj_type {
+                VLNK => visit::Kind::Symlink,
+                VREG => visit::Kind::File,
+                _ => visit::Kind::Other,
+            };
+            let entry = filename
+                .filter(|_| scanner.keep_names())
+                .map(|name| (name.into(), kind));
+
+            if matches!(obj_type, VREG | VLNK | VBLK | VCHR | VSOCK | VFIFO) {
+                tally.add(obj_type == VLNK, nlink, data_length.unwrap_or(0));
+            }
+
+            // Handle different file types
+            match (obj_type, alloc_size) {
+                (VREG, Some(alloc_size)) => {
+                    // Regular file - count its allocation size
+                    files.push(FileInfo {
+                        blocks: blocks_from_bytes(alloc_size),
+                        dev,
+                        inode,
+                        nlink,
+                        clone,
+                        flags,
+                        data_length,
+                        xattr_bytes,
+                        owner,
+                        dated,
+                        entry,
+                    });
+                }
+                (VDIR, _) => {
+                    // Directory - add to subdirectories list
+                    if let Some(name) = filename {
+                        subdirs.push(SubdirInfo {
+                            name: name.into(),
+                            entries,
+                            mount_point,
+                            dataless: flags & dataless::SF_DATALESS != 0,
+                            dev,
+                            inode,
+                            mtime,
+                        });
+                    }
+                }
+                (VLNK, _) => {
+                    if let Some(name) = filename.filter(|_| scanner.options.broken_symlinks) {
+                        symlinks.push(name.into());
+                    }
+                    // Symlink - count the link itself as 1 (du default behavior)
+                    files.push(FileInfo {
+                        blocks: 1,
+                        dev,
+                        inode,
+                        nlink,
+                        clone: None,
+                        flags,
+                        data_length,
+                        xattr_bytes,
+                        owner,
+                        dated,
+                        entry,
+                    });
+                }
+                (VBLK | VCHR | VSOCK | VFIFO, alloc_size) => {
+                    // Device, socket or FIFO - count whatever it has allocated,
+                    // as du does, and link it like any other inode
+                    files.push(FileInfo {
+                        blocks: alloc_size.map_or(0, blocks_from_bytes),
+                        dev,
+                        inode,
+                        nlink,
+                        clone: None,
+                        flags,
+                        data_length,
+                        xattr_bytes,
+                        owner,
+                        dated,
+                        entry,
+                    });
+                }
+                _ => {
+                    // Unknown types (VNON, VBAD, ...) - treat as zero-size
+                }
+            }
+        }
+        profile::PARSE.record(parse_start);
+        if unsupported {
+            return Ok(None);
+        }
+        profile::ENTRIES.add(retcount as u64);
+    }
+    profile::DIRS.add(1);
+
+    for (name, errno) in failed {
+        match name {
+            Some(name) => entry_error(scanner, c_path, &name, errno),
+            None => {
+                scanner.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    for name in symlinks {
+        scanner.broken_symlinks.check(dirfd, c_path, &name);
+    }
+
+    Ok(Some(DirInfo {
+        files,
+        subdirs,
+        tally,
+    }))
+}
@@ -1,353 +1,599 @@
-use parking_lot::Mutex;
-use rayon::prelude::*;
-use std::collections::HashSet;
+mod alert;
+mod apfs;
+mod apps;
+mod bench;
+mod check;
+mod clean;
+mod cli;
+mod daemon;
+mod devjunk;
+mod diff;
+mod dupes;
+mod failure;
+mod history;
+mod json;
+mod package;
+mod plist;
+mod prometheus;
+mod reference;
+mod remote;
+mod schedule;
+mod serve;
+mod statsd;
+mod trash;
+mod tui;
+mod webhook;
+
+use dumac::internal::hold_limit;
+use dumac::watch::{self, Totals};
+use dumac::{
+    background, dataless, format_size, mounts, pool, profile, snapshot, volume, Counts,
+    ScanOptions, Scanner,
+};
 use std::env;
 use std::ffi::CString;
-use std::path::Path;
-use std::sync::LazyLock;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-// macOS-specific constants not in libc crate
-const ATTR_CMN_ERROR: u32 = 0x20000000;
-const VNON: u32 = 0;
-const VREG: u32 = 1;
-const VDIR: u32 = 2;
-const VLNK: u32 = 5;
+// File handles kept free for stdio and anything else the process opens
+const FD_RESERVE: usize = 32;
 
-// Max file handles open
-const MAX_FILE_HANDLES: usize = 224;
+// Exit statuses: 1 for usage and scan errors, as du, and this for a root
+// over --fail-if-over, even if there were errors too
+const EXIT_OVER_LIMIT: i32 = 2;
 
-// Sharded inode tracking
-const SHARD_COUNT: usize = 128;
-
-// File information for size calculation
-#[derive(Debug)]
-struct FileInfo {
-    blocks: i64,
-    inode: u64,
-}
-
-// Directory contents
-#[derive(Debug)]
-struct DirInfo {
-    files: Vec<FileInfo>,
-    subdirs: Vec<String>,
-}
-
-// Global sharded inode set for hardlink deduplication
-static SEEN_INODES: LazyLock<[Mutex<HashSet<u64>>; SHARD_COUNT]> =
-    LazyLock::new(|| std::array::from_fn(|_| Mutex::new(HashSet::new())));
-
-fn shard_for_inode(inode: u64) -> usize {
-    ((inode >> 8) % SHARD_COUNT as u64) as usize
-}
-
-// Clear all seen inodes (for testing)
-#[cfg(test)]
-pub fn clear_seen_inodes() {
-    for shard in SEEN_INODES.iter() {
-        shard.lock().clear();
-    }
-}
-
-// Returns the blocks to add (blocks if newly seen, 0 if already seen)
-fn check_and_add_inode(inode: u64, blocks: i64) -> i64 {
-    let shard_idx = shard_for_inode(inode);
-    let mut seen = SEEN_INODES[shard_idx].lock();
-    if seen.insert(inode) {
-        blocks // Inode was newly added, count the blocks
-    } else {
-        0 // Inode already seen, don't count
-    }
-}
-
-// Convert bytes to 512-byte blocks (du default)
-fn blocks_from_bytes(bytes: i64) -> i64 {
-    (bytes + 511) / 512
-}
-
-// Convert blocks to human readable format (du -h style)
-fn format_size(blocks: i64) -> String {
-    let bytes = blocks * 512;
-
-    if bytes < 1024 {
-        format!("{}B", bytes)
-    } else if bytes < 1024 * 1024 {
-        let kb = bytes as f64 / 1024.0;
-        if kb.fract() == 0.0 {
-            format!("{}K", kb as i64)
-        } else {
-            format!("{:.1}K", kb)
-        }
-    } else if bytes < 1024 * 1024 * 1024 {
-        let mb = bytes as f64 / (1024.0 * 1024.0);
-        if mb.fract() == 0.0 {
-            format!("{}M", mb as i64)
-        } else {
-            format!("{:.1}M", mb)
-        }
-    } else if bytes < 1024_i64.pow(4) {
-        let gb = bytes as f64 / (1024.0 * 1024.0 * 1024.0);
-        if gb.fract() == 0.0 {
-            format!("{}G", gb as i64)
-        } else {
-            format!("{:.1}G", gb)
-        }
-    } else {
-        let tb = bytes as f64 / (1024.0 * 1024.0 * 1024.0 * 1024.0);
-        if tb.fract() == 0.0 {
-            format!("{}T", tb as i64)
-        } else {
-            format!("{:.1}T", tb)
-        }
-    }
-}
-
-fn is_dot_or_dotdot(filename: &str) -> bool {
-    filename == "." || filename == ".."
-}
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("usage: {} directory", args[0]);
-        std::process::exit(1);
-    }
-
-    let root_dir = &args[1];
-
-    let max_threads = std::thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(1)
-        // Limit to MAX_FILE_HANDLES to avoid file handle limit errors
-        .min(MAX_FILE_HANDLES);
-    let pool = rayon::ThreadPoolBuilder::new()
-        // Larger than default stack size to handle deep directory trees
-        .stack_size(16 * 1024 * 1024)
-        .num_threads(max_threads)
-        .build()
-        .expect("Failed to build thread pool");
-
-    let result = pool.install(|| calculate_size(root_dir.clone()));
-
-    match result {
-        Ok(total_blocks) => {
-            println!("{}\t{}", format_size(total_blocks), root_dir);
-        }
+    let mut args = match cli::parse_args(&args) {
+        Ok(parsed) => parsed,
         Err(e) => {
-            eprintln!("{}: {}", args[0], e);
+            eprintln!("dumac: {}", e);
+            eprintln!("{}", cli::usage(&args[0]));
             std::process::exit(1);
         }
-    }
-}
-
-// Calculate total size recursively using rayon work stealing
-pub fn calculate_size(root_dir: String) -> Result<i64, String> {
-    // Get directory contents
-    let dir_info = get_dir_info(&root_dir)?;
-
-    let mut all_inodes = Vec::new();
+    };
 
-    // Process files in this directory, deduplicating by inode
-    let total_size: i64 = dir_info
-        .files
-        .iter()
-        .map(|file| {
-            let blocks = check_and_add_inode(file.inode, file.blocks);
-            all_inodes.push(file.inode);
-            blocks
-        })
-        .sum();
+    let socket = args
+        .socket
+        .as_ref()
+        .map_or_else(daemon::socket_path, PathBuf::from);
+    if let cli::Command::Query = args.command {
+        std::process::exit(query(&socket, &args.roots));
+    }
+    if let cli::Command::Schedule(action) = &args.command {
+        std::process::exit(schedule(action, &args.roots));
+    }
 
-    // Process subdirectories in parallel
-    let subdir_size = if !dir_info.subdirs.is_empty() {
-        dir_info
-            .subdirs
-            .into_par_iter()
-            .map(|subdir| {
-                let subdir_path = Path::new(&root_dir)
-                    .join(&subdir)
-                    .to_string_lossy()
-                    .to_string();
-                calculate_size(subdir_path)
-            })
-            .map(|result| match result {
-                Ok(size) => size,
-                Err(e) => {
-                    eprintln!("dumac: {}", e);
-                    0
-                }
-            })
-            .sum()
-    } else {
-        0
+    let mounts = match args.all_volumes {
+        true => mounts::mounted(),
+        false => Vec::new(),
     };
+    if args.all_volumes {
+        if mounts.is_empty() {
+            eprintln!("dumac: cannot list the mounted volumes");
+            std::process::exit(1);
+        }
+        args.roots = mounts
+            .iter()
+            .map(|mount| mount.mount_point.clone())
+            .collect();
+    }
 
-    Ok(total_size + subdir_size)
-}
-
-fn get_dir_info(path: &str) -> Result<DirInfo, String> {
-    // Open directory
-    let c_path = CString::new(path).map_err(|_| format!("{}: Invalid path", path))?;
-    let dirfd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY) };
-    if dirfd == -1 {
-        let errno = unsafe { *libc::__error() };
-        let error_msg = match errno {
-            libc::ENOENT => "No such file or directory",
-            libc::EACCES => "Permission denied",
-            libc::ENOTDIR => "Not a directory",
-            _ => "Cannot access directory",
+    // Thread sizing and the cache go by the first root, if any is local
+    let root_dir = args.roots.first().map_or(".", String::as_str);
+    if args.profile {
+        profile::enable();
+    }
+    // Diagnostics for slow or odd volumes: volume changes, retries and the
+    // readdir fallback at -v, each directory's listing time at -vv
+    if args.verbose > 0 {
+        let level = match args.verbose {
+            1 => tracing::Level::DEBUG,
+            _ => tracing::Level::TRACE,
         };
-        return Err(format!("{}: {}", path, error_msg));
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(std::io::stderr)
+            .with_target(false)
+            .init();
+    }
+    volume::set_overrides(args.volume_threads.clone());
+    if let Err(e) = dataless::never_materialize() {
+        eprintln!("dumac: {}", e);
+    }
+    let mut options = ScanOptions::new()
+        .clones(args.clones)
+        .compression(args.compression)
+        .cloud(args.cloud)
+        .by_volume(args.by_volume)
+        .by_extension(args.by_extension)
+        .by_owner(args.by_owner)
+        .by_age(args.by_age)
+        .report_broken_symlinks(args.report_broken_symlinks)
+        .report_hardlinks(args.report_hardlinks)
+        .report_clones(args.report_clones)
+        .report_empty(match args.report_empty {
+            true => args.empty_below.unwrap_or(1) as u64,
+            false => 0,
+        })
+        .largest_files(args.largest_files)
+        .xattrs(args.xattrs)
+        .respect_gitignore(args.respect_gitignore)
+        .respect_duignore(args.respect_duignore)
+        .skip_hidden(args.skip_hidden)
+        .exclude_backups(args.exclude_backups)
+        .exclude_system(args.exclude_system)
+        .one_volume(args.all_volumes || args.one_file_system);
+    if let Some(bytes) = args.inode_memory {
+        options = options.inode_memory(bytes);
+    }
+    for name in &args.exclude {
+        options = options.exclude(name);
+    }
+    if let Some(bytes) = args.min_file_size {
+        options = options.min_file_size(bytes as u64);
+    }
+    if let Some(bytes) = args.max_file_size {
+        options = options.max_file_size(bytes as u64);
+    }
+    if let Some(age) = args.newer_than {
+        options = options.newer_than(age);
+    }
+    if let Some(age) = args.older_than {
+        options = options.older_than(age);
+    }
+    options = options.birthtime(args.birthtime);
+    if let Some((_, uid)) = args.user {
+        options = options.user(uid);
+    }
+    for pattern in &args.include_only {
+        options = options.include_only(pattern);
+    }
+    for extension in &args.ext {
+        options = options.extension(extension);
+    }
+    for extension in &args.no_ext {
+        options = options.skip_extension(extension);
+    }
+    for regex in &args.exclude_regex {
+        options = options.exclude_regex(regex.clone());
+    }
+    // --webhook's summary quotes the errors, which it still prints
+    let report = webhook::Report::new(&args.roots);
+    if args.webhook.is_some() {
+        options = options.error_sink(report.clone());
     }
 
-    // Set up attribute list for getattrlistbulk
-    let mut attrlist = libc::attrlist {
-        bitmapcount: libc::ATTR_BIT_MAP_COUNT as u16,
-        reserved: 0,
-        commonattr: libc::ATTR_CMN_RETURNED_ATTRS
-            | libc::ATTR_CMN_NAME
-            | ATTR_CMN_ERROR
-            | libc::ATTR_CMN_OBJTYPE
-            | libc::ATTR_CMN_FILEID,
-        volattr: 0,
-        dirattr: 0,
-        fileattr: libc::ATTR_FILE_ALLOCSIZE,
-        forkattr: 0,
+    // Each worker holds at most one directory open at a time, so the thread
+    // count must stay within the open file budget
+    let fd_budget = fd_budget();
+    let threads = match args.threads {
+        Some(threads) if threads > fd_budget => {
+            eprintln!(
+                "dumac: --threads {} exceeds the open file limit, using {}",
+                threads, fd_budget
+            );
+            fd_budget
+        }
+        Some(threads) => threads,
+        None => auto_threads(fd_budget, root_dir),
     };
 
-    let mut attrbuf = [0u8; 128 * 1024];
-    let mut files = Vec::new();
-    let mut subdirs = Vec::new();
-
-    loop {
-        let retcount = unsafe {
-            libc::getattrlistbulk(
-                dirfd,
-                &mut attrlist as *mut libc::attrlist as *mut libc::c_void,
-                attrbuf.as_mut_ptr() as *mut libc::c_void,
-                attrbuf.len(),
-                0,
-            )
-        };
+    // Stay out of the way of interactive work when asked to
+    if args.background {
+        if let Err(e) = background::throttle_io() {
+            eprintln!("dumac: {}", e);
+        }
+        background::lower_thread_qos();
+    }
 
-        if retcount <= 0 {
-            if retcount < 0 {
-                let errno = unsafe { *libc::__error() };
-                let error_msg = match errno {
-                    libc::EACCES => "Permission denied",
-                    libc::ENOENT => "No such file or directory",
-                    _ => "Cannot read directory contents",
+    let low_priority = args.background;
+    let pool = pool::build(threads, move || {
+        if low_priority {
+            background::lower_thread_qos();
+        }
+    })
+    .expect("Failed to build thread pool");
+
+    let limit = args.fail_if_over.map(|bytes| (bytes as i64 + 511) / 512);
+    let start = Instant::now();
+    let mut failed = false;
+    let mut over_limit = false;
+    match args.command {
+        cli::Command::Scan => {
+            // Remote scans run while the local ones do
+            let remote_flags = cli::remote_flags(&args);
+            let pending: Vec<remote::Pending> = args
+                .remotes
+                .iter()
+                .map(|target| remote::start(target.clone(), remote_flags.clone()))
+                .collect();
+            let mut scanner = Scanner::new(options);
+            if args.webhook.is_some() {
+                report.attach(&mut scanner);
+            }
+            if args.cache && !args.roots.is_empty() {
+                if let Err(e) = scanner.load_cache(root_dir) {
+                    eprintln!("dumac: {}", e);
+                }
+            }
+            let sizes = pool.install(|| scanner.calculate_sizes(&args.roots));
+            let mut totals = Vec::new();
+            let mut over = Vec::new();
+            let mut failed_roots = Vec::new();
+            let mut total =
+                |root: &str, blocks: i64, counts: Option<Counts>, mount: Option<&mounts::Mount>| {
+                    let counts = counts.filter(|_| args.count);
+                    // With --count, between the size and the path
+                    let column = match counts {
+                        Some(counts) => format!(
+                            "{}\t{}\t",
+                            plural(counts.files, "file"),
+                            plural(counts.dirs, "dir")
+                        ),
+                        None => String::new(),
+                    };
+                    match (args.ndjson, mount) {
+                        (true, _) => println!("{}", remote::total_line(root, blocks, counts)),
+                        // With --all-volumes, beside what the volume holds
+                        (false, Some(mount)) => println!(
+                            "{}\t{}{}\t{}",
+                            format_size(blocks),
+                            column,
+                            root,
+                            mount.describe()
+                        ),
+                        (false, None) => println!("{}\t{}{}", format_size(blocks), column, root),
+                    }
+                    totals.push((root.to_string(), blocks));
+                    // A partial total already over the limit is over it
+                    if let Some(limit) = limit.filter(|&limit| blocks > limit) {
+                        alert::over_limit(root, blocks, limit, args.notify);
+                        over.push(root.to_string());
+                    }
                 };
-                return Err(format!("{}: {}", path, error_msg));
+            // Like du, a root that can't be read doesn't stop the others
+            for (i, (root, size)) in args.roots.iter().zip(sizes).enumerate() {
+                match size {
+                    Ok(total_blocks) => {
+                        total(root, total_blocks, scanner.counts(root), mounts.get(i))
+                    }
+                    Err(e) => {
+                        eprintln!("dumac: {}", e);
+                        failed_roots.push(e.to_string());
+                    }
+                }
             }
-            break;
-        }
-
-        // Parse attribute buffer
-        let mut entry_ptr = attrbuf.as_ptr();
-        for _ in 0..retcount {
-            unsafe {
-                // Read entry length and advance to attribute data
-                let entry_length = std::ptr::read_unaligned(entry_ptr as *const u32);
-                let mut field_ptr = entry_ptr.add(std::mem::size_of::<u32>());
-
-                // Read returned attributes bitmask
-                let returned_attrs =
-                    std::ptr::read_unaligned(field_ptr as *const libc::attribute_set_t);
-                field_ptr = field_ptr.add(std::mem::size_of::<libc::attribute_set_t>());
-
-                // Extract filename
-                let mut filename: Option<String> = None;
-                if returned_attrs.commonattr & libc::ATTR_CMN_NAME != 0 {
-                    let name_start = field_ptr; // Save start of attrreference_t
-                    let name_info =
-                        std::ptr::read_unaligned(field_ptr as *const libc::attrreference_t);
-                    field_ptr = field_ptr.add(std::mem::size_of::<libc::attrreference_t>());
-                    let name_ptr = name_start.add(name_info.attr_dataoffset as usize);
-
-                    if name_info.attr_length > 0 {
-                        let name_slice = std::slice::from_raw_parts(
-                            name_ptr,
-                            (name_info.attr_length - 1) as usize,
-                        );
-                        if let Ok(name_str) = std::str::from_utf8(name_slice) {
-                            if is_dot_or_dotdot(name_str) {
-                                entry_ptr = entry_ptr.add(entry_length as usize);
-                                continue;
-                            }
-                            filename = Some(name_str.to_string());
+            let mut largest: Vec<(String, i64)> = scanner
+                .largest_files()
+                .into_iter()
+                .map(|(path, blocks)| (path.display().to_string(), blocks))
+                .collect();
+            for scan in pending {
+                match scan.finish() {
+                    Ok(results) => {
+                        for (root, blocks, counts) in &results.totals {
+                            total(root, *blocks, *counts, None);
                         }
+                        largest.extend(results.largest);
+                        failed |= !results.succeeded;
+                    }
+                    Err(e) => {
+                        eprintln!("dumac: {}", e);
+                        failed_roots.push(e.to_string());
                     }
                 }
-
-                // Check for errors
-                if returned_attrs.commonattr & ATTR_CMN_ERROR != 0 {
-                    let error_code = std::ptr::read_unaligned(field_ptr as *const u32);
-                    field_ptr = field_ptr.add(std::mem::size_of::<u32>());
-                    if error_code != 0 {
-                        if let Some(name) = &filename {
-                            eprintln!("cannot access '{}/{}': error {}", path, name, error_code);
-                        }
-                        entry_ptr = entry_ptr.add(entry_length as usize);
-                        continue;
+            }
+            if args.largest_files > 0 {
+                largest.sort_by_key(|(_, blocks)| std::cmp::Reverse(*blocks));
+                largest.truncate(args.largest_files);
+                if !args.ndjson {
+                    println!("largest files:");
+                }
+                for (path, blocks) in largest {
+                    match args.ndjson {
+                        true => println!("{}", remote::file_line(&path, blocks)),
+                        false => println!("  {}\t{}", format_size(blocks), path),
                     }
                 }
-
-                // Get object type
-                let obj_type = if returned_attrs.commonattr & libc::ATTR_CMN_OBJTYPE != 0 {
-                    let obj_type = std::ptr::read_unaligned(field_ptr as *const u32);
-                    field_ptr = field_ptr.add(std::mem::size_of::<u32>());
-                    obj_type
-                } else {
-                    VNON
+            }
+            scanner.report();
+            if let Some(file) = &args.prometheus {
+                if let Err(e) = prometheus::write(Path::new(file), &totals, start.elapsed()) {
+                    eprintln!("dumac: {}: {}", file, e);
+                    failed = true;
+                }
+            }
+            if let Some(address) = &args.statsd {
+                if let Err(e) = statsd::send(address, &totals, start.elapsed()) {
+                    eprintln!("dumac: {}: {}", address, e);
+                    failed = true;
+                }
+            }
+            over_limit |= !over.is_empty();
+            failed |= !failed_roots.is_empty();
+            if let Some(url) = &args.webhook {
+                let summary = webhook::Summary {
+                    totals: &totals,
+                    limit,
+                    over_limit: &over,
+                    failed_roots: &failed_roots,
+                    elapsed: start.elapsed(),
                 };
-
-                // Get inode
-                let inode = if returned_attrs.commonattr & libc::ATTR_CMN_FILEID != 0 {
-                    let inode = std::ptr::read_unaligned(field_ptr as *const u64);
-                    field_ptr = field_ptr.add(std::mem::size_of::<u64>());
-                    inode
-                } else {
-                    0
+                if let Err(e) = webhook::send(url, &report, &summary) {
+                    eprintln!("dumac: {}: {}", url, e);
+                    failed = true;
+                }
+            }
+            if let Err(e) = scanner.save_cache() {
+                eprintln!("dumac: {}", e);
+            }
+            // Like du, unreadable directories along the way fail the run too
+            failed |= scanner.errors() > 0;
+            if args.stats {
+                let result = scanner.result(0, start.elapsed());
+                eprintln!(
+                    "entries: {} files, {} directories, {} symlinks",
+                    result.files, result.dirs, result.symlinks
+                );
+                eprintln!(
+                    "apparent size: {}",
+                    format_size((result.apparent_bytes + 511) / 512)
+                );
+                eprintln!("hardlinks counted once: {}", result.hardlinks);
+            }
+        }
+        cli::Command::Check | cli::Command::VerifyDu => {
+            // The reference walker only does plain accounting, so its
+            // comparison leaves out every option that changes it
+            let mut plain = ScanOptions::new();
+            if let Some(bytes) = args.inode_memory {
+                plain = plain.inode_memory(bytes);
+            }
+            for root in &args.roots {
+                let compared = match args.command {
+                    cli::Command::VerifyDu => check::verify_du(&plain, &pool, root),
+                    _ => check::run(&options, &pool, root),
                 };
-
-                // Handle different file types
-                match obj_type {
-                    VREG => {
-                        // Regular file - get allocation size
-                        if returned_attrs.fileattr & libc::ATTR_FILE_ALLOCSIZE != 0 {
-                            let alloc_size = std::ptr::read_unaligned(field_ptr as *const i64);
-                            files.push(FileInfo {
-                                blocks: blocks_from_bytes(alloc_size),
-                                inode,
-                            });
-                        }
-                    }
-                    VDIR => {
-                        // Directory - add to subdirectories list
-                        if let Some(name) = filename {
-                            subdirs.push(name);
-                        }
-                    }
-                    VLNK => {
-                        // Symlink - count the link itself as 1 (du default behavior)
-                        files.push(FileInfo { blocks: 1, inode });
-                    }
-                    _ => {
-                        // Other file types (devices, etc.) - treat as zero-size
+                match compared {
+                    Ok(consistent) => failed |= !consistent,
+                    Err(e) => {
+                        eprintln!("dumac: {}", e);
+                        failed = true;
                     }
                 }
+            }
+        }
+        cli::Command::Watch => {
+            if let Err(e) = watch(&options, &pool, root_dir, limit, args.notify) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Daemon => {
+            if let Err(e) = daemon::serve(&options, &pool, root_dir, &socket) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Snapshot => {
+            let output = args.output.as_deref().unwrap_or_default();
+            let saved = snapshot::Snapshot::scan(&options, &pool, root_dir)
+                .and_then(|snapshot| snapshot.save(Path::new(output)));
+            if let Err(e) = saved {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Diff => {
+            let after = args.roots.get(1).map(String::as_str);
+            if let Err(e) = diff::run(&options, &pool, root_dir, after) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::ApfsDiff => {
+            let (old, dir) = (&args.roots[0], &args.roots[args.roots.len() - 1]);
+            let new = (args.roots.len() == 3).then(|| args.roots[1].as_str());
+            if let Err(e) = apfs::run_diff(&options, &pool, old, new, dir) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        // Handled before anything is set up for scanning
+        cli::Command::Query | cli::Command::Schedule(_) => {}
+        cli::Command::Dupes => {
+            if let Err(e) = dupes::run(&options, &pool, root_dir) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::DevJunk => {
+            if let Err(e) = devjunk::run(&options, &pool, root_dir) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Serve => {
+            let listen = args.listen.as_deref().unwrap_or_default();
+            if let Err(e) = serve::serve(&options, &pool, root_dir, listen, args.package_depth) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Trash => {
+            if let Err(e) = trash::run(&options, &pool, &args.roots) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Apps => {
+            if let Err(e) = apps::run(&options, &pool, root_dir) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Clean { yes, dry_run } => {
+            match clean::run(&options, &pool, root_dir, yes, dry_run) {
+                Ok(removed_all) => failed |= !removed_all,
+                Err(e) => {
+                    eprintln!("dumac: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        cli::Command::History { depth, show } => {
+            if let Err(e) = history::run(&options, &pool, root_dir, depth, show) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Interactive => {
+            if let Err(e) = tui::run(&options, &pool, root_dir, args.package_depth) {
+                eprintln!("dumac: {}", e);
+                failed = true;
+            }
+        }
+        cli::Command::Bench { iterations, cold } => {
+            let run = || bench::run(&options, args.cache, root_dir, iterations, cold);
+            if let Err(e) = pool.install(run) {
+                eprintln!("dumac: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.profile {
+        let mut out = String::new();
+        let _ = profile::report(&mut out);
+        eprint!("{}", out);
+    }
+
+    if args.stats {
+        eprintln!("threads: {}", threads);
+        eprintln!("elapsed: {:.3}s", start.elapsed().as_secs_f64());
+    }
+    if over_limit {
+        std::process::exit(EXIT_OVER_LIMIT);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-                // Move to next entry
-                entry_ptr = entry_ptr.add(entry_length as usize);
+// Print each directory's total from the daemon like a scan would, returning
+// the exit status
+fn query(socket: &Path, paths: &[String]) -> i32 {
+    let results = match daemon::query(socket, paths) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("dumac: {} (is `dumac daemon` running?)", e);
+            return 1;
+        }
+    };
+    let mut status = 0;
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(blocks) => println!("{}\t{}", format_size(blocks), path),
+            Err(message) => {
+                eprintln!("dumac: {}: {}", path, message);
+                status = 1;
             }
         }
     }
+    status
+}
 
-    // Close directory
-    unsafe {
-        libc::close(dirfd);
+// A count and its noun, like "1 file" or "3 files"
+fn plural(n: u64, noun: &str) -> String {
+    match n {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
     }
+}
 
-    Ok(DirInfo { files, subdirs })
+// Scan a directory, then rescan it whenever FSEvents reports changes,
+// printing the changed directories' totals and the new overall total each
+// time. With a `limit` in blocks, the directory going over it is reported
+// each time it does, in Notification Center too with `notify`.
+fn watch(
+    options: &ScanOptions,
+    pool: &pool::ThreadPool,
+    root_dir: &str,
+    limit: Option<i64>,
+    notify: bool,
+) -> Result<(), dumac::DumacError> {
+    let mut over_limit = false;
+    watch::watch(
+        options,
+        pool,
+        root_dir,
+        Totals::Changed,
+        |root, blocks, dirs| {
+            for dir in dirs.iter().filter(|dir| dir.path != root) {
+                println!("{}\t{}", format_size(dir.blocks), dir.path.display());
+            }
+            println!("{}\t{}", format_size(blocks), root_dir);
+            // Once per crossing rather than after every rescan
+            let over = limit.is_some_and(|limit| blocks > limit);
+            if let Some(limit) = limit.filter(|_| over && !over_limit) {
+                alert::over_limit(root_dir, blocks, limit, notify);
+            }
+            over_limit = over;
+        },
+    )
+}
+
+// Install, remove or list launchd agents, returning the exit status
+fn schedule(action: &cli::ScheduleAction, roots: &[String]) -> i32 {
+    let done = match action {
+        cli::ScheduleAction::Install {
+            interval,
+            prometheus,
+            flags,
+        } => schedule::install(&roots[0], *interval, prometheus.as_deref(), flags)
+            .map(|file| println!("installed {}", file.display())),
+        cli::ScheduleAction::Remove => schedule::remove(&roots[0]),
+        cli::ScheduleAction::Status => {
+            schedule::status().map(|agents| schedule::print_status(&agents))
+        }
+    };
+    match done {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("dumac: {}", e);
+            1
+        }
+    }
+}
+
+// Number of file handles available to worker threads, after those the scan
+// holds open for their subdirectories
+fn fd_budget() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return 1;
+    }
+    usize::try_from(limit.rlim_cur)
+        .unwrap_or(usize::MAX)
+        .saturating_sub(FD_RESERVE + hold_limit())
+        .max(1)
+}
+
+// Default worker count: one per CPU, or more if the root volume wants more
+// requests in flight (network filesystems), within the open file budget
+fn auto_threads(fd_budget: usize, root_dir: &str) -> usize {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let volume_concurrency = CString::new(root_dir)
+        .ok()
+        .and_then(|c_root| volume::volume_for_path(&c_root))
+        .and_then(|volume| volume::concurrency_for(&volume.fstype))
+        .unwrap_or(0);
+    cpus.max(volume_concurrency).min(fd_budget)
 }
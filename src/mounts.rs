@@ -0,0 +1,59 @@
+// The volumes mounted, for --all-volumes. Each is scanned on its own, with
+// what's mounted inside it left to its own scan, so every file counts once.
+use super::format_size;
+use std::ffi::CStr;
+
+// Filesystems with nothing on disk to count
+const PSEUDO_FSTYPES: &[&str] = &["devfs", "autofs", "nullfs"];
+
+// A mounted volume and its space, in 512-byte blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub mount_point: String,
+    pub fstype: String,
+    pub capacity: i64,
+    pub free: i64,
+}
+
+impl Mount {
+    // What the usage is put beside
+    pub fn describe(&self) -> String {
+        format!(
+            "{}, {} capacity, {} free",
+            self.fstype,
+            format_size(self.capacity),
+            format_size(self.free)
+        )
+    }
+}
+
+// Every mounted volume with files of its own, in mount order. The
+// read-only system volume is mounted from a snapshot and left out: what
+// isn't sealed in it is on the data volume.
+pub fn mounted() -> Vec<Mount> {
+    let mut list: *mut libc::statfs = std::ptr::null_mut();
+    // Without waiting on unresponsive network volumes
+    let count = unsafe { libc::getmntinfo(&mut list, libc::MNT_NOWAIT) };
+    if count <= 0 || list.is_null() {
+        return Vec::new();
+    }
+    let mounts = unsafe { std::slice::from_raw_parts(list, count as usize) };
+    mounts
+        .iter()
+        .filter(|fs| fs.f_flags & libc::MNT_SNAPSHOT as u32 == 0)
+        .map(|fs| {
+            let block_size = fs.f_bsize as i64;
+            Mount {
+                mount_point: unsafe { CStr::from_ptr(fs.f_mntonname.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+                fstype: unsafe { CStr::from_ptr(fs.f_fstypename.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+                capacity: fs.f_blocks as i64 * block_size / 512,
+                free: fs.f_bavail as i64 * block_size / 512,
+            }
+        })
+        .filter(|mount| !PSEUDO_FSTYPES.contains(&mount.fstype.as_str()))
+        .collect()
+}
@@ -0,0 +1,142 @@
+use super::format_size;
+use super::sync::Mutex;
+use std::cmp::Reverse;
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Write};
+
+// Files owned by one user or group
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    files: u64,
+    blocks: i64,
+}
+
+// Keyed by uid or gid. A directory's files usually have one owner, so a
+// list searched in order does.
+type Table = Vec<(u32, Usage)>;
+
+// Blocks counted for each user and group, for --by-owner
+#[derive(Default)]
+pub struct Owners {
+    tables: Mutex<Counts>,
+}
+
+// One directory's files, added to the scan's once it's been processed
+#[derive(Default)]
+pub struct Counts {
+    users: Table,
+    groups: Table,
+}
+
+impl Counts {
+    pub fn add(&mut self, uid: u32, gid: u32, blocks: i64) {
+        add(&mut self.users, uid, 1, blocks);
+        add(&mut self.groups, gid, 1, blocks);
+    }
+}
+
+impl Owners {
+    pub fn record(&self, counts: Counts) {
+        let mut tables = self.tables.lock();
+        for (uid, usage) in counts.users {
+            add(&mut tables.users, uid, usage.files, usage.blocks);
+        }
+        for (gid, usage) in counts.groups {
+            add(&mut tables.groups, gid, usage.files, usage.blocks);
+        }
+    }
+
+    // Largest first, by name where the account still exists
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let tables = std::mem::take(&mut *self.tables.lock());
+        writeln!(out, "by owner:")?;
+        print_table(tables.users, user_name, out)?;
+        writeln!(out, "by group:")?;
+        print_table(tables.groups, group_name, out)
+    }
+}
+
+fn add(table: &mut Table, id: u32, files: u64, blocks: i64) {
+    let usage = match table.iter().position(|(known, _)| *known == id) {
+        Some(i) => &mut table[i].1,
+        None => {
+            table.push((id, Usage::default()));
+            &mut table.last_mut().unwrap().1
+        }
+    };
+    usage.files += files;
+    usage.blocks += blocks;
+}
+
+fn print_table(mut table: Table, name: fn(u32) -> Option<String>, out: &mut String) -> fmt::Result {
+    table.sort_unstable_by_key(|(id, usage)| (Reverse(usage.blocks), *id));
+    for (id, usage) in table {
+        let noun = if usage.files == 1 { "file" } else { "files" };
+        let label = match name(id) {
+            Some(name) => format!("{} ({})", name, id),
+            None => id.to_string(),
+        };
+        writeln!(
+            out,
+            "  {}\t{} {}\t{}",
+            format_size(usage.blocks),
+            usage.files,
+            noun,
+            label
+        )?;
+    }
+    Ok(())
+}
+
+// The uid of the user called `name`, for --user
+pub fn user_id(name: &str) -> Option<u32> {
+    let c_name = CString::new(name).ok()?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let found = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if found != 0 || result.is_null() {
+        return None;
+    }
+    Some(pwd.pw_uid)
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let found =
+        unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if found != 0 || result.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(pwd.pw_name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn group_name(gid: u32) -> Option<String> {
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let found =
+        unsafe { libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if found != 0 || result.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(grp.gr_name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
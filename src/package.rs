@@ -0,0 +1,127 @@
+// Packages: directories Finder shows as one item, like apps and photo
+// libraries. With --package-depth, -i and serve list only that many levels
+// inside one, summing the rest into the deepest directory listed.
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// Extensions that make a directory a package whatever its Finder flags
+const EXTENSIONS: &[&str] = &[
+    "app",
+    "appex",
+    "bundle",
+    "dsym",
+    "fcpbundle",
+    "framework",
+    "imovielibrary",
+    "kext",
+    "mdimporter",
+    "musiclibrary",
+    "photoslibrary",
+    "pkg",
+    "plugin",
+    "prefpane",
+    "qlgenerator",
+    "saver",
+    "xcarchive",
+    "xpc",
+];
+
+// Finder flags are the big-endian u16 at this offset of
+// com.apple.FinderInfo, and kHasBundle marks a package
+const FINDER_FLAGS: usize = 8;
+const HAS_BUNDLE: u16 = 0x2000;
+
+// Whether Finder shows a directory as a single item
+pub fn is_package(path: &Path) -> bool {
+    let known = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        });
+    known || has_bundle_bit(path)
+}
+
+// The bundle bit from the directory's Finder info, unset when it has none
+fn has_bundle_bit(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut info = [0u8; 32];
+    let read = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            c"com.apple.FinderInfo".as_ptr(),
+            info.as_mut_ptr() as *mut libc::c_void,
+            info.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    read > (FINDER_FLAGS + 1) as isize
+        && u16::from_be_bytes([info[FINDER_FLAGS], info[FINDER_FLAGS + 1]]) & HAS_BUNDLE != 0
+}
+
+// Where a directory is relative to packages, which decides whether what's
+// in it is listed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Packages {
+    // Levels listed inside a package, every level when None
+    limit: Option<usize>,
+    // How far below the outermost package holding it, None outside any
+    depth: Option<usize>,
+}
+
+impl Packages {
+    pub fn new(limit: Option<usize>) -> Self {
+        Packages { limit, depth: None }
+    }
+
+    // The same for a directory in this one. Without a limit nothing is
+    // checked, so a plain -i never reads Finder info.
+    pub fn enter(self, path: &Path) -> Self {
+        let depth = match (self.limit, self.depth) {
+            (None, _) => None,
+            (Some(_), Some(depth)) => Some(depth + 1),
+            (Some(_), None) => is_package(path).then_some(0),
+        };
+        Packages { depth, ..self }
+    }
+
+    // Whether what's in this directory gets entries of its own
+    pub fn lists_contents(self) -> bool {
+        match (self.limit, self.depth) {
+            (Some(limit), Some(depth)) => depth < limit,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_depth() {
+        let outside = Packages::new(Some(1));
+        let app = outside.enter(Path::new("/Applications/Safari.app"));
+        let contents = app.enter(Path::new("/Applications/Safari.app/Contents"));
+        assert!(outside.lists_contents());
+        assert!(app.lists_contents());
+        assert!(!contents.lists_contents());
+        // Only the outermost package counts
+        let nested = contents.enter(Path::new("/Applications/Safari.app/Contents/X.framework"));
+        assert!(!nested.lists_contents());
+
+        assert!(!Packages::new(Some(0))
+            .enter(Path::new("/a/B.PhotosLibrary"))
+            .lists_contents());
+        assert!(Packages::new(None)
+            .enter(Path::new("/a/B.app"))
+            .lists_contents());
+        assert!(!is_package(Path::new("/a/app")));
+    }
+}
@@ -0,0 +1,117 @@
+use libc::c_int;
+use std::ffi::{CStr, CString};
+
+// Most directories a scan keeps open at once, see hold_limit
+const HELD_DIRS: usize = 256;
+
+// Lexically tidy a root for building child paths: repeated slashes collapse
+// and `.` components and trailing slashes go, so "./src//" walks as "src".
+// `..` is kept, after a symlink it doesn't cancel the previous component.
+// The root is still printed and opened as given.
+pub fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{}", joined)
+    } else if joined.is_empty() && !path.is_empty() {
+        ".".to_string()
+    } else {
+        joined
+    }
+}
+
+// Open a directory by a path of any length. A path past PATH_MAX, as deep
+// trees can build, is opened a piece at a time, each relative to the last.
+// Returns -1 with errno set on failure, like open.
+pub fn open_dir(c_path: &CStr, flags: c_int) -> c_int {
+    let path_max = libc::PATH_MAX as usize;
+    let mut rest = c_path.to_bytes();
+    if rest.len() < path_max {
+        return retry(|| unsafe { libc::open(c_path.as_ptr(), flags) });
+    }
+
+    let mut fd = libc::AT_FDCWD;
+    while !rest.is_empty() {
+        // As many whole components as fit
+        let (piece, tail) = if rest.len() < path_max {
+            (rest, &rest[rest.len()..])
+        } else {
+            match rest[..path_max].iter().rposition(|&b| b == b'/') {
+                Some(cut) if cut > 0 => (&rest[..cut], &rest[cut + 1..]),
+                _ => {
+                    close_keeping_errno(fd);
+                    unsafe { *libc::__error() = libc::ENAMETOOLONG };
+                    return -1;
+                }
+            }
+        };
+        // Taken from a CStr, so there's no NUL inside
+        let piece = CString::new(piece).unwrap();
+        let piece_flags = if tail.is_empty() {
+            flags
+        } else {
+            libc::O_RDONLY | libc::O_DIRECTORY
+        };
+        let next = retry(|| unsafe { libc::openat(fd, piece.as_ptr(), piece_flags) });
+        close_keeping_errno(fd);
+        if next == -1 {
+            return -1;
+        }
+        fd = next;
+        rest = tail;
+    }
+    fd
+}
+
+// stat a directory by a path of any length
+pub fn stat_dir(c_path: &CStr) -> Option<libc::stat> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if c_path.to_bytes().len() < libc::PATH_MAX as usize {
+        let result = unsafe { libc::stat(c_path.as_ptr(), &mut st) };
+        return (result == 0).then_some(st);
+    }
+    let fd = open_dir(c_path, libc::O_RDONLY | libc::O_DIRECTORY);
+    if fd == -1 {
+        return None;
+    }
+    let result = unsafe { libc::fstat(fd, &mut st) };
+    unsafe { libc::close(fd) };
+    (result == 0).then_some(st)
+}
+
+// How many listed directories a scan keeps open for their subdirectories to
+// be opened relative to: a quarter of the open file limit, as workers need
+// the rest
+pub fn hold_limit() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return 0;
+    }
+    usize::try_from(limit.rlim_cur / 4)
+        .unwrap_or(usize::MAX)
+        .min(HELD_DIRS)
+}
+
+// Retry a call interrupted by a signal
+pub fn retry(mut call: impl FnMut() -> c_int) -> c_int {
+    loop {
+        let result = call();
+        if result != -1 || unsafe { *libc::__error() } != libc::EINTR {
+            return result;
+        }
+    }
+}
+
+fn close_keeping_errno(fd: c_int) {
+    if fd != libc::AT_FDCWD {
+        let errno = unsafe { *libc::__error() };
+        unsafe { libc::close(fd) };
+        unsafe { *libc::__error() = errno };
+    }
+}
@@ -0,0 +1,254 @@
+// Patterns matched against paths relative to the scanned directory. Regular
+// expressions are POSIX extended ones, compiled by the system's regcomp, so
+// they behave as they do in grep -E and find -E. Globs are those of
+// .gitignore files.
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::sync::Arc;
+
+// A regular expression given with --exclude-regex
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,
+    compiled: Arc<Compiled>,
+}
+
+struct Compiled(libc::regex_t);
+
+// regexec only reads the compiled pattern, so workers can share it
+unsafe impl Send for Compiled {}
+unsafe impl Sync for Compiled {}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        unsafe { libc::regfree(&mut self.0) };
+    }
+}
+
+impl Regex {
+    // Compile `pattern`, or say what's wrong with it
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let c_pattern =
+            CString::new(pattern).map_err(|_| "contains a NUL character".to_string())?;
+        let mut regex: libc::regex_t = unsafe { std::mem::zeroed() };
+        let flags = libc::REG_EXTENDED | libc::REG_NOSUB;
+        let code = unsafe { libc::regcomp(&mut regex, c_pattern.as_ptr(), flags) };
+        if code != 0 {
+            let mut message = [0u8; 256];
+            unsafe {
+                libc::regerror(
+                    code,
+                    &regex,
+                    message.as_mut_ptr() as *mut libc::c_char,
+                    message.len(),
+                )
+            };
+            let message = CStr::from_bytes_until_nul(&message)
+                .map_or_else(|_| "invalid pattern".into(), CStr::to_string_lossy);
+            return Err(message.into_owned());
+        }
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            compiled: Arc::new(Compiled(regex)),
+        })
+    }
+
+    // As it was given
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // Whether it matches anywhere in `path`
+    pub fn is_match(&self, path: &CStr) -> bool {
+        let found =
+            unsafe { libc::regexec(&self.compiled.0, path.as_ptr(), 0, std::ptr::null_mut(), 0) };
+        found == 0
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.pattern).finish()
+    }
+}
+
+// Whether all of `path` matches a glob: `*` and `?` match within a
+// component, `[...]` matches one of a set (`!` or `^` negates it, `a-z` is a
+// range), `\` escapes, and `**` as a whole component matches any number of
+// components
+//
+// Each pattern component matches exactly one path component, so only the
+// last `*` and the last `**/` need retrying on a mismatch: a `*` takes one
+// more byte of its component, and once it can't, `**/` takes one more
+// component. That keeps the work to the lengths' product, where trying every
+// split is exponential in the number of stars.
+pub fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where the pattern resumes after the last `*` and `**/`, and the next
+    // byte of the path for them to take
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    // At the start of a pattern component, where `**` spans components
+    let mut component_start = true;
+    loop {
+        if component_start && pattern[p..].starts_with(b"**") {
+            match pattern.get(p + 2) {
+                None => return true,
+                Some(b'/') => {
+                    p += 3;
+                    globstar = Some((p, s));
+                    star = None;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        component_start = false;
+        let byte = path.get(s).filter(|&&byte| byte != b'/');
+        let step = match pattern.get(p) {
+            None if s == path.len() => return true,
+            None => None,
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, s));
+                continue;
+            }
+            Some(b'?') => byte.map(|_| 1),
+            Some(b'[') => match set_match(&pattern[p + 1..], byte) {
+                Some((matched, rest)) => {
+                    (byte.is_some() && matched).then_some(pattern.len() - rest.len() - p)
+                }
+                // An unclosed bracket is just a bracket
+                None => (path.get(s) == Some(&b'[')).then_some(1),
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                (path.get(s) == Some(&pattern[p + 1])).then_some(2)
+            }
+            Some(&literal) => {
+                component_start = literal == b'/';
+                (path.get(s) == Some(&literal)).then_some(1)
+            }
+        };
+        if let Some(length) = step {
+            p += length;
+            s += 1;
+            continue;
+        }
+        component_start = false;
+        if let Some((resume, next)) = star {
+            if path.get(next).is_some_and(|&byte| byte != b'/') {
+                star = Some((resume, next + 1));
+                (p, s) = (resume, next + 1);
+                continue;
+            }
+        }
+        if let Some((resume, next)) = globstar {
+            if let Some(slash) = path[next..].iter().position(|&byte| byte == b'/') {
+                globstar = Some((resume, next + slash + 1));
+                star = None;
+                (p, s, component_start) = (resume, next + slash + 1, true);
+                continue;
+            }
+        }
+        return false;
+    }
+}
+
+// Whether `byte` is in the set that `pattern` starts, just after its `[`,
+// and the pattern after the set's `]`. None when the set isn't closed.
+fn set_match<'a>(pattern: &'a [u8], byte: Option<&u8>) -> Option<(bool, &'a [u8])> {
+    let (negated, mut i) = match pattern.first() {
+        Some(b'!' | b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        // A `]` straight away is part of the set
+        if low == b']' && !first {
+            break;
+        }
+        first = false;
+        if low == b'\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        let mut high = low;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&b| b != b']') {
+            i += 2;
+            high = pattern[i];
+            if high == b'\\' {
+                i += 1;
+                high = *pattern.get(i)?;
+            }
+        }
+        matched |= byte.is_some_and(|&b| (low..=high).contains(&b));
+        i += 1;
+    }
+    Some((matched != negated, &pattern[i + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_stars_stay_in_a_component() {
+        assert!(glob_match(b"*.rs", b"main.rs"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"a*b*c", b"aXbYbZc"));
+        assert!(!glob_match(b"*.rs", b"src/main.rs"));
+        assert!(glob_match(b"src/*.rs", b"src/main.rs"));
+        assert!(!glob_match(b"src/*", b"src/a/b"));
+        assert!(glob_match(b"?.txt", b"a.txt"));
+        assert!(!glob_match(b"a?b", b"a/b"));
+        assert!(!glob_match(b"*.rs", b"main.rsx"));
+    }
+
+    #[test]
+    fn test_double_star_spans_components() {
+        assert!(glob_match(b"**/target", b"target"));
+        assert!(glob_match(b"**/target", b"a/b/target"));
+        assert!(!glob_match(b"**/target", b"a/b/targets"));
+        assert!(glob_match(b"a/**/b", b"a/b"));
+        assert!(glob_match(b"a/**/b", b"a/x/y/b"));
+        assert!(!glob_match(b"a/**/b", b"ax/b"));
+        assert!(glob_match(b"a/**", b"a/x/y"));
+        assert!(glob_match(b"**/x/**/*.o", b"p/x/q/r/m.o"));
+        assert!(!glob_match(b"**/x/**/*.o", b"p/x/q/r/m.c"));
+        // Not a whole component, so just two stars
+        assert!(glob_match(b"a**", b"abc"));
+        assert!(!glob_match(b"a**", b"a/c"));
+    }
+
+    #[test]
+    fn test_sets_and_escapes() {
+        assert!(glob_match(b"[abc].o", b"b.o"));
+        assert!(!glob_match(b"[abc].o", b"d.o"));
+        assert!(glob_match(b"[a-c]x", b"bx"));
+        assert!(glob_match(b"[!a-c]x", b"dx"));
+        assert!(glob_match(b"[^a-c]x", b"dx"));
+        assert!(!glob_match(b"[!a-c]x", b"ax"));
+        assert!(glob_match(b"[]]", b"]"));
+        assert!(glob_match(b"[a-]", b"-"));
+        assert!(!glob_match(b"a[!x]b", b"a/b"));
+        // An unclosed bracket is a literal one
+        assert!(glob_match(b"[ab", b"[ab"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"a"));
+        assert!(glob_match(b"\\[a]", b"[a]"));
+        assert!(glob_match(b"[\\]]", b"]"));
+    }
+
+    #[test]
+    fn test_many_stars_finish_quickly() {
+        let path = [b'a'; 200];
+        let started = Instant::now();
+        assert!(!glob_match(b"*a*a*a*a*a*a*a*a*a*a*b", &path));
+        let nested: Vec<u8> = [&b"a/"[..]; 60].concat();
+        assert!(!glob_match(b"**/a/**/a/**/a/**/a/**/b", &nested));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
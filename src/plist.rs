@@ -0,0 +1,145 @@
+// String values from the top-level dictionary of a property list, XML or
+// binary, which is all Info.plist and container metadata need, and arrays
+// of strings from XML ones, which is how dumac writes launchd agents.
+// Unknown or malformed input reads as no value.
+use std::fs;
+use std::path::Path;
+
+const BINARY_MAGIC: &[u8] = b"bplist00";
+
+pub fn string(path: &Path, key: &str) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    match data.starts_with(BINARY_MAGIC) {
+        true => Binary::new(&data)?.string(key),
+        false => xml_string(std::str::from_utf8(&data).ok()?, key),
+    }
+}
+
+// The <string> after <key>key</key>
+fn xml_string(plist: &str, key: &str) -> Option<String> {
+    let after = &plist[plist.find(&format!("<key>{}</key>", key))?..];
+    let after = after[after.find("</key>")? + "</key>".len()..].trim_start();
+    let value = after.strip_prefix("<string>")?;
+    Some(unescape(&value[..value.find("</string>")?]))
+}
+
+// The <string>s in the <array> after <key>key</key>, from an XML property
+// list
+pub fn strings(path: &Path, key: &str) -> Option<Vec<String>> {
+    let plist = fs::read_to_string(path).ok()?;
+    let after = &plist[plist.find(&format!("<key>{}</key>", key))?..];
+    let after = after[after.find("</key>")? + "</key>".len()..].trim_start();
+    let array = after.strip_prefix("<array>")?;
+    let array = &array[..array.find("</array>")?];
+    let strings = array
+        .split("<string>")
+        .skip(1)
+        .map(|item| unescape(item.split("</string>").next().unwrap_or_default()))
+        .collect();
+    Some(strings)
+}
+
+// Text with XML's entities written out
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// A bplist00 file: objects, a table of their offsets, and a trailer saying
+// how wide offsets and references are and which object is the top one
+struct Binary<'a> {
+    data: &'a [u8],
+    offsets: &'a [u8],
+    offset_size: usize,
+    ref_size: usize,
+    objects: usize,
+    top: usize,
+}
+
+impl<'a> Binary<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let trailer = data.get(data.len().checked_sub(32)?..)?;
+        let offset_size = trailer[6] as usize;
+        let ref_size = trailer[7] as usize;
+        let objects = be(&trailer[8..16]) as usize;
+        let top = be(&trailer[16..24]) as usize;
+        let table = be(&trailer[24..32]) as usize;
+        let offsets = data.get(table..table.checked_add(objects.checked_mul(offset_size)?)?)?;
+        Some(Binary {
+            data,
+            offsets,
+            offset_size,
+            ref_size,
+            objects,
+            top,
+        })
+    }
+
+    fn string(&self, key: &str) -> Option<String> {
+        let (marker, count, body) = self.object(self.top)?;
+        if marker != 0xd {
+            return None;
+        }
+        let refs = body.get(..count.checked_mul(2 * self.ref_size)?)?;
+        let (keys, values) = refs.split_at(count * self.ref_size);
+        let index = keys
+            .chunks(self.ref_size)
+            .position(|r| self.text(be(r) as usize).as_deref() == Some(key))?;
+        let value = &values[index * self.ref_size..(index + 1) * self.ref_size];
+        self.text(be(value) as usize)
+    }
+
+    // An object's type, its length and what follows the length
+    fn object(&self, index: usize) -> Option<(u8, usize, &'a [u8])> {
+        if index >= self.objects {
+            return None;
+        }
+        let at = self
+            .offsets
+            .get(index * self.offset_size..(index + 1) * self.offset_size)?;
+        let body = self.data.get(be(at) as usize..)?;
+        let (&marker, mut body) = body.split_first()?;
+        let mut count = (marker & 0xf) as usize;
+        // Longer lengths follow as an integer object
+        if count == 0xf {
+            let (&int, rest) = body.split_first()?;
+            let width = 1usize << (int & 0xf);
+            count = be(rest.get(..width)?) as usize;
+            body = &rest[width..];
+        }
+        Some((marker >> 4, count, body))
+    }
+
+    fn text(&self, index: usize) -> Option<String> {
+        let (marker, count, body) = self.object(index)?;
+        match marker {
+            // ASCII
+            0x5 => Some(String::from_utf8_lossy(body.get(..count)?).into_owned()),
+            // UTF-16, big-endian
+            0x6 => {
+                let units: Vec<u16> = body
+                    .get(..count.checked_mul(2)?)?
+                    .chunks(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                Some(String::from_utf16_lossy(&units))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn be(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| value << 8 | byte as u64)
+}
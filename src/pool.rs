@@ -0,0 +1,94 @@
+// Where scans run. With the default `parallel` feature that's a rayon pool
+// and directories are read concurrently. Without it rayon isn't linked and
+// everything runs on the calling thread, in the same depth-first order
+// every time.
+#[cfg(feature = "parallel")]
+pub use rayon::ThreadPool;
+#[cfg(feature = "parallel")]
+pub use rayon::{scope, Scope};
+#[cfg(not(feature = "parallel"))]
+pub use sequential::ThreadPool;
+#[cfg(not(feature = "parallel"))]
+pub use sequential::{scope, Scope};
+
+// A pool of `threads` workers, each calling `start` as it starts
+#[cfg(feature = "parallel")]
+pub fn build(
+    threads: usize,
+    start: impl Fn() + Send + Sync + 'static,
+) -> Result<ThreadPool, String> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .start_handler(move |_| start())
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Without workers there's nothing to start, the caller's thread does the work
+#[cfg(not(feature = "parallel"))]
+pub fn build(
+    _threads: usize,
+    _start: impl Fn() + Send + Sync + 'static,
+) -> Result<ThreadPool, String> {
+    Ok(ThreadPool)
+}
+
+// The calling worker's index in its pool, 0 outside one
+#[cfg(feature = "parallel")]
+pub(crate) fn current_thread_index() -> usize {
+    rayon::current_thread_index().unwrap_or(0)
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn current_thread_index() -> usize {
+    0
+}
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    use std::cell::RefCell;
+
+    // Stands in for rayon's pool
+    #[derive(Debug)]
+    pub struct ThreadPool;
+
+    impl ThreadPool {
+        pub fn install<R>(&self, op: impl FnOnce() -> R) -> R {
+            op()
+        }
+    }
+
+    type Job<'scope> = Box<dyn FnOnce(&Scope<'scope>) + 'scope>;
+
+    // Spawned jobs, run once the scope's own closure returns
+    pub struct Scope<'scope> {
+        jobs: RefCell<Vec<Job<'scope>>>,
+    }
+
+    impl<'scope> Scope<'scope> {
+        pub fn spawn<F>(&self, job: F)
+        where
+            F: FnOnce(&Scope<'scope>) + Send + 'scope,
+        {
+            self.jobs.borrow_mut().push(Box::new(job));
+        }
+    }
+
+    // Newest jobs run first, as a lone rayon worker pops its own deque, so
+    // the walk is depth-first and only holds one directory's subdirectories
+    // per level
+    pub fn scope<'scope, R>(op: impl FnOnce(&Scope<'scope>) -> R) -> R {
+        let scope = Scope {
+            jobs: RefCell::new(Vec::new()),
+        };
+        let result = op(&scope);
+        loop {
+            let job = scope.jobs.borrow_mut().pop();
+            match job {
+                Some(job) => job(&scope),
+                None => break,
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+// Set once at startup by --profile, counters are no-ops otherwise
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Call count and total time spent in an operation
+pub struct Timer {
+    calls: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Timer {
+    const fn new() -> Self {
+        Timer {
+            calls: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+        }
+    }
+
+    // Record one call that began at `start` (from profile::start)
+    pub fn record(&self, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.nanos
+                .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    fn secs(&self) -> f64 {
+        self.nanos.load(Ordering::Relaxed) as f64 / 1e9
+    }
+}
+
+// Plain event counter
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, n: u64) {
+        if enabled() {
+            self.0.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub static OPEN: Timer = Timer::new();
+pub static GETATTRLISTBULK: Timer = Timer::new();
+pub static PARSE: Timer = Timer::new();
+pub static DIRS: Counter = Counter::new();
+pub static ENTRIES: Counter = Counter::new();
+pub static INODE_INSERTS: Counter = Counter::new();
+pub static INODE_CAS_RETRIES: Counter = Counter::new();
+pub static BLOOM_SKIPS: Counter = Counter::new();
+pub static INODE_SPILLS: Counter = Counter::new();
+pub static VOLUME_WAITS: Counter = Counter::new();
+pub static CACHE_HITS: Counter = Counter::new();
+pub static FALLBACK_VOLUMES: Counter = Counter::new();
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Start timing an operation, None when profiling is off
+pub fn start() -> Option<Instant> {
+    enabled().then(Instant::now)
+}
+
+// Write out the collected counters. Times are summed across all worker
+// threads.
+pub fn report(out: &mut String) -> fmt::Result {
+    let dirs = DIRS.get().max(1) as f64;
+    writeln!(out, "profile (times summed across threads):")?;
+    writeln!(
+        out,
+        "  open             {:>10} calls  {:>9.3}s",
+        OPEN.calls(),
+        OPEN.secs()
+    )?;
+    writeln!(
+        out,
+        "  getattrlistbulk  {:>10} calls  {:>9.3}s  ({:.2} per directory)",
+        GETATTRLISTBULK.calls(),
+        GETATTRLISTBULK.secs(),
+        GETATTRLISTBULK.calls() as f64 / dirs
+    )?;
+    writeln!(
+        out,
+        "  parse            {:>10} entries{:>9.3}s",
+        ENTRIES.get(),
+        PARSE.secs()
+    )?;
+    writeln!(
+        out,
+        "  inode set        {:>10} inserts, {} CAS retries, {} chain walks skipped",
+        INODE_INSERTS.get(),
+        INODE_CAS_RETRIES.get(),
+        BLOOM_SKIPS.get()
+    )?;
+    writeln!(out, "  inode spills     {:>10}", INODE_SPILLS.get())?;
+    writeln!(out, "  volume waits     {:>10}", VOLUME_WAITS.get())?;
+    writeln!(out, "  cache hits       {:>10}", CACHE_HITS.get())?;
+    writeln!(out, "  readdir volumes  {:>10}", FALLBACK_VOLUMES.get())
+}
@@ -0,0 +1,58 @@
+// --prometheus FILE: a scan's totals in the Prometheus text format, for
+// node_exporter's textfile collector. The file is replaced in one rename so
+// the collector never reads half of it.
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Each root's total and how long the scan took, written to `path`
+pub fn write(path: &Path, totals: &[(String, i64)], elapsed: Duration) -> io::Result<()> {
+    let mut metrics = Vec::new();
+    writeln!(
+        metrics,
+        "# HELP dumac_directory_size_bytes Disk usage of a directory scanned by dumac."
+    )?;
+    writeln!(metrics, "# TYPE dumac_directory_size_bytes gauge")?;
+    for (root, blocks) in totals {
+        writeln!(
+            metrics,
+            "dumac_directory_size_bytes{{path=\"{}\"}} {}",
+            label_value(root),
+            blocks * 512
+        )?;
+    }
+    writeln!(
+        metrics,
+        "# HELP dumac_scan_duration_seconds How long the scan took."
+    )?;
+    writeln!(metrics, "# TYPE dumac_scan_duration_seconds gauge")?;
+    writeln!(
+        metrics,
+        "dumac_scan_duration_seconds {:.3}",
+        elapsed.as_secs_f64()
+    )?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    writeln!(
+        metrics,
+        "# HELP dumac_last_scan_timestamp_seconds When the scan finished."
+    )?;
+    writeln!(metrics, "# TYPE dumac_last_scan_timestamp_seconds gauge")?;
+    writeln!(metrics, "dumac_last_scan_timestamp_seconds {}", now)?;
+
+    // Beside the file, so the rename stays on one volume
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&partial, &metrics)?;
+    fs::rename(&partial, path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+fn label_value(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,108 @@
+// Python module, built into the cdylib with maturin (see pyproject.toml):
+//
+//     import dumac
+//     dumac.scan("/Users", clones=True, exclude=["node_modules"])["blocks"]
+//
+// Scans release the GIL, so other Python threads keep running.
+use super::{scan_streaming, strerror, DumacError, ScanOptions, ScanResult};
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+// The same counting and exclusion options as the command line, as keyword
+// arguments. Returns the ScanResult as a dict, with `tree=True` adding each
+// directory's blocks keyed by path under "tree".
+#[pyfunction]
+#[pyo3(signature = (
+    path,
+    *,
+    clones = false,
+    compression = false,
+    cloud = false,
+    xattrs = false,
+    exclude = Vec::new(),
+    exclude_backups = false,
+    exclude_system = false,
+    threads = None,
+    tree = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn scan<'py>(
+    py: Python<'py>,
+    path: &str,
+    clones: bool,
+    compression: bool,
+    cloud: bool,
+    xattrs: bool,
+    exclude: Vec<String>,
+    exclude_backups: bool,
+    exclude_system: bool,
+    threads: Option<usize>,
+    tree: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let mut options = ScanOptions::new()
+        .clones(clones)
+        .compression(compression)
+        .cloud(cloud)
+        .xattrs(xattrs)
+        .exclude_backups(exclude_backups)
+        .exclude_system(exclude_system);
+    for name in &exclude {
+        options = options.exclude(name);
+    }
+    if let Some(threads) = threads {
+        options = options.threads(threads);
+    }
+
+    let (dirs, result) = py.detach(|| match tree {
+        true => {
+            let mut stream = scan_streaming(path, &options);
+            let dirs: Vec<_> = stream.by_ref().collect();
+            (dirs, stream.finish())
+        }
+        false => (Vec::new(), super::scan(path, &options)),
+    });
+    let result = result.map_err(error)?;
+
+    let dict = to_dict(py, &result)?;
+    if tree {
+        let totals = PyDict::new(py);
+        for dir in dirs {
+            totals.set_item(dir.path.into_os_string(), dir.blocks)?;
+        }
+        dict.set_item("tree", totals)?;
+    }
+    Ok(dict)
+}
+
+fn to_dict<'py>(py: Python<'py>, result: &ScanResult) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("blocks", result.blocks)?;
+    dict.set_item("bytes", result.bytes)?;
+    dict.set_item("apparent_bytes", result.apparent_bytes)?;
+    dict.set_item("files", result.files)?;
+    dict.set_item("dirs", result.dirs)?;
+    dict.set_item("symlinks", result.symlinks)?;
+    dict.set_item("hardlinks", result.hardlinks)?;
+    dict.set_item("errors", result.errors)?;
+    dict.set_item("cancelled", result.cancelled)?;
+    dict.set_item("elapsed", result.elapsed.as_secs_f64())?;
+    Ok(dict)
+}
+
+// Errors on the root as OSError, so Python picks the subclass from the errno,
+// e.g. FileNotFoundError or PermissionError
+fn error(e: DumacError) -> PyErr {
+    match e {
+        DumacError::Io { path, errno, .. } | DumacError::Protected { path, errno } => {
+            PyOSError::new_err((errno, strerror(errno), path.into_os_string()))
+        }
+        DumacError::InvalidPath(_) => PyValueError::new_err(e.to_string()),
+        DumacError::Cache(_) | DumacError::Threads(_) => PyOSError::new_err(e.to_string()),
+    }
+}
+
+#[pymodule]
+fn dumac(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(scan, module)?)
+}
@@ -0,0 +1,78 @@
+use dumac::internal::normalize;
+use dumac::{DumacError, Listing, Listings};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+
+// A slow, deliberately plain walk to check dumac against: one directory at a
+// time with readdir and an fstatat per entry, no getattrlistbulk, no threads.
+// It keeps dumac's accounting (only files count, a symlink is one block,
+// hardlinks once) so a difference points at a bug rather than a convention.
+pub fn walk(root_dir: &str) -> Result<(i64, Listings), DumacError> {
+    let metadata = fs::metadata(root_dir).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: root_dir.into(),
+        errno: e.raw_os_error().unwrap_or(0),
+    })?;
+    let mut listings = Listings::new();
+    if !metadata.is_dir() {
+        return Ok((metadata.blocks() as i64, listings));
+    }
+
+    let mut seen = HashSet::new();
+    let mut total_blocks = 0;
+    // Paths are built the way dumac builds them so the listings line up
+    let mut pending = vec![normalize(root_dir).into_bytes()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(OsStr::from_bytes(&dir)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("dumac: reference: {}: {}", String::from_utf8_lossy(&dir), e);
+                continue;
+            }
+        };
+
+        let mut listing = Listing {
+            files: 0,
+            subdirs: 0,
+            blocks: 0,
+        };
+        for entry in entries {
+            // DirEntry::metadata is an fstatat that doesn't follow symlinks
+            let (name, metadata) =
+                match entry.and_then(|entry| Ok((entry.file_name(), entry.metadata()?))) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        eprintln!("dumac: reference: {}: {}", String::from_utf8_lossy(&dir), e);
+                        continue;
+                    }
+                };
+
+            if metadata.is_dir() {
+                listing.subdirs += 1;
+                let mut path = dir.clone();
+                if !path.ends_with(b"/") {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(name.as_bytes());
+                pending.push(path);
+                continue;
+            }
+
+            let blocks = if metadata.file_type().is_symlink() {
+                1
+            } else {
+                metadata.blocks() as i64
+            };
+            listing.files += 1;
+            listing.blocks += blocks;
+            if metadata.nlink() <= 1 || seen.insert((metadata.dev(), metadata.ino())) {
+                total_blocks += blocks;
+            }
+        }
+        listings.insert(dir.into(), listing);
+    }
+    Ok((total_blocks, listings))
+}
@@ -0,0 +1,282 @@
+// --remote [USER@]HOST:PATH: scan a directory on another Mac over ssh. The
+// remote dumac runs with --ndjson and its lines come back here, so the
+// totals print, alert and export like local ones. A dumac installed there
+// is used, or else this binary is copied to ~/.cache/dumac on the host,
+// when both are built for the same system.
+use super::failure::Failure;
+use super::json::{self, Value};
+use dumac::{format_size, Counts};
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::thread;
+
+const SSH: &str = "ssh";
+
+// The shell's exit status for a command not found
+const NOT_FOUND: i32 = 127;
+
+// Where a copied agent goes, under the remote home, with its version so a
+// newer one is copied again
+const AGENT_DIR: &str = ".cache/dumac";
+
+// Where dumac may be installed that isn't on the PATH ssh commands get
+const EXTRA_PATH: &str = "/opt/homebrew/bin:/usr/local/bin:$HOME/.cargo/bin";
+
+// A directory on another host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    // As ssh takes it, with the user if given
+    pub host: String,
+    pub path: String,
+}
+
+impl Target {
+    // From [USER@]HOST:PATH. An empty path is the remote home.
+    pub fn parse(target: &str) -> Option<Target> {
+        let (host, path) = target.split_once(':')?;
+        if host.is_empty() || host.starts_with('-') || host.ends_with('@') {
+            return None;
+        }
+        Some(Target {
+            host: host.to_string(),
+            path: match path {
+                "" => ".".to_string(),
+                path => path.to_string(),
+            },
+        })
+    }
+}
+
+// What a remote scan printed
+#[derive(Debug, Default)]
+pub struct Results {
+    // Each root's total, as host:path, with its counts for --count
+    pub totals: Vec<(String, i64, Option<Counts>)>,
+    // With --largest-files, also as host:path
+    pub largest: Vec<(String, i64)>,
+    // Whether it exited cleanly, so without errors on the way
+    pub succeeded: bool,
+}
+
+// A remote scan under way
+pub struct Pending {
+    scan: thread::JoinHandle<Result<Results, Failure>>,
+}
+
+impl Pending {
+    // Wait for the scan to end
+    pub fn finish(self) -> Result<Results, Failure> {
+        self.scan
+            .join()
+            .unwrap_or_else(|_| Err(Failure::Remote("remote scan panicked".to_string())))
+    }
+}
+
+// Start scanning `target` with `flags` given to the remote dumac. Its
+// stderr is printed here as it comes, each line after the host's name.
+pub fn start(target: Target, flags: Vec<String>) -> Pending {
+    Pending {
+        scan: thread::spawn(move || scan(&target, &flags)),
+    }
+}
+
+fn scan(target: &Target, flags: &[String]) -> Result<Results, Failure> {
+    let mut arguments = vec!["--ndjson".to_string()];
+    arguments.extend(flags.iter().map(|flag| quote(flag)));
+    arguments.push("--".to_string());
+    arguments.push(quote_path(&target.path));
+    let arguments = arguments.join(" ");
+
+    if let Some(results) = run(target, &arguments)? {
+        return Ok(results);
+    }
+    copy_agent(target)?;
+    run(target, &arguments)?
+        .ok_or_else(|| Failure::Remote(format!("{}: cannot run dumac", target.host)))
+}
+
+// Run the remote dumac, None when there's none to run
+fn run(target: &Target, arguments: &str) -> Result<Option<Results>, Failure> {
+    let agent = format!(
+        "\"$HOME/{}/dumac-{}\"",
+        AGENT_DIR,
+        env!("CARGO_PKG_VERSION")
+    );
+    let script = format!(
+        "PATH=\"$PATH:{path}\"; if [ -x {agent} ]; then exec {agent} {arguments}; \
+         elif command -v dumac >/dev/null 2>&1; then exec dumac {arguments}; fi; exit {missing}",
+        path = EXTRA_PATH,
+        agent = agent,
+        arguments = arguments,
+        missing = NOT_FOUND,
+    );
+    let mut child = Command::new(SSH)
+        .args(["--", &target.host, &script])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Failure::Remote(format!("cannot run {}: {}", SSH, e)))?;
+
+    let host = target.host.clone();
+    let stderr = child.stderr.take().map(|stderr| {
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{}: {}", host, line);
+            }
+        })
+    });
+    let mut results = Results::default();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            read_line(target, &line, &mut results);
+        }
+    }
+    let status = child
+        .wait()
+        .map_err(|e| Failure::Remote(format!("{}: {}", target.host, e)))?;
+    if let Some(stderr) = stderr {
+        let _ = stderr.join();
+    }
+    match status.code() {
+        Some(NOT_FOUND) => Ok(None),
+        // ssh's own failures, such as the host being unreachable
+        Some(255) => Err(Failure::Remote(format!("{}: ssh failed", target.host))),
+        code => {
+            results.succeeded = code == Some(0);
+            Ok(Some(results))
+        }
+    }
+}
+
+fn read_line(target: &Target, line: &str, results: &mut Results) {
+    let Some(fields) = json::object(line) else {
+        return;
+    };
+    let Some(Value::Integer(blocks)) = fields.get("blocks") else {
+        return;
+    };
+    let name = |path: &str| format!("{}:{}", target.host, path);
+    match (fields.get("path"), fields.get("file")) {
+        (Some(Value::String(path)), _) => {
+            let counts = match (fields.get("files"), fields.get("dirs")) {
+                (Some(Value::Integer(files)), Some(Value::Integer(dirs))) => Some(Counts {
+                    files: *files as u64,
+                    dirs: *dirs as u64,
+                }),
+                _ => None,
+            };
+            results.totals.push((name(path), *blocks, counts));
+        }
+        (_, Some(Value::String(file))) => results.largest.push((name(file), *blocks)),
+        _ => {}
+    }
+}
+
+// Copy this binary to the host's agent directory, if it can run there
+fn copy_agent(target: &Target) -> Result<(), Failure> {
+    let local = system();
+    let output = Command::new(SSH)
+        .args(["--", &target.host, "uname -sm"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Failure::Remote(format!("cannot run {}: {}", SSH, e)))?;
+    let remote = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || remote != local {
+        return Err(Failure::Remote(format!(
+            "{}: dumac isn't installed there, and this one is built for {}, not {}",
+            target.host,
+            local,
+            if remote.is_empty() {
+                "an unknown system"
+            } else {
+                &remote
+            }
+        )));
+    }
+
+    let program = std::env::current_exe()
+        .and_then(File::open)
+        .map_err(|e| Failure::Remote(format!("cannot read the dumac binary: {}", e)))?;
+    let agent = format!("{}/dumac-{}", AGENT_DIR, env!("CARGO_PKG_VERSION"));
+    // Renamed into place, so an interrupted copy is never run, and by a
+    // name of its own, so scans of the same host copying at once are fine
+    let script = format!(
+        "mkdir -p {dir} && cat > {agent}.$$ && chmod 755 {agent}.$$ && mv {agent}.$$ {agent}",
+        dir = AGENT_DIR,
+        agent = agent,
+    );
+    let copied = Command::new(SSH)
+        .args(["--", &target.host, &script])
+        .stdin(program)
+        .status()
+        .map_err(|e| Failure::Remote(format!("cannot run {}: {}", SSH, e)))?;
+    match copied.success() {
+        true => Ok(()),
+        false => Err(Failure::Remote(format!(
+            "{}: cannot copy dumac to ~/{}",
+            target.host, AGENT_DIR
+        ))),
+    }
+}
+
+// What `uname -sm` prints here
+fn system() -> String {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return String::new();
+    }
+    let field = |field: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(field.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    format!("{} {}", field(&name.sysname), field(&name.machine))
+}
+
+// A word for the remote shell
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// As quote, leaving a leading ~ or ~user for the shell to expand
+fn quote_path(path: &str) -> String {
+    let (home, rest) = path.split_once('/').unwrap_or((path, ""));
+    let user = home.strip_prefix('~').filter(|user| {
+        user.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    });
+    match (user, rest) {
+        (None, _) => quote(path),
+        (Some(_), "") if !path.contains('/') => home.to_string(),
+        (Some(_), "") => format!("{}/", home),
+        (Some(_), rest) => format!("{}/{}", home, quote(rest)),
+    }
+}
+
+// A root's total as --ndjson prints it
+pub fn total_line(path: &str, blocks: i64, counts: Option<Counts>) -> String {
+    let counts = match counts {
+        Some(counts) => format!(",\"files\":{},\"dirs\":{}", counts.files, counts.dirs),
+        None => String::new(),
+    };
+    format!(
+        "{{\"path\":{},\"blocks\":{},\"size\":{}{}}}",
+        json::string(path),
+        blocks,
+        json::string(&format_size(blocks)),
+        counts
+    )
+}
+
+// One of the largest files as --ndjson prints it
+pub fn file_line(path: &str, blocks: i64) -> String {
+    format!(
+        "{{\"file\":{},\"blocks\":{},\"size\":{}}}",
+        json::string(path),
+        blocks,
+        json::string(&format_size(blocks))
+    )
+}
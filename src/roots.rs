@@ -0,0 +1,74 @@
+use super::sync::Mutex;
+use super::{paths, DirNode};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Device and inode of a root, and the node it was reached as
+type Watched = HashMap<(u32, u64), Option<Arc<DirNode>>>;
+
+// Roots that lie inside another root, or repeat one, with the node they were
+// reached as while scanning the enclosing root
+#[derive(Default)]
+pub struct Nested {
+    roots: Mutex<Watched>,
+    // Skips the lookup for every directory when no root is nested
+    watching: AtomicBool,
+}
+
+impl Nested {
+    // Look out for a root while scanning the one enclosing it
+    pub fn watch(&self, root: &str) -> Option<(u32, u64)> {
+        let c_root = CString::new(root).ok()?;
+        let st = paths::stat_dir(&c_root)?;
+        let key = (st.st_dev as u32, st.st_ino);
+        self.roots.lock().insert(key, None);
+        self.watching.store(true, Ordering::Relaxed);
+        Some(key)
+    }
+
+    // Note a directory reached by a scan, in case another root names it
+    pub fn reached(&self, dev: u32, inode: u64, node: &Arc<DirNode>) {
+        if !self.watching.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(slot) = self.roots.lock().get_mut(&(dev, inode)) {
+            slot.get_or_insert_with(|| node.clone());
+        }
+    }
+
+    // The node of a watched root, once its enclosing scan is done
+    pub fn take(&self, key: (u32, u64)) -> Option<Arc<DirNode>> {
+        self.roots.lock().remove(&key)?
+    }
+}
+
+// For each root, the index of a root that contains it or an earlier one
+// that is the same directory, compared by canonical path
+pub fn enclosing_roots(roots: &[String]) -> Vec<Option<usize>> {
+    let canonical: Vec<Option<PathBuf>> = roots
+        .iter()
+        .map(|root| fs::canonicalize(root).ok())
+        .collect();
+    canonical
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let path = path.as_ref()?;
+            canonical
+                .iter()
+                .enumerate()
+                .filter_map(|(j, outer)| Some((j, outer.as_ref()?)))
+                .filter(|&(j, outer)| {
+                    let same = outer == path;
+                    j != i && path.starts_with(outer) && (!same || j < i)
+                })
+                // The outermost, which isn't nested itself
+                .min_by_key(|&(j, outer)| (outer.components().count(), j))
+                .map(|(j, _)| j)
+        })
+        .collect()
+}
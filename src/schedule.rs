@@ -0,0 +1,329 @@
+// `dumac schedule`: launchd agents that scan a directory every hour, day or
+// week, recording each scan to the history database or writing the totals
+// to a Prometheus textfile. Each directory gets an agent of its own in
+// ~/Library/LaunchAgents, loaded into the user's login session.
+use super::failure::Failure;
+use super::plist;
+use dumac::DumacError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const LAUNCHCTL: &str = "/bin/launchctl";
+
+// Agents' labels, and their files' names, start with this
+const LABEL_PREFIX: &str = "com.healeycodes.dumac.";
+
+// When scheduled scans run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interval {
+    Hourly,
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl Interval {
+    // From --hourly, --daily or --weekly
+    pub fn from_flag(flag: &str) -> Option<Interval> {
+        match flag {
+            "--hourly" => Some(Interval::Hourly),
+            "--daily" => Some(Interval::Daily),
+            "--weekly" => Some(Interval::Weekly),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Interval::Hourly => "hourly",
+            Interval::Daily => "daily",
+            Interval::Weekly => "weekly",
+        }
+    }
+
+    // Daily and weekly scans run at 3:00, weekly ones on Sundays. A Mac
+    // asleep then runs the scan when it wakes.
+    fn launchd_keys(self) -> &'static str {
+        match self {
+            Interval::Hourly => "\t<key>StartInterval</key>\n\t<integer>3600</integer>\n",
+            Interval::Daily => concat!(
+                "\t<key>StartCalendarInterval</key>\n\t<dict>\n",
+                "\t\t<key>Hour</key>\n\t\t<integer>3</integer>\n",
+                "\t\t<key>Minute</key>\n\t\t<integer>0</integer>\n\t</dict>\n"
+            ),
+            Interval::Weekly => concat!(
+                "\t<key>StartCalendarInterval</key>\n\t<dict>\n",
+                "\t\t<key>Weekday</key>\n\t\t<integer>0</integer>\n",
+                "\t\t<key>Hour</key>\n\t\t<integer>3</integer>\n",
+                "\t\t<key>Minute</key>\n\t\t<integer>0</integer>\n\t</dict>\n"
+            ),
+        }
+    }
+}
+
+// What the agent runs after the dumac binary: `dumac history` on the
+// directory, or a scan writing `prometheus`, with `flags` and at background
+// priority
+pub fn job_arguments(directory: &str, prometheus: Option<&str>, flags: &[String]) -> Vec<String> {
+    let mut arguments = Vec::new();
+    if prometheus.is_none() {
+        arguments.push("history".to_string());
+    }
+    arguments.extend(flags.iter().cloned());
+    if !flags
+        .iter()
+        .any(|flag| flag == "--background" || flag == "--nice")
+    {
+        arguments.push("--background".to_string());
+    }
+    if let Some(file) = prometheus {
+        arguments.extend(["--prometheus".to_string(), file.to_string()]);
+    }
+    arguments.push(directory.to_string());
+    arguments
+}
+
+// An agent as installed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+    pub label: String,
+    pub interval: Interval,
+    // The command line it runs
+    pub arguments: Vec<String>,
+    pub loaded: bool,
+    // How its latest run ended, as launchctl puts it
+    pub last_exit: Option<String>,
+}
+
+impl Agent {
+    // The directory scanned, last on the command line
+    pub fn directory(&self) -> &str {
+        self.arguments.last().map_or("", String::as_str)
+    }
+}
+
+// Write and load an agent scanning `directory`, replacing any it had.
+// Returns the agent's file.
+pub fn install(
+    directory: &str,
+    interval: Interval,
+    prometheus: Option<&str>,
+    flags: &[String],
+) -> Result<PathBuf, Failure> {
+    // launchd runs jobs from /, so paths given relative to here won't do
+    let directory = resolve(directory)?;
+    let prometheus = match prometheus {
+        Some(file) => Some(absolute(file)?),
+        None => None,
+    };
+    let program = std::env::current_exe()
+        .and_then(fs::canonicalize)
+        .map_err(|e| Failure::Schedule(format!("cannot find the dumac binary: {}", e)))?;
+
+    let label = label(&directory);
+    let agents = agents_dir()?;
+    let log = home()?
+        .join("Library/Logs/dumac")
+        .join(format!("{}.log", label));
+    let mut arguments = vec![program.to_string_lossy().into_owned()];
+    arguments.extend(job_arguments(&directory, prometheus.as_deref(), flags));
+
+    let mut agent = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ",
+        "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+        "<plist version=\"1.0\">\n<dict>\n"
+    ));
+    agent.push_str(&format!(
+        "\t<key>Label</key>\n\t<string>{}</string>\n",
+        plist::escape(&label)
+    ));
+    agent.push_str("\t<key>ProgramArguments</key>\n\t<array>\n");
+    for argument in &arguments {
+        agent.push_str(&format!(
+            "\t\t<string>{}</string>\n",
+            plist::escape(argument)
+        ));
+    }
+    agent.push_str("\t</array>\n");
+    agent.push_str(interval.launchd_keys());
+    agent.push_str("\t<key>ProcessType</key>\n\t<string>Background</string>\n");
+    agent.push_str("\t<key>LowPriorityIO</key>\n\t<true/>\n");
+    for key in ["StandardOutPath", "StandardErrorPath"] {
+        agent.push_str(&format!(
+            "\t<key>{}</key>\n\t<string>{}</string>\n",
+            key,
+            plist::escape(&log.to_string_lossy())
+        ));
+    }
+    agent.push_str("</dict>\n</plist>\n");
+
+    let file = agents.join(format!("{}.plist", label));
+    let written = fs::create_dir_all(&agents)
+        .and_then(|_| fs::create_dir_all(log.parent().unwrap_or(Path::new("/"))))
+        .and_then(|_| fs::write(&file, agent));
+    written.map_err(|e| Failure::Schedule(format!("{}: {}", file.display(), e)))?;
+
+    // Loading again over a loaded agent fails, so any earlier one goes first
+    let _ = launchctl(&["bootout", &service(&label)]);
+    let loaded = launchctl(&["bootstrap", &domain(), &file.to_string_lossy()])?;
+    if !loaded.status.success() {
+        return Err(launchctl_error("bootstrap", &loaded));
+    }
+    Ok(file)
+}
+
+// Unload and delete the agent scanning `directory`
+pub fn remove(directory: &str) -> Result<(), Failure> {
+    let directory = fs::canonicalize(directory)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| directory.to_string());
+    let label = label(&directory);
+    let file = agents_dir()?.join(format!("{}.plist", label));
+    if !file.exists() {
+        return Err(Failure::Schedule(format!(
+            "{}: no scheduled scan",
+            directory
+        )));
+    }
+    let _ = launchctl(&["bootout", &service(&label)]);
+    fs::remove_file(&file).map_err(|e| Failure::Schedule(format!("{}: {}", file.display(), e)))
+}
+
+// Every agent installed, by label
+pub fn status() -> Result<Vec<Agent>, Failure> {
+    let agents = agents_dir()?;
+    let entries = match fs::read_dir(&agents) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Failure::Schedule(format!("{}: {}", agents.display(), e)));
+        }
+    };
+    let mut installed = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(label) = name
+            .strip_suffix(".plist")
+            .filter(|label| label.starts_with(LABEL_PREFIX))
+        else {
+            continue;
+        };
+        let file = entry.path();
+        let text = fs::read_to_string(&file).unwrap_or_default();
+        let interval = match (
+            text.contains("<key>StartInterval</key>"),
+            text.contains("<key>Weekday</key>"),
+        ) {
+            (true, _) => Interval::Hourly,
+            (false, true) => Interval::Weekly,
+            (false, false) => Interval::Daily,
+        };
+        let printed = launchctl(&["print", &service(label)]).ok();
+        let loaded = printed
+            .as_ref()
+            .is_some_and(|output| output.status.success());
+        let last_exit = printed.and_then(|output| {
+            let text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.lines()
+                .find_map(|line| line.trim().strip_prefix("last exit code = "))
+                .map(str::to_string)
+        });
+        installed.push(Agent {
+            label: label.to_string(),
+            interval,
+            arguments: plist::strings(&file, "ProgramArguments").unwrap_or_default(),
+            loaded,
+            last_exit,
+        });
+    }
+    installed.sort_by(|a, b| a.label.cmp(&b.label));
+    Ok(installed)
+}
+
+// Each agent's directory and state, then what it runs
+pub fn print_status(agents: &[Agent]) {
+    if agents.is_empty() {
+        println!("no scheduled scans");
+    }
+    for agent in agents {
+        let state = match (&agent.loaded, &agent.last_exit) {
+            (false, _) => "not loaded".to_string(),
+            (true, Some(exit)) => format!("loaded, last exit code {}", exit),
+            (true, None) => "loaded".to_string(),
+        };
+        println!(
+            "{}\t{}, {}",
+            agent.directory(),
+            agent.interval.name(),
+            state
+        );
+        println!("\t{}", agent.arguments.join(" "));
+    }
+}
+
+// com.healeycodes.dumac.Users-me for /Users/me
+fn label(directory: &str) -> String {
+    let mut name = String::new();
+    for c in directory.chars() {
+        match c.is_ascii_alphanumeric() {
+            true => name.push(c),
+            false if !name.is_empty() && !name.ends_with('-') => name.push('-'),
+            false => {}
+        }
+    }
+    let name = name.trim_end_matches('-');
+    match name.is_empty() {
+        true => format!("{}root", LABEL_PREFIX),
+        false => format!("{}{}", LABEL_PREFIX, name),
+    }
+}
+
+fn resolve(directory: &str) -> Result<String, Failure> {
+    let resolved = fs::canonicalize(directory).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: directory.into(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+// A file that may not exist yet, from here
+fn absolute(file: &str) -> Result<String, Failure> {
+    let path = std::env::current_dir()
+        .map(|here| here.join(file))
+        .map_err(|e| Failure::Schedule(format!("{}: {}", file, e)))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn home() -> Result<PathBuf, Failure> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| Failure::Schedule("cannot find the home directory, set HOME".to_string()))
+}
+
+fn agents_dir() -> Result<PathBuf, Failure> {
+    Ok(home()?.join("Library/LaunchAgents"))
+}
+
+// The user's login session, where agents run
+fn domain() -> String {
+    format!("gui/{}", unsafe { libc::getuid() })
+}
+
+fn service(label: &str) -> String {
+    format!("{}/{}", domain(), label)
+}
+
+fn launchctl(arguments: &[&str]) -> Result<Output, Failure> {
+    Command::new(LAUNCHCTL)
+        .args(arguments)
+        .output()
+        .map_err(|e| Failure::Schedule(format!("cannot run {}: {}", LAUNCHCTL, e)))
+}
+
+fn launchctl_error(subcommand: &str, output: &Output) -> Failure {
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Failure::Schedule(format!("launchctl {} failed: {}", subcommand, message))
+}
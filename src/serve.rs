@@ -0,0 +1,519 @@
+// `dumac serve`: a directory's scan results over HTTP on a loopback
+// address, for dashboards and launcher extensions. Replies are JSON:
+//
+//   GET /status              the latest scan's total and whether one is running
+//   POST /scan               start a scan, or another once the running one ends
+//   GET /tree?path=P&depth=N a directory's total and its subdirectories', N
+//                            levels down (1 when not given)
+//   GET /progress            server-sent events with the running scan's
+//                            progress, ending once it's done
+//
+// Each connection gets its own thread and one reply, then is closed, so
+// request bodies and keep-alive are never needed.
+//
+// Being on loopback doesn't keep web pages out: a page can point its own
+// host name at 127.0.0.1 and read the replies, or post to /scan from another
+// origin. So requests must name this server in their Host header, and a
+// browser's POST must come from a page it served, which it never does.
+use super::json::string;
+use super::package::Packages;
+use dumac::internal::{Condvar, Mutex};
+use dumac::pool::ThreadPool;
+use dumac::stream::DirResult;
+use dumac::{format_size, DumacError, ScanOptions, Scanner};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How often /progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// How long a client has to send its request, and to take each write
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The longest request line and headers read, in bytes
+const MAX_REQUEST: u64 = 16 * 1024;
+
+// Connections answered at once, others are closed straight away
+const MAX_CLIENTS: usize = 64;
+
+// The deepest /tree goes, which also bounds how deep rendering recurses
+const MAX_DEPTH: usize = 64;
+
+// Every directory's total from the latest scan, keyed by resolved path
+struct Tree {
+    blocks: HashMap<PathBuf, i64>,
+    children: HashMap<PathBuf, Vec<PathBuf>>,
+    // Seconds since the epoch
+    finished: u64,
+}
+
+#[derive(Default)]
+struct State {
+    // A scan asked for and not yet started
+    requested: bool,
+    running: bool,
+    started: Option<Instant>,
+    scans: u64,
+    tree: Option<Tree>,
+    // Why the latest scan failed
+    error: Option<String>,
+}
+
+struct Shared {
+    root: PathBuf,
+    // Host headers naming this server, like localhost:8080
+    hosts: Vec<String>,
+    // Connections being answered
+    clients: AtomicUsize,
+    state: Mutex<State>,
+    changed: Condvar,
+    // Directories done in the running scan
+    progress: AtomicUsize,
+}
+
+// Scan `root_dir`, then answer requests on `listen` and scan again when
+// asked. Runs until interrupted or the address can't be listened on.
+// Inside a package, directories deeper than `package_depth` levels aren't
+// in the tree.
+pub fn serve(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    listen: &str,
+    package_depth: Option<usize>,
+) -> Result<(), DumacError> {
+    let bind_error = |errno: i32| DumacError::Io {
+        op: "bind".into(),
+        path: listen.into(),
+        errno,
+    };
+    let address: SocketAddr = listen.parse().map_err(|_| bind_error(libc::EINVAL))?;
+    // The replies name every directory, which only the user should see
+    if !address.ip().is_loopback() {
+        return Err(bind_error(libc::EADDRNOTAVAIL));
+    }
+    let listener = TcpListener::bind(address)
+        .map_err(|e| bind_error(e.raw_os_error().unwrap_or(libc::EIO)))?;
+    // Port 0 picks one, which the Host header has to name
+    let port = listener
+        .local_addr()
+        .map_or(address.port(), |bound| bound.port());
+    let hosts = vec![
+        format!("127.0.0.1:{}", port),
+        format!("localhost:{}", port),
+        format!("[::1]:{}", port),
+    ];
+    let root = fs::canonicalize(root_dir).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: root_dir.into(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    let scan_root = root
+        .to_str()
+        .ok_or_else(|| DumacError::InvalidPath(root.to_string_lossy().into_owned()))?
+        .to_string();
+
+    let shared = Arc::new(Shared {
+        root,
+        hosts,
+        clients: AtomicUsize::new(0),
+        state: Mutex::new(State {
+            requested: true,
+            ..State::default()
+        }),
+        changed: Condvar::new(),
+        progress: AtomicUsize::new(0),
+    });
+    let accepting = shared.clone();
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            if accepting.clients.fetch_add(1, Ordering::Relaxed) >= MAX_CLIENTS {
+                accepting.clients.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+            let shared = accepting.clone();
+            thread::spawn(move || {
+                answer(&shared, client);
+                shared.clients.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+
+    loop {
+        {
+            let mut state = shared.state.lock();
+            while !state.requested {
+                shared.changed.wait(&mut state);
+            }
+            state.requested = false;
+            state.running = true;
+            state.started = Some(Instant::now());
+            shared.progress.store(0, Ordering::Relaxed);
+        }
+        shared.changed.notify_all();
+        let scanned = scan(options, pool, &scan_root, package_depth, &shared);
+        let mut state = shared.state.lock();
+        state.running = false;
+        match scanned {
+            Ok(tree) => {
+                state.tree = Some(tree);
+                state.error = None;
+                state.scans += 1;
+            }
+            Err(e) => state.error = Some(e.to_string()),
+        }
+        drop(state);
+        shared.changed.notify_all();
+    }
+}
+
+fn scan(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    package_depth: Option<usize>,
+    shared: &Arc<Shared>,
+) -> Result<Tree, DumacError> {
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let mut scanner = Scanner::new(options.clone());
+    let results = found.clone();
+    let counting = shared.clone();
+    scanner.on_result(move |dir: DirResult| {
+        counting.progress.fetch_add(1, Ordering::Relaxed);
+        results.lock().push(dir);
+    });
+    pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+
+    let mut tree = Tree {
+        blocks: HashMap::new(),
+        children: HashMap::new(),
+        finished: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+    };
+    // Parents before their children, so each is known to be listed or not
+    // before what's in it
+    let mut found = std::mem::take(&mut *found.lock());
+    found.sort_by_key(|dir| dir.path.components().count());
+    let mut listed = HashMap::new();
+    for dir in found {
+        let packages = match dir.path.parent().filter(|_| dir.path != shared.root) {
+            Some(parent) => match listed.get(parent) {
+                Some(&packages) => {
+                    tree.children
+                        .entry(parent.to_path_buf())
+                        .or_default()
+                        .push(dir.path.clone());
+                    packages
+                }
+                // Its total is already in the package's
+                None => continue,
+            },
+            None => Packages::new(package_depth),
+        };
+        let packages = packages.enter(&dir.path);
+        if packages.lists_contents() {
+            listed.insert(dir.path.clone(), packages);
+        }
+        tree.blocks.insert(dir.path, dir.blocks);
+    }
+    Ok(tree)
+}
+
+// The headers a request is checked with
+#[derive(Default)]
+struct Headers {
+    host: Option<String>,
+    origin: Option<String>,
+    // Sec-Fetch-Site, which browsers send with every request
+    fetch_site: Option<String>,
+}
+
+fn answer(shared: &Shared, client: TcpStream) {
+    // A client that stalls mustn't hold its thread for good
+    let _ = client.set_read_timeout(Some(CLIENT_TIMEOUT));
+    let _ = client.set_write_timeout(Some(CLIENT_TIMEOUT));
+    let Ok(mut reply) = client.try_clone() else {
+        return;
+    };
+    // Reading stops at the limit, which a request that fits never reaches
+    let mut request = BufReader::new(client).take(MAX_REQUEST);
+    let mut line = String::new();
+    if request.read_line(&mut line).is_err() {
+        return;
+    }
+    // All headers are read, so closing doesn't reset the connection before
+    // the client has the reply
+    let mut headers = Headers::default();
+    let mut header = String::new();
+    while request.read_line(&mut header).is_ok_and(|read| read > 2) {
+        if let Some((name, value)) = header.split_once(':') {
+            let value = Some(value.trim().to_string());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "host" => headers.host = value,
+                "origin" => headers.origin = value,
+                "sec-fetch-site" => headers.fetch_site = value,
+                _ => {}
+            }
+        }
+        header.clear();
+    }
+    if request.limit() == 0 {
+        let _ = send(
+            &mut reply,
+            "431 Request Header Fields Too Large",
+            &error("request too large"),
+        );
+        return;
+    }
+    if !headers.host.as_ref().is_some_and(|host| {
+        shared
+            .hosts
+            .iter()
+            .any(|known| host.eq_ignore_ascii_case(known))
+    }) {
+        let _ = send(
+            &mut reply,
+            "421 Misdirected Request",
+            &error("unknown host"),
+        );
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" && cross_origin(shared, &headers) {
+        let _ = send(&mut reply, "403 Forbidden", &error("cross-origin request"));
+        return;
+    }
+    let (route, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = parse_query(query);
+    let _ = match (method, route) {
+        ("GET", "/status") => send(&mut reply, "200 OK", &status(shared)),
+        ("POST", "/scan") => {
+            shared.state.lock().requested = true;
+            shared.changed.notify_all();
+            send(&mut reply, "202 Accepted", &status(shared))
+        }
+        ("GET", "/tree") => match tree(shared, &params) {
+            Ok(body) => send(&mut reply, "200 OK", &body),
+            Err((code, message)) => send(&mut reply, code, &error(&message)),
+        },
+        ("GET", "/progress") => progress(shared, &mut reply),
+        (_, "/status" | "/scan" | "/tree" | "/progress") => send(
+            &mut reply,
+            "405 Method Not Allowed",
+            &error("method not allowed"),
+        ),
+        _ => send(&mut reply, "404 Not Found", &error("no such endpoint")),
+    };
+}
+
+// Whether a browser sent the request from a page this server didn't serve.
+// Clients that aren't browsers, like curl, send neither header.
+fn cross_origin(shared: &Shared, headers: &Headers) -> bool {
+    let foreign_origin = headers.origin.as_ref().is_some_and(|origin| {
+        !origin.strip_prefix("http://").is_some_and(|host| {
+            shared
+                .hosts
+                .iter()
+                .any(|known| host.eq_ignore_ascii_case(known))
+        })
+    });
+    let foreign_site = headers
+        .fetch_site
+        .as_ref()
+        .is_some_and(|site| site != "same-origin" && site != "none");
+    foreign_origin || foreign_site
+}
+
+fn send(client: &mut TcpStream, code: &str, body: &str) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    )
+}
+
+fn status(shared: &Shared) -> String {
+    let state = shared.state.lock();
+    let total = state
+        .tree
+        .as_ref()
+        .and_then(|tree| tree.blocks.get(&shared.root));
+    let mut json = String::from("{");
+    let _ = write!(json, "\"root\":{}", string(&shared.root.to_string_lossy()));
+    let _ = write!(json, ",\"running\":{}", state.running);
+    let _ = write!(json, ",\"queued\":{}", state.requested);
+    let _ = write!(json, ",\"scans\":{}", state.scans);
+    if state.running {
+        let _ = write!(
+            json,
+            ",\"directories_done\":{},\"elapsed\":{:.3}",
+            shared.progress.load(Ordering::Relaxed),
+            state
+                .started
+                .map_or(0.0, |started| started.elapsed().as_secs_f64())
+        );
+    }
+    match total {
+        Some(&blocks) => {
+            let _ = write!(
+                json,
+                ",\"blocks\":{},\"size\":{}",
+                blocks,
+                string(&format_size(blocks))
+            );
+        }
+        None => json.push_str(",\"blocks\":null"),
+    }
+    if let Some(tree) = &state.tree {
+        let _ = write!(json, ",\"scanned_at\":{}", tree.finished);
+    }
+    if let Some(message) = &state.error {
+        let _ = write!(json, ",\"error\":{}", string(message));
+    }
+    json.push('}');
+    json
+}
+
+fn tree(
+    shared: &Shared,
+    params: &HashMap<String, String>,
+) -> Result<String, (&'static str, String)> {
+    let path = match params.get("path") {
+        // Keyed by resolved path, as the root was scanned
+        Some(path) => fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)),
+        None => shared.root.clone(),
+    };
+    let depth = match params.get("depth") {
+        Some(depth) => depth
+            .parse::<usize>()
+            .map_err(|_| ("400 Bad Request", format!("invalid depth '{}'", depth)))?,
+        None => 1,
+    }
+    .min(MAX_DEPTH);
+    let state = shared.state.lock();
+    let Some(tree) = &state.tree else {
+        return Err((
+            "503 Service Unavailable",
+            "no scan has finished yet".to_string(),
+        ));
+    };
+    if !tree.blocks.contains_key(&path) {
+        let message = match path.starts_with(&shared.root) {
+            true => "not a directory, or excluded from the scan".to_string(),
+            false => format!("not under {}", shared.root.display()),
+        };
+        return Err(("404 Not Found", message));
+    }
+    let mut json = String::new();
+    write_node(&mut json, tree, &path, depth);
+    Ok(json)
+}
+
+// A directory with its subdirectories `depth` levels down, largest first
+fn write_node(json: &mut String, tree: &Tree, path: &Path, depth: usize) {
+    let blocks = tree.blocks.get(path).copied().unwrap_or(0);
+    let _ = write!(
+        json,
+        "{{\"path\":{},\"blocks\":{},\"size\":{}",
+        string(&path.to_string_lossy()),
+        blocks,
+        string(&format_size(blocks))
+    );
+    if depth > 0 {
+        let mut children: Vec<&PathBuf> = tree.children.get(path).into_iter().flatten().collect();
+        children.sort_by_key(|child| std::cmp::Reverse(tree.blocks.get(*child).copied()));
+        json.push_str(",\"children\":[");
+        for (i, child) in children.into_iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write_node(json, tree, child, depth - 1);
+        }
+        json.push(']');
+    }
+    json.push('}');
+}
+
+// The status as it changes until the running scan ends, or once when none
+// is running
+fn progress(shared: &Shared, client: &mut TcpStream) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    loop {
+        let running = shared.state.lock().running;
+        write!(client, "data: {}\n\n", status(shared))?;
+        client.flush()?;
+        if !running {
+            return Ok(());
+        }
+        thread::sleep(PROGRESS_INTERVAL);
+    }
+}
+
+fn error(message: &str) -> String {
+    format!("{{\"error\":{}}}", string(message))
+}
+
+// name=value pairs, percent-encoded
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (percent_decode(name), percent_decode(value)))
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // from_str_radix would take a sign, as in %+f
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Fb+c%c3%a9"), "a/b c\u{e9}");
+        // Not two hex digits, so the % is left as it is
+        assert_eq!(percent_decode("%+f%-1%zz%4"), "% f%-1%zz%4");
+    }
+}
@@ -0,0 +1,104 @@
+use super::sync::Mutex;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// A directory or entry the scan couldn't read, and so didn't count
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanError {
+    pub path: PathBuf,
+    // What went wrong, naming the path
+    pub message: String,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+// Where a scan's non-fatal errors go. Called from the scan's workers.
+// Breaking stops the scan as if it were cancelled.
+pub trait ErrorSink: Send + Sync {
+    fn error(&self, error: ScanError) -> ControlFlow<()>;
+
+    // The reports asked for in the options, once Scanner::report is called
+    // after the scan. Dropped unless the sink keeps them.
+    fn report(&self, _report: &str) {}
+}
+
+// Print each error to stderr and carry on, as the command line does
+pub struct Print;
+
+impl ErrorSink for Print {
+    fn error(&self, error: ScanError) -> ControlFlow<()> {
+        eprintln!("dumac: {}", error);
+        ControlFlow::Continue(())
+    }
+
+    fn report(&self, report: &str) {
+        eprint!("{}", report);
+    }
+}
+
+// Keep the errors and reports for the caller to look at after the scan
+#[derive(Default)]
+pub struct Collect {
+    errors: Mutex<Vec<ScanError>>,
+    reports: Mutex<String>,
+}
+
+impl Collect {
+    pub fn take(&self) -> Vec<ScanError> {
+        std::mem::take(&mut self.errors.lock())
+    }
+
+    pub fn take_reports(&self) -> String {
+        std::mem::take(&mut self.reports.lock())
+    }
+}
+
+impl ErrorSink for Collect {
+    fn error(&self, error: ScanError) -> ControlFlow<()> {
+        self.errors.lock().push(error);
+        ControlFlow::Continue(())
+    }
+
+    fn report(&self, report: &str) {
+        self.reports.lock().push_str(report);
+    }
+}
+
+// Print the first error and stop the scan
+pub struct Abort;
+
+impl ErrorSink for Abort {
+    fn error(&self, error: ScanError) -> ControlFlow<()> {
+        eprintln!("dumac: {}", error);
+        ControlFlow::Break(())
+    }
+
+    fn report(&self, report: &str) {
+        eprint!("{}", report);
+    }
+}
+
+// The sink in ScanOptions, printing unless the caller chose another
+#[derive(Clone)]
+pub(super) struct Sink(pub(super) Arc<dyn ErrorSink>);
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink(Arc::new(Print))
+    }
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ErrorSink")
+    }
+}
@@ -0,0 +1,144 @@
+// Every directory's total saved to a file, and the directories whose totals
+// differ between two of them, for `dumac snapshot` and `dumac diff`
+use super::cache::{read_path, read_u64, write_path};
+use super::pool::ThreadPool;
+use super::stream::DirResult;
+use super::sync::Mutex;
+use super::{DumacError, ScanOptions, Scanner};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Bumped whenever the file layout changes
+const MAGIC: &[u8; 8] = b"dumacs1\n";
+
+// Every directory's total from one scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    // As given to the scan
+    pub root: PathBuf,
+    // Keyed by path relative to the root, the root itself being empty, so
+    // snapshots of a tree that has moved still line up
+    pub dirs: BTreeMap<PathBuf, i64>,
+}
+
+// A directory whose total differs, 0 on the side it's missing from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub before: i64,
+    pub after: i64,
+}
+
+impl Snapshot {
+    pub fn scan(
+        options: &ScanOptions,
+        pool: &ThreadPool,
+        root_dir: &str,
+    ) -> Result<Snapshot, DumacError> {
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let mut scanner = Scanner::new(options.clone());
+        let results = found.clone();
+        scanner.results = Some(Box::new(move |dir: DirResult| results.lock().push(dir)));
+        pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+        scanner.report();
+
+        let found = std::mem::take(&mut *found.lock());
+        let dirs = found
+            .into_iter()
+            .filter_map(|dir| {
+                let relative = dir.path.strip_prefix(root_dir).ok()?.to_path_buf();
+                Some((relative, dir.blocks))
+            })
+            .collect();
+        Ok(Snapshot {
+            root: PathBuf::from(root_dir),
+            dirs,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DumacError> {
+        File::create(path)
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                self.write(&mut out)?;
+                out.flush()
+            })
+            .map_err(|e| snapshot_error("write", path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, DumacError> {
+        File::open(path)
+            .and_then(|file| Snapshot::read(&mut BufReader::new(file)))
+            .map_err(|e| snapshot_error("read", path, e))
+    }
+
+    // Whether the file at `path` looks like a snapshot, rather than e.g. a
+    // directory to scan
+    pub fn is_snapshot(path: &Path) -> bool {
+        let mut magic = [0u8; 8];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok_and(|_| &magic == MAGIC)
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_path(out, &self.root)?;
+        out.write_all(&(self.dirs.len() as u64).to_le_bytes())?;
+        for (path, blocks) in &self.dirs {
+            write_path(out, path)?;
+            out.write_all(&blocks.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<Snapshot> {
+        let mut magic = [0u8; 8];
+        if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a dumac snapshot",
+            ));
+        }
+        let root = read_path(input)?;
+        let count = read_u64(input)?;
+        let mut dirs = BTreeMap::new();
+        for _ in 0..count {
+            let path = read_path(input)?;
+            dirs.insert(path, read_u64(input)? as i64);
+        }
+        Ok(Snapshot { root, dirs })
+    }
+}
+
+// Directories that grew, shrank, appeared or went away between two
+// snapshots, the biggest changes first
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<Change> {
+    let paths: BTreeSet<&PathBuf> = before.dirs.keys().chain(after.dirs.keys()).collect();
+    let mut changes: Vec<Change> = paths
+        .into_iter()
+        .filter_map(|path| {
+            let old = before.dirs.get(path).copied().unwrap_or(0);
+            let new = after.dirs.get(path).copied().unwrap_or(0);
+            (old != new).then(|| Change {
+                path: path.clone(),
+                before: old,
+                after: new,
+            })
+        })
+        .collect();
+    changes.sort_by_key(|change| std::cmp::Reverse((change.after - change.before).abs()));
+    changes
+}
+
+// A file that isn't a snapshot, or is cut short, is the wrong type of file
+fn snapshot_error(op: &'static str, path: &Path, e: io::Error) -> DumacError {
+    DumacError::Io {
+        op: op.into(),
+        path: path.to_path_buf(),
+        errno: e.raw_os_error().unwrap_or(libc::EFTYPE),
+    }
+}
@@ -0,0 +1,381 @@
+use super::tally::Tally;
+use super::{visit, DirInfo, FileInfo, ListError, Scanner, SubdirInfo};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The root's identity and, when it isn't a directory, its size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metadata {
+    pub dev: u32,
+    pub inode: u64,
+    pub is_dir: bool,
+    pub blocks: i64,
+    pub apparent_bytes: i64,
+}
+
+// A non-directory entry in a listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedFile {
+    pub name: OsString,
+    pub blocks: i64,
+    pub dev: u32,
+    pub inode: u64,
+    pub nlink: u32,
+    pub symlink: bool,
+    pub apparent_bytes: i64,
+    // Seconds since the epoch, for --by-age and the age filters. Files
+    // without the one asked for aren't counted by an age filter.
+    pub modified: Option<i64>,
+    pub created: Option<i64>,
+    // User and group, for --by-owner and --user. Files without them aren't
+    // counted by --user.
+    pub owner: Option<(u32, u32)>,
+}
+
+// A subdirectory in a listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedDir {
+    pub name: OsString,
+    pub dev: u32,
+    pub inode: u64,
+    pub mount_point: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    pub files: Vec<ListedFile>,
+    pub dirs: Vec<ListedDir>,
+}
+
+// Where a scan's listings come from, in place of getattrlistbulk and
+// readdir. Counting, hardlink dedup, exclusion and cycle checks work the same
+// over any source, so they can be tested on trees held in memory. Called
+// from the scan's workers.
+pub trait DirSource: Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    fn list(&self, path: &Path) -> io::Result<Listing>;
+}
+
+// The source in ScanOptions, none meaning the filesystem
+#[derive(Clone)]
+pub(super) struct Source(pub(super) Arc<dyn DirSource>);
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DirSource")
+    }
+}
+
+fn path(c_path: &CStr) -> &Path {
+    Path::new(OsStr::from_bytes(c_path.to_bytes()))
+}
+
+pub(super) fn metadata(source: &Source, c_path: &CStr) -> Result<Metadata, i32> {
+    source
+        .0
+        .metadata(path(c_path))
+        .map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))
+}
+
+// A listing from the source in the scanner's own form
+pub(super) fn list(
+    scanner: &Scanner,
+    source: &Source,
+    c_path: &CStr,
+) -> Result<DirInfo, ListError> {
+    let listing = source
+        .0
+        .list(path(c_path))
+        .map_err(|e| ListError::new(c_path, e.raw_os_error().unwrap_or(libc::EIO)))?;
+
+    let mut tally = Tally::default();
+    let files = listing
+        .files
+        .into_iter()
+        .map(|file| {
+            tally.add(file.symlink, file.nlink, file.apparent_bytes);
+            let kind = match file.symlink {
+                true => visit::Kind::Symlink,
+                false => visit::Kind::File,
+            };
+            FileInfo {
+                blocks: file.blocks,
+                dev: file.dev,
+                inode: file.inode,
+                nlink: file.nlink,
+                clone: None,
+                flags: 0,
+                data_length: Some(file.apparent_bytes),
+                xattr_bytes: 0,
+                owner: file.owner.filter(|_| scanner.reads_owners()),
+                dated: match scanner.options.birthtime {
+                    true => file.created,
+                    false => file.modified,
+                }
+                .filter(|_| scanner.dates_files()),
+                entry: scanner
+                    .keep_names()
+                    .then(|| (file.name.into_vec().into(), kind)),
+            }
+        })
+        .collect();
+    let subdirs = listing
+        .dirs
+        .into_iter()
+        .map(|dir| SubdirInfo {
+            name: dir.name.into_vec().into(),
+            entries: 0,
+            mount_point: dir.mount_point,
+            dataless: false,
+            dev: dir.dev,
+            inode: dir.inode,
+            mtime: None,
+        })
+        .collect();
+    Ok(DirInfo {
+        files,
+        subdirs,
+        tally,
+    })
+}
+
+// A tree held in memory, for tests. Parent directories are added as needed,
+// every entry is on device 1 unless moved with `device`, and a hardlink
+// shares its target's inode.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    entries: BTreeMap<PathBuf, Node>,
+    binds: BTreeMap<PathBuf, PathBuf>,
+    next_inode: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Dir {
+        inode: u64,
+    },
+    // Lists the directory at the path it's keyed by in `binds`
+    Bind {
+        inode: u64,
+    },
+    File {
+        dev: u32,
+        inode: u64,
+        blocks: i64,
+        symlink: bool,
+        dated: Option<i64>,
+        owner: Option<(u32, u32)>,
+    },
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.add_dir(path.as_ref());
+        self
+    }
+
+    pub fn file(mut self, path: impl AsRef<Path>, blocks: i64) -> Self {
+        let inode = self.inode();
+        self.add(
+            path.as_ref(),
+            Node::File {
+                dev: 1,
+                inode,
+                blocks,
+                symlink: false,
+                dated: None,
+                owner: None,
+            },
+        );
+        self
+    }
+
+    pub fn symlink(mut self, path: impl AsRef<Path>) -> Self {
+        let inode = self.inode();
+        self.add(
+            path.as_ref(),
+            Node::File {
+                dev: 1,
+                inode,
+                blocks: 1,
+                symlink: true,
+                dated: None,
+                owner: None,
+            },
+        );
+        self
+    }
+
+    // Another name for the file at `target`, which must already be added
+    pub fn hardlink(mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> Self {
+        let node = self.entries[target.as_ref()];
+        self.add(path.as_ref(), node);
+        self
+    }
+
+    // Date the file at `path`, which must already be added, as modified
+    // and created `secs` after the epoch
+    pub fn dated(mut self, path: impl AsRef<Path>, secs: i64) -> Self {
+        let path = path.as_ref();
+        let Some(Node::File { dated, .. }) = self.entries.get_mut(path) else {
+            panic!("{} is not a file", path.display());
+        };
+        *dated = Some(secs);
+        self
+    }
+
+    // Give the file at `path`, which must already be added, an owner
+    pub fn owned(mut self, path: impl AsRef<Path>, uid: u32, gid: u32) -> Self {
+        let path = path.as_ref();
+        let Some(Node::File { owner, .. }) = self.entries.get_mut(path) else {
+            panic!("{} is not a file", path.display());
+        };
+        *owner = Some((uid, gid));
+        self
+    }
+
+    // Put the file at `path`, which must already be added, on device `dev`.
+    // A hardlink keeps its inode, as a file on another volume can have the
+    // same number.
+    pub fn device(mut self, path: impl AsRef<Path>, dev: u32) -> Self {
+        let path = path.as_ref();
+        let Some(Node::File { dev: device, .. }) = self.entries.get_mut(path) else {
+            panic!("{} is not a file", path.display());
+        };
+        *device = dev;
+        self
+    }
+
+    // The directory at `target` seen again at `path`, as through a bind mount
+    // or a directory hardlink. `target` must already be added, and may be an
+    // ancestor of `path` to make a cycle.
+    pub fn bind(mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> Self {
+        let target = target.as_ref();
+        let Node::Dir { inode } = self.entries[target] else {
+            panic!("{} is not a directory", target.display());
+        };
+        self.add(path.as_ref(), Node::Bind { inode });
+        self.binds
+            .insert(path.as_ref().to_path_buf(), target.to_path_buf());
+        self
+    }
+
+    // Where a path through binds leads
+    fn resolve(&self, path: &Path) -> PathBuf {
+        let mut path = path.to_path_buf();
+        // A cycle can be gone round any number of times
+        while let Some((bind, target)) = self.binds.iter().find(|(bind, _)| path.starts_with(bind))
+        {
+            path = target.join(path.strip_prefix(bind).unwrap());
+        }
+        path
+    }
+
+    fn inode(&mut self) -> u64 {
+        self.next_inode += 1;
+        self.next_inode
+    }
+
+    fn add_dir(&mut self, path: &Path) {
+        if !self.entries.contains_key(path) {
+            let inode = self.inode();
+            self.add(path, Node::Dir { inode });
+        }
+    }
+
+    fn add(&mut self, path: &Path, node: Node) {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            self.add_dir(parent);
+        }
+        self.entries.insert(path.to_path_buf(), node);
+    }
+
+    fn nlink(&self, dev: u32, inode: u64) -> u32 {
+        let links = self.entries.values().filter(|node| {
+            matches!(node, Node::File { dev: on, inode: other, .. } if (*on, *other) == (dev, inode))
+        });
+        links.count() as u32
+    }
+}
+
+impl DirSource for MemorySource {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let node = self
+            .entries
+            .get(&self.resolve(path))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        Ok(match *node {
+            Node::Dir { inode } | Node::Bind { inode } => Metadata {
+                dev: 1,
+                inode,
+                is_dir: true,
+                blocks: 0,
+                apparent_bytes: 0,
+            },
+            Node::File {
+                dev, inode, blocks, ..
+            } => Metadata {
+                dev,
+                inode,
+                is_dir: false,
+                blocks,
+                apparent_bytes: blocks * 512,
+            },
+        })
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Listing> {
+        let path = self.resolve(path);
+        if !matches!(self.entries.get(&path), Some(Node::Dir { .. })) {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        }
+        let mut listing = Listing::default();
+        let children = self
+            .entries
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(&path));
+        for (child, node) in children {
+            let name = child.file_name().unwrap_or_default().to_os_string();
+            match *node {
+                Node::Dir { inode } | Node::Bind { inode } => listing.dirs.push(ListedDir {
+                    name,
+                    dev: 1,
+                    inode,
+                    mount_point: false,
+                }),
+                Node::File {
+                    dev,
+                    inode,
+                    blocks,
+                    symlink,
+                    dated,
+                    owner,
+                } => listing.files.push(ListedFile {
+                    name,
+                    blocks,
+                    dev,
+                    inode,
+                    nlink: self.nlink(dev, inode),
+                    symlink,
+                    apparent_bytes: blocks * 512,
+                    modified: dated,
+                    created: dated,
+                    owner,
+                }),
+            }
+        }
+        Ok(listing)
+    }
+}
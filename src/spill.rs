@@ -0,0 +1,308 @@
+use super::inode_set::Budget;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+
+// Keys buffered in memory before being written out as a sorted run, at most
+// and at least: the buffer grows within the budget, and is flushed early
+// once it can't
+const BUFFER_KEYS: usize = 1 << 20;
+const MIN_BUFFER_KEYS: usize = 1 << 10;
+
+// Keys per indexed block of a run, a lookup reads one block (a 4KB page)
+const BLOCK_KEYS: usize = 256;
+
+// Runs are merged into one once there are more than this
+const MAX_RUNS: usize = 8;
+
+const KEY_BYTES: usize = size_of::<u128>();
+
+// Numbers run files, which every set in the process names the same way
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+// A sorted file of keys with the first key of every block kept in memory
+struct Run {
+    file: File,
+    index: Vec<u128>,
+    len: usize,
+    last: u128,
+}
+
+impl Run {
+    fn contains(&self, key: u128) -> io::Result<bool> {
+        if self.index.is_empty() || key < self.index[0] || key > self.last {
+            return Ok(false);
+        }
+        let block = self.index.partition_point(|&first| first <= key) - 1;
+        let start = block * BLOCK_KEYS;
+        let count = BLOCK_KEYS.min(self.len - start);
+
+        let mut block = [0u8; BLOCK_KEYS * KEY_BYTES];
+        let bytes = &mut block[..count * KEY_BYTES];
+        self.file.read_exact_at(bytes, (start * KEY_BYTES) as u64)?;
+        let key_at =
+            |i: usize| u128::from_le_bytes(bytes[i * KEY_BYTES..][..KEY_BYTES].try_into().unwrap());
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let mid = (low + high) / 2;
+            match key_at(mid).cmp(&key) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(true),
+            }
+        }
+        Ok(false)
+    }
+
+    fn index_bytes(&self) -> usize {
+        self.index.len() * KEY_BYTES
+    }
+}
+
+// Memory a buffer able to hold `keys` takes: a key and a control byte per
+// slot, with an eighth of the slots kept empty
+fn buffer_bytes(keys: usize) -> usize {
+    keys * (KEY_BYTES + 1) * 8 / 7
+}
+
+// Disk-backed set of u128 keys with bounded memory use: recent keys are
+// buffered, then written out as sorted runs that are searched on lookup
+// and periodically merged. Run files are unlinked as soon as they are
+// created, so nothing is left behind however the process exits. The buffer
+// and the runs' indexes count against the inode sets' budget.
+// Not thread-safe, callers serialize access.
+pub struct SpillSet {
+    buffer: HashSet<u128>,
+    runs: Vec<Run>,
+    budget: Arc<Budget>,
+    // Taken from the budget for the buffer and the indexes
+    buffer_bytes: usize,
+    index_bytes: usize,
+}
+
+impl SpillSet {
+    pub fn new(budget: Arc<Budget>) -> Self {
+        SpillSet {
+            buffer: HashSet::new(),
+            runs: Vec::new(),
+            budget,
+            buffer_bytes: 0,
+            index_bytes: 0,
+        }
+    }
+
+    // Returns true if the key was newly inserted, false if already present
+    pub fn insert(&mut self, key: u128) -> io::Result<bool> {
+        if self.buffer.contains(&key) {
+            return Ok(false);
+        }
+        for run in &self.runs {
+            if run.contains(key)? {
+                return Ok(false);
+            }
+        }
+
+        if !self.make_room() {
+            self.flush()?;
+        }
+        self.buffer.insert(key);
+        Ok(true)
+    }
+
+    // Whether the buffer has room for another key, growing it if the budget
+    // allows. The smallest buffer is taken whatever the budget.
+    fn make_room(&mut self) -> bool {
+        let capacity = self.buffer.capacity();
+        if self.buffer.len() < capacity.min(BUFFER_KEYS) {
+            return true;
+        }
+        if capacity >= BUFFER_KEYS {
+            return false;
+        }
+        let wanted = (capacity * 2).clamp(MIN_BUFFER_KEYS, BUFFER_KEYS);
+        let bytes = buffer_bytes(wanted) - self.buffer_bytes;
+        if capacity == 0 {
+            self.budget.charge(bytes);
+        } else if !self.budget.take(bytes) {
+            return false;
+        }
+        self.buffer.reserve(wanted - self.buffer.len());
+        self.buffer_bytes += bytes;
+        // The table rounds up to a power of two
+        let actual = buffer_bytes(self.buffer.capacity());
+        if actual > self.buffer_bytes {
+            self.budget.charge(actual - self.buffer_bytes);
+            self.buffer_bytes = actual;
+        }
+        true
+    }
+
+    // Write the buffered keys out as a run, keeping them buffered if that
+    // fails. The buffer keeps its memory for the keys that come next.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut keys: Vec<u128> = self.buffer.drain().collect();
+        keys.sort_unstable();
+        match self.write_run(keys.iter().copied().map(Ok)) {
+            Ok(run) => self.runs.push(run),
+            Err(e) => {
+                self.buffer.extend(keys);
+                return Err(e);
+            }
+        }
+        if self.runs.len() > MAX_RUNS {
+            self.merge_runs()?;
+        }
+        self.count_indexes();
+        Ok(())
+    }
+
+    // Bring what's taken from the budget for the runs' indexes up to date
+    fn count_indexes(&mut self) {
+        let bytes = self.runs.iter().map(Run::index_bytes).sum();
+        if bytes > self.index_bytes {
+            self.budget.charge(bytes - self.index_bytes);
+        } else {
+            self.budget.give_back(self.index_bytes - bytes);
+        }
+        self.index_bytes = bytes;
+    }
+
+    fn write_run(&self, keys: impl Iterator<Item = io::Result<u128>>) -> io::Result<Run> {
+        let path = std::env::temp_dir().join(format!(
+            "dumac-inodes-{}-{}",
+            std::process::id(),
+            NEXT_RUN.fetch_add(1, atomic::Ordering::Relaxed)
+        ));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+
+        let mut writer = BufWriter::new(&file);
+        let mut index = Vec::new();
+        let mut len = 0;
+        let mut last = 0;
+        for key in keys {
+            let key = key?;
+            if len % BLOCK_KEYS == 0 {
+                index.push(key);
+            }
+            writer.write_all(&key.to_le_bytes())?;
+            len += 1;
+            last = key;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        Ok(Run {
+            file,
+            index,
+            len,
+            last,
+        })
+    }
+
+    // K-way merge of all runs into a single run. The runs are only replaced
+    // once it's written, so a failed merge loses nothing.
+    fn merge_runs(&mut self) -> io::Result<()> {
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|run| {
+                let mut file = run.file.try_clone()?;
+                file.rewind()?;
+                Ok(BufReader::new(file))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(key) = read_key(reader)? {
+                heap.push(Reverse((key, i)));
+            }
+        }
+
+        // Runs hold disjoint keys, so no deduplication is needed while merging
+        let keys = std::iter::from_fn(|| {
+            let Reverse((key, i)) = heap.pop()?;
+            match read_key(&mut readers[i]) {
+                Ok(Some(next)) => heap.push(Reverse((next, i))),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            Some(Ok(key))
+        });
+        let merged = self.write_run(keys)?;
+        self.runs = vec![merged];
+        Ok(())
+    }
+}
+
+impl Drop for SpillSet {
+    fn drop(&mut self) {
+        self.budget.give_back(self.buffer_bytes + self.index_bytes);
+    }
+}
+
+fn read_key(reader: &mut impl Read) -> io::Result<Option<u128>> {
+    let mut bytes = [0u8; KEY_BYTES];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(u128::from_le_bytes(bytes))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Spread keys over the whole range, as (dev, inode) pairs are
+    fn key(i: u64) -> u128 {
+        ((i % 7) as u128) << 64 | i.wrapping_mul(0x9E37_79B9_7F4A_7C15) as u128
+    }
+
+    #[test]
+    fn test_keys_found_across_runs() {
+        // Nothing to spare, so the buffer stays at its smallest and is
+        // flushed over and over
+        let mut set = SpillSet::new(Budget::new(0));
+        let count = 20 * MIN_BUFFER_KEYS as u64;
+        for i in 0..count {
+            assert!(set.insert(key(i)).unwrap());
+        }
+        assert!(!set.runs.is_empty());
+        assert!(set.runs.len() <= MAX_RUNS);
+        assert!(set.buffer.capacity() < 2 * MIN_BUFFER_KEYS);
+        for i in 0..count {
+            assert!(!set.insert(key(i)).unwrap(), "key {i} was lost");
+        }
+        for i in count..count + 100 {
+            assert!(set.insert(key(i)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_runs_cover_block_edges() {
+        let mut set = SpillSet::new(Budget::new(0));
+        let keys: Vec<u128> = (0..MIN_BUFFER_KEYS as u128 * 3).map(|i| i * 2).collect();
+        for &key in &keys {
+            assert!(set.insert(key).unwrap());
+        }
+        // Every key, including each block's first and last, and the gaps
+        // between them
+        for &key in &keys {
+            assert!(!set.insert(key).unwrap());
+        }
+        for &key in &keys {
+            assert!(set.insert(key + 1).unwrap());
+        }
+    }
+}
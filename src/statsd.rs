@@ -0,0 +1,73 @@
+// --statsd HOST:PORT: a scan's totals and duration sent as StatsD gauges
+// over UDP, which graphite and most metrics agents accept. Each path
+// becomes a metric name of its own, dumac.size.Users.me.Library for
+// /Users/me/Library.
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+// Metrics go out several to a datagram, within what fits in an Ethernet
+// frame
+const MAX_DATAGRAM: usize = 1432;
+
+// Each root's total in bytes and how long the scan took, sent to `address`
+pub fn send(address: &str, totals: &[(String, i64)], elapsed: Duration) -> io::Result<()> {
+    let mut metrics = Vec::new();
+    for (root, blocks) in totals {
+        metrics.push(format!(
+            "dumac.size.{}:{}|g",
+            metric_name(root),
+            blocks * 512
+        ));
+    }
+    metrics.push(format!(
+        "dumac.scan_duration_seconds:{:.3}|g",
+        elapsed.as_secs_f64()
+    ));
+
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for the host"))?;
+    let socket = UdpSocket::bind(match address {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    socket.connect(address)?;
+    let mut datagram = String::new();
+    for metric in metrics {
+        if !datagram.is_empty() && datagram.len() + 1 + metric.len() > MAX_DATAGRAM {
+            socket.send(datagram.as_bytes())?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(&metric);
+    }
+    socket.send(datagram.as_bytes())?;
+    Ok(())
+}
+
+// A path's components as the dotted parts of a name, with anything StatsD
+// or graphite would read as syntax replaced. The root is "root".
+fn metric_name(path: &str) -> String {
+    let parts: Vec<String> = path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.chars()
+                .map(
+                    |c| match c.is_ascii_alphanumeric() || matches!(c, '-' | '_') {
+                        true => c,
+                        false => '_',
+                    },
+                )
+                .collect()
+        })
+        .collect();
+    match parts.is_empty() {
+        true => "root".to_string(),
+        false => parts.join("."),
+    }
+}
@@ -0,0 +1,68 @@
+use super::{DumacError, ScanResult};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+// Completed directories waiting for the consumer before workers block
+pub(super) const BOUND: usize = 1024;
+
+// Hands a completed directory to the consumer, from the scan's workers
+pub(super) type Sender = Box<dyn Fn(DirResult) + Send + Sync>;
+
+// A directory whose subtree has been fully counted
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirResult {
+    pub path: PathBuf,
+    // Its total, hardlinks counted under whichever directory reached them first
+    pub blocks: i64,
+}
+
+// Directories from `scan_streaming` as they complete, children before their
+// parents and the scanned directory last
+pub struct Stream {
+    pub(super) results: mpsc::Receiver<DirResult>,
+    pub(super) scan: thread::JoinHandle<Result<ScanResult, DumacError>>,
+}
+
+impl Iterator for Stream {
+    type Item = DirResult;
+
+    fn next(&mut self) -> Option<DirResult> {
+        self.results.recv().ok()
+    }
+}
+
+impl Stream {
+    // Wait for the scan and return its result. Directories not yet taken
+    // are dropped, so the scan doesn't wait on them.
+    pub fn finish(self) -> Result<ScanResult, DumacError> {
+        drop(self.results);
+        self.scan
+            .join()
+            .unwrap_or_else(|_| Err(DumacError::Threads("scan thread panicked".to_string())))
+    }
+}
+
+// Directories from `scan_async` as they complete, in the same order
+#[cfg(feature = "async")]
+pub struct AsyncStream {
+    pub(super) results: tokio::sync::mpsc::Receiver<DirResult>,
+    pub(super) scan: tokio::task::JoinHandle<Result<ScanResult, DumacError>>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncStream {
+    pub async fn next(&mut self) -> Option<DirResult> {
+        self.results.recv().await
+    }
+
+    // Wait for the scan and return its result, dropping directories not yet
+    // taken like Stream::finish
+    pub async fn finish(self) -> Result<ScanResult, DumacError> {
+        drop(self.results);
+        self.scan
+            .await
+            .unwrap_or_else(|_| Err(DumacError::Threads("scan task panicked".to_string())))
+    }
+}
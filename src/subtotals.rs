@@ -0,0 +1,65 @@
+use super::format_size;
+use super::sync::Mutex;
+use super::volume::Volume;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Blocks counted on each volume a scan crossed into, for --by-volume and
+// for scans that cross mount points without -x
+#[derive(Default)]
+pub struct Subtotals {
+    // The first volume counted, usually the root's, which holds most
+    // directories and so is added to without the lock
+    first: OnceLock<Arc<Volume>>,
+    first_blocks: AtomicI64,
+    others: Mutex<HashMap<u64, (Arc<Volume>, i64)>>,
+}
+
+impl Subtotals {
+    // Count a directory's files towards the volume it lives on
+    pub fn record(&self, volume: &Arc<Volume>, blocks: i64) {
+        if self.first.get_or_init(|| volume.clone()).dev == volume.dev {
+            self.first_blocks.fetch_add(blocks, Ordering::Relaxed);
+            return;
+        }
+        self.others
+            .lock()
+            .entry(volume.dev)
+            .or_insert_with(|| (volume.clone(), 0))
+            .1 += blocks;
+    }
+
+    // Whether more than one volume was counted
+    pub fn crossed(&self) -> bool {
+        !self.others.lock().is_empty()
+    }
+
+    // Largest first, so external and network volumes stand out under the
+    // boot volume
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let others = self.others.lock();
+        let mut subtotals: Vec<_> = others.values().cloned().collect();
+        if let Some(first) = self.first.get() {
+            subtotals.push((first.clone(), self.first_blocks.load(Ordering::Relaxed)));
+        }
+        subtotals.sort_unstable_by_key(|(volume, blocks)| (Reverse(*blocks), volume.dev));
+        writeln!(out, "by volume:")?;
+        for (volume, blocks) in subtotals {
+            let mount_point = match volume.mount_point.as_str() {
+                "" => "unknown volume",
+                mount_point => mount_point,
+            };
+            writeln!(
+                out,
+                "  {}\t{} ({})",
+                format_size(blocks),
+                mount_point,
+                volume.fstype
+            )?;
+        }
+        Ok(())
+    }
+}
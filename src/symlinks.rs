@@ -0,0 +1,43 @@
+use super::sync::Mutex;
+use super::{entry_path, errno};
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Write};
+
+// Symlinks whose targets don't resolve, for --report-broken-symlinks
+#[derive(Default)]
+pub struct Broken {
+    paths: Mutex<Vec<String>>,
+}
+
+impl Broken {
+    // Follow a symlink listed in `dir`, open as `dirfd`, and note it if its
+    // target doesn't resolve
+    pub fn check(&self, dirfd: libc::c_int, dir: &CStr, name: &[u8]) {
+        let Ok(c_name) = CString::new(name) else {
+            return;
+        };
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatat(dirfd, c_name.as_ptr(), &mut st, 0) } == 0 {
+            return;
+        }
+        // A target that can't be reached for other reasons, such as
+        // permissions, may well exist
+        if !matches!(errno(), libc::ENOENT | libc::ENOTDIR | libc::ELOOP) {
+            return;
+        }
+        let path = entry_path(dir, name);
+        self.paths
+            .lock()
+            .push(String::from_utf8_lossy(&path).into_owned());
+    }
+
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        let mut paths = self.paths.lock();
+        paths.sort_unstable();
+        writeln!(out, "broken symlinks: {}", paths.len())?;
+        for path in paths.iter() {
+            writeln!(out, "  {}", path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+// Locks. parking_lot's with the `parallel` feature, otherwise std's behind
+// the same interface so the rest of the crate doesn't care which.
+#[cfg(feature = "parallel")]
+pub use parking_lot::{Condvar, Mutex, MutexGuard};
+#[cfg(not(feature = "parallel"))]
+pub use std_locks::{Condvar, Mutex, MutexGuard};
+
+#[cfg(not(feature = "parallel"))]
+mod std_locks {
+    use std::ops::{Deref, DerefMut};
+    use std::sync::{self, PoisonError};
+
+    // Never poisoned, a panicking scan has nothing to protect
+    #[derive(Debug, Default)]
+    pub struct Mutex<T>(sync::Mutex<T>);
+
+    // Only empty while Condvar::wait has the lock
+    pub struct MutexGuard<'a, T>(Option<sync::MutexGuard<'a, T>>);
+
+    impl<T> Mutex<T> {
+        pub fn new(value: T) -> Self {
+            Mutex(sync::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            MutexGuard(Some(self.0.lock().unwrap_or_else(PoisonError::into_inner)))
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.0.as_ref().unwrap()
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            self.0.as_mut().unwrap()
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Condvar(sync::Condvar);
+
+    impl Condvar {
+        pub fn new() -> Self {
+            Condvar(sync::Condvar::new())
+        }
+
+        pub fn notify_one(&self) {
+            self.0.notify_one();
+        }
+
+        pub fn notify_all(&self) {
+            self.0.notify_all();
+        }
+
+        pub fn wait<T>(&self, guard: &mut MutexGuard<'_, T>) {
+            let held = guard.0.take().unwrap();
+            guard.0 = Some(self.0.wait(held).unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+}
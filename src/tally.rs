@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+// What one directory's listing held. Kept with cached listings, which
+// collapse plain files into one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tally {
+    // Regular files, devices, sockets and FIFOs
+    pub files: u64,
+    pub symlinks: u64,
+    // Logical sizes of files with a single link. Hardlinked ones are added
+    // once per inode as they're counted, as `du -A` does.
+    pub apparent_bytes: i64,
+}
+
+impl Tally {
+    pub fn add(&mut self, symlink: bool, nlink: u32, apparent_bytes: i64) {
+        if symlink {
+            self.symlinks += 1;
+        } else {
+            self.files += 1;
+        }
+        if nlink <= 1 {
+            self.apparent_bytes += apparent_bytes;
+        }
+    }
+}
+
+// Counts across a scan, added to once per directory
+#[derive(Default)]
+pub struct Totals {
+    pub files: AtomicU64,
+    pub dirs: AtomicU64,
+    pub symlinks: AtomicU64,
+    pub apparent_bytes: AtomicI64,
+    // Files skipped because another link to them was already counted
+    pub hardlinks: AtomicU64,
+}
+
+impl Totals {
+    pub fn record_dir(&self, tally: &Tally) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        self.files.fetch_add(tally.files, Ordering::Relaxed);
+        self.symlinks.fetch_add(tally.symlinks, Ordering::Relaxed);
+        self.apparent_bytes
+            .fetch_add(tally.apparent_bytes, Ordering::Relaxed);
+    }
+
+    // A file given to scan in place of a directory
+    pub fn record_file(&self, apparent_bytes: i64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.record_apparent(apparent_bytes);
+    }
+
+    // A hardlinked file reached for the first time
+    pub fn record_apparent(&self, apparent_bytes: i64) {
+        self.apparent_bytes
+            .fetch_add(apparent_bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_hardlink(&self) {
+        self.hardlinks.fetch_add(1, Ordering::Relaxed);
+    }
+}
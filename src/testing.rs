@@ -0,0 +1,212 @@
+// Reproducible random trees on disk for tests and benchmarks, behind the
+// `testing` feature. The same seed and settings give the same tree:
+//
+//     let tree = TreeBuilder::new(7).depth(3).fanout(3).hardlinks(0.1).build(dir)?;
+//     assert_eq!(dumac::scan(dir, &options)?.blocks, tree.blocks);
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// What to generate. Ratios are the chance of each file being made that way.
+#[derive(Debug, Clone)]
+pub struct TreeBuilder {
+    seed: u64,
+    depth: usize,
+    fanout: usize,
+    files: usize,
+    max_file_size: u64,
+    hardlinks: f64,
+    sparse: f64,
+    non_utf8: f64,
+}
+
+// What was generated, counted the way a scan counts it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tree {
+    pub path: PathBuf,
+    // Including the root
+    pub dirs: u64,
+    // Every name that isn't a directory, hardlinks included
+    pub files: u64,
+    // Names sharing an inode with a file made earlier
+    pub hardlinks: u64,
+    pub sparse: u64,
+    // Names that aren't UTF-8. APFS and HFS+ refuse them, so on those
+    // volumes this stays 0 and the files get UTF-8 names instead.
+    pub non_utf8: u64,
+    // Allocated blocks of the files, each inode once
+    pub blocks: i64,
+}
+
+impl TreeBuilder {
+    pub fn new(seed: u64) -> Self {
+        TreeBuilder {
+            seed,
+            depth: 3,
+            fanout: 3,
+            files: 8,
+            max_file_size: 16 * 1024,
+            hardlinks: 0.0,
+            sparse: 0.0,
+            non_utf8: 0.0,
+        }
+    }
+
+    // Levels of directories below the root
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    // Subdirectories per directory
+    pub fn fanout(mut self, fanout: usize) -> Self {
+        self.fanout = fanout;
+        self
+    }
+
+    // Files per directory, hardlinks included
+    pub fn files(mut self, files: usize) -> Self {
+        self.files = files;
+        self
+    }
+
+    // Sizes are picked uniformly up to this
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    pub fn hardlinks(mut self, ratio: f64) -> Self {
+        self.hardlinks = ratio;
+        self
+    }
+
+    // Sparse files are a megabyte long with only their last bytes written
+    pub fn sparse(mut self, ratio: f64) -> Self {
+        self.sparse = ratio;
+        self
+    }
+
+    pub fn non_utf8_names(mut self, ratio: f64) -> Self {
+        self.non_utf8 = ratio;
+        self
+    }
+
+    // Generate the tree under `root`, which is created if needed and should
+    // be empty
+    pub fn build(&self, root: impl AsRef<Path>) -> io::Result<Tree> {
+        let root = root.as_ref();
+        fs::create_dir_all(root)?;
+        let mut generator = Generator {
+            builder: self,
+            rng: Rng(self.seed),
+            tree: Tree {
+                path: root.to_path_buf(),
+                dirs: 1,
+                ..Tree::default()
+            },
+            linkable: Vec::new(),
+        };
+        generator.dir(root, self.depth)?;
+        Ok(generator.tree)
+    }
+}
+
+struct Generator<'a> {
+    builder: &'a TreeBuilder,
+    rng: Rng,
+    tree: Tree,
+    // Files made so far, for hardlinks to point at
+    linkable: Vec<PathBuf>,
+}
+
+impl Generator<'_> {
+    fn dir(&mut self, dir: &Path, depth: usize) -> io::Result<()> {
+        for index in 0..self.builder.files {
+            self.file(dir, index)?;
+        }
+        if depth == 0 {
+            return Ok(());
+        }
+        for index in 0..self.builder.fanout {
+            let subdir = dir.join(format!("dir{}", index));
+            fs::create_dir(&subdir)?;
+            self.tree.dirs += 1;
+            self.dir(&subdir, depth - 1)?;
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, dir: &Path, index: usize) -> io::Result<()> {
+        let path = self.name(dir, index)?;
+        self.tree.files += 1;
+
+        if !self.linkable.is_empty() && self.rng.chance(self.builder.hardlinks) {
+            let target = &self.linkable[self.rng.below(self.linkable.len() as u64) as usize];
+            fs::hard_link(target, &path)?;
+            self.tree.hardlinks += 1;
+            return Ok(());
+        }
+
+        let mut file = File::create(&path)?;
+        if self.rng.chance(self.builder.sparse) {
+            file.seek(SeekFrom::Start(1024 * 1024))?;
+            file.write_all(b"sparse")?;
+            self.tree.sparse += 1;
+        } else {
+            let size = self.rng.below(self.builder.max_file_size + 1);
+            file.write_all(&vec![0xa5; size as usize])?;
+        }
+        drop(file);
+        self.tree.blocks += fs::symlink_metadata(&path)?.blocks() as i64;
+        self.linkable.push(path);
+        Ok(())
+    }
+
+    // A free name for the directory's `index`th file, not UTF-8 when the
+    // ratio picks it and the filesystem allows it
+    fn name(&mut self, dir: &Path, index: usize) -> io::Result<PathBuf> {
+        let path = dir.join(format!("file{}", index));
+        if !self.rng.chance(self.builder.non_utf8) {
+            return Ok(path);
+        }
+        let mut name = format!("file{}-", index).into_bytes();
+        name.extend_from_slice(b"\xff\xfe");
+        let odd = dir.join(OsString::from_vec(name));
+        match File::create(&odd) {
+            Ok(_) => {
+                fs::remove_file(&odd)?;
+                self.tree.non_utf8 += 1;
+                Ok(odd)
+            }
+            Err(e) if e.raw_os_error() == Some(libc::EILSEQ) => Ok(path),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// splitmix64, so trees don't depend on a rand crate's version
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+
+    fn chance(&mut self, ratio: f64) -> bool {
+        // The top 53 bits as a fraction in [0, 1)
+        let unit = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        unit < ratio
+    }
+}
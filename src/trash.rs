@@ -0,0 +1,205 @@
+// `dumac trash`: what the Trash holds on a volume and how much of the
+// volume is purgeable, which between them explain why Finder and df show
+// different free space. df shows statfs's available blocks. Finder also
+// counts what macOS removes by itself when space runs low, such as caches
+// and local Time Machine snapshots. No statfs field or volume attribute
+// holds that, so it comes from CoreFoundation as the capacity available for
+// important usage, less what statfs says is available.
+use dumac::pool::ThreadPool;
+use dumac::{format_size, DumacError, ScanOptions, Scanner};
+use std::ffi::{c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+
+type CFAllocatorRef = *const c_void;
+type CFStringRef = *const c_void;
+type CFURLRef = *const c_void;
+type CFIndex = isize;
+
+const K_CF_NUMBER_SINT64_TYPE: CFIndex = 4;
+
+#[cfg_attr(target_os = "macos", link(name = "CoreFoundation", kind = "framework"))]
+extern "C" {
+    static kCFURLVolumeAvailableCapacityForImportantUsageKey: CFStringRef;
+    fn CFURLCreateFromFileSystemRepresentation(
+        allocator: CFAllocatorRef,
+        buffer: *const u8,
+        length: CFIndex,
+        is_directory: u8,
+    ) -> CFURLRef;
+    fn CFURLCopyResourcePropertyForKey(
+        url: CFURLRef,
+        key: CFStringRef,
+        value: *mut *const c_void,
+        error: *mut *const c_void,
+    ) -> u8;
+    fn CFNumberGetValue(number: *const c_void, kind: CFIndex, value: *mut c_void) -> u8;
+    fn CFRelease(object: *const c_void);
+}
+
+// Where the system volume's data lives since macOS 10.15. It shares its
+// APFS container, and so its free space, with the read-only system volume.
+const DATA_VOLUME: &str = "/System/Volumes/Data";
+
+// A volume's space, in 512-byte blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Space {
+    pub mount_point: String,
+    pub fstype: String,
+    pub capacity: i64,
+    pub used: i64,
+    // What df shows
+    pub free: i64,
+    // What Finder shows, when CoreFoundation says
+    pub available: Option<i64>,
+}
+
+impl Space {
+    // Space macOS can free by itself
+    pub fn purgeable(&self) -> Option<i64> {
+        self.available
+            .map(|available| (available - self.free).max(0))
+    }
+}
+
+// The space on the volume holding `path`
+pub fn space(path: &str) -> Result<Space, DumacError> {
+    let c_path = CString::new(path).map_err(|_| DumacError::InvalidPath(path.to_string()))?;
+    let mut fs: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut fs) } != 0 {
+        return Err(DumacError::Io {
+            op: "statfs".into(),
+            path: path.into(),
+            errno: std::io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO),
+        });
+    }
+    let block_size = fs.f_bsize as i64;
+    let blocks = |count: u64| count as i64 * block_size / 512;
+    Ok(Space {
+        mount_point: unsafe { CStr::from_ptr(fs.f_mntonname.as_ptr()) }
+            .to_string_lossy()
+            .into_owned(),
+        fstype: unsafe { CStr::from_ptr(fs.f_fstypename.as_ptr()) }
+            .to_string_lossy()
+            .into_owned(),
+        capacity: blocks(fs.f_blocks),
+        used: blocks(fs.f_blocks - fs.f_bfree),
+        free: blocks(fs.f_bavail),
+        available: important_capacity(path).map(|bytes| bytes / 512),
+    })
+}
+
+// NSURLVolumeAvailableCapacityForImportantUsageKey, in bytes
+fn important_capacity(path: &str) -> Option<i64> {
+    let url = unsafe {
+        CFURLCreateFromFileSystemRepresentation(
+            std::ptr::null(),
+            path.as_ptr(),
+            path.len() as CFIndex,
+            1,
+        )
+    };
+    if url.is_null() {
+        return None;
+    }
+    let mut number = std::ptr::null();
+    let copied = unsafe {
+        CFURLCopyResourcePropertyForKey(
+            url,
+            kCFURLVolumeAvailableCapacityForImportantUsageKey,
+            &mut number,
+            std::ptr::null_mut(),
+        )
+    };
+    let mut bytes: i64 = 0;
+    let read = copied != 0
+        && !number.is_null()
+        && unsafe {
+            CFNumberGetValue(
+                number,
+                K_CF_NUMBER_SINT64_TYPE,
+                &mut bytes as *mut i64 as *mut c_void,
+            )
+        } != 0;
+    unsafe {
+        if !number.is_null() {
+            CFRelease(number);
+        }
+        CFRelease(url);
+    }
+    read.then_some(bytes)
+}
+
+// The volume's Trashes folder, and the user's Trash when it's on the volume
+fn trashes(space: &Space) -> Vec<PathBuf> {
+    let mut trashes = vec![Path::new(&space.mount_point).join(".Trashes")];
+    if let Some(home) = std::env::var_os("HOME") {
+        let home_volume = home.to_str().and_then(mount_point);
+        let on_volume = home_volume.as_deref() == Some(space.mount_point.as_str())
+            || (space.mount_point == "/" && home_volume.as_deref() == Some(DATA_VOLUME));
+        if on_volume {
+            trashes.insert(0, Path::new(&home).join(".Trash"));
+        }
+    }
+    trashes.into_iter().filter(|trash| trash.is_dir()).collect()
+}
+
+fn mount_point(path: &str) -> Option<String> {
+    let c_path = CString::new(path).ok()?;
+    let mut fs: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut fs) } != 0 {
+        return None;
+    }
+    let mount_point = unsafe { CStr::from_ptr(fs.f_mntonname.as_ptr()) };
+    Some(mount_point.to_string_lossy().into_owned())
+}
+
+// For each volume a root is on, its space and what its Trash holds
+pub fn run(options: &ScanOptions, pool: &ThreadPool, roots: &[String]) -> Result<(), DumacError> {
+    let mut shown = Vec::new();
+    for root in roots {
+        let space = space(root)?;
+        if shown.contains(&space.mount_point) {
+            continue;
+        }
+        shown.push(space.mount_point.clone());
+        print_space(options, pool, &space);
+    }
+    Ok(())
+}
+
+fn print_space(options: &ScanOptions, pool: &ThreadPool, space: &Space) {
+    println!("{} ({})", space.mount_point, space.fstype);
+    println!("  {}\tcapacity", format_size(space.capacity));
+    println!("  {}\tused", format_size(space.used));
+    println!("  {}\tfree, as df counts it", format_size(space.free));
+    if let (Some(available), Some(purgeable)) = (space.available, space.purgeable()) {
+        println!(
+            "  {}\tpurgeable, freed by macOS as space runs low",
+            format_size(purgeable)
+        );
+        println!(
+            "  {}\tavailable, as Finder counts it",
+            format_size(available)
+        );
+    }
+
+    // One scanner, so files in more than one Trash count once
+    let scanner = Scanner::new(options.clone());
+    let mut total = 0;
+    for trash in trashes(space) {
+        let Some(path) = trash.to_str() else {
+            continue;
+        };
+        match pool.install(|| scanner.calculate_size(path.to_string())) {
+            Ok(blocks) => {
+                println!("  {}\tin {}", format_size(blocks), trash.display());
+                total += blocks;
+            }
+            Err(e) => eprintln!("dumac: {}", e),
+        }
+    }
+    scanner.report();
+    println!("  {}\tfreed by emptying the Trash", format_size(total));
+}
@@ -0,0 +1,668 @@
+// `dumac -i`: browsing the scanned tree in the terminal, ncdu-style, and
+// deleting from it. The scan keeps every entry the visitor sees, then the
+// terminal is put in raw mode and redrawn after each key.
+use super::package::Packages;
+use dumac::internal::{normalize, strerror, Mutex};
+use dumac::pool::ThreadPool;
+use dumac::visit::{self, Kind};
+use dumac::{format_size, Collect, DumacError, ScanOptions, Scanner};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::ControlFlow;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const TTY: &str = "/dev/tty";
+
+// The bottom line, after the totals
+const HELP: &str =
+    "arrows move/open/back  space mark  d delete  s size  n name  a apparent  q quit";
+
+// Width of the bar comparing an entry with the largest beside it
+const BAR_WIDTH: usize = 10;
+
+// An entry in the tree, its parent and children by index
+struct Node {
+    name: OsString,
+    kind: Kind,
+    // For directories, the sum of everything below, so that the rows under
+    // a directory add up to it. A file hardlinked in two places counts in
+    // both.
+    blocks: i64,
+    apparent_bytes: i64,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+// Everything the scan visited, the root first. Deleted entries stay in
+// `nodes`, only unlinked from their parent.
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    // `packages` is where the directory holding `root_dir` is
+    fn scan(
+        scanner: &mut Scanner,
+        pool: &ThreadPool,
+        root_dir: &str,
+        packages: Packages,
+    ) -> Result<Tree, DumacError> {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let visited = entries.clone();
+        scanner.visit(move |entry: &visit::Entry| {
+            visited.lock().push((
+                entry.path.to_path_buf(),
+                entry.kind,
+                entry.blocks,
+                entry.apparent_bytes,
+                entry.depth,
+            ));
+            ControlFlow::Continue(())
+        });
+        pool.install(|| scanner.calculate_size(root_dir.to_string()))?;
+
+        let mut nodes = vec![Node {
+            name: OsString::from(root_dir),
+            kind: Kind::Dir,
+            blocks: 0,
+            apparent_bytes: 0,
+            parent: None,
+            children: Vec::new(),
+        }];
+        // Entries are named under the root in its tidied form. Directories
+        // whose contents aren't listed map to the node they're summed into.
+        let root = PathBuf::from(normalize(root_dir));
+        let packages = packages.enter(&root);
+        let mut dirs = HashMap::from([(root, (0, Some(packages)))]);
+        let mut entries = std::mem::take(&mut *entries.lock());
+        // Parents before their children, so every child is added after it
+        entries.sort_by_key(|entry| entry.4);
+        for (path, kind, blocks, apparent_bytes, _) in entries {
+            let Some(&(parent, within)) = path.parent().and_then(|parent| dirs.get(parent)) else {
+                continue;
+            };
+            // Deeper than --package-depth inside a package
+            let Some(within) = within.filter(|within| within.lists_contents()) else {
+                nodes[parent].blocks += blocks;
+                nodes[parent].apparent_bytes += apparent_bytes;
+                if kind == Kind::Dir {
+                    dirs.insert(path, (parent, None));
+                }
+                continue;
+            };
+            let index = nodes.len();
+            nodes.push(Node {
+                name: path.file_name().unwrap_or_default().to_os_string(),
+                kind,
+                blocks,
+                apparent_bytes,
+                parent: Some(parent),
+                children: Vec::new(),
+            });
+            nodes[parent].children.push(index);
+            if kind == Kind::Dir {
+                let within = within.enter(&path);
+                dirs.insert(path, (index, Some(within)));
+            }
+        }
+        // Children come after their parents, so going backwards sums each
+        // subtree before adding it to its parent
+        for index in (1..nodes.len()).rev() {
+            if let Some(parent) = nodes[index].parent {
+                nodes[parent].blocks += nodes[index].blocks;
+                nodes[parent].apparent_bytes += nodes[index].apparent_bytes;
+            }
+        }
+        Ok(Tree { nodes })
+    }
+
+    // Set an entry's size, changing its directories' totals to match
+    fn resize(&mut self, index: usize, blocks: i64, apparent_bytes: i64) {
+        let node = &self.nodes[index];
+        let (blocks, apparent_bytes) = (blocks - node.blocks, apparent_bytes - node.apparent_bytes);
+        let mut next = Some(index);
+        while let Some(index) = next {
+            self.nodes[index].blocks += blocks;
+            self.nodes[index].apparent_bytes += apparent_bytes;
+            next = self.nodes[index].parent;
+        }
+    }
+
+    // Take a deleted entry out of the tree and its size out of the totals
+    fn remove(&mut self, index: usize) {
+        self.resize(index, 0, 0);
+        if let Some(parent) = self.nodes[index].parent {
+            self.nodes[parent].children.retain(|&child| child != index);
+        }
+    }
+
+    // Replace a directory's contents with those of a new scan of it
+    fn graft(&mut self, index: usize, subtree: Tree) {
+        let offset = self.nodes.len() - 1;
+        let moved = |child: usize| child + offset;
+        let mut nodes = subtree.nodes.into_iter();
+        let Some(root) = nodes.next() else {
+            return;
+        };
+        self.resize(index, root.blocks, root.apparent_bytes);
+        self.nodes[index].children = root.children.into_iter().map(moved).collect();
+        for mut node in nodes {
+            node.parent = node.parent.map(|parent| match parent {
+                0 => index,
+                parent => moved(parent),
+            });
+            node.children = node.children.into_iter().map(moved).collect();
+            self.nodes.push(node);
+        }
+    }
+
+    // Whether an entry is still reachable from the root
+    fn attached(&self, index: usize) -> bool {
+        match self.nodes[index].parent {
+            Some(parent) => self.nodes[parent].children.contains(&index) && self.attached(parent),
+            None => true,
+        }
+    }
+
+    // Where the directory holding an entry is, from where the root's is
+    fn packages(&self, index: usize, outside: Packages) -> Packages {
+        match self.nodes[index].parent {
+            Some(parent) => self.packages(parent, outside).enter(&self.path(parent)),
+            None => outside,
+        }
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        match self.nodes[index].parent {
+            Some(parent) => self.path(parent).join(&self.nodes[index].name),
+            None => PathBuf::from(&self.nodes[index].name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Order {
+    // Largest first
+    Size,
+    Name,
+}
+
+// What's on screen: a directory of the tree, its entries in order and the
+// one selected, or the entries about to be deleted
+struct Browser<'a> {
+    tree: Tree,
+    dir: usize,
+    listing: Vec<usize>,
+    selected: usize,
+    // The first entry shown, once the list is longer than the screen
+    top: usize,
+    order: Order,
+    apparent: bool,
+    // Entries picked for deletion, in any directory
+    marked: BTreeSet<usize>,
+    // Entries waiting for the deletion to be confirmed
+    confirm: Option<Vec<usize>>,
+    // The outcome of the last deletion, shown until the next key
+    message: Option<String>,
+    // For measuring a directory again after it was only partly deleted
+    options: ScanOptions,
+    // Where the directory holding the root is
+    packages: Packages,
+    pool: &'a ThreadPool,
+}
+
+impl<'a> Browser<'a> {
+    fn new(tree: Tree, options: &ScanOptions, packages: Packages, pool: &'a ThreadPool) -> Self {
+        let mut browser = Browser {
+            tree,
+            dir: 0,
+            listing: Vec::new(),
+            selected: 0,
+            top: 0,
+            order: Order::Size,
+            apparent: false,
+            marked: BTreeSet::new(),
+            confirm: None,
+            message: None,
+            // Errors would land on the browser's screen
+            options: options.clone().error_sink(Arc::new(Collect::default())),
+            packages,
+            pool,
+        };
+        browser.list();
+        browser
+    }
+
+    // The size shown and sorted by, in bytes
+    fn size(&self, index: usize) -> i64 {
+        let node = &self.tree.nodes[index];
+        match self.apparent {
+            true => node.apparent_bytes,
+            false => node.blocks * 512,
+        }
+    }
+
+    fn list(&mut self) {
+        let mut listing = self.tree.nodes[self.dir].children.clone();
+        match self.order {
+            Order::Size => listing.sort_by_key(|&index| std::cmp::Reverse(self.size(index))),
+            Order::Name => listing.sort_by(|&a, &b| {
+                let nodes = &self.tree.nodes;
+                nodes[a].name.cmp(&nodes[b].name)
+            }),
+        }
+        self.listing = listing;
+        self.selected = self.selected.min(self.listing.len().saturating_sub(1));
+    }
+
+    fn open(&mut self, dir: usize) {
+        self.dir = dir;
+        self.selected = 0;
+        self.top = 0;
+        self.list();
+    }
+
+    // Returns false to quit
+    fn key(&mut self, key: &[u8], rows: usize) -> bool {
+        self.message = None;
+        if let Some(doomed) = self.confirm.take() {
+            if key == b"y" {
+                self.delete(doomed);
+            }
+            return true;
+        }
+        let last = self.listing.len().saturating_sub(1);
+        match key {
+            b"q" | b"\x03" => return false,
+            b"\x1b[A" | b"k" => self.selected = self.selected.saturating_sub(1),
+            b"\x1b[B" | b"j" => self.selected = (self.selected + 1).min(last),
+            b"\x1b[5~" => self.selected = self.selected.saturating_sub(rows),
+            b"\x1b[6~" => self.selected = (self.selected + rows).min(last),
+            b"\x1b[H" | b"g" => self.selected = 0,
+            b"\x1b[F" | b"G" => self.selected = last,
+            b"\x1b[C" | b"\r" | b"l" => {
+                let chosen = self.listing.get(self.selected).copied();
+                if let Some(dir) = chosen.filter(|&index| self.tree.nodes[index].kind == Kind::Dir)
+                {
+                    self.open(dir);
+                }
+            }
+            b"\x1b[D" | b"h" | b"\x7f" => {
+                if let Some(parent) = self.tree.nodes[self.dir].parent {
+                    let child = self.dir;
+                    self.open(parent);
+                    self.selected = self
+                        .listing
+                        .iter()
+                        .position(|&index| index == child)
+                        .unwrap_or(0);
+                }
+            }
+            b"s" => {
+                self.order = Order::Size;
+                self.list();
+            }
+            b"n" => {
+                self.order = Order::Name;
+                self.list();
+            }
+            b"a" => {
+                self.apparent = !self.apparent;
+                self.list();
+            }
+            b" " => {
+                if let Some(&index) = self.listing.get(self.selected) {
+                    if !self.marked.remove(&index) {
+                        self.marked.insert(index);
+                    }
+                    self.selected = (self.selected + 1).min(last);
+                }
+            }
+            b"d" => {
+                let doomed: Vec<usize> = match self.marked.is_empty() {
+                    true => self
+                        .listing
+                        .get(self.selected)
+                        .copied()
+                        .into_iter()
+                        .collect(),
+                    false => self.marked.iter().copied().collect(),
+                };
+                // Entries inside a marked directory go with it
+                let doomed: Vec<usize> = doomed
+                    .iter()
+                    .copied()
+                    .filter(|&index| !self.inside(index, &doomed))
+                    .collect();
+                if !doomed.is_empty() {
+                    self.confirm = Some(doomed);
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn inside(&self, index: usize, dirs: &[usize]) -> bool {
+        let mut next = self.tree.nodes[index].parent;
+        while let Some(parent) = next {
+            if dirs.contains(&parent) {
+                return true;
+            }
+            next = self.tree.nodes[parent].parent;
+        }
+        false
+    }
+
+    fn delete(&mut self, doomed: Vec<usize>) {
+        let mut failed = Vec::new();
+        for &index in &doomed {
+            let path = self.tree.path(index);
+            let is_dir = self.tree.nodes[index].kind == Kind::Dir;
+            // Symlinks are removed, not what they point to
+            let removed = match is_dir {
+                true => fs::remove_dir_all(&path),
+                false => fs::remove_file(&path),
+            };
+            match removed {
+                Ok(()) => self.tree.remove(index),
+                Err(e) => {
+                    failed.push(format!(
+                        "{}: {}",
+                        path.display(),
+                        strerror(e.raw_os_error().unwrap_or(libc::EIO))
+                    ));
+                    // Part of it may be gone, so it's measured again
+                    if let Some(path) = path.to_str().filter(|_| is_dir) {
+                        let mut scanner = Scanner::new(self.options.clone());
+                        let packages = self.tree.packages(index, self.packages);
+                        if let Ok(subtree) = Tree::scan(&mut scanner, self.pool, path, packages) {
+                            self.tree.graft(index, subtree);
+                        }
+                    }
+                }
+            }
+        }
+        self.marked.clear();
+        self.message = Some(match failed.first() {
+            None => format!(" Deleted {} item(s)", doomed.len()),
+            Some(first) => format!(" {} of {} failed, {}", failed.len(), doomed.len(), first),
+        });
+
+        // The directory on screen may have been inside one deleted
+        while !self.tree.attached(self.dir) {
+            self.dir = self.tree.nodes[self.dir].parent.unwrap_or(0);
+        }
+        self.list();
+    }
+
+    // What deleting the entries would free, listed for confirmation
+    fn draw_confirm(
+        &self,
+        doomed: &[usize],
+        out: &mut impl Write,
+        rows: usize,
+        cols: usize,
+    ) -> io::Result<()> {
+        let header = format!(
+            " Delete {} item(s)? y deletes, any other key cancels",
+            doomed.len()
+        );
+        write!(out, "\x1b[H\x1b[7m{}\x1b[K\x1b[0m\r\n", fit(&header, cols))?;
+        for row in 0..rows.saturating_sub(2) {
+            match doomed.get(row) {
+                Some(&index) => {
+                    let line = format!(
+                        "{:>9}  {}",
+                        format_size(self.tree.nodes[index].blocks),
+                        self.tree.path(index).display()
+                    );
+                    write!(out, "{}\x1b[K\r\n", fit(&line, cols))?;
+                }
+                None => write!(out, "\x1b[K\r\n")?,
+            }
+        }
+        let blocks: i64 = doomed
+            .iter()
+            .map(|&index| self.tree.nodes[index].blocks)
+            .sum();
+        // A file with links elsewhere keeps its blocks
+        let status = format!(
+            " Frees up to {}, less for files hardlinked elsewhere",
+            format_size(blocks)
+        );
+        write!(out, "\x1b[7m{}\x1b[K\x1b[0m", fit(&status, cols))?;
+        out.flush()
+    }
+
+    fn draw(&mut self, out: &mut impl Write, rows: usize, cols: usize) -> io::Result<()> {
+        if let Some(doomed) = &self.confirm {
+            return self.draw_confirm(doomed, out, rows, cols);
+        }
+        // A header and a status line around the entries
+        let shown = rows.saturating_sub(2).max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + shown {
+            self.top = self.selected + 1 - shown;
+        }
+
+        let mode = match self.apparent {
+            true => "apparent size",
+            false => "disk usage",
+        };
+        let header = format!(" dumac  {}  ({})", self.tree.path(self.dir).display(), mode);
+        write!(out, "\x1b[H\x1b[7m{}\x1b[K\x1b[0m\r\n", fit(&header, cols))?;
+
+        let largest = self.listing.iter().map(|&index| self.size(index)).max();
+        let largest = largest.unwrap_or(0);
+        for row in 0..shown {
+            let Some(&index) = self.listing.get(self.top + row) else {
+                write!(out, "\x1b[K\r\n")?;
+                continue;
+            };
+            let node = &self.tree.nodes[index];
+            let size = self.size(index);
+            let filled = match largest {
+                0 => 0,
+                _ => (size as i128 * BAR_WIDTH as i128 / largest as i128) as usize,
+            };
+            let suffix = if node.kind == Kind::Dir { "/" } else { "" };
+            let mark = if self.marked.contains(&index) {
+                '*'
+            } else {
+                ' '
+            };
+            let line = format!(
+                "{}{:>9} [{:<width$}] {}{}",
+                mark,
+                format_size((size + 511) / 512),
+                "#".repeat(filled),
+                node.name.to_string_lossy(),
+                suffix,
+                width = BAR_WIDTH
+            );
+            if self.top + row == self.selected {
+                write!(out, "\x1b[7m{}\x1b[K\x1b[0m\r\n", fit(&line, cols))?;
+            } else {
+                write!(out, "{}\x1b[K\r\n", fit(&line, cols))?;
+            }
+        }
+
+        let total = self.size(self.dir);
+        let status = match &self.message {
+            Some(message) => message.clone(),
+            None => format!(
+                " Total: {}  Items: {}  {}",
+                format_size((total + 511) / 512),
+                self.listing.len(),
+                HELP
+            ),
+        };
+        write!(out, "\x1b[7m{}\x1b[K\x1b[0m", fit(&status, cols))?;
+        out.flush()
+    }
+}
+
+// Cut to the terminal's width, so lines don't wrap
+fn fit(line: &str, cols: usize) -> String {
+    line.chars().take(cols).collect()
+}
+
+// The controlling terminal in raw mode on the alternate screen, restored
+// when dropped
+struct Terminal {
+    tty: File,
+    saved: libc::termios,
+}
+
+impl Terminal {
+    fn open() -> io::Result<Terminal> {
+        let tty = OpenOptions::new().read(true).write(true).open(TTY)?;
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Keys arrive one at a time, unechoed, and ^C is a key like any other
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut terminal = Terminal { tty, saved };
+        // The alternate screen, without a cursor
+        terminal.tty.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    // Rows and columns
+    fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } != 0
+            || size.ws_row == 0
+        {
+            return (24, 80);
+        }
+        (size.ws_row as usize, size.ws_col as usize)
+    }
+
+    fn key(&mut self) -> io::Result<Vec<u8>> {
+        // Escape sequences for arrows and paging arrive in one read
+        let mut key = [0u8; 16];
+        let read = self.tty.read(&mut key)?;
+        Ok(key[..read].to_vec())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.tty.write_all(b"\x1b[?25h\x1b[?1049l");
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSAFLUSH, &self.saved) };
+    }
+}
+
+// Scan a directory and browse the result until q is pressed. Inside a
+// package, entries deeper than `package_depth` levels aren't listed.
+pub fn run(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    package_depth: Option<usize>,
+) -> Result<(), DumacError> {
+    let tty_error = |e: io::Error| DumacError::Io {
+        op: "open".into(),
+        path: Path::new(TTY).to_path_buf(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    };
+    // Checked before the scan, which can take a while
+    drop(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(TTY)
+            .map_err(tty_error)?,
+    );
+    eprintln!("Scanning {}...", root_dir);
+    let mut scanner = Scanner::new(options.clone());
+    let packages = Packages::new(package_depth);
+    let tree = Tree::scan(&mut scanner, pool, root_dir, packages)?;
+    scanner.report();
+    let mut browser = Browser::new(tree, options, packages, pool);
+
+    let mut terminal = Terminal::open().map_err(tty_error)?;
+    loop {
+        let (rows, cols) = terminal.size();
+        browser
+            .draw(&mut terminal.tty, rows, cols)
+            .map_err(tty_error)?;
+        let key = terminal.key().map_err(tty_error)?;
+        if !browser.key(&key, rows.saturating_sub(2)) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dumac::source::MemorySource;
+
+    #[test]
+    fn test_tree_rows_add_up() {
+        let tree = MemorySource::new()
+            .file("/tree/a/one", 8)
+            .file("/tree/a/two", 16)
+            .hardlink("/tree/b/two", "/tree/a/two")
+            .file("/tree/three", 40);
+        let options = ScanOptions::new().dir_source(Arc::new(tree));
+        let pool = dumac::pool::build(2, || {}).unwrap();
+        let mut scanner = Scanner::new(options);
+
+        // Named as it might be typed, not as entries are named under it
+        let tree = Tree::scan(&mut scanner, &pool, "/tree/./", Packages::default()).unwrap();
+        assert_eq!(tree.nodes[0].children.len(), 3);
+        assert_eq!(tree.nodes[0].blocks, 8 + 16 + 16 + 40);
+        for node in tree.nodes.iter().filter(|node| node.kind == Kind::Dir) {
+            let rows: i64 = node.children.iter().map(|&i| tree.nodes[i].blocks).sum();
+            assert_eq!(rows, node.blocks);
+        }
+    }
+
+    #[test]
+    fn test_package_depth() {
+        let tree = MemorySource::new()
+            .file("/tree/Mail.app/Contents/MacOS/Mail", 8)
+            .file("/tree/Mail.app/Contents/Info.plist", 16)
+            .file("/tree/Mail.app/icon", 24)
+            .file("/tree/notes", 40);
+        let options = ScanOptions::new().dir_source(Arc::new(tree));
+        let pool = dumac::pool::build(2, || {}).unwrap();
+        let count = |depth| {
+            let mut scanner = Scanner::new(options.clone());
+            let tree = Tree::scan(&mut scanner, &pool, "/tree", Packages::new(depth)).unwrap();
+            // Everything is summed whatever is listed
+            assert_eq!(tree.nodes[0].blocks, 8 + 16 + 24 + 40);
+            let app = tree.nodes[0]
+                .children
+                .iter()
+                .find(|&&i| tree.nodes[i].name == "Mail.app")
+                .copied()
+                .unwrap();
+            assert_eq!(tree.nodes[app].blocks, 8 + 16 + 24);
+            tree.nodes.len()
+        };
+        // The root, notes, Mail.app, then Contents and icon, then MacOS,
+        // Info.plist and Mail
+        assert_eq!(count(None), 8);
+        assert_eq!(count(Some(0)), 3);
+        assert_eq!(count(Some(1)), 5);
+        assert_eq!(count(Some(2)), 7);
+    }
+}
@@ -0,0 +1,51 @@
+use std::ffi::OsStr;
+use std::ops::ControlFlow;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// What an entry is, from the listing it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    // Devices, sockets and FIFOs
+    Other,
+}
+
+// An entry reached by `scan_with_visitor`
+#[derive(Debug)]
+pub struct Entry<'a> {
+    pub path: &'a Path,
+    // The entry's own blocks, before hardlinks and clones are counted once.
+    // Directories are visited before their contents, so theirs is 0.
+    pub blocks: i64,
+    // Its logical length, also 0 for directories
+    pub apparent_bytes: i64,
+    pub kind: Kind,
+    // 1 for entries directly under the scanned directory
+    pub depth: usize,
+}
+
+// Called from the scan's workers, so it must be safe to share between them
+pub(super) type Visitor = Box<dyn Fn(&Entry) -> ControlFlow<()> + Send + Sync>;
+
+// Offer an entry to the visitor. Returns whether the scan should count it.
+pub(super) fn visit(
+    visitor: &Visitor,
+    path: &[u8],
+    blocks: i64,
+    apparent_bytes: i64,
+    kind: Kind,
+    depth: usize,
+) -> bool {
+    let entry = Entry {
+        path: Path::new(OsStr::from_bytes(path)),
+        blocks,
+        apparent_bytes,
+        kind,
+        depth,
+    };
+    visitor(&entry).is_continue()
+}
@@ -0,0 +1,202 @@
+use super::sync::{Condvar, Mutex};
+use super::{paths, profile};
+use libc::c_int;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+
+// Filesystems served over the network, where latency hides behind many requests in flight
+const NETWORK_FSTYPES: &[&str] = &["smbfs", "nfs", "afpfs", "webdav", "cifs"];
+
+// Filesystems usually found on spinning or USB disks, which thrash under parallel access
+const SLOW_FSTYPES: &[&str] = &["hfs", "msdos", "exfat", "ntfs"];
+
+// In-flight directory reads per CPU on network volumes
+const NETWORK_CONCURRENCY_PER_CPU: usize = 4;
+
+// In-flight directory reads on slow local volumes
+const SLOW_CONCURRENCY: usize = 2;
+
+// User overrides from --volume-threads, as (fstype, concurrency)
+static OVERRIDES: OnceLock<Vec<(String, usize)>> = OnceLock::new();
+
+// Volumes seen so far, keyed by device id
+static VOLUMES: LazyLock<Mutex<HashMap<u64, Arc<Volume>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Counting semaphore bounding concurrent directory reads on a volume
+struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct Permit<'a>(&'a Semaphore);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+// A mounted filesystem and its concurrency limit
+pub struct Volume {
+    pub dev: u64,
+    pub fstype: String,
+    // Where it's mounted, empty when unknown
+    pub mount_point: String,
+    // Whether names differing only in case are different names, as on
+    // case-sensitive APFS
+    pub case_sensitive: bool,
+    // A mounted read-only snapshot, such as a Time Machine local snapshot
+    pub snapshot: bool,
+    limit: Option<Semaphore>,
+    // Set once getattrlistbulk turns out unreliable here, listings then use readdir
+    fallback: AtomicBool,
+}
+
+impl Volume {
+    // Volume with no known type and no limit, used when statfs fails
+    pub fn unknown() -> Self {
+        Volume {
+            dev: 0,
+            fstype: String::new(),
+            mount_point: String::new(),
+            // Matching names exactly is the safe guess
+            case_sensitive: true,
+            snapshot: false,
+            limit: None,
+            fallback: AtomicBool::new(false),
+        }
+    }
+
+    fn new(
+        dev: u64,
+        fstype: String,
+        mount_point: String,
+        case_sensitive: bool,
+        snapshot: bool,
+    ) -> Self {
+        let limit = concurrency_for(&fstype).map(|permits| Semaphore {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        });
+        Volume {
+            dev,
+            fstype,
+            mount_point,
+            case_sensitive,
+            snapshot,
+            limit,
+            fallback: AtomicBool::new(false),
+        }
+    }
+
+    pub fn uses_fallback(&self) -> bool {
+        self.fallback.load(Ordering::Relaxed)
+    }
+
+    pub fn fall_back(&self) {
+        if !self.fallback.swap(true, Ordering::Relaxed) {
+            profile::FALLBACK_VOLUMES.add(1);
+            tracing::debug!(
+                mount_point = %self.mount_point,
+                fstype = %self.fstype,
+                "getattrlistbulk falls short, listing with readdir"
+            );
+        }
+    }
+
+    // Wait for a free slot on this volume, held until the permit is dropped
+    pub fn acquire(&self) -> Option<Permit<'_>> {
+        let semaphore = self.limit.as_ref()?;
+        let mut permits = semaphore.permits.lock();
+        if *permits == 0 {
+            profile::VOLUME_WAITS.add(1);
+        }
+        while *permits == 0 {
+            semaphore.released.wait(&mut permits);
+        }
+        *permits -= 1;
+        Some(Permit(semaphore))
+    }
+}
+
+pub fn set_overrides(overrides: Vec<(String, usize)>) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+// Preferred number of concurrent directory reads for a filesystem type,
+// None meaning as many as there are workers
+pub fn concurrency_for(fstype: &str) -> Option<usize> {
+    let overrides = OVERRIDES.get().map(Vec::as_slice).unwrap_or_default();
+    if let Some((_, concurrency)) = overrides.iter().find(|(name, _)| name == fstype) {
+        return Some(*concurrency);
+    }
+    if NETWORK_FSTYPES.contains(&fstype) {
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        return Some(cpus * NETWORK_CONCURRENCY_PER_CPU);
+    }
+    if SLOW_FSTYPES.contains(&fstype) {
+        return Some(SLOW_CONCURRENCY);
+    }
+    None
+}
+
+// Look up the volume a directory lives on, registering it on first sight
+pub fn volume_for_path(c_path: &CStr) -> Option<Arc<Volume>> {
+    let fd = paths::open_dir(c_path, libc::O_RDONLY | libc::O_DIRECTORY);
+    if fd == -1 {
+        return None;
+    }
+    let dir = unsafe { OwnedFd::from_raw_fd(fd) };
+    volume_for_fd(dir.as_raw_fd())
+}
+
+// Look up the volume of an open file or directory
+pub fn volume_for_fd(fd: c_int) -> Option<Arc<Volume>> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 {
+        return None;
+    }
+
+    let mut volumes = VOLUMES.lock();
+    if let Some(volume) = volumes.get(&(st.st_dev as u64)) {
+        return Some(volume.clone());
+    }
+
+    let mut fs: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(fd, &mut fs) } != 0 {
+        return None;
+    }
+    let fstype = unsafe { CStr::from_ptr(fs.f_fstypename.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+    let mount_point = unsafe { CStr::from_ptr(fs.f_mntonname.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+
+    // 1 when case-sensitive, 0 when not, -1 when the volume doesn't say
+    let case_sensitive = unsafe { libc::fpathconf(fd, libc::_PC_CASE_SENSITIVE) } != 0;
+    let snapshot = fs.f_flags & libc::MNT_SNAPSHOT as u32 != 0;
+
+    let volume = Arc::new(Volume::new(
+        st.st_dev as u64,
+        fstype,
+        mount_point,
+        case_sensitive,
+        snapshot,
+    ));
+    tracing::debug!(
+        mount_point = %volume.mount_point,
+        fstype = %volume.fstype,
+        limit = ?volume.limit.as_ref().map(|limit| *limit.permits.lock()),
+        "entering volume"
+    );
+    volumes.insert(st.st_dev as u64, volume.clone());
+    Some(volume)
+}
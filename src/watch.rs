@@ -0,0 +1,112 @@
+use super::cache::Cache;
+use super::fsevents::{Change, EventStream};
+use super::pool::ThreadPool;
+use super::stream::DirResult;
+use super::sync::Mutex;
+use super::{DumacError, ScanOptions, Scanner};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long FSEvents gathers changes before handing them over
+const LATENCY: Duration = Duration::from_millis(500);
+
+// Which directories' totals each scan hands back
+pub enum Totals {
+    // Those FSEvents named since the scan before, none the first time
+    Changed,
+    // Every directory, e.g. to answer queries from
+    All,
+}
+
+// Scan a directory, then rescan it whenever FSEvents reports changes. A
+// rescan reuses every listing from the one before except those of the
+// directories FSEvents named, so it costs a stat per directory rather than a
+// listing. `scanned` gets the resolved root, its total and the directories
+// `totals` asks for, sorted by path, after every scan. Runs until
+// interrupted or the directory can't be scanned.
+pub fn watch(
+    options: &ScanOptions,
+    pool: &ThreadPool,
+    root_dir: &str,
+    totals: Totals,
+    mut scanned: impl FnMut(&Path, i64, Vec<DirResult>),
+) -> Result<(), DumacError> {
+    // FSEvents reports resolved paths, e.g. /private/tmp for /tmp
+    let root = fs::canonicalize(root_dir).map_err(|e| DumacError::Io {
+        op: "stat".into(),
+        path: root_dir.into(),
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })?;
+    let root = root
+        .into_os_string()
+        .into_string()
+        .map_err(|_| DumacError::InvalidPath(root_dir.to_string()))?;
+    // Started first, so changes made during the first scan aren't missed
+    let (_events, changes) = EventStream::start(Path::new(&root), LATENCY).map_err(|message| {
+        tracing::debug!("{}", message);
+        // FSEvents gives no reason, most likely it doesn't cover the volume
+        DumacError::Io {
+            op: "watch".into(),
+            path: root.clone().into(),
+            errno: libc::ENOTSUP,
+        }
+    })?;
+
+    let mut cache = Cache::in_memory();
+    let mut changed = BTreeSet::new();
+    loop {
+        let mut scanner = Scanner::new(options.clone());
+        scanner.cache = Some(cache);
+        let found = Arc::new(Mutex::new(Vec::new()));
+        match totals {
+            Totals::Changed if changed.is_empty() => {}
+            Totals::Changed => {
+                let wanted: BTreeSet<PathBuf> = changed.clone();
+                let found = found.clone();
+                scanner.results = Some(Box::new(move |dir: DirResult| {
+                    if wanted.contains(&dir.path) {
+                        found.lock().push(dir);
+                    }
+                }));
+            }
+            Totals::All => {
+                let found = found.clone();
+                scanner.results = Some(Box::new(move |dir: DirResult| found.lock().push(dir)));
+            }
+        }
+        let blocks = pool.install(|| scanner.calculate_size(root.clone()))?;
+
+        let mut dirs = std::mem::take(&mut *found.lock());
+        dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        scanned(Path::new(&root), blocks, dirs);
+
+        // Wait for changes, along with any more that queued up meanwhile
+        let Ok(mut batch) = changes.recv() else {
+            return Ok(());
+        };
+        batch.extend(changes.try_iter().flatten());
+
+        cache = scanner
+            .cache
+            .take()
+            .map_or_else(Cache::in_memory, Cache::into_next);
+        changed.clear();
+        for Change { path, recursive } in batch {
+            if !path.starts_with(&root) {
+                continue;
+            }
+            if recursive {
+                // Which directories below changed is lost, and the cache is
+                // keyed by inode rather than path, so list everything again
+                cache = Cache::in_memory();
+            } else if let Ok(metadata) = fs::symlink_metadata(&path) {
+                cache.forget(metadata.dev(), metadata.ino());
+            }
+            changed.insert(path);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// --webhook URL: a JSON summary of the scan POSTed once it's done, for
+// Slack or Teams alerts from scheduled runs. Both take the "text" field as
+// the message; the rest is there for anything else reading it. It's sent
+// with curl, which every Mac has, so HTTPS needs nothing of dumac's own.
+use super::json::string;
+use dumac::internal::{normalize, Mutex};
+use dumac::stream::DirResult;
+use dumac::{format_size, ErrorSink, ScanError, Scanner};
+use std::cmp::Reverse;
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CURL: &str = "/usr/bin/curl";
+
+// How long to wait for the endpoint, in seconds
+const TIMEOUT: &str = "30";
+
+// Directories the summary lists, the largest right under the roots
+const TOP_DIRECTORIES: usize = 10;
+
+// Errors the summary quotes, out of however many there were
+const QUOTED_ERRORS: usize = 20;
+
+// What the summary needs from the scan besides the totals: the roots'
+// subdirectories, and the errors, which are still printed as they happen
+#[derive(Default)]
+pub struct Report {
+    roots: Vec<PathBuf>,
+    children: Mutex<Vec<(PathBuf, i64)>>,
+    errors: Mutex<Vec<String>>,
+    error_count: AtomicU64,
+}
+
+impl Report {
+    pub fn new(roots: &[String]) -> Arc<Report> {
+        Arc::new(Report {
+            // Directories are named under a root in its tidied form
+            roots: roots
+                .iter()
+                .map(|root| PathBuf::from(normalize(root)))
+                .collect(),
+            ..Report::default()
+        })
+    }
+
+    // Keep the totals of `scanner`'s roots' subdirectories. The report
+    // should be the error sink of the scanner's options too.
+    pub fn attach(self: &Arc<Report>, scanner: &mut Scanner) {
+        let report = self.clone();
+        scanner.on_result(move |dir: DirResult| {
+            let below_root = dir
+                .path
+                .parent()
+                .is_some_and(|parent| report.roots.iter().any(|root| root == parent));
+            if below_root {
+                report.children.lock().push((dir.path, dir.blocks));
+            }
+        });
+    }
+}
+
+impl ErrorSink for Report {
+    fn error(&self, error: ScanError) -> ControlFlow<()> {
+        eprintln!("dumac: {}", error);
+        self.error_count.fetch_add(1, Ordering::Relaxed);
+        let mut errors = self.errors.lock();
+        if errors.len() < QUOTED_ERRORS {
+            errors.push(error.message);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn report(&self, report: &str) {
+        eprint!("{}", report);
+    }
+}
+
+// How the scan went
+pub struct Summary<'a> {
+    // Each root's total, including any scanned remotely
+    pub totals: &'a [(String, i64)],
+    // --fail-if-over's limit and the roots over it
+    pub limit: Option<i64>,
+    pub over_limit: &'a [String],
+    // Roots that couldn't be scanned at all
+    pub failed_roots: &'a [String],
+    pub elapsed: Duration,
+}
+
+// POST the summary to `url`
+pub fn send(url: &str, report: &Report, summary: &Summary) -> io::Result<()> {
+    let body = payload(report, summary);
+    let mut curl = Command::new(CURL)
+        .args(["--silent", "--show-error", "--fail", "--max-time", TIMEOUT])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", "--output", "/dev/null", "--", url])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run {}: {}", CURL, e)))?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        let _ = stdin.write_all(body.as_bytes());
+    }
+    match curl.wait()?.success() {
+        true => Ok(()),
+        // curl has said why
+        false => Err(io::Error::other("the webhook wasn't delivered")),
+    }
+}
+
+fn payload(report: &Report, summary: &Summary) -> String {
+    let error_count =
+        report.error_count.load(Ordering::Relaxed) + summary.failed_roots.len() as u64;
+    let errors = report.errors.lock();
+    let mut top = report.children.lock().clone();
+    top.sort_by_key(|(path, blocks)| (Reverse(*blocks), path.clone()));
+    top.truncate(TOP_DIRECTORIES);
+
+    let mut json = String::from("{");
+    let _ = write!(json, "\"text\":{}", string(&text(summary, error_count)));
+    let _ = write!(json, ",\"host\":{}", string(&hostname()));
+    let _ = write!(json, ",\"elapsed\":{:.3}", summary.elapsed.as_secs_f64());
+    json.push_str(",\"totals\":");
+    entries(
+        &mut json,
+        summary.totals.iter().map(|(p, b)| (p.clone(), *b)),
+    );
+    json.push_str(",\"top_directories\":");
+    entries(
+        &mut json,
+        top.iter()
+            .map(|(path, blocks)| (path.to_string_lossy().into_owned(), *blocks)),
+    );
+    let _ = write!(json, ",\"error_count\":{}", error_count);
+    json.push_str(",\"errors\":");
+    strings(&mut json, errors.iter().chain(summary.failed_roots));
+    match summary.limit {
+        Some(limit) => {
+            let _ = write!(
+                json,
+                ",\"limit\":{{\"blocks\":{},\"size\":{}}}",
+                limit,
+                string(&format_size(limit))
+            );
+        }
+        None => json.push_str(",\"limit\":null"),
+    }
+    json.push_str(",\"over_limit\":");
+    strings(&mut json, summary.over_limit.iter());
+    json.push('}');
+    json
+}
+
+// One line for a chat message
+fn text(summary: &Summary, error_count: u64) -> String {
+    let totals: Vec<String> = summary
+        .totals
+        .iter()
+        .map(|(root, blocks)| format!("{} {}", root, format_size(*blocks)))
+        .collect();
+    let mut text = format!("dumac on {}: {}", hostname(), totals.join(", "));
+    if totals.is_empty() {
+        text.push_str("nothing scanned");
+    }
+    if let Some(limit) = summary.limit.filter(|_| !summary.over_limit.is_empty()) {
+        let _ = write!(
+            text,
+            "; over the {} limit: {}",
+            format_size(limit),
+            summary.over_limit.join(", ")
+        );
+    }
+    match error_count {
+        0 => {}
+        1 => text.push_str("; 1 error"),
+        errors => {
+            let _ = write!(text, "; {} errors", errors);
+        }
+    }
+    text
+}
+
+fn entries(json: &mut String, entries: impl Iterator<Item = (String, i64)>) {
+    json.push('[');
+    for (i, (path, blocks)) in entries.enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"path\":{},\"blocks\":{},\"size\":{}}}",
+            string(&path),
+            blocks,
+            string(&format_size(blocks))
+        );
+    }
+    json.push(']');
+}
+
+fn strings<'a>(json: &mut String, items: impl Iterator<Item = &'a String>) {
+    json.push('[');
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&string(item));
+    }
+    json.push(']');
+}
+
+fn hostname() -> String {
+    let mut name = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr(), name.len()) } != 0 {
+        return String::new();
+    }
+    unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dumac::source::MemorySource;
+    use dumac::ScanOptions;
+
+    #[test]
+    fn test_children_of_untidy_root() {
+        let tree = MemorySource::new()
+            .file("/tree/a/one", 8)
+            .file("/tree/b/two", 16);
+        let report = Report::new(&["/tree//".to_string()]);
+        let options = ScanOptions::new()
+            .dir_source(Arc::new(tree))
+            .error_sink(report.clone());
+        let mut scanner = Scanner::new(options);
+        report.attach(&mut scanner);
+        let pool = dumac::pool::build(2, || {}).unwrap();
+        pool.install(|| scanner.calculate_size("/tree//".to_string()))
+            .unwrap();
+
+        let mut children = report.children.lock().clone();
+        children.sort();
+        assert_eq!(
+            children,
+            [
+                (PathBuf::from("/tree/a"), 8),
+                (PathBuf::from("/tree/b"), 16)
+            ]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use super::format_size;
+use libc::c_int;
+use std::ffi::CString;
+use std::fmt::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+// Listed as an attribute, but it's the resource fork, which is already
+// counted with the file's allocation
+const RESOURCE_FORK: &[u8] = b"com.apple.ResourceFork";
+
+// Totals for the --xattrs report
+#[derive(Default)]
+pub struct Stats {
+    files: AtomicU64,
+    blocks: AtomicI64,
+}
+
+impl Stats {
+    // Count a file's extended attributes, once per inode like its blocks
+    pub fn record(&self, blocks: i64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.blocks.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, out: &mut String) -> fmt::Result {
+        writeln!(
+            out,
+            "xattrs: {} files, {} included in the total",
+            self.files.load(Ordering::Relaxed),
+            format_size(self.blocks.load(Ordering::Relaxed))
+        )
+    }
+}
+
+// Combined size of the extended attributes of a file or symlink listed in
+// `dirfd`, 0 when it has none or they can't be read. Symlinks aren't
+// followed. Other kinds of entry aren't opened, as opening a device or a
+// fifo can have side effects, and count as having none.
+pub fn size(dirfd: c_int, name: &[u8]) -> i64 {
+    let Ok(name) = CString::new(name) else {
+        return 0;
+    };
+    let flags = libc::O_RDONLY | libc::O_SYMLINK | libc::O_NONBLOCK | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dirfd, name.as_ptr(), flags) };
+    if fd == -1 {
+        return 0;
+    }
+    let file = unsafe { OwnedFd::from_raw_fd(fd) };
+    let fd = file.as_raw_fd();
+
+    // Sized first, the names can change in between, in which case the
+    // attributes are left uncounted until the next scan
+    let len = unsafe { libc::flistxattr(fd, ptr::null_mut(), 0, 0) };
+    if len <= 0 {
+        return 0;
+    }
+    let mut names = vec![0u8; len as usize];
+    let len =
+        unsafe { libc::flistxattr(fd, names.as_mut_ptr() as *mut libc::c_char, names.len(), 0) };
+    if len <= 0 {
+        return 0;
+    }
+    names.truncate(len as usize);
+
+    names
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty() && *name != RESOURCE_FORK)
+        .map(|name| {
+            // Split at NULs, so there are none inside
+            let name = CString::new(name).unwrap();
+            let size = unsafe { libc::fgetxattr(fd, name.as_ptr(), ptr::null_mut(), 0, 0, 0) };
+            size.max(0) as i64
+        })
+        .sum()
+}
@@ -1,125 +1,972 @@
-use std::fs::{self, File, hard_link};
+use std::fs::{self, hard_link, File};
 use std::io::Write;
+use std::ops::ControlFlow;
 use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::TempDir;
 
-// Import the main module
-#[path = "../src/main.rs"]
-mod main;
-
-use main::calculate_size;
+use dumac::snapshot::Snapshot;
+use dumac::source::{DirSource, MemorySource};
+use dumac::testing::TreeBuilder;
+use dumac::visit::Kind;
+use dumac::{Collect, DumacError, Regex, ScanOptions, ScanResult, Scanner};
 
 #[test]
 fn test_basic_file_size_calculation() {
-    // Clear the seen inodes cache to ensure test isolation
-    main::clear_seen_inodes();
-    
+    // Each scanner has its own seen inodes, which keeps tests isolated
+    let scanner = Scanner::new(ScanOptions::new());
+
     // Create a temporary directory
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let temp_path = temp_dir.path();
-    
+
     // Create a file with known content
     let file_path = temp_path.join("test_file.txt");
     let mut file = File::create(&file_path).expect("Failed to create test file");
-    
+
     // Write 1000 bytes
     let content = "a".repeat(1000);
-    file.write_all(content.as_bytes()).expect("Failed to write to file");
+    file.write_all(content.as_bytes())
+        .expect("Failed to write to file");
     file.sync_all().expect("Failed to sync file");
     drop(file);
-    
+
     // Calculate size
-    let result = calculate_size(temp_path.to_string_lossy().to_string());
+    let result = scanner.calculate_size(temp_path.to_string_lossy().to_string());
     assert!(result.is_ok(), "calculate_size should succeed");
-    
+
     let total_blocks = result.unwrap();
-    
+
     // 1000 bytes should be at least 2 blocks (1000 + 511) / 512 = 2 blocks
     // But filesystem allocation might be larger
-    assert!(total_blocks >= 2, "Should have at least 2 blocks for 1000 bytes, got {}", total_blocks);
-    
+    assert!(
+        total_blocks >= 2,
+        "Should have at least 2 blocks for 1000 bytes, got {}",
+        total_blocks
+    );
+
     // Cleanup happens automatically when TempDir is dropped
 }
 
 #[test]
 fn test_nested_directories() {
-    // Clear the seen inodes cache to ensure test isolation
-    main::clear_seen_inodes();
-    
+    // Each scanner has its own seen inodes, which keeps tests isolated
+    let scanner = Scanner::new(ScanOptions::new());
+
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let temp_path = temp_dir.path();
-    
+
     // Create nested directory structure
     let subdir = temp_path.join("subdir");
     fs::create_dir(&subdir).expect("Failed to create subdir");
-    
+
     // Create files in both root and subdir
     let root_file = temp_path.join("root.txt");
     let mut file1 = File::create(&root_file).expect("Failed to create root file");
-    file1.write_all(b"hello").expect("Failed to write to root file");
+    file1
+        .write_all(b"hello")
+        .expect("Failed to write to root file");
     drop(file1);
-    
+
     let sub_file = subdir.join("sub.txt");
     let mut file2 = File::create(&sub_file).expect("Failed to create sub file");
-    file2.write_all(b"world").expect("Failed to write to sub file");
+    file2
+        .write_all(b"world")
+        .expect("Failed to write to sub file");
     drop(file2);
-    
+
     // Calculate total size
-    let result = calculate_size(temp_path.to_string_lossy().to_string());
-    assert!(result.is_ok(), "calculate_size should succeed for nested dirs");
-    
+    let result = scanner.calculate_size(temp_path.to_string_lossy().to_string());
+    assert!(
+        result.is_ok(),
+        "calculate_size should succeed for nested dirs"
+    );
+
     let total_blocks = result.unwrap();
     // Should have blocks for both files (minimum 2 blocks total)
-    assert!(total_blocks >= 2, "Should have at least 2 blocks for two files, got {}", total_blocks);
+    assert!(
+        total_blocks >= 2,
+        "Should have at least 2 blocks for two files, got {}",
+        total_blocks
+    );
 }
 
 #[test]
 fn test_hardlink_deduplication() {
-    // Clear the seen inodes cache to ensure test isolation
-    main::clear_seen_inodes();
-    
+    // Each scanner has its own seen inodes, which keeps tests isolated
+    let scanner = Scanner::new(ScanOptions::new());
+
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let temp_path = temp_dir.path();
-    
+
     // Create original file with substantial content
     let original_file = temp_path.join("original.txt");
     let mut file = File::create(&original_file).expect("Failed to create original file");
-    
+
     // Write 2048 bytes (should be 4 blocks: (2048 + 511) / 512 = 4)
     let content = "x".repeat(2048);
-    file.write_all(content.as_bytes()).expect("Failed to write to original file");
+    file.write_all(content.as_bytes())
+        .expect("Failed to write to original file");
     file.sync_all().expect("Failed to sync original file");
     drop(file);
-    
+
     // Calculate size with just the original file
-    let size_original = calculate_size(temp_path.to_string_lossy().to_string())
+    let size_original = scanner
+        .calculate_size(temp_path.to_string_lossy().to_string())
         .expect("Failed to calculate size for original");
-    
+
     // Create hard link to the same file
     let hardlink_file = temp_path.join("hardlink.txt");
     hard_link(&original_file, &hardlink_file).expect("Failed to create hard link");
-    
+
     // Verify the hardlink was created successfully
     let original_metadata = fs::metadata(&original_file).expect("Failed to get original metadata");
     let hardlink_metadata = fs::metadata(&hardlink_file).expect("Failed to get hardlink metadata");
-    assert_eq!(original_metadata.ino(), hardlink_metadata.ino(), "Hardlink should have same inode");
-    
-    // Clear cache again before second calculation to test deduplication logic
-    main::clear_seen_inodes();
-    
+    assert_eq!(
+        original_metadata.ino(),
+        hardlink_metadata.ino(),
+        "Hardlink should have same inode"
+    );
+
+    // Fresh scanner before second calculation to test deduplication logic
+    let scanner = Scanner::new(ScanOptions::new());
+
     // Calculate size again - should be the same due to deduplication
-    let size_with_hardlink = calculate_size(temp_path.to_string_lossy().to_string())
+    let size_with_hardlink = scanner
+        .calculate_size(temp_path.to_string_lossy().to_string())
         .expect("Failed to calculate size with hardlink");
-    
+
     // The total size should be the same because hardlinks should be deduplicated
     assert_eq!(
-        size_original, 
-        size_with_hardlink,
+        size_original, size_with_hardlink,
         "Hardlinked files should not double-count blocks. Original: {}, With hardlink: {}",
-        size_original,
-        size_with_hardlink
+        size_original, size_with_hardlink
     );
-    
+
     // Verify the original size is reasonable (at least 4 blocks for 2048 bytes)
-    assert!(size_original >= 4, "Should have at least 4 blocks for 2048 bytes, got {}", size_original);
-} 
\ No newline at end of file
+    assert!(
+        size_original >= 4,
+        "Should have at least 4 blocks for 2048 bytes, got {}",
+        size_original
+    );
+}
+#[test]
+fn test_deep_directory_tree() {
+    // Each scanner has its own seen inodes, which keeps tests isolated
+    let scanner = Scanner::new(ScanOptions::new());
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path();
+
+    // Create a deep chain of directories with a file at every level
+    let depth = 200;
+    let mut current = temp_path.to_path_buf();
+    for _ in 0..depth {
+        current = current.join("d");
+        fs::create_dir(&current).expect("Failed to create nested dir");
+        let mut file = File::create(current.join("f")).expect("Failed to create nested file");
+        file.write_all(b"x")
+            .expect("Failed to write to nested file");
+    }
+
+    // Traversal shouldn't depend on a large stack for deep trees
+    let total_blocks = scanner
+        .calculate_size(temp_path.to_string_lossy().to_string())
+        .expect("calculate_size should succeed for deep trees");
+
+    // Every level contributes at least one block
+    assert!(
+        total_blocks >= depth,
+        "Should have at least {} blocks, got {}",
+        depth,
+        total_blocks
+    );
+}
+
+#[test]
+fn test_paths_longer_than_path_max() {
+    use std::ffi::CString;
+
+    let scanner = Scanner::new(ScanOptions::new());
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path();
+
+    // Long names nested until the full path is well past PATH_MAX, created
+    // relative to each parent since the full path can't be opened directly
+    let name = CString::new("n".repeat(200)).unwrap();
+    let levels = libc::PATH_MAX as usize / 200 * 2;
+    let root = CString::new(temp_path.to_string_lossy().as_bytes()).unwrap();
+    let mut fd = unsafe { libc::open(root.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY) };
+    for _ in 0..levels {
+        assert_eq!(
+            unsafe { libc::mkdirat(fd, name.as_ptr(), 0o755) },
+            0,
+            "Failed to create nested dir"
+        );
+        let next = unsafe { libc::openat(fd, name.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY) };
+        unsafe { libc::close(fd) };
+        assert!(next >= 0, "Failed to open nested dir");
+        fd = next;
+    }
+    // A file at the bottom, 4096 bytes so it takes at least 8 blocks
+    let file = CString::new("f").unwrap();
+    let file_fd = unsafe { libc::openat(fd, file.as_ptr(), libc::O_WRONLY | libc::O_CREAT, 0o644) };
+    assert!(file_fd >= 0, "Failed to create deep file");
+    let data = [b'x'; 4096];
+    assert_eq!(
+        unsafe { libc::write(file_fd, data.as_ptr() as *const libc::c_void, data.len()) },
+        4096
+    );
+    // And an extended attribute as big, for --xattrs to find
+    let attr = CString::new("com.example.dumac").unwrap();
+    assert_eq!(
+        unsafe {
+            libc::fsetxattr(
+                file_fd,
+                attr.as_ptr(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+                0,
+                0,
+            )
+        },
+        0
+    );
+    unsafe { libc::close(file_fd) };
+    unsafe { libc::close(fd) };
+
+    let total_blocks = scanner
+        .calculate_size(temp_path.to_string_lossy().to_string())
+        .expect("calculate_size should succeed past PATH_MAX");
+    assert!(
+        total_blocks >= 8,
+        "The deepest file should be counted, got {} blocks",
+        total_blocks
+    );
+    assert_eq!(scanner.errors(), 0, "Nothing should fail to open");
+
+    // Attributes are read relative to the directory, not by the full path
+    let with_xattrs = Scanner::new(ScanOptions::new().xattrs(true));
+    let xattr_blocks = with_xattrs
+        .calculate_size(temp_path.to_string_lossy().to_string())
+        .expect("calculate_size should succeed past PATH_MAX");
+    assert!(
+        xattr_blocks >= total_blocks + 8,
+        "The deepest file's attribute should be counted, got {} blocks",
+        xattr_blocks
+    );
+
+    // TempDir can't remove what it can't reach by path, so unwind by hand
+    let mut fds = vec![unsafe { libc::open(root.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY) }];
+    for _ in 0..levels {
+        let parent = *fds.last().unwrap();
+        fds.push(unsafe {
+            libc::openat(parent, name.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY)
+        });
+    }
+    unsafe { libc::unlinkat(*fds.last().unwrap(), file.as_ptr(), 0) };
+    while let Some(fd) = fds.pop() {
+        if let Some(&parent) = fds.last() {
+            unsafe { libc::unlinkat(parent, name.as_ptr(), libc::AT_REMOVEDIR) };
+        }
+        unsafe { libc::close(fd) };
+    }
+}
+
+#[test]
+fn test_symlink_and_file_roots() {
+    let scanner = Scanner::new(ScanOptions::new());
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let dir_path = temp_dir.path().join("dir");
+    fs::create_dir(&dir_path).expect("Failed to create dir");
+
+    let file_path = dir_path.join("file");
+    fs::write(&file_path, "a".repeat(10000)).expect("Failed to write file");
+    let file_blocks = fs::metadata(&file_path).unwrap().blocks() as i64;
+
+    // A symlinked root is followed and scanned like its target
+    let link_path = temp_dir.path().join("link");
+    std::os::unix::fs::symlink(&dir_path, &link_path).expect("Failed to create symlink");
+    let link_blocks = scanner
+        .calculate_size(link_path.to_string_lossy().to_string())
+        .unwrap();
+    assert_eq!(link_blocks, file_blocks);
+
+    // A file root counts on its own
+    let single_blocks = scanner
+        .calculate_size(file_path.to_string_lossy().to_string())
+        .unwrap();
+    assert_eq!(single_blocks, file_blocks);
+}
+
+#[test]
+fn test_root_path_forms() {
+    let scanner = Scanner::new(ScanOptions::new());
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let sub_path = temp_dir.path().join("sub");
+    fs::create_dir(&sub_path).expect("Failed to create subdir");
+    fs::write(sub_path.join("file"), "a".repeat(10000)).expect("Failed to write file");
+
+    let plain = temp_dir.path().to_string_lossy().to_string();
+    let expected = scanner.calculate_size(plain.clone()).unwrap();
+
+    // Redundant slashes and `.` components don't change what is counted
+    for form in [
+        format!("{}/", plain),
+        format!("{}//./", plain),
+        format!("{}/./sub/..", plain),
+    ] {
+        assert_eq!(
+            scanner.calculate_size(form.clone()).unwrap(),
+            expected,
+            "for {}",
+            form
+        );
+    }
+}
+
+#[test]
+fn test_hardlinks_counted_once_per_scanner() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let first = temp_dir.path().join("first");
+    let second = temp_dir.path().join("second");
+    fs::create_dir(&first).expect("Failed to create dir");
+    fs::create_dir(&second).expect("Failed to create dir");
+    fs::write(first.join("file"), "a".repeat(10000)).expect("Failed to write file");
+    hard_link(first.join("file"), second.join("link")).expect("Failed to create hard link");
+
+    // Within one scanner the link is only counted under the first root
+    let scanner = Scanner::new(ScanOptions::new());
+    let sizes = scanner.calculate_sizes(&[
+        first.to_string_lossy().to_string(),
+        second.to_string_lossy().to_string(),
+    ]);
+    assert!(sizes[0].clone().unwrap() > 0);
+    assert_eq!(sizes[1].clone().unwrap(), 0);
+
+    // A separate scanner starts from scratch
+    let other = Scanner::new(ScanOptions::new());
+    assert_eq!(
+        other
+            .calculate_size(second.to_string_lossy().to_string())
+            .unwrap(),
+        sizes[0].clone().unwrap()
+    );
+}
+
+#[test]
+fn test_scan_api() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    fs::write(temp_dir.path().join("file"), "a".repeat(10000)).expect("Failed to write file");
+    let path = temp_dir.path().to_string_lossy().to_string();
+
+    let result = dumac::scan(&path, &ScanOptions::new()).expect("scan should succeed");
+    let expected = Scanner::new(ScanOptions::new())
+        .calculate_size(path.clone())
+        .unwrap();
+    assert_eq!(result.blocks, expected);
+    assert_eq!(result.errors, 0);
+    assert_eq!(result.bytes, expected * 512);
+    assert_eq!(result.apparent_bytes, 10000);
+    assert_eq!((result.files, result.dirs, result.symlinks), (1, 1, 0));
+
+    // On the caller's pool
+    let pool = Arc::new(dumac::pool::build(2, || {}).unwrap());
+    let options = ScanOptions::new().thread_pool(pool);
+    assert_eq!(dumac::scan(&path, &options).unwrap().blocks, expected);
+
+    let error = dumac::scan("/nonexistent/dumac", &ScanOptions::new()).unwrap_err();
+    assert!(matches!(
+        error,
+        DumacError::Io {
+            errno: libc::ENOENT,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_scan_with_visitor() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path();
+    fs::create_dir_all(temp_path.join("keep/inner")).expect("Failed to create dirs");
+    fs::create_dir(temp_path.join("skip")).expect("Failed to create dir");
+    fs::write(temp_path.join("keep/inner/file"), "a".repeat(10000)).expect("Failed to write file");
+    fs::write(temp_path.join("skip/file"), "b".repeat(10000)).expect("Failed to write file");
+    let path = temp_path.to_string_lossy().to_string();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let visited = seen.clone();
+    let result = dumac::scan_with_visitor(&path, &ScanOptions::new(), move |entry| {
+        visited
+            .lock()
+            .unwrap()
+            .push((entry.path.to_path_buf(), entry.kind, entry.depth));
+        if entry.path.ends_with("skip") {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .expect("scan should succeed");
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        seen,
+        vec![
+            (temp_path.join("keep"), Kind::Dir, 1),
+            (temp_path.join("keep/inner"), Kind::Dir, 2),
+            (temp_path.join("keep/inner/file"), Kind::File, 3),
+            (temp_path.join("skip"), Kind::Dir, 1),
+        ]
+    );
+
+    // Only the file outside the pruned directory is counted
+    let kept = fs::metadata(temp_path.join("keep/inner/file"))
+        .unwrap()
+        .blocks() as i64;
+    assert_eq!(result.blocks, kept);
+}
+
+#[test]
+fn test_scan_streaming() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path();
+    fs::create_dir_all(temp_path.join("a/b")).expect("Failed to create dirs");
+    fs::write(temp_path.join("a/b/file"), "a".repeat(10000)).expect("Failed to write file");
+    fs::write(temp_path.join("a/file"), "b".repeat(10000)).expect("Failed to write file");
+    let path = temp_path.to_string_lossy().to_string();
+
+    let mut stream = dumac::scan_streaming(&path, &ScanOptions::new());
+    let results: Vec<_> = stream.by_ref().collect();
+    let result = stream.finish().expect("scan should succeed");
+
+    // Children complete before their parents
+    let paths: Vec<_> = results.iter().map(|dir| dir.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            temp_path.join("a/b"),
+            temp_path.join("a"),
+            temp_path.to_path_buf()
+        ]
+    );
+    let inner = fs::metadata(temp_path.join("a/b/file")).unwrap().blocks() as i64;
+    let outer = fs::metadata(temp_path.join("a/file")).unwrap().blocks() as i64;
+    assert_eq!(results[0].blocks, inner);
+    assert_eq!(results[1].blocks, inner + outer);
+    assert_eq!(results[2].blocks, result.blocks);
+}
+
+#[test]
+fn test_cancelled_scan() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path();
+    fs::create_dir(temp_path.join("sub")).expect("Failed to create dir");
+    fs::write(temp_path.join("file"), "a".repeat(10000)).expect("Failed to write file");
+    fs::write(temp_path.join("sub/file"), "b".repeat(10000)).expect("Failed to write file");
+    let path = temp_path.to_string_lossy().to_string();
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let options = ScanOptions::new().cancel_on(cancel.clone());
+    let result = dumac::scan(&path, &options).expect("scan should succeed");
+    assert!(!result.cancelled);
+
+    // Cancelled up front, only the root's own listing is counted
+    cancel.store(true, Ordering::Relaxed);
+    let result = dumac::scan(&path, &options).expect("scan should succeed");
+    assert!(result.cancelled);
+    let root_file = fs::metadata(temp_path.join("file")).unwrap().blocks() as i64;
+    assert_eq!(result.blocks, root_file);
+}
+
+// Scan /tree from an in-memory source, with the options `with` adds
+fn scan_tree(
+    source: &Arc<dyn DirSource>,
+    with: impl FnOnce(ScanOptions) -> ScanOptions,
+) -> ScanResult {
+    let options = with(ScanOptions::new().dir_source(source.clone()));
+    dumac::scan("/tree", &options).expect("scan should succeed")
+}
+
+#[test]
+fn test_memory_source() {
+    let tree = MemorySource::new()
+        .file("/tree/a/one", 8)
+        .hardlink("/tree/b/one", "/tree/a/one")
+        .file("/tree/b/two", 16)
+        .symlink("/tree/b/link")
+        .file("/tree/node_modules/dep", 1000);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // The hardlink counts once
+    let result = scan_tree(&source, |options| options);
+    assert_eq!(result.blocks, 8 + 16 + 1 + 1000);
+    assert_eq!((result.files, result.dirs, result.symlinks), (4, 4, 1));
+    assert_eq!(result.hardlinks, 1);
+
+    let result = scan_tree(&source, |options| options.exclude("node_modules"));
+    assert_eq!(result.blocks, 8 + 16 + 1);
+}
+
+#[test]
+fn test_hardlinks_keyed_by_device() {
+    // Each volume numbers its own inodes, so the same number on two is two
+    // files, each linked twice here
+    let tree = MemorySource::new()
+        .file("/tree/a/one", 8)
+        .hardlink("/tree/a/again", "/tree/a/one")
+        .hardlink("/tree/b/one", "/tree/a/one")
+        .hardlink("/tree/b/again", "/tree/a/one")
+        .device("/tree/b/one", 2)
+        .device("/tree/b/again", 2);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    let result = scan_tree(&source, |options| options);
+    assert_eq!(result.blocks, 8 + 8);
+}
+
+#[test]
+fn test_directories_reached_twice() {
+    // b/view shows a again, as a bind mount would, and a/up leads back to
+    // the root
+    let tree = MemorySource::new()
+        .file("/tree/a/one", 8)
+        .file("/tree/b/two", 16)
+        .bind("/tree/a/up", "/tree")
+        .bind("/tree/b/view", "/tree/a");
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+    let collect = Arc::new(Collect::default());
+    let result = scan_tree(&source, |options| options.error_sink(collect.clone()));
+
+    // Each directory counted once, and only the cycle reported
+    assert_eq!(result.blocks, 8 + 16);
+    let errors = collect.take();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].message.ends_with("directory causes a cycle"));
+}
+
+#[test]
+fn test_exclude_normalizes_names() {
+    // Precomposed as listed, decomposed as given, the same name either way
+    let tree = MemorySource::new()
+        .file("/tree/Caf\u{e9}/menu", 8)
+        .file("/tree/Cafe/menu", 16)
+        .file("/tree/CAF\u{c9}/menu", 40);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // A volume that can't be looked up, as here, is taken to compare case
+    let result = scan_tree(&source, |options| options.exclude("Cafe\u{301}"));
+    assert_eq!(result.blocks, 16 + 40);
+}
+
+#[test]
+fn test_exclude_follows_volume_case() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir(root.join("Caches")).unwrap();
+    fs::write(root.join("Caches/blob"), vec![1u8; 64 * 1024]).unwrap();
+    // APFS usually doesn't compare case, but it can be made to
+    let case_sensitive = !root.join("CACHES").exists();
+
+    let root = root.to_str().unwrap();
+    let all = dumac::scan(root, &ScanOptions::new()).unwrap();
+    let result = dumac::scan(root, &ScanOptions::new().exclude("caches")).unwrap();
+    assert_eq!(result.blocks < all.blocks, !case_sensitive);
+}
+
+#[test]
+fn test_exclude_regex() {
+    let tree = MemorySource::new()
+        .file("/tree/build-17/out", 800)
+        .file("/tree/src/build-17.rs", 8)
+        .file("/tree/src/main.o", 16)
+        .file("/tree/src/main.c", 40);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+    let regex = |pattern| Regex::new(pattern).unwrap();
+
+    // Directories and files alike
+    let result = scan_tree(&source, |options| {
+        options
+            .exclude_regex(regex("(^|/)build-[0-9]+$"))
+            .exclude_regex(regex("\\.o$"))
+    });
+    assert_eq!(result.blocks, 8 + 40);
+
+    // Matched against the path from the scanned directory
+    let result = scan_tree(&source, |options| options.exclude_regex(regex("^src/")));
+    assert_eq!(result.blocks, 800);
+}
+
+#[test]
+fn test_respect_duignore() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let write = |path: &str, contents: &[u8]| {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    };
+    write(".duignore", b"build/\n");
+    write("build/out.bin", &[0; 4096]);
+    write("a/.duignore", b"*.log\n!keep.log\n");
+    write("a/trace.log", &[0; 4096]);
+    write("a/keep.log", &[0; 4096]);
+    write("a/b/deep.log", &[0; 4096]);
+    write("notes.txt", b"notes");
+
+    let scan = |duignore| {
+        let options = ScanOptions::new().respect_duignore(duignore);
+        dumac::scan(root.to_str().unwrap(), &options).unwrap()
+    };
+    let result = scan(false);
+    assert_eq!((result.files, result.dirs), (7, 4));
+    // Each file's rules apply from its directory down, a later line taking
+    // back an earlier one. Left are the two .duignore files, keep.log and
+    // notes.txt.
+    let result = scan(true);
+    assert_eq!((result.files, result.dirs), (4, 3));
+}
+
+#[test]
+fn test_skip_hidden() {
+    let tree = MemorySource::new()
+        .file("/tree/notes.txt", 8)
+        .file("/tree/.DS_Store", 16)
+        .file("/tree/.git/objects/pack", 1000)
+        .file("/tree/a/.env", 40);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    let result = scan_tree(&source, |options| options.skip_hidden(true));
+    assert_eq!(result.blocks, 8);
+    assert_eq!(result.files, 1);
+}
+
+#[test]
+fn test_include_only() {
+    let tree = MemorySource::new()
+        .file("/tree/app.log", 8)
+        .file("/tree/a/old.log", 16)
+        .file("/tree/a/main.rs", 300)
+        .file("/tree/b/c/trace.log", 40);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // Every directory is still scanned for matching files
+    let result = scan_tree(&source, |options| options.include_only("*.log"));
+    assert_eq!(result.blocks, 8 + 16 + 40);
+
+    // With a slash, matched from the scanned directory
+    let result = scan_tree(&source, |options| options.include_only("a/*"));
+    assert_eq!(result.blocks, 16 + 300);
+}
+
+#[test]
+fn test_extension_filters() {
+    let tree = MemorySource::new()
+        .file("/tree/clip.MP4", 800)
+        .file("/tree/a/photo.heic", 40)
+        .file("/tree/a/main.o", 16)
+        .file("/tree/b/Makefile", 8);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // Regardless of case, and with or without the dot
+    let result = scan_tree(&source, |options| {
+        options.extension("mp4").extension(".heic")
+    });
+    assert_eq!(result.blocks, 800 + 40);
+
+    // Files without an extension aren't left out by --no-ext
+    let result = scan_tree(&source, |options| options.skip_extension("o"));
+    assert_eq!(result.blocks, 800 + 40 + 8);
+}
+
+#[test]
+fn test_size_band() {
+    // Sized by their length, 512 bytes to a block here
+    let tree = MemorySource::new()
+        .file("/tree/tiny", 1)
+        .file("/tree/a/mid", 8)
+        .file("/tree/a/b/large", 2048);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // Both ends are inclusive
+    let result = scan_tree(&source, |options| {
+        options.min_file_size(8 * 512).max_file_size(8 * 512)
+    });
+    assert_eq!(result.blocks, 8);
+    assert_eq!(result.files, 1);
+
+    let result = scan_tree(&source, |options| options.min_file_size(8 * 512 + 1));
+    assert_eq!(result.blocks, 2048);
+
+    let result = scan_tree(&source, |options| options.max_file_size(1024 * 1024 - 1));
+    assert_eq!(result.blocks, 1 + 8);
+}
+
+#[test]
+fn test_age_filters() {
+    const DAY: u64 = 24 * 60 * 60;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let tree = MemorySource::new()
+        .file("/tree/recent", 8)
+        .dated("/tree/recent", now - 10 * DAY as i64)
+        .file("/tree/a/stale", 16)
+        .dated("/tree/a/stale", now - 400 * DAY as i64)
+        .file("/tree/a/undated", 40);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    // A file without a date is in neither range
+    let result = scan_tree(&source, |options| {
+        options.newer_than(Duration::from_secs(90 * DAY))
+    });
+    assert_eq!(result.blocks, 8);
+    let result = scan_tree(&source, |options| {
+        options.older_than(Duration::from_secs(365 * DAY))
+    });
+    assert_eq!(result.blocks, 16);
+}
+
+#[test]
+fn test_user_filter() {
+    let tree = MemorySource::new()
+        .file("/tree/mine", 8)
+        .owned("/tree/mine", 501, 20)
+        .file("/tree/a/theirs", 16)
+        .owned("/tree/a/theirs", 502, 20)
+        .file("/tree/a/b/mine", 40)
+        .owned("/tree/a/b/mine", 501, 80)
+        .file("/tree/a/unowned", 300);
+    let source: Arc<dyn DirSource> = Arc::new(tree);
+
+    let result = scan_tree(&source, |options| options.user(501));
+    // Only the user's files, not ones with no owner known
+    assert_eq!(result.blocks, 8 + 40);
+    assert_eq!(result.files, 2);
+}
+
+#[test]
+fn test_ages_from_scan_start() {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let tree = MemorySource::new()
+        .file("/tree/new", 8)
+        .dated("/tree/new", now);
+    let options = ScanOptions::new()
+        .dir_source(Arc::new(tree))
+        .older_than(Duration::from_secs(1));
+    let result = dumac::scan("/tree", &options).expect("scan should succeed");
+    assert_eq!(result.blocks, 0);
+
+    // Reused once the file has aged, as by --watch
+    std::thread::sleep(Duration::from_secs(2));
+    let result = dumac::scan("/tree", &options).expect("scan should succeed");
+    assert_eq!(result.blocks, 8);
+}
+
+#[test]
+fn test_reports_go_to_error_sink() {
+    let tree = MemorySource::new()
+        .file("/tree/clip.mp4", 800)
+        .file("/tree/a/notes.txt", 8);
+    let collect = Arc::new(Collect::default());
+    let options = ScanOptions::new()
+        .dir_source(Arc::new(tree))
+        .by_extension(true)
+        .error_sink(collect.clone());
+    let pool = dumac::pool::build(4, || {}).unwrap();
+    let scanner = Scanner::new(options);
+    pool.install(|| scanner.calculate_size("/tree".to_string()))
+        .unwrap();
+    assert_eq!(collect.take_reports(), "");
+
+    scanner.report();
+    let reports = collect.take_reports();
+    assert!(reports.starts_with("by extension:\n"), "{}", reports);
+    assert_eq!(reports.lines().count(), 3);
+}
+
+#[test]
+fn test_largest_files() {
+    let tree = MemorySource::new()
+        .file("/tree/a/one", 8)
+        .file("/tree/a/two", 300)
+        .hardlink("/tree/b/two", "/tree/a/two")
+        .file("/tree/b/three", 40)
+        .file("/tree/b/c/four", 200)
+        .symlink("/tree/b/c/link");
+    let options = ScanOptions::new()
+        .dir_source(Arc::new(tree))
+        .largest_files(3);
+    let pool = dumac::pool::build(4, || {}).unwrap();
+    let scanner = Scanner::new(options);
+    pool.install(|| scanner.calculate_size("/tree".to_string()))
+        .unwrap();
+
+    // Biggest first, and a hardlinked file only once
+    let largest: Vec<_> = scanner
+        .largest_files()
+        .into_iter()
+        .map(|(path, blocks)| (path.to_string_lossy().into_owned(), blocks))
+        .collect();
+    assert_eq!(largest.len(), 3);
+    assert!(largest[0].0.ends_with("/two") && largest[0].1 == 300);
+    assert_eq!(largest[1], ("/tree/b/c/four".to_string(), 200));
+    assert_eq!(largest[2], ("/tree/b/three".to_string(), 40));
+}
+
+#[test]
+fn test_root_counts() {
+    let tree = MemorySource::new()
+        .file("/tree/a/one", 8)
+        .file("/tree/a/two", 300)
+        .hardlink("/tree/b/two", "/tree/a/two")
+        .file("/tree/b/c/three", 40)
+        .symlink("/tree/b/c/link");
+    let options = ScanOptions::new().dir_source(Arc::new(tree));
+    let scanner = Scanner::new(options);
+    assert_eq!(scanner.counts("/tree"), None);
+    scanner.calculate_size("/tree".to_string()).unwrap();
+
+    // Every link and symlink is an entry, and the root is a directory
+    let counts = scanner.counts("/tree").unwrap();
+    assert_eq!((counts.files, counts.dirs), (5, 4));
+}
+
+#[test]
+fn test_generated_tree() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("tree");
+    let builder = TreeBuilder::new(42)
+        .depth(3)
+        .fanout(3)
+        .files(6)
+        .hardlinks(0.2)
+        .sparse(0.1)
+        .non_utf8_names(0.1);
+    let tree = builder.build(&root).unwrap();
+    assert_eq!(tree.dirs, 40);
+    assert!(tree.hardlinks > 0 && tree.sparse > 0);
+
+    // The same seed gives the same tree
+    let again = builder.build(temp_dir.path().join("again")).unwrap();
+    assert_eq!(
+        (again.files, again.hardlinks, again.sparse),
+        (tree.files, tree.hardlinks, tree.sparse)
+    );
+
+    let result = dumac::scan(root.to_str().unwrap(), &ScanOptions::new()).unwrap();
+    assert_eq!(result.blocks, tree.blocks);
+    assert_eq!(result.files, tree.files);
+    assert_eq!(result.dirs, tree.dirs);
+    assert_eq!(result.hardlinks, tree.hardlinks);
+    assert_eq!(result.errors, 0);
+}
+
+#[test]
+fn test_malformed_attribute_buffers() {
+    // One entry as getattrlistbulk lays it out: length, returned attributes,
+    // a name reference, the object type, then the name itself
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&40u32.to_ne_bytes());
+    for attrs in [libc::ATTR_CMN_NAME | libc::ATTR_CMN_OBJTYPE, 0, 0, 0, 0] {
+        entry.extend_from_slice(&attrs.to_ne_bytes());
+    }
+    entry.extend_from_slice(&12i32.to_ne_bytes());
+    entry.extend_from_slice(&2u32.to_ne_bytes());
+    entry.extend_from_slice(&1u32.to_ne_bytes());
+    entry.extend_from_slice(b"a\0\0\0");
+
+    let parsed: Vec<_> = dumac::attrbuf::entries(&entry, 1).collect();
+    assert_eq!(parsed.len(), 1);
+    let parsed = parsed[0].as_ref().unwrap();
+    assert_eq!((parsed.name, parsed.obj_type), (Some(&b"a"[..]), 1));
+
+    // More entries claimed than the buffer holds
+    let two: Vec<_> = dumac::attrbuf::entries(&entry, 2).collect();
+    assert!(two[0].is_ok() && two[1].is_err());
+
+    // Every truncation is refused rather than read past
+    for len in 0..entry.len() {
+        let mut parsed = dumac::attrbuf::entries(&entry[..len], 1);
+        assert!(parsed.next().unwrap().is_err(), "{} bytes", len);
+        assert!(parsed.next().is_none());
+    }
+
+    // A zero length, and a name pointing outside the entry
+    let mut zero = entry.clone();
+    zero[..4].copy_from_slice(&0u32.to_ne_bytes());
+    assert!(dumac::attrbuf::entries(&zero, 3).all(|entry| entry.is_err()));
+    let mut outside = entry.clone();
+    outside[24..28].copy_from_slice(&1000i32.to_ne_bytes());
+    assert!(dumac::attrbuf::entries(&outside, 1)
+        .next()
+        .unwrap()
+        .is_err());
+}
+
+#[test]
+fn test_snapshot_diff() {
+    let pool = dumac::pool::build(2, || {}).unwrap();
+    let snapshot = |tree: MemorySource| {
+        let options = ScanOptions::new().dir_source(Arc::new(tree));
+        Snapshot::scan(&options, &pool, "/tree").expect("scan should succeed")
+    };
+    let before = snapshot(
+        MemorySource::new()
+            .file("/tree/a/one", 8)
+            .file("/tree/b/two", 16)
+            .file("/tree/c/three", 4),
+    );
+    let after = snapshot(
+        MemorySource::new()
+            .file("/tree/a/one", 8)
+            .file("/tree/b/two", 100)
+            .file("/tree/d/four", 2),
+    );
+
+    // Saved and loaded unchanged
+    let temp_dir = TempDir::new().unwrap();
+    let dump = temp_dir.path().join("before.dump");
+    before.save(&dump).unwrap();
+    assert!(Snapshot::is_snapshot(&dump));
+    assert_eq!(Snapshot::load(&dump).unwrap(), before);
+    // Anything else is the wrong type of file
+    let other = temp_dir.path().join("other");
+    fs::write(&other, "not a snapshot").unwrap();
+    assert!(matches!(
+        Snapshot::load(&other),
+        Err(DumacError::Io {
+            errno: libc::EFTYPE,
+            ..
+        })
+    ));
+
+    let changes: Vec<_> = dumac::snapshot::diff(&before, &after)
+        .into_iter()
+        .map(|change| {
+            (
+                change.path.to_string_lossy().into_owned(),
+                change.before,
+                change.after,
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("b".to_string(), 16, 100),
+            ("".to_string(), 28, 110),
+            ("c".to_string(), 4, 0),
+            ("d".to_string(), 0, 2),
+        ]
+    );
+}